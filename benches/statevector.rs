@@ -0,0 +1,79 @@
+//! Benchmarks of the statevector kernels and simulator.
+//!
+//! Run with `cargo bench --bench statevector`. The number of qubits defaults to 20, and can be
+//! set with the `TRIDENT_BENCH_QUBITS` environment variable.
+
+use std::time::{Duration, Instant};
+
+use trident::linalg::DMatrix;
+use trident::operation::OpKind;
+use trident::random::RandomCircuit;
+use trident::simulator::{Statevector, StatevectorSimulator};
+
+/// Runs the function a few times and returns the best duration.
+fn time(mut f: impl FnMut()) -> Duration {
+    (0..3)
+        .map(|_| {
+            let start = Instant::now();
+            f();
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn bench_kernels(num_qubits: usize, threads: usize) {
+    let gates: [(&str, DMatrix, usize); 5] = [
+        ("diagonal 1q (rz)", OpKind::RZ.matrix(&[0.3]).unwrap(), 1),
+        ("permutation 1q (x)", OpKind::X.matrix(&[]).unwrap(), 1),
+        ("dense 1q (h)", OpKind::H.matrix(&[]).unwrap(), 1),
+        ("permutation 2q (cx)", OpKind::CX.matrix(&[]).unwrap(), 2),
+        ("dense 2q (h x h)", OpKind::H.matrix(&[]).unwrap().kronecker(&OpKind::H.matrix(&[]).unwrap()), 2),
+    ];
+
+    let mut state = Statevector::new(num_qubits);
+    state.set_threads(threads);
+
+    for (name, matrix, arity) in &gates {
+        // Every qubit is targeted, to cover both contiguous and strided layouts.
+        let elapsed = time(|| (0..num_qubits + 1 - arity).for_each(|q| {
+            let qubits: Vec<usize> = (q..q + arity).collect();
+            state.apply(matrix, &qubits);
+        }));
+        let per_amplitude = elapsed.as_nanos() as f64 / ((num_qubits + 1 - arity) << num_qubits) as f64;
+        println!("{name:<24} {threads:>3} threads  {per_amplitude:>8.3} ns/amplitude");
+    }
+}
+
+fn bench_simulator(num_qubits: usize, threads: usize) {
+    let mut random = RandomCircuit::new(num_qubits, 20);
+    random.gates = vec!["h", "rx", "rz", "t", "cx", "cz", "cp"];
+    random.measure = true;
+    let circ = random.generate_seeded(0).unwrap();
+
+    for fusion in [1, 2, 3, 4] {
+        let mut simulator = StatevectorSimulator::new(1000);
+        simulator.seed = Some(0);
+        simulator.threads = threads;
+        simulator.fusion = fusion;
+
+        let elapsed = time(|| {
+            simulator.sample(&circ).unwrap();
+        });
+        println!("random circuit, fusion {fusion}  {threads:>3} threads  {:>10.3} ms", elapsed.as_secs_f64() * 1E3);
+    }
+}
+
+fn main() {
+    let num_qubits = std::env::var("TRIDENT_BENCH_QUBITS").ok().and_then(|n| n.parse().ok()).unwrap_or(20);
+    let available = std::thread::available_parallelism().map_or(1, usize::from);
+
+    println!("{num_qubits} qubits");
+    for threads in [1, available] {
+        bench_kernels(num_qubits, threads);
+        bench_simulator(num_qubits, threads);
+        if available == 1 {
+            break;
+        }
+    }
+}
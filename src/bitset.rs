@@ -1,4 +1,6 @@
-#[derive(Clone, PartialEq, Eq, Debug)]
+use std::ops::{BitAnd, BitOr, BitXor, BitXorAssign};
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub struct BitSet {
     size: usize,
     data: Box<[u8]>,
@@ -18,7 +20,7 @@ impl BitSet {
     pub fn new(size: usize) -> Self {
         Self {
             size,
-            data: vec![0; word(size)].into_boxed_slice(),
+            data: vec![0; word(size + 7)].into_boxed_slice(),
         }
     }
 
@@ -26,8 +28,12 @@ impl BitSet {
         self.size
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
     pub fn get(&self, index: usize) -> Option<bool> {
-        (index < self.size).then(|| self.data[word(index)] & mask(index) == 1)
+        (index < self.size).then(|| self.data[word(index)] & mask(index) != 0)
     }
 
     pub fn set(&mut self, index: usize, value: bool) -> Option<()> {
@@ -37,4 +43,47 @@ impl BitSet {
             self.data[word(index)] &= !mask(index)
         })
     }
-}
\ No newline at end of file
+
+    /// Returns the number of bits set to `true`.
+    pub fn count_ones(&self) -> usize {
+        self.data.iter().map(|word| word.count_ones() as usize).sum()
+    }
+
+    /// Returns `true` if any bit is set to `true`.
+    pub fn any(&self) -> bool {
+        self.data.iter().any(|&word| word != 0)
+    }
+
+    /// Returns an iterator over the indices of the bits set to `true`.
+    pub fn ones(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.size).filter(|&index| self.data[word(index)] & mask(index) != 0)
+    }
+}
+
+// Implements the bitwise operation $op between two bitsets of the same length.
+macro_rules! bitset_op {
+    { $op: ident: $fn: ident, $word_op: tt } => {
+        impl $op<Self> for &BitSet {
+            type Output = BitSet;
+
+            fn $fn(self, rhs: Self) -> BitSet {
+                assert_eq!(self.size, rhs.size, "bitsets must have the same length");
+                BitSet {
+                    size: self.size,
+                    data: self.data.iter().zip(rhs.data.iter()).map(|(a, b)| a $word_op b).collect(),
+                }
+            }
+        }
+    }
+}
+
+bitset_op! { BitAnd: bitand, & }
+bitset_op! { BitOr: bitor, | }
+bitset_op! { BitXor: bitxor, ^ }
+
+impl BitXorAssign<&BitSet> for BitSet {
+    fn bitxor_assign(&mut self, rhs: &BitSet) {
+        assert_eq!(self.size, rhs.size, "bitsets must have the same length");
+        self.data.iter_mut().zip(rhs.data.iter()).for_each(|(a, b)| *a ^= b);
+    }
+}
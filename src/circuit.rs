@@ -1,9 +1,16 @@
+use std::borrow::Cow;
 use std::marker::PhantomData;
+use std::mem;
+use std::convert::Infallible;
 use std::ops::{Deref, DerefMut};
 
 use thiserror::Error;
 
-use crate::instruction::InstrVec;
+use crate::definition::{GateDefinition, GateRef, InvalidDefinition};
+use crate::expression::Expr;
+use crate::instruction::{self, Blocks, ForLoop, InstrIter, InstrVec, Modifier, Remap};
+use crate::operation::OpKind;
+use crate::parameter::Parameter;
 use crate::provider::Architecture;
 use crate::symbol::{SymbolTuple, Symbol, Qubit, Ancillas, Bit, FormalParameter};
 
@@ -11,18 +18,93 @@ use crate::symbol::{SymbolTuple, Symbol, Qubit, Ancillas, Bit, FormalParameter};
 pub enum CircuitError {
     #[error("quantum allocator overflow")]
     AllocOverflow,
+    #[error("invalid gate definition: {0}")]
+    InvalidDefinition(#[from] InvalidDefinition),
+    #[error("calls can only be raised to constant integer powers of at most {} in magnitude", u32::MAX)]
+    CallPower,
+    #[error("called gate can't be inverted: {0}")]
+    Inverse(InverseError),
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Error)]
 #[error("quantum allocator overflow")]
 pub struct CircuitAllocOverflow;
 
+impl From<CircuitAllocOverflow> for CircuitError {
+    fn from(_: CircuitAllocOverflow) -> Self {
+        Self::AllocOverflow
+    }
+}
+
+impl From<InverseError> for CircuitError {
+    fn from(error: InverseError) -> Self {
+        match error {
+            InverseError::AllocOverflow => Self::AllocOverflow,
+            error => Self::Inverse(error),
+        }
+    }
+}
+
+/// The error of [`ConcreteCircuit::transpile`], whose calls are inlined before the backend
+/// transpiles the circuit.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Error)]
+pub enum TranspileError<E> {
+    #[error("circuit could not be inlined: {0}")]
+    Inline(CircuitError),
+    #[error(transparent)]
+    Backend(E),
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Error)]
+pub enum BindError {
+    #[error("expected {0} values for the formal parameters, got {1}")]
+    WrongCount(usize, usize),
+    #[error("parameter evaluates to a non-finite value")]
+    NonFinite,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Error)]
+pub enum InverseError {
+    #[error("quantum allocator overflow")]
+    AllocOverflow,
+    #[error("operation `{0}` is not unitary and can't be inverted")]
+    NotUnitary(&'static str),
+    #[error("instructions with classical modifiers can't be inverted")]
+    ClassicalModifier,
+    #[error("measurements can't be inverted")]
+    Measure,
+    #[error("resets can't be inverted")]
+    Reset,
+    #[error("control flow depending on classical bits can't be inverted")]
+    ControlFlow,
+}
+
+impl InverseError {
+    /// Returns the error for an operation without adjoint.
+    fn not_invertible(op: &OpKind<'_>) -> Self {
+        match op {
+            OpKind::Measure => Self::Measure,
+            OpKind::Reset => Self::Reset,
+            OpKind::IfElse(_) | OpKind::While(_) | OpKind::Switch(_) => Self::ControlFlow,
+            op => Self::NotUnitary(op.label()),
+        }
+    }
+}
+
+impl From<CircuitAllocOverflow> for InverseError {
+    fn from(_: CircuitAllocOverflow) -> Self {
+        Self::AllocOverflow
+    }
+}
+
 #[derive(Clone, Default, Debug)]
 pub struct QuantumCircuit {
     num_qubits: u32,
     num_bits: u32,
     num_formals: u32,
     num_ancillas: u32,
+    exprs: Vec<Expr<'static>>,
+    definitions: Vec<GateDefinition>,
     data: Vec<u32>,
 }
 
@@ -62,13 +144,151 @@ impl QuantumCircuit {
         self.num_ancillas as usize
     }
 
-    pub fn bind(self, parameters: &[f32]) -> Option<ConcreteCircuit> {
-        (parameters.len() == self.num_formals()).then(|| {
-            todo!() // TODO: implement this somehow.
+    /// Returns an iterator over the instructions of the circuit.
+    pub fn iter(&self) -> InstrIter<'_> {
+        InstrIter::new(&self.data)
+    }
+
+    /// Returns the expression referenced by the parameter, if it references one.
+    pub fn expr<'a>(&self, param: Parameter<'a>) -> Option<Expr<'a>> {
+        param.as_expr().map(|index| self.exprs[index as usize].rebrand())
+    }
+
+    /// Returns the gates defined in the circuit, see [`GateDefinition`].
+    pub fn definitions(&self) -> &[GateDefinition] {
+        &self.definitions
+    }
+
+    /// Returns the definition of the gate.
+    pub fn definition(&self, gate: GateRef<'_>) -> &GateDefinition {
+        &self.definitions[gate.id() as usize]
+    }
+
+    /// Replaces every call by the body of the called gate, recursively, with the qubits and
+    /// parameters of the call. Calls under classical modifiers are replaced by an
+    /// [`OpKind::For`] loop running the body once, under the same modifiers.
+    ///
+    /// The returned circuit has no gate definitions.
+    pub fn inline(self) -> Result<Self, CircuitError> {
+        if self.definitions.is_empty() {
+            return Ok(self);
+        }
+
+        self.edit(|builder| builder.inline())
+    }
+
+    /// Returns the circuit running this circuit and the other one side by side, on the qubits,
+    /// bits and formal parameters of this circuit followed by those of the other one. The
+    /// ancillas of both circuits, in the same order, are the ancillas of the returned circuit.
+    pub fn tensor(&self, other: &QuantumCircuit) -> Result<Self, CircuitError> {
+        Self::new(|builder| {
+            let num_qubits = self.num_qubit() + other.num_qubit();
+            let qubits = (0..num_qubits).map(|_| builder.qubit()).collect::<Result<Vec<_>, _>>()?;
+            builder.set_num_ancillas(self.num_ancillas() + other.num_ancillas())?;
+            let ancillas: Vec<_> = (0..builder.num_ancillas()).map(|n| Qubit::new_unchecked((num_qubits + n) as u32)).collect();
+            let bits = (0..self.num_bits() + other.num_bits()).map(|_| builder.bit()).collect::<Result<Vec<_>, _>>()?;
+            let parameters = (0..self.num_formals() + other.num_formals())
+                .map(|_| builder.formal().map(Parameter::from))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let (qubits1, qubits2) = qubits.split_at(self.num_qubit());
+            let (ancillas1, ancillas2) = ancillas.split_at(self.num_ancillas());
+            let (bits1, bits2) = bits.split_at(self.num_bits());
+            let (parameters1, parameters2) = parameters.split_at(self.num_formals());
+
+            builder.append_circuit(self, &[qubits1, ancillas1].concat(), bits1, parameters1)?;
+            builder.append_circuit(other, &[qubits2, ancillas2].concat(), bits2, parameters2)?;
+            Ok(())
+        })
+    }
+
+    /// Returns the inverse of the circuit, running the adjoint of each instruction in reverse
+    /// order. Fixed gates are replaced by their adjoint, rotations and phase shifts have their
+    /// angle negated, instructions under quantum modifiers get an extra [`Modifier::Inverse`],
+    /// and the called gate definitions are replaced by their inverse, see
+    /// [`GateDefinition::inverse`].
+    /// [`OpKind::For`] loops are kept, with their body inverted, as are the repetitions of
+    /// [`Modifier::ForConst`].
+    ///
+    /// Fails if the circuit measures or resets a qubit, or has an instruction depending on
+    /// classical bits, be it through a classical modifier or control flow.
+    pub fn inverse(&self) -> Result<Self, InverseError> {
+        let mut builder = CircuitBuilder::from_circ(self.clone());
+        builder.invert()?;
+        Ok(builder.into_circ())
+    }
+
+    /// Returns the circuit with it's calls inlined, see [`QuantumCircuit::inline`], borrowing
+    /// it if it has no gate definitions.
+    pub(crate) fn inlined(&self) -> Result<Cow<'_, Self>, CircuitError> {
+        match self.definitions.is_empty() {
+            true => Ok(Cow::Borrowed(self)),
+            false => self.clone().inline().map(Cow::Owned),
+        }
+    }
+
+    /// Returns the value of the parameter, where the `n`th formal parameter takes the
+    /// value `values[n]`. Returns `None` if a formal parameter has no value.
+    pub fn parameter_value(&self, param: Parameter<'_>, values: &[f64]) -> Option<f64> {
+        if let Some(value) = param.as_value() {
+            Some(value.into())
+        } else if let Some(formal) = param.as_formal() {
+            values.get(formal.id() as usize).cloned()
+        } else {
+            self.exprs[param.as_expr()? as usize].eval(values)
+        }
+    }
+
+    /// Binds the formal parameters of the circuit to the given values, evaluating
+    /// every expression. Fails if the number of values is not the number of formal
+    /// parameters, or if a value or a parameter evaluates to a value which is not finite.
+    pub fn bind(self, parameters: &[f32]) -> Result<ConcreteCircuit, BindError> {
+        if parameters.len() != self.num_formals() {
+            return Err(BindError::WrongCount(self.num_formals(), parameters.len()));
+        } else if !parameters.iter().all(|x| x.is_finite()) {
+            return Err(BindError::NonFinite);
+        }
+
+        let values: Vec<f64> = parameters.iter().map(|&x| x.into()).collect();
+        let bind = |param: Parameter<'_>| self.parameter_value(param, &values)
+            .map(|value| value as f32)
+            .filter(|value| value.is_finite())
+            .map(Parameter::from)
+            .ok_or(BindError::NonFinite);
+
+        let mut data = Vec::with_capacity(self.data.len());
+        instruction::rewrite(&self.data, &mut data, &mut |instr, dest| {
+            let bound = instr.parameters.iter()
+                .map(|&param| bind(param))
+                .collect::<Result<Vec<_>, _>>()?;
+            let modifiers = instr.modifiers.iter()
+                .map(|modifier| match modifier {
+                    Modifier::Power(exponent) => bind(*exponent).map(Modifier::Power),
+                    modifier => Ok(modifier.clone()),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            instruction::write_parts(dest, &instr.op, instr.qubits, instr.bits, &bound, &modifiers);
+            Ok(())
+        })?;
+
+        Ok(self.with_concrete_data(data))
+    }
+
+    /// Returns a concrete circuit with the same symbols as this one, but with the given
+    /// instructions, which should not reference any formal parameter.
+    pub(crate) fn with_concrete_data(&self, data: Vec<u32>) -> ConcreteCircuit {
+        ConcreteCircuit::new(QuantumCircuit {
+            num_qubits: self.num_qubits,
+            num_bits: self.num_bits,
+            num_formals: 0,
+            num_ancillas: self.num_ancillas,
+            exprs: Vec::new(),
+            definitions: self.definitions.clone(),
+            data,
         })
     }
 
-    pub fn bind_copy(&self, parameters: &[f32]) -> Option<ConcreteCircuit> {
+    pub fn bind_copy(&self, parameters: &[f32]) -> Result<ConcreteCircuit, BindError> {
         self.clone().bind(parameters)
     }
 
@@ -87,6 +307,8 @@ pub struct CircuitBuilder<'id> {
     num_bits: u32,
     num_formals: u32,
     num_ancillas: u32,
+    exprs: Vec<Expr<'id>>,
+    definitions: Vec<GateDefinition>,
     data: InstrVec<'id>,
 }
 
@@ -120,6 +342,8 @@ impl<'id> CircuitBuilder<'id> {
             num_ancillas: circ.num_ancillas,
             num_bits: circ.num_bits,
             num_formals: circ.num_formals,
+            exprs: circ.exprs.iter().map(Expr::rebrand).collect(),
+            definitions: circ.definitions,
             data: InstrVec::new(circ.data),
         }
     }
@@ -131,6 +355,8 @@ impl<'id> CircuitBuilder<'id> {
             num_ancillas: self.num_ancillas,
             num_bits: self.num_bits,
             num_formals: self.num_formals,
+            exprs: self.exprs.iter().map(Expr::rebrand).collect(),
+            definitions: self.definitions,
             data: self.data.take(),
         }
     }
@@ -156,13 +382,13 @@ impl<'id> CircuitBuilder<'id> {
 
     pub fn qubit(&mut self) -> Result<Qubit<'id>, CircuitAllocOverflow> {
         (1 < Qubit::MAX as usize - self.width())
-            .then(|| Qubit::new_unchecked(incr(&mut self.num_bits)))
+            .then(|| Qubit::new_unchecked(incr(&mut self.num_qubits)))
             .ok_or(CircuitAllocOverflow)
     }
 
     pub fn qubits<const N: usize>(&mut self) -> Result<[Qubit<'id>; N], CircuitAllocOverflow> {
-        (N < Qubit::MAX as usize - self.num_bits())
-            .then(|| [(); N].map(|_| Qubit::new_unchecked(incr(&mut self.num_bits))))
+        (N < Qubit::MAX as usize - self.width())
+            .then(|| [(); N].map(|_| Qubit::new_unchecked(incr(&mut self.num_qubits))))
             .ok_or(CircuitAllocOverflow)
     }
 
@@ -172,16 +398,237 @@ impl<'id> CircuitBuilder<'id> {
 
     pub fn formal(&mut self) -> Result<FormalParameter<'id>, CircuitAllocOverflow> {
         (1 < FormalParameter::MAX as usize - self.num_formals())
-            .then(|| FormalParameter::new_unchecked(incr(&mut self.num_bits)))
+            .then(|| FormalParameter::new_unchecked(incr(&mut self.num_formals)))
             .ok_or(CircuitAllocOverflow)
     }
 
     pub fn formals<const N: usize>(&mut self) -> Result<[FormalParameter<'id>; N], CircuitAllocOverflow> {
         (N < FormalParameter::MAX as usize - self.num_formals())
-            .then(|| [(); N].map(|_| FormalParameter::new_unchecked(incr(&mut self.num_bits))))
+            .then(|| [(); N].map(|_| FormalParameter::new_unchecked(incr(&mut self.num_formals))))
+            .ok_or(CircuitAllocOverflow)
+    }
+
+    /// Registers the expression in the circuit's expression table, and returns a parameter
+    /// referencing it. Expressions that do not depend on any formal parameter are evaluated
+    /// right away, unless their value is not finite, in which case binding the circuit fails.
+    pub fn expr(&mut self, expr: impl Into<Expr<'id>>) -> Result<Parameter<'id>, CircuitAllocOverflow> {
+        let expr = expr.into().simplify();
+
+        if let Some(value) = expr.as_value().map(|value| value as f32).filter(|value| value.is_finite()) {
+            return Ok(Parameter::from(value));
+        }
+
+        if let Expr::Formal(formal) = expr {
+            return Ok(Parameter::from(formal));
+        }
+
+        (self.exprs.len() < Parameter::MAX_EXPRS as usize)
+            .then(|| {
+                self.exprs.push(expr);
+                Parameter::expr(self.exprs.len() as u32 - 1)
+            })
             .ok_or(CircuitAllocOverflow)
     }
 
+    /// Returns the expression equal to the parameter.
+    fn param_expr(&self, param: Parameter<'_>) -> Expr<'id> {
+        if let Some(value) = param.as_value() {
+            Expr::Value(value.into())
+        } else if let Some(formal) = param.as_formal() {
+            Expr::Formal(FormalParameter::new_unchecked(formal.id()))
+        } else {
+            self.exprs[param.as_expr().unwrap() as usize].clone()
+        }
+    }
+
+    /// Registers the gate in the circuit's definition table, and returns a reference to call
+    /// it with, see [`CircuitBuilder::call`].
+    pub fn define(&mut self, definition: GateDefinition) -> Result<GateRef<'id>, CircuitAllocOverflow> {
+        (self.definitions.len() < GateRef::MAX as usize)
+            .then(|| {
+                self.definitions.push(definition);
+                GateRef::new_unchecked(self.definitions.len() as u32 - 1)
+            })
+            .ok_or(CircuitAllocOverflow)
+    }
+
+    /// Returns the definition of the gate.
+    pub fn definition(&self, gate: GateRef<'id>) -> &GateDefinition {
+        &self.definitions[gate.id() as usize]
+    }
+
+    /// Appends a call to the gate on the qubits, with the parameters.
+    ///
+    /// Panics if the number of qubits or parameters is not that of the gate's definition.
+    pub fn call(&mut self, gate: GateRef<'id>, qubits: &[Qubit<'id>], parameters: &[Parameter<'id>]) -> &mut Self {
+        let definition = self.definition(gate);
+        assert_eq!(definition.num_qubits(), qubits.len(), "wrong number of qubits for gate `{}`", definition.name());
+        assert_eq!(definition.num_parameters(), parameters.len(), "wrong number of parameters for gate `{}`", definition.name());
+
+        self.data.push(OpKind::Call(gate), qubits, &[], parameters);
+        self
+    }
+
+    /// Replaces every call by the body of the called gate, see [`QuantumCircuit::inline`].
+    pub fn inline(&mut self) -> Result<(), CircuitError> {
+        let bodies = mem::take(&mut self.definitions).into_iter()
+            .map(|definition| definition.body().clone().inline())
+            .collect::<Result<Vec<_>, _>>()?;
+        let data = mem::take(&mut self.data).take();
+        let mut res = Vec::with_capacity(data.len());
+
+        instruction::rewrite(&data, &mut res, &mut |instr, dest| {
+            let OpKind::Call(gate) = instr.op else {
+                instr.write(dest);
+                return Ok(());
+            };
+
+            let body = &bodies[gate.id() as usize];
+            let qubits: Vec<_> = instr.qubits.iter().map(|qubit| Qubit::new_unchecked(qubit.id())).collect();
+            let parameters: Vec<_> = instr.parameters.iter().map(|param| param.rebrand()).collect();
+
+            let mut inlined = Vec::with_capacity(body.data.len());
+            let mut mapping = Mapping { builder: self, circ: body, qubits: &qubits, bits: &[], parameters: &parameters, gates: 0 };
+            instruction::remap(&body.data, &mut inlined, &mut mapping)?;
+
+            // The inverse and powers apply in order to the body, as repetitions of it's inverse
+            // for negative powers, see `check_modifiers`, and the controls to each instruction.
+            let quantum = instr.modifiers.iter().take_while(|modifier| !modifier.is_classical()).count();
+            for modifier in &instr.modifiers[..quantum] {
+                let exponent = match modifier {
+                    Modifier::Inverse => -1,
+                    Modifier::Power(exponent) => instruction::call_exponent(*exponent).ok_or(CircuitError::CallPower)?,
+                    _ => continue,
+                };
+
+                if exponent < 0 {
+                    let mut inverse = Vec::with_capacity(inlined.len());
+                    self.write_inverse(&inlined, &mut inverse)?;
+                    inlined = inverse;
+                }
+                if exponent.abs() != 1 {
+                    let count = u32::try_from(exponent.unsigned_abs()).map_err(|_| CircuitError::CallPower)?;
+                    let op = OpKind::For(ForLoop { count, body: Blocks::default() });
+                    let mut repeated = Vec::with_capacity(inlined.len() + 4);
+                    instruction::write_control_flow(&mut repeated, &op, &[&inlined], &[], &[]);
+                    inlined = repeated;
+                }
+            }
+
+            let (controls, states) = instr.controls();
+            if !controls.is_empty() {
+                let controls: Vec<_> = controls.iter().map(|qubit| Qubit::new_unchecked(qubit.id())).collect();
+                let mut controlled = Vec::with_capacity(inlined.len());
+                instruction::rewrite(&inlined, &mut controlled, &mut |instr, dest| {
+                    match instr.op {
+                        OpKind::Nop => instr.write(dest),
+                        _ => instruction::write_with_controls(dest, instr, &controls, &states),
+                    }
+                    Ok::<_, Infallible>(())
+                }).unwrap_or_else(|never| match never {});
+                inlined = controlled;
+            }
+
+            let classical = &instr.modifiers[quantum..];
+            if classical.is_empty() {
+                dest.extend(inlined);
+            } else {
+                let op = OpKind::For(ForLoop { count: 1, body: Blocks::default() });
+                instruction::write_control_flow(dest, &op, &[&inlined], &[], classical);
+            }
+            Ok::<_, CircuitError>(())
+        })?;
+
+        self.data = InstrVec::new(res);
+        Ok(())
+    }
+
+    /// Replaces the instructions by their inverse, see [`QuantumCircuit::inverse`].
+    fn invert(&mut self) -> Result<(), InverseError> {
+        let data = mem::take(&mut self.data).take();
+        let mut res = Vec::with_capacity(data.len());
+        self.write_inverse(&data, &mut res)?;
+
+        // The gates that are never called are left as they are.
+        let mut called = vec![false; self.definitions.len()];
+        instruction::visit(&res, &mut |instr| {
+            if let OpKind::Call(gate) = instr.op {
+                called[gate.id() as usize] = true;
+            }
+            Ok::<_, Infallible>(())
+        }).unwrap_or_else(|never| match never {});
+        for (definition, _) in self.definitions.iter_mut().zip(called).filter(|(_, called)| *called) {
+            *definition = definition.inverse()?;
+        }
+
+        self.data = InstrVec::new(res);
+        Ok(())
+    }
+
+    /// Writes the inverse of the instructions to the destination, in reverse order.
+    fn write_inverse(&mut self, src: &[u32], dest: &mut Vec<u32>) -> Result<(), InverseError> {
+        let instrs = InstrVec::new(src.to_vec());
+        for instr in instrs.iter_rev() {
+            // Repetitions commute with the inverse, unlike the other classical modifiers.
+            if instr.modifiers.iter().any(|modifier| modifier.is_classical() && !matches!(modifier, Modifier::ForConst(_))) {
+                return Err(InverseError::ClassicalModifier);
+            }
+
+            match &instr.op {
+                // gate definitions are inverted in place
+                OpKind::Nop | OpKind::Call(_) => instr.write(dest),
+                OpKind::For(repeat) => {
+                    let mut body = Vec::new();
+                    self.write_inverse(repeat.body.bodies().next().unwrap(), &mut body)?;
+                    instruction::write_control_flow(dest, &instr.op, &[&body], instr.bits, &instr.modifiers);
+                }
+                op if instr.has_quantum_modifier() => {
+                    let mut modifiers = instr.modifiers.clone();
+                    let quantum = modifiers.iter().take_while(|modifier| !modifier.is_classical()).count();
+                    if modifiers[quantum - 1] == Modifier::Inverse {
+                        modifiers.remove(quantum - 1);
+                    } else {
+                        modifiers.insert(quantum, Modifier::Inverse);
+                    }
+                    instruction::write_parts(dest, op, instr.qubits, instr.bits, instr.parameters, &modifiers);
+                }
+                op => {
+                    let parameters: Vec<_> = instr.parameters.iter().map(|&param| self.param_expr(param)).collect();
+                    let (adjoint, parameters) = op.adjoint(&parameters).ok_or_else(|| InverseError::not_invertible(op))?;
+                    let parameters = parameters.into_iter()
+                        .map(|expr| self.expr(expr).map(Parameter::rebrand))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    instruction::write_parts(dest, &adjoint, instr.qubits, instr.bits, &parameters, &instr.modifiers);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Appends the instructions of the circuit, it's `n`th qubit, ancillas included, bit and
+    /// formal parameter being replaced by `qubits[n]`, `bits[n]` and `parameters[n]`. The gates
+    /// defined in the circuit are added to the definition table.
+    ///
+    /// Panics if the number of qubits, bits or parameters is not the width, number of bits or
+    /// number of formal parameters of the circuit.
+    pub fn append_circuit(&mut self, circ: &QuantumCircuit, qubits: &[Qubit<'id>], bits: &[Bit<'id>], parameters: &[Parameter<'id>]) -> Result<&mut Self, CircuitError> {
+        assert_eq!(circ.width(), qubits.len(), "wrong number of qubits for the appended circuit");
+        assert_eq!(circ.num_bits(), bits.len(), "wrong number of bits for the appended circuit");
+        assert_eq!(circ.num_formals(), parameters.len(), "wrong number of parameters for the appended circuit");
+
+        if self.definitions.len() + circ.definitions.len() > GateRef::MAX as usize {
+            return Err(CircuitError::AllocOverflow);
+        }
+        let mut data = Vec::with_capacity(circ.data.len());
+        let gates = self.definitions.len() as u32;
+        instruction::remap(&circ.data, &mut data, &mut Mapping { builder: self, circ, qubits, bits, parameters, gates })?;
+
+        self.definitions.extend(circ.definitions.iter().cloned());
+        self.data.extend(&InstrVec::new(data));
+        Ok(self)
+    }
+
     pub fn num_bits(&self) -> usize {
         self.num_bits as usize
     }
@@ -225,6 +672,197 @@ impl<'id> CircuitBuilder<'id> {
     }
 }
 
+/// Replaces the symbols of another circuit by those of a builder, see
+/// [`CircuitBuilder::append_circuit`].
+struct Mapping<'a, 'id> {
+    builder: &'a mut CircuitBuilder<'id>,
+    /// The other circuit, whose expressions are registered in the builder.
+    circ: &'a QuantumCircuit,
+    qubits: &'a [Qubit<'id>],
+    bits: &'a [Bit<'id>],
+    parameters: &'a [Parameter<'id>],
+    /// The offset of the gates of the other circuit in the definition table of the builder.
+    gates: u32,
+}
+
+impl<'a> Remap<'a> for Mapping<'_, '_> {
+    type Error = CircuitAllocOverflow;
+
+    fn qubit(&self, qubit: Qubit<'a>) -> Qubit<'a> {
+        Qubit::new_unchecked(self.qubits[qubit.id() as usize].id())
+    }
+
+    fn bit(&self, bit: Bit<'a>) -> Bit<'a> {
+        Bit::new_unchecked(self.bits[bit.id() as usize].id())
+    }
+
+    fn parameter(&mut self, param: Parameter<'a>) -> Result<Parameter<'a>, Self::Error> {
+        if param.is_value() {
+            return Ok(param);
+        }
+
+        if let Some(formal) = param.as_formal() {
+            return Ok(self.parameters[formal.id() as usize].rebrand());
+        }
+
+        let expr = self.circ.expr(param).unwrap().substitute(&|formal| self.builder.param_expr(self.parameters[formal.id() as usize]));
+        self.builder.expr(expr).map(Parameter::rebrand)
+    }
+
+    fn gate(&self, gate: GateRef<'a>) -> GateRef<'a> {
+        GateRef::new_unchecked(gate.id() + self.gates)
+    }
+}
+
+/// Defines shorthands on the circuit builder to append gates.
+macro_rules! gates {
+    {
+        $(
+            $(#[doc$($args: tt)*])*
+            $fn: ident => $op: ident ( $($param: ident),* ; $($qubit: ident),* ),
+        )*
+    } => {
+        impl<'id> CircuitBuilder<'id> {
+            $(
+                $(#[doc$($args)*])*
+                pub fn $fn(&mut self, $($param: impl Into<Parameter<'id>>,)* $($qubit: Qubit<'id>),*) -> &mut Self {
+                    self.data.push(OpKind::$op, &[$($qubit),*], &[], &[$($param.into()),*]);
+                    self
+                }
+            )*
+        }
+    }
+}
+
+gates! {
+    /// Appends a Hadamard gate.
+    h => H(; qubit),
+    /// Appends a Pauli X gate.
+    x => X(; qubit),
+    /// Appends a Pauli Y gate.
+    y => Y(; qubit),
+    /// Appends a Pauli Z gate.
+    z => Z(; qubit),
+    /// Appends an S gate.
+    s => S(; qubit),
+    /// Appends an S† gate.
+    sdg => Sdg(; qubit),
+    /// Appends a T gate.
+    t => T(; qubit),
+    /// Appends a T† gate.
+    tdg => Tdg(; qubit),
+    /// Appends a square root of X gate.
+    sx => SX(; qubit),
+    /// Appends the adjoint of a square root of X gate.
+    sxdg => SXdg(; qubit),
+    /// Appends a rotation around the X axis.
+    rx => RX(theta; qubit),
+    /// Appends a rotation around the Y axis.
+    ry => RY(theta; qubit),
+    /// Appends a rotation around the Z axis.
+    rz => RZ(theta; qubit),
+    /// Appends a phase shift.
+    p => Phase(lambda; qubit),
+    /// Appends a generic single qubit gate.
+    u => U(theta, phi, lambda; qubit),
+    /// Appends a controlled X gate.
+    cx => CX(; control, target),
+    /// Appends a controlled Y gate.
+    cy => CY(; control, target),
+    /// Appends a controlled Z gate.
+    cz => CZ(; qubit1, qubit2),
+    /// Appends a swap gate.
+    swap => Swap(; qubit1, qubit2),
+    /// Appends a controlled phase shift.
+    cp => CPhase(lambda; qubit1, qubit2),
+    /// Appends a Toffoli gate.
+    ccx => CCX(; control1, control2, target),
+}
+
+impl<'id> CircuitBuilder<'id> {
+    /// Appends a measurement of the qubit, stored in the bit.
+    pub fn measure(&mut self, qubit: Qubit<'id>, bit: Bit<'id>) -> &mut Self {
+        self.data.push(OpKind::Measure, &[qubit], &[bit], &[]);
+        self
+    }
+
+    /// Appends a reset of the qubit.
+    pub fn reset(&mut self, qubit: Qubit<'id>) -> &mut Self {
+        self.data.push(OpKind::Reset, &[qubit], &[], &[]);
+        self
+    }
+
+    /// Returns the instructions appended to the builder by the closure, which are not kept
+    /// in the circuit.
+    fn body<F>(&mut self, build: F) -> Result<InstrVec<'id>, CircuitError>
+    where
+        F: FnOnce(&mut Self) -> Result<(), CircuitError>
+    {
+        let outer = mem::take(&mut self.data);
+        let res = build(self);
+        let body = mem::replace(&mut self.data, outer);
+        res.map(|_| body)
+    }
+
+    /// Appends an if/else block, running the instructions appended by `then` if the bit is
+    /// `true`, and those appended by `otherwise` if it is `false`.
+    pub fn if_else<T, E>(&mut self, bit: Bit<'id>, then: T, otherwise: E) -> Result<&mut Self, CircuitError>
+    where
+        T: FnOnce(&mut Self) -> Result<(), CircuitError>,
+        E: FnOnce(&mut Self) -> Result<(), CircuitError>,
+    {
+        let then = self.body(then)?;
+        let otherwise = self.body(otherwise)?;
+        self.data.push_if_else(bit, &then, &otherwise);
+        Ok(self)
+    }
+
+    /// Appends an if block, running the instructions appended by `then` if the bit is `true`.
+    pub fn if_then<T>(&mut self, bit: Bit<'id>, then: T) -> Result<&mut Self, CircuitError>
+    where
+        T: FnOnce(&mut Self) -> Result<(), CircuitError>,
+    {
+        self.if_else(bit, then, |_| Ok(()))
+    }
+
+    /// Appends a while loop, running the instructions appended by `body` while the bit is `true`.
+    pub fn while_loop<F>(&mut self, bit: Bit<'id>, body: F) -> Result<&mut Self, CircuitError>
+    where
+        F: FnOnce(&mut Self) -> Result<(), CircuitError>,
+    {
+        let body = self.body(body)?;
+        self.data.push_while(bit, &body);
+        Ok(self)
+    }
+
+    /// Appends a for loop, running the instructions appended by `body` `count` times.
+    pub fn for_loop<F>(&mut self, count: u32, body: F) -> Result<&mut Self, CircuitError>
+    where
+        F: FnOnce(&mut Self) -> Result<(), CircuitError>,
+    {
+        let body = self.body(body)?;
+        self.data.push_for(count, &body);
+        Ok(self)
+    }
+
+    /// Appends a switch over the value of the register, the first bit being the least
+    /// significant. `case` is called with `Some(n)` for each `n < cases` to build the body run
+    /// when the value is `n`, then with `None` to build the default body.
+    ///
+    /// Panics if the register has more than 32 bits.
+    pub fn switch<F>(&mut self, register: &[Bit<'id>], cases: u32, mut case: F) -> Result<&mut Self, CircuitError>
+    where
+        F: FnMut(&mut Self, Option<u32>) -> Result<(), CircuitError>,
+    {
+        let bodies = (0..cases)
+            .map(|n| self.body(|builder| case(builder, Some(n))))
+            .collect::<Result<Vec<_>, _>>()?;
+        let default = self.body(|builder| case(builder, None))?;
+        self.data.push_switch(register, &bodies.iter().collect::<Vec<_>>(), &default);
+        Ok(self)
+    }
+}
+
 #[derive(Clone, Default, Debug)]
 pub struct ConcreteCircuit {
     circ: QuantumCircuit,
@@ -238,14 +876,14 @@ impl ConcreteCircuit {
         Self { circ }
     }
 
-    pub fn transpile<'arch, T: Architecture>(self, backend: &T) -> Result<TranspiledCircuit<T>, T::TranspileError> {
-        let mut circ = self.take();
+    pub fn transpile<T: Architecture>(self, backend: &T) -> Result<TranspiledCircuit<T>, TranspileError<T::TranspileError>> {
+        let mut circ = self.take().inline().map_err(TranspileError::Inline)?;
         let ancillas = Ancillas::new(&circ);
-        circ.data = backend.transpile(InstrVec::new(circ.data), ancillas)?.take();
+        circ.data = backend.transpile(InstrVec::new(circ.data), ancillas).map_err(TranspileError::Backend)?.take();
         Ok(TranspiledCircuit::new(circ))
     }
 
-    pub fn transpile_copy<T: Architecture>(&self, backend: &T) -> Result<TranspiledCircuit<T>, T::TranspileError> {
+    pub fn transpile_copy<T: Architecture>(&self, backend: &T) -> Result<TranspiledCircuit<T>, TranspileError<T::TranspileError>> {
         self.clone().transpile(backend)
     }
 
@@ -299,4 +937,356 @@ impl<T: Architecture> Deref for TranspiledCircuit<T> {
     fn deref(&self) -> &Self::Target {
         &self.circ
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::definition::{GateDefinition, InvalidDefinition};
+    use crate::equivalence;
+    use crate::expression::Expr;
+    use crate::instruction::Modifier;
+    use crate::operation::OpKind;
+    use crate::parameter::Parameter;
+    use crate::simulator::DensityMatrixSimulator;
+    use crate::symbol::Qubit;
+
+    use super::{BindError, CircuitBuilder, CircuitError, InverseError, QuantumCircuit};
+
+    /// An instruction as it's label, or the index of the called gate, and the indices of it's
+    /// qubits and bits and the values of it's parameters.
+    type Summary = (String, Vec<u32>, Vec<u32>, Vec<f32>);
+
+    /// Returns the instructions of the circuit bound to the values.
+    fn summary(circ: &QuantumCircuit, values: &[f32]) -> Vec<Summary> {
+        let circ = circ.bind_copy(values).unwrap();
+        circ.iter().map(|instr| {
+            let label = match &instr.op {
+                OpKind::Call(gate) => format!("call {}", gate.id()),
+                op => op.label().to_string(),
+            };
+            (
+                label,
+                instr.qubits.iter().map(|qubit| qubit.id()).collect(),
+                instr.bits.iter().map(|bit| bit.id()).collect(),
+                instr.parameters.iter().map(|param| param.as_value().unwrap()).collect(),
+            )
+        }).collect()
+    }
+
+    fn instr(label: &str, qubits: &[u32], bits: &[u32], parameters: &[f32]) -> Summary {
+        (label.to_string(), qubits.to_vec(), bits.to_vec(), parameters.to_vec())
+    }
+
+    /// Defines the gate $ T_1 \, CX_{0, 1} \, R_y(\theta)_0 $, with the formal parameter $ \theta $.
+    fn define<'id>(b: &mut CircuitBuilder<'id>) -> Result<OpKind<'id>, CircuitError> {
+        let body = QuantumCircuit::new(|b| {
+            let [a, c] = b.qubits()?;
+            let theta = b.formal()?;
+            b.ry(theta, a).cx(a, c).t(c);
+            Ok(())
+        })?;
+        Ok(OpKind::Call(b.define(GateDefinition::new("g", body)?)?))
+    }
+
+    #[test]
+    fn bind_rejects_non_finite_values() {
+        let circ = QuantumCircuit::new(|b| {
+            let qubit = b.qubit()?;
+            let theta = b.formal()?;
+            let inverse = b.expr(1.0 / theta)?;
+            b.rx(inverse, qubit);
+            Ok(())
+        }).unwrap();
+
+        assert!(circ.bind_copy(&[2.0]).is_ok());
+        assert_eq!(circ.bind_copy(&[0.0]).unwrap_err(), BindError::NonFinite);
+        assert_eq!(circ.bind_copy(&[f32::NAN]).unwrap_err(), BindError::NonFinite);
+        assert_eq!(circ.bind_copy(&[]).unwrap_err(), BindError::WrongCount(1, 0));
+    }
+
+    #[test]
+    fn bind_rejects_non_finite_formals() {
+        let circ = QuantumCircuit::new(|b| {
+            let qubit = b.qubit()?;
+            let theta = b.formal()?;
+            let zero = b.expr(0.0 * theta)?;
+            b.rx(zero, qubit);
+            Ok(())
+        }).unwrap();
+
+        assert!(circ.bind_copy(&[1.0]).is_ok());
+        assert_eq!(circ.bind_copy(&[f32::INFINITY]).unwrap_err(), BindError::NonFinite);
+    }
+
+    #[test]
+    fn non_finite_constants_are_not_folded() {
+        let circ = QuantumCircuit::new(|b| {
+            let qubit = b.qubit()?;
+            let param = b.expr(Expr::from(1.0) / 0.0)?;
+            b.rz(param, qubit);
+            Ok(())
+        }).unwrap();
+
+        assert!(circ.iter().all(|instr| instr.parameters.iter().all(|param| !param.is_value())));
+        assert_eq!(circ.bind(&[]).unwrap_err(), BindError::NonFinite);
+    }
+
+    #[test]
+    fn inlines_quantum_modifiers_of_calls() {
+        // The gate, possibly inverted then raised to the power, controlled on $ |0 \rangle $.
+        let called = |inverse: bool, exponent: f32| QuantumCircuit::new(|b| {
+            let [c, a, t] = b.qubits()?;
+            let gate = define(b)?;
+            let mut modifiers = vec![Modifier::Power(Parameter::from(exponent))];
+            if inverse {
+                modifiers.insert(0, Modifier::Inverse);
+            }
+            b.instructions_mut().push_controlled_modified(gate, &[c], &[false], &[a, t], &[Parameter::from(0.7)], &modifiers)?;
+            Ok(())
+        }).unwrap();
+
+        // The inverse of the gate, controlled on $ |0 \rangle $ and repeated twice.
+        let expected = QuantumCircuit::new(|b| {
+            let [c, a, t] = b.qubits()?;
+            for _ in 0..2 {
+                b.instructions_mut().push_controlled(OpKind::Tdg, &[c], &[false], &[t], &[]);
+                b.instructions_mut().push_controlled(OpKind::CX, &[c], &[false], &[a, t], &[]);
+                b.instructions_mut().push_controlled(OpKind::RY, &[c], &[false], &[a], &[Parameter::from(-0.7)]);
+            }
+            Ok(())
+        }).unwrap();
+
+        let identity = QuantumCircuit::new(|b| {
+            b.qubits::<3>()?;
+            Ok(())
+        }).unwrap();
+
+        assert!(equivalence::equivalent(&called(true, 2.0), &expected).unwrap());
+        assert!(equivalence::equivalent(&called(false, -2.0), &expected).unwrap());
+        assert!(!equivalence::equivalent(&called(false, 2.0), &expected).unwrap());
+        assert!(equivalence::equivalent(&called(true, 0.0), &identity).unwrap());
+        assert!(equivalence::equivalent(&called(true, 2.0).inverse().unwrap(), &called(false, 2.0)).unwrap());
+    }
+
+    #[test]
+    fn simulates_modified_calls() {
+        // The call flips the target when the control is in $ |1 \rangle $ and the bit is set.
+        let circ = QuantumCircuit::new(|b| {
+            let [c, a, t] = b.qubits()?;
+            let bits = b.bits::<2>()?;
+            let body = QuantumCircuit::new(|b| {
+                let [a, t] = b.qubits()?;
+                b.h(a).cx(a, t).h(a);
+                Ok(())
+            })?;
+            let gate = OpKind::Call(b.define(GateDefinition::new("flip", body)?)?);
+
+            b.x(c).x(a).measure(a, bits[0]);
+            b.instructions_mut().push_controlled_modified(gate, &[c], &[true], &[a, t], &[], &[Modifier::Power(Parameter::from(3.0)), Modifier::IfBit(bits[0])])?;
+            b.measure(t, bits[1]);
+            Ok(())
+        }).unwrap();
+
+        let branches = DensityMatrixSimulator::new(0).run(&circ).unwrap();
+        let flipped: f64 = branches.iter().filter(|branch| branch.bits.get(1).unwrap()).map(|branch| branch.probability).sum();
+        assert!((flipped - 0.5).abs() < 1E-9);
+    }
+
+    #[test]
+    fn rejects_non_integer_powers_of_calls() {
+        for exponent in [Some(0.5), Some(4294967296.0), None] {
+            let res = QuantumCircuit::new(|b| {
+                let [a, t] = b.qubits()?;
+                let gate = define(b)?;
+                let exponent = match exponent {
+                    Some(exponent) => Parameter::from(exponent),
+                    None => b.formal()?.into(),
+                };
+                b.instructions_mut().push_with_modifiers(gate, &[a, t], &[], &[Parameter::from(0.1)], &[Modifier::Power(exponent)])?;
+                Ok(())
+            });
+            assert_eq!(res.unwrap_err(), CircuitError::CallPower);
+        }
+    }
+
+    #[test]
+    fn definitions_must_be_unitary() {
+        let reset = QuantumCircuit::new(|b| {
+            let qubit = b.qubit()?;
+            b.for_loop(2, |b| {
+                b.h(qubit).reset(qubit);
+                Ok(())
+            })?;
+            Ok(())
+        }).unwrap();
+        assert_eq!(GateDefinition::new("reset", reset).unwrap_err(), InvalidDefinition::NotUnitary("reset"));
+
+        let measure = QuantumCircuit::new(|b| {
+            let qubit = b.qubit()?;
+            let bit = b.bit()?;
+            b.measure(qubit, bit);
+            Ok(())
+        }).unwrap();
+        assert_eq!(GateDefinition::new("measure", measure).unwrap_err(), InvalidDefinition::Classical);
+    }
+
+    #[test]
+    fn append_circuit_remaps_symbols() {
+        let inner = QuantumCircuit::new(|b| {
+            let [a, c] = b.qubits()?;
+            let [bit] = b.bits()?;
+            let [x, y] = [b.formal()?, b.formal()?];
+            let gate = define(b)?;
+            let affine = b.expr(Expr::from(x) * Expr::from(y) + 1.0)?;
+            b.rx(x, a).cx(a, c).rz(affine, c).rz(0.75, a);
+            b.instructions_mut().push(gate, &[c, a], &[], &[y.into()]);
+            b.measure(c, bit);
+            Ok(())
+        }).unwrap();
+
+        // The gate defined by the inner circuit comes after the one of the outer circuit.
+        let outer = QuantumCircuit::new(|b| {
+            let [q0, q1, q2] = b.qubits()?;
+            let bits = b.bits::<2>()?;
+            let phi = b.formal()?;
+            let gate = define(b)?;
+            b.instructions_mut().push(gate, &[q0, q1], &[], &[phi.into()]);
+            b.append_circuit(&inner, &[q2, q0], &[bits[1]], &[phi.into(), Parameter::from(0.5)])?;
+            Ok(())
+        }).unwrap();
+
+        assert_eq!(outer.definitions().len(), 2);
+        assert_eq!(summary(&outer, &[0.25]), [
+            instr("call 0", &[0, 1], &[], &[0.25]),
+            instr("rx", &[2], &[], &[0.25]),
+            instr("cx", &[2, 0], &[], &[]),
+            instr("rz", &[0], &[], &[1.125]),
+            instr("rz", &[2], &[], &[0.75]),
+            instr("call 1", &[0, 2], &[], &[0.5]),
+            instr("measure", &[0], &[1], &[]),
+        ]);
+    }
+
+    #[test]
+    fn tensor_places_ancillas_after_qubits() {
+        let first = QuantumCircuit::new(|b| {
+            let [a, c] = b.qubits()?;
+            let [bit] = b.bits()?;
+            let theta = b.formal()?;
+            b.set_num_ancillas(1)?;
+            b.rx(theta, a).cx(c, Qubit::new_unchecked(2)).measure(a, bit);
+            Ok(())
+        }).unwrap();
+
+        let second = QuantumCircuit::new(|b| {
+            let qubit = b.qubit()?;
+            let [bit] = b.bits()?;
+            let theta = b.formal()?;
+            let double = b.expr(2.0 * theta)?;
+            b.set_num_ancillas(2)?;
+            let [a1, a2] = [Qubit::new_unchecked(1), Qubit::new_unchecked(2)];
+            b.cx(qubit, a2).ry(double, a1).measure(a1, bit);
+            Ok(())
+        }).unwrap();
+
+        let circ = first.tensor(&second).unwrap();
+        assert_eq!((circ.num_qubit(), circ.num_ancillas(), circ.num_bits(), circ.num_formals()), (3, 3, 2, 2));
+        assert_eq!(summary(&circ, &[0.5, 0.25]), [
+            instr("rx", &[0], &[], &[0.5]),
+            instr("cx", &[1, 3], &[], &[]),
+            instr("measure", &[0], &[0], &[]),
+            instr("cx", &[2, 5], &[], &[]),
+            instr("ry", &[4], &[], &[0.5]),
+            instr("measure", &[4], &[1], &[]),
+        ]);
+    }
+
+    #[test]
+    fn inverse_errors() {
+        let inverse = |build: fn(&mut CircuitBuilder<'_>) -> Result<(), CircuitError>| {
+            QuantumCircuit::new(build).unwrap().inverse().unwrap_err()
+        };
+
+        assert_eq!(inverse(|b| {
+            let [qubit] = b.qubits()?;
+            let bit = b.bit()?;
+            b.h(qubit).measure(qubit, bit);
+            Ok(())
+        }), InverseError::Measure);
+        assert_eq!(inverse(|b| {
+            let [qubit] = b.qubits()?;
+            b.for_loop(2, |b| {
+                b.reset(qubit);
+                Ok(())
+            })?;
+            Ok(())
+        }), InverseError::Reset);
+        assert_eq!(inverse(|b| {
+            let [qubit] = b.qubits()?;
+            let bit = b.bit()?;
+            b.if_else(bit, |b| {
+                b.x(qubit);
+                Ok(())
+            }, |_| Ok(()))?;
+            Ok(())
+        }), InverseError::ControlFlow);
+        assert_eq!(inverse(|b| {
+            let [qubit] = b.qubits()?;
+            let bit = b.bit()?;
+            b.instructions_mut().push_modified(OpKind::X, &[qubit], &[], &[], Modifier::IfBit(bit))?;
+            Ok(())
+        }), InverseError::ClassicalModifier);
+    }
+
+    #[test]
+    fn inverts_called_definitions_only() {
+        let circ = QuantumCircuit::new(|b| {
+            let [a, t] = b.qubits()?;
+            let gate = define(b)?;
+            let body = QuantumCircuit::new(|b| {
+                let qubit = b.qubit()?;
+                b.s(qubit);
+                Ok(())
+            })?;
+            b.define(GateDefinition::new("unused", body)?)?;
+            b.instructions_mut().push(gate, &[a, t], &[], &[Parameter::from(0.3)]);
+            Ok(())
+        }).unwrap();
+
+        let inverse = circ.inverse().unwrap();
+        let names: Vec<_> = inverse.definitions().iter().map(GateDefinition::name).collect();
+        assert_eq!(names, ["g_dg", "unused"]);
+        assert_eq!(inverse.definitions()[1].body().iter().next().unwrap().op, OpKind::S);
+    }
+    #[test]
+    fn inverts_repeated_and_modified_gates() {
+        let circ = QuantumCircuit::new(|b| {
+            let [a, c] = b.qubits()?;
+            b.instructions_mut().push_modified(OpKind::T, &[a], &[], &[], Modifier::ForConst(3))?;
+            b.instructions_mut().push_controlled_modified(OpKind::RY, &[a], &[true], &[c], &[Parameter::from(0.4)], &[Modifier::Power(Parameter::from(0.5)), Modifier::ForConst(2)])?;
+            Ok(())
+        }).unwrap();
+
+        // The inverse is added after the quantum modifiers, and the repetitions are kept.
+        let inverse = circ.inverse().unwrap();
+        let mut iter = inverse.iter();
+        let ry = iter.next().unwrap();
+        assert_eq!(ry.op, OpKind::RY);
+        assert!(matches!(ry.modifiers[..], [Modifier::Controlled(_), Modifier::Power(_), Modifier::Inverse, Modifier::ForConst(2)]));
+        let tdg = iter.next().unwrap();
+        assert_eq!(tdg.op, OpKind::Tdg);
+        assert!(matches!(tdg.modifiers[..], [Modifier::ForConst(3)]));
+
+        let identity = QuantumCircuit::new(|b| {
+            b.qubits::<2>()?;
+            Ok(())
+        }).unwrap();
+        let both = QuantumCircuit::new(|b| {
+            let qubits = b.qubits::<2>()?;
+            b.append_circuit(&circ, &qubits, &[], &[])?;
+            b.append_circuit(&inverse, &qubits, &[], &[])?;
+            Ok(())
+        }).unwrap();
+        assert!(equivalence::equivalent(&both, &identity).unwrap());
+    }
+}
@@ -0,0 +1,452 @@
+//! Dependency graph of the instructions of a circuit, for the passes that reason about which
+//! instructions commute, cancel or can run at the same time rather than about their order.
+//!
+//! Each node of a [`CircuitDag`] is an instruction, and each of the qubits and bits it acts on
+//! is a [`Wire`]. The nodes acting on a wire are ordered, and each node depends on the
+//! previous node of each of it's wires. Besides the qubits and bits of the instruction, the
+//! wires of a node include it's control qubits, the bits read by it's classical modifiers and
+//! compute payload, and the wires of the instructions of it's control flow bodies.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::convert::Infallible;
+
+use crate::genericity::Id;
+use crate::instruction::{self, Instr, InstrIter, InstrVec, Modifier};
+use crate::operation::OpKind;
+use crate::symbol::{Bit, Qubit};
+
+/// A qubit or a bit, along which the nodes of a [`CircuitDag`] are ordered.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum Wire<'id> {
+    Qubit(Qubit<'id>),
+    Bit(Bit<'id>),
+}
+
+/// A reference to a node of a [`CircuitDag`]. Node references are never reused, even after
+/// the node is substituted.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct NodeId(usize);
+
+#[derive(Clone, Debug)]
+struct Node<'id> {
+    /// The compact representation of the instruction.
+    data: Vec<u32>,
+    /// The position of the instruction, by which the nodes are ordered: the nodes substituted
+    /// for a node take it's position, followed by their index.
+    position: Vec<usize>,
+    wires: Vec<Wire<'id>>,
+    /// The previous node on each wire.
+    prev: Vec<Option<NodeId>>,
+    /// The next node on each wire.
+    next: Vec<Option<NodeId>>,
+}
+
+impl<'id> Node<'id> {
+    /// Returns the position of the wire among those of the node.
+    ///
+    /// Panics if the node does not act on the wire.
+    fn position(&self, wire: Wire<'id>) -> usize {
+        self.wires.iter().position(|&w| w == wire).expect("node does not act on the wire")
+    }
+}
+
+/// Returns the wires of the instruction, see the [module documentation](self).
+pub(crate) fn wires<'id>(instr: &Instr<'_>) -> Vec<Wire<'id>> {
+    fn add<'id>(instr: &Instr<'_>, wires: &mut Vec<Wire<'id>>) {
+        let mut qubits = instr.qubits.to_vec();
+        let mut bits = instr.bits.to_vec();
+        qubits.extend(instr.controls().0);
+
+        if let OpKind::Compute(compute) = &instr.op {
+            bits.extend(compute.bits);
+        }
+        for modifier in &instr.modifiers {
+            match modifier {
+                Modifier::IfBit(bit) | Modifier::WhileBit(bit) => bits.push(*bit),
+                Modifier::IfCompute(compute) | Modifier::WhileCompute(compute) => bits.extend(compute.bits),
+                Modifier::ForCompute(compute) => bits.extend(compute.bits),
+                _ => (),
+            }
+        }
+
+        let qubits = qubits.iter().map(|qubit| Wire::Qubit(Qubit::new_unchecked(qubit.id())));
+        let bits = bits.iter().map(|bit| Wire::Bit(Bit::new_unchecked(bit.id())));
+        for wire in qubits.chain(bits) {
+            if !wires.contains(&wire) {
+                wires.push(wire);
+            }
+        }
+    }
+
+    let mut res = Vec::new();
+    add(instr, &mut res);
+
+    if let Some(blocks) = instr.op.blocks() {
+        for body in blocks.bodies() {
+            instruction::visit(body, &mut |instr| {
+                add(instr, &mut res);
+                Ok::<_, Infallible>(())
+            }).unwrap();
+        }
+    }
+
+    res
+}
+
+/// A dependency graph of instructions, see the [module documentation](self).
+#[derive(Clone, Default, Debug)]
+pub struct CircuitDag<'id> {
+    _id: Id<'id>,
+    /// The nodes, `None` once substituted.
+    nodes: Vec<Option<Node<'id>>>,
+    /// The first and last nodes of each wire.
+    ends: HashMap<Wire<'id>, (NodeId, NodeId)>,
+}
+
+impl<'id> CircuitDag<'id> {
+    /// Builds the dependency graph of the instructions.
+    pub fn from_instrs(instructions: &InstrVec<'id>) -> Self {
+        let mut res = Self::default();
+        let mut last = HashMap::new();
+
+        for (n, instr) in instructions.iter().enumerate() {
+            res.insert(&instr, vec![n], &mut last);
+        }
+        for (wire, last) in last {
+            res.link(last, wire, None);
+        }
+
+        res
+    }
+
+    /// Returns the instructions of the graph in a topological order, that is such that each
+    /// node comes after it's predecessors. Among the nodes that may come next, the one coming
+    /// first in the instructions the graph was built from is chosen, so their order is kept,
+    /// the instructions substituted for a node taking it's place, see
+    /// [`CircuitDag::substitute`].
+    pub fn to_instrs(&self) -> InstrVec<'id> {
+        let mut data = Vec::new();
+        self.topological(|node| data.extend(&self.node(node).data));
+        InstrVec::new(data)
+    }
+
+    /// Returns the number of nodes in the graph.
+    pub fn len(&self) -> usize {
+        self.nodes.iter().flatten().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.iter().all(Option::is_none)
+    }
+
+    /// Returns the nodes of the graph, in the order of their instructions, see
+    /// [`CircuitDag::to_instrs`].
+    pub fn nodes(&self) -> Vec<NodeId> {
+        let mut res: Vec<_> = (0..self.nodes.len()).map(NodeId).filter(|&node| self.contains(node)).collect();
+        self.sort(&mut res);
+        res
+    }
+
+    /// Returns `true` if the node is in the graph, that is if it has not been substituted.
+    pub fn contains(&self, node: NodeId) -> bool {
+        self.nodes.get(node.0).is_some_and(Option::is_some)
+    }
+
+    /// Returns the node.
+    ///
+    /// Panics if the node is not in the graph.
+    fn node(&self, node: NodeId) -> &Node<'id> {
+        self.nodes[node.0].as_ref().expect("node is not in the graph")
+    }
+
+    fn node_mut(&mut self, node: NodeId) -> &mut Node<'id> {
+        self.nodes[node.0].as_mut().expect("node is not in the graph")
+    }
+
+    /// Returns the instruction of the node.
+    ///
+    /// Panics if the node is not in the graph.
+    pub fn instr(&self, node: NodeId) -> Instr<'_> {
+        InstrIter::new(&self.node(node).data).next().unwrap()
+    }
+
+    /// Returns the wires of the node.
+    ///
+    /// Panics if the node is not in the graph.
+    pub fn wires(&self, node: NodeId) -> &[Wire<'id>] {
+        &self.node(node).wires
+    }
+
+    /// Returns the nodes the node directly depends on, without duplicates and in the order of
+    /// their instructions.
+    ///
+    /// Panics if the node is not in the graph.
+    pub fn predecessors(&self, node: NodeId) -> Vec<NodeId> {
+        self.dedup(self.node(node).prev.iter().flatten().copied())
+    }
+
+    /// Returns the nodes directly depending on the node, without duplicates and in the order of
+    /// their instructions.
+    ///
+    /// Panics if the node is not in the graph.
+    pub fn successors(&self, node: NodeId) -> Vec<NodeId> {
+        self.dedup(self.node(node).next.iter().flatten().copied())
+    }
+
+    /// Returns the previous node on the wire, or `None` if the node is the first one.
+    ///
+    /// Panics if the node is not in the graph or does not act on the wire.
+    pub fn prev_on(&self, node: NodeId, wire: Wire<'id>) -> Option<NodeId> {
+        let node = self.node(node);
+        node.prev[node.position(wire)]
+    }
+
+    /// Returns the next node on the wire, or `None` if the node is the last one.
+    ///
+    /// Panics if the node is not in the graph or does not act on the wire.
+    pub fn next_on(&self, node: NodeId, wire: Wire<'id>) -> Option<NodeId> {
+        let node = self.node(node);
+        node.next[node.position(wire)]
+    }
+
+    /// Returns the first node on the wire, or `None` if no node acts on it.
+    pub fn first_on(&self, wire: Wire<'id>) -> Option<NodeId> {
+        self.ends.get(&wire).map(|&(first, _)| first)
+    }
+
+    /// Returns the last node on the wire, or `None` if no node acts on it.
+    pub fn last_on(&self, wire: Wire<'id>) -> Option<NodeId> {
+        self.ends.get(&wire).map(|&(_, last)| last)
+    }
+
+    /// Returns the nodes without predecessors, in the order of their instructions.
+    pub fn front_layer(&self) -> Vec<NodeId> {
+        self.nodes().into_iter().filter(|&node| self.node(node).prev.iter().all(Option::is_none)).collect()
+    }
+
+    /// Returns the nodes grouped in layers, the first layer being the front layer and each
+    /// node being in the layer following the last layer of it's predecessors. The nodes of a
+    /// layer act on distinct wires, and are in the order of their instructions.
+    pub fn layers(&self) -> Vec<Vec<NodeId>> {
+        let mut depths = vec![0; self.nodes.len()];
+        let mut res: Vec<Vec<NodeId>> = Vec::new();
+
+        self.topological(|node| {
+            let depth = self.node(node).prev.iter().flatten().map(|prev| depths[prev.0] + 1).max().unwrap_or(0);
+            depths[node.0] = depth;
+            if res.len() <= depth {
+                res.push(Vec::new());
+            }
+            res[depth].push(node);
+        });
+
+        res.iter_mut().for_each(|layer| self.sort(layer));
+        res
+    }
+
+    /// Replaces the node by the given instructions, in order, and returns their nodes. The
+    /// instructions may only act on the wires of the node, so the rest of the graph is left
+    /// untouched. The node is removed when there are no instructions.
+    ///
+    /// Panics if the node is not in the graph, or if an instruction acts on a wire the node
+    /// does not act on.
+    pub fn substitute(&mut self, node: NodeId, instructions: &InstrVec<'id>) -> Vec<NodeId> {
+        let removed = self.nodes[node.0].take().expect("node is not in the graph");
+        let mut last: HashMap<_, _> = removed.wires.iter().zip(&removed.prev).map(|(&wire, &prev)| (wire, prev)).collect();
+        let mut res = Vec::new();
+
+        for (n, instr) in instructions.iter().enumerate() {
+            assert!(wires(&instr).iter().all(|wire| last.contains_key(wire)), "substituted instruction acts on a wire the node does not");
+            let position = removed.position.iter().copied().chain([n]).collect();
+            res.push(self.insert(&instr, position, &mut last));
+        }
+
+        for (&wire, &next) in removed.wires.iter().zip(&removed.next) {
+            let prev = last[&wire];
+            self.link(prev, wire, next);
+        }
+
+        res
+    }
+
+    /// Removes the node, linking it's predecessors to it's successors.
+    ///
+    /// Panics if the node is not in the graph.
+    pub fn remove(&mut self, node: NodeId) {
+        self.substitute(node, &InstrVec::default());
+    }
+
+    /// Adds a node for the instruction at the position, after the last nodes of it's wires,
+    /// which it becomes, and returns it. The node is left without successors, to be linked to
+    /// by the caller.
+    fn insert(&mut self, instr: &Instr<'_>, position: Vec<usize>, last: &mut HashMap<Wire<'id>, Option<NodeId>>) -> NodeId {
+        let id = NodeId(self.nodes.len());
+        let wires = wires(instr);
+        let prev: Vec<_> = wires.iter().map(|wire| last.get(wire).copied().flatten()).collect();
+
+        let mut data = Vec::new();
+        instr.write(&mut data);
+        self.nodes.push(Some(Node { data, position, next: vec![None; wires.len()], prev: prev.clone(), wires: wires.clone() }));
+
+        for (wire, prev) in wires.into_iter().zip(prev) {
+            self.link(prev, wire, Some(id));
+            last.insert(wire, Some(id));
+        }
+
+        id
+    }
+
+    /// Makes `next` follow `prev` on the wire, `None` standing for an end of the wire, and
+    /// updates the ends of the wire accordingly.
+    fn link(&mut self, prev: Option<NodeId>, wire: Wire<'id>, next: Option<NodeId>) {
+        if let Some(prev) = prev {
+            let node = self.node_mut(prev);
+            let n = node.position(wire);
+            node.next[n] = next;
+        }
+        if let Some(next) = next {
+            let node = self.node_mut(next);
+            let n = node.position(wire);
+            node.prev[n] = prev;
+        }
+
+        match (prev, next) {
+            (None, None) => { self.ends.remove(&wire); }
+            (Some(prev), None) => self.ends.entry(wire).or_insert((prev, prev)).1 = prev,
+            (None, Some(next)) => self.ends.entry(wire).or_insert((next, next)).0 = next,
+            (Some(_), Some(_)) => (),
+        }
+    }
+
+    /// Calls `visit` on each node in a topological order, see [`CircuitDag::to_instrs`].
+    fn topological<F: FnMut(NodeId)>(&self, mut visit: F) {
+        let mut remaining: Vec<_> = self.nodes.iter()
+            .map(|node| node.as_ref().map_or(0, |node| node.prev.iter().flatten().count()))
+            .collect();
+        let key = |node: NodeId| Reverse((&self.node(node).position, node));
+        let mut ready: BinaryHeap<_> = self.front_layer().into_iter().map(key).collect();
+
+        while let Some(Reverse((_, node))) = ready.pop() {
+            visit(node);
+
+            for &next in self.node(node).next.iter().flatten() {
+                remaining[next.0] -= 1;
+                if remaining[next.0] == 0 {
+                    ready.push(key(next));
+                }
+            }
+        }
+    }
+
+    /// Sorts the nodes in the order of their instructions.
+    fn sort(&self, nodes: &mut [NodeId]) {
+        nodes.sort_by(|&a, &b| self.node(a).position.cmp(&self.node(b).position));
+    }
+
+    /// Collects the nodes, without duplicates and in the order of their instructions.
+    fn dedup(&self, nodes: impl Iterator<Item = NodeId>) -> Vec<NodeId> {
+        let mut res: Vec<_> = nodes.collect();
+        self.sort(&mut res);
+        res.dedup();
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::circuit::QuantumCircuit;
+    use crate::instruction::{InstrVec, Modifier};
+    use crate::operation::OpKind;
+
+    use super::{CircuitDag, NodeId, Wire};
+
+    /// Returns the labels of the operations of the nodes.
+    fn labels(dag: &CircuitDag<'_>, nodes: &[NodeId]) -> Vec<&'static str> {
+        nodes.iter().map(|&node| dag.instr(node).op.label()).collect()
+    }
+
+    #[test]
+    fn links_nodes_along_wires() {
+        QuantumCircuit::new(|b| {
+            let [a, c, d] = b.qubits()?;
+            let bit = b.bit()?;
+            b.h(a).cx(a, c).measure(c, bit).h(d);
+            b.instructions_mut().push_modified(OpKind::X, &[d], &[], &[], Modifier::IfBit(bit))?;
+
+            let dag = CircuitDag::from_instrs(b.instructions());
+            let [h, cx, measure, hd, x] = dag.nodes().try_into().unwrap();
+            assert_eq!(labels(&dag, &[h, cx, measure, hd, x]), ["h", "cx", "measure", "h", "x"]);
+
+            assert_eq!(dag.front_layer(), [h, hd]);
+            assert_eq!(dag.layers(), [vec![h, hd], vec![cx], vec![measure], vec![x]]);
+            assert_eq!(dag.predecessors(x), [measure, hd]);
+            assert_eq!(dag.successors(cx), [measure]);
+            assert_eq!(dag.prev_on(x, Wire::Bit(bit)), Some(measure));
+            assert_eq!(dag.next_on(cx, Wire::Qubit(a)), None);
+            assert_eq!(dag.first_on(Wire::Qubit(c)), Some(cx));
+            assert_eq!(dag.last_on(Wire::Bit(bit)), Some(x));
+
+            let instrs = dag.to_instrs();
+            assert!(instrs.iter().map(|instr| instr.op.label()).eq(["h", "cx", "measure", "h", "x"]));
+            Ok(())
+        }).unwrap();
+    }
+
+    #[test]
+    fn substitutes_nodes_in_place() {
+        QuantumCircuit::new(|b| {
+            let [a, c] = b.qubits()?;
+            b.cx(a, c).z(a).t(c);
+
+            let mut dag = CircuitDag::from_instrs(b.instructions());
+            let [cx, z, t] = dag.nodes().try_into().unwrap();
+
+            let mut replacement = InstrVec::default();
+            replacement.push(OpKind::H, &[c], &[], &[]);
+            replacement.push(OpKind::CZ, &[a, c], &[], &[]);
+            replacement.push(OpKind::H, &[c], &[], &[]);
+            let [h1, cz, h2] = dag.substitute(cx, &replacement).try_into().unwrap();
+
+            assert!(!dag.contains(cx));
+            assert_eq!(dag.len(), 5);
+            assert_eq!(dag.nodes(), [h1, cz, h2, z, t]);
+            assert_eq!(dag.first_on(Wire::Qubit(a)), Some(cz));
+            assert_eq!(dag.first_on(Wire::Qubit(c)), Some(h1));
+            assert_eq!(dag.predecessors(z), [cz]);
+            assert_eq!(dag.predecessors(t), [h2]);
+            assert_eq!(dag.successors(cz), [h2, z]);
+            assert_eq!(dag.layers(), [vec![h1], vec![cz], vec![h2, z], vec![t]]);
+
+            // The substituted instructions come before the instructions following the node.
+            let instrs = dag.to_instrs();
+            assert!(instrs.iter().map(|instr| instr.op.label()).eq(["h", "cz", "h", "z", "t"]));
+
+            dag.remove(h2);
+            dag.remove(cz);
+            assert_eq!(dag.successors(h1), [t]);
+            assert_eq!(dag.prev_on(z, Wire::Qubit(a)), None);
+            assert_eq!(dag.front_layer(), [h1, z]);
+
+            dag.remove(z);
+            assert_eq!(dag.first_on(Wire::Qubit(a)), None);
+            assert_eq!(dag.last_on(Wire::Qubit(c)), Some(t));
+            Ok(())
+        }).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "substituted instruction acts on a wire the node does not")]
+    fn substitutes_on_the_wires_of_the_node_only() {
+        QuantumCircuit::new(|b| {
+            let [a, c] = b.qubits()?;
+            b.h(a).h(c);
+
+            let mut dag = CircuitDag::from_instrs(b.instructions());
+            let mut replacement = InstrVec::default();
+            replacement.push(OpKind::CX, &[a, c], &[], &[]);
+            dag.substitute(dag.nodes()[0], &replacement);
+            Ok(())
+        }).unwrap();
+    }
+}
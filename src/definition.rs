@@ -0,0 +1,102 @@
+//! Reusable gate definitions, such as a QFT block or a layer of an ansatz.
+//!
+//! A gate is defined once, as a circuit acting on it's own qubits and formal parameters, and
+//! stored in a table held by the circuit. Instructions reference it with a [`GateRef`] in an
+//! [`OpKind::Call`], giving the qubits and parameters it is
+//! called with, and may be inlined on demand with
+//! [`QuantumCircuit::inline`](crate::circuit::QuantumCircuit::inline).
+
+use thiserror::Error;
+
+use crate::circuit::{InverseError, QuantumCircuit};
+use crate::genericity::Id;
+use crate::instruction::{self, Instr};
+use crate::operation::OpKind;
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Error)]
+pub enum InvalidDefinition {
+    #[error("gate definitions can't have classical bits nor ancillas")]
+    Classical,
+    #[error("gate definitions must be unitary, but `{0}` is not")]
+    NotUnitary(&'static str),
+}
+
+/// A named gate, whose body is a unitary circuit without classical bits nor ancillas. It's
+/// `n`th qubit and formal parameter are replaced by the `n`th qubit and parameter of each call.
+///
+/// The body may itself call the gates of it's own definition table.
+#[derive(Clone, Debug)]
+pub struct GateDefinition {
+    name: String,
+    body: QuantumCircuit,
+}
+
+impl GateDefinition {
+    /// Defines a gate with the given name and body.
+    pub fn new(name: impl Into<String>, body: QuantumCircuit) -> Result<Self, InvalidDefinition> {
+        if body.num_bits() != 0 || body.num_ancillas() != 0 {
+            return Err(InvalidDefinition::Classical);
+        }
+
+        // Without bits, only resets are not unitary, possibly in the body of a for loop.
+        let check = |instr: &Instr<'_>| match &instr.op {
+            OpKind::Nop | OpKind::For(_) => Ok(()),
+            op if op.is_unitary() => Ok(()),
+            op => Err(InvalidDefinition::NotUnitary(op.label())),
+        };
+        for instr in body.iter() {
+            check(&instr)?;
+            if let Some(blocks) = instr.op.blocks() {
+                blocks.bodies().try_for_each(|body| instruction::visit(body, &mut |instr| check(instr)))?;
+            }
+        }
+
+        Ok(Self { name: name.into(), body })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn body(&self) -> &QuantumCircuit {
+        &self.body
+    }
+
+    /// Returns the inverse gate, named after this one with a `_dg` suffix, whose body is the
+    /// inverse of this gate's body, see [`QuantumCircuit::inverse`].
+    pub fn inverse(&self) -> Result<Self, InverseError> {
+        Ok(Self { name: format!("{}_dg", self.name), body: self.body.inverse()? })
+    }
+
+    /// Returns the number of qubits the gate is called on.
+    pub fn num_qubits(&self) -> usize {
+        self.body.num_qubit()
+    }
+
+    /// Returns the number of parameters the gate is called with.
+    pub fn num_parameters(&self) -> usize {
+        self.body.num_formals()
+    }
+}
+
+/// A reference to a gate in the definition table of a circuit.
+#[repr(transparent)]
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Debug)]
+pub struct GateRef<'id> {
+    _id: Id<'id>,
+    n: u32,
+}
+
+impl GateRef<'_> {
+    /// The maximum number of gates that may be defined in a single quantum circuit.
+    pub const MAX: u32 = u32::MAX - 1;
+
+    pub fn new_unchecked(n: u32) -> Self {
+        Self { n, _id: Id::default() }
+    }
+
+    /// Returns the index of the gate in the definition table.
+    pub fn id(self) -> u32 {
+        self.n
+    }
+}
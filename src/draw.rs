@@ -0,0 +1,532 @@
+//! Text rendering of circuits, for debugging and display in a terminal.
+//!
+//! A circuit is drawn with one horizontal wire per qubit, ancilla and bit, and each of it's
+//! instructions as a column of gates across those wires. Instructions acting on distinct wires
+//! share a column when possible. Gates are boxed with their label and parameters, control
+//! qubits are drawn as dots, and measurements as an arrow down to their bit. Modifiers are
+//! annotated after the label, such as `X† if c0`. Calls and control flow operations are drawn
+//! as boxes on every wire they act on, without their bodies. Gates without qubits are drawn on
+//! their last control qubit, and instructions acting on no wire, such as empty loops, are not
+//! drawn.
+
+use std::fmt;
+
+use crate::circuit::QuantumCircuit;
+use crate::dag::{self, Wire};
+use crate::instruction::{Instr, Modifier};
+use crate::operation::OpKind;
+use crate::parameter::Parameter;
+use crate::symbol::Bit;
+
+/// The characters a circuit is drawn with.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Default, Debug)]
+pub enum Charset {
+    Ascii,
+    #[default]
+    Unicode,
+}
+
+/// The characters of a charset, see [`Charset`].
+struct Glyphs {
+    wire: char,
+    bit_wire: char,
+    vertical: char,
+    double_vertical: char,
+    /// The crossing of a single and of a double vertical line with a qubit wire, then with a
+    /// bit wire.
+    crossings: [char; 4],
+    box_horizontal: char,
+    /// The top left, top right, bottom left and bottom right corners of a box.
+    corners: [char; 4],
+    /// The left and right sides of a box on a qubit wire, then on a bit wire.
+    sides: [char; 4],
+    /// The single and double lines leaving a box from the top, then from the bottom.
+    tees: [char; 4],
+    dot: char,
+    open_dot: char,
+    swap: char,
+    arrow: char,
+    dagger: &'static str,
+}
+
+const UNICODE: Glyphs = Glyphs {
+    wire: '─',
+    bit_wire: '═',
+    vertical: '│',
+    double_vertical: '║',
+    crossings: ['┼', '╫', '╪', '╬'],
+    box_horizontal: '─',
+    corners: ['┌', '┐', '└', '┘'],
+    sides: ['┤', '├', '╡', '╞'],
+    tees: ['┴', '╨', '┬', '╥'],
+    dot: '■',
+    open_dot: '○',
+    swap: '╳',
+    arrow: '▼',
+    dagger: "†",
+};
+
+const ASCII: Glyphs = Glyphs {
+    wire: '-',
+    bit_wire: '=',
+    vertical: '|',
+    double_vertical: '|',
+    crossings: ['+', '+', '+', '+'],
+    box_horizontal: '-',
+    corners: ['+', '+', '+', '+'],
+    sides: ['|', '|', '|', '|'],
+    tees: ['+', '+', '+', '+'],
+    dot: '*',
+    open_dot: 'o',
+    swap: 'x',
+    arrow: 'v',
+    dagger: "dg",
+};
+
+/// Draws circuits as text, see the [module documentation](self).
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Drawer {
+    pub charset: Charset,
+    /// The maximum width of the lines, past which the circuit is wrapped, or `None` to never
+    /// wrap it.
+    pub width: Option<usize>,
+}
+
+impl Default for Drawer {
+    fn default() -> Self {
+        Self::new(Charset::default())
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Link {
+    None,
+    Single,
+    Double,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Symbol {
+    Box(String),
+    /// A control, in the given state.
+    Dot(bool),
+    Swap,
+    Arrow,
+    /// A vertical line crossing the wire.
+    Cross,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct Cell {
+    symbol: Symbol,
+    up: Link,
+    down: Link,
+}
+
+impl Cell {
+    fn width(&self) -> usize {
+        match &self.symbol {
+            Symbol::Box(label) => label.chars().count() + 4,
+            _ => 3,
+        }
+    }
+}
+
+/// A column of cells, one per row.
+type Column = Vec<Option<Cell>>;
+
+impl Drawer {
+    /// The width past which circuits are wrapped by default.
+    pub const DEFAULT_WIDTH: usize = 80;
+
+    pub fn new(charset: Charset) -> Self {
+        Self { charset, width: Some(Self::DEFAULT_WIDTH) }
+    }
+
+    fn glyphs(&self) -> &'static Glyphs {
+        match self.charset {
+            Charset::Ascii => &ASCII,
+            Charset::Unicode => &UNICODE,
+        }
+    }
+
+    /// Draws the circuit, each line ending with a newline.
+    pub fn draw(&self, circ: &QuantumCircuit) -> String {
+        let num_rows = circ.width() + circ.num_bits();
+        let mut columns: Vec<Column> = Vec::new();
+        // The first column where each row is free.
+        let mut free = vec![0; num_rows];
+
+        for instr in circ.iter() {
+            if instr.op == OpKind::Nop {
+                continue;
+            }
+
+            let cells = self.cells(circ, &instr);
+            let drawn: Vec<_> = cells.iter().map(|&(row, _)| row).collect();
+            let (Some(&min), Some(&max)) = (drawn.iter().min(), drawn.iter().max()) else {
+                continue;
+            };
+            let link = if instr.op == OpKind::Measure { Link::Double } else { Link::Single };
+
+            let rows: Vec<_> = dag::wires(&instr).iter().map(|&wire| row(circ, wire)).chain(min..=max).collect();
+            let n = rows.iter().map(|&row| free[row]).max().unwrap_or(0);
+            rows.iter().for_each(|&row| free[row] = n + 1);
+            if columns.len() <= n {
+                columns.push(vec![None; num_rows]);
+            }
+
+            for cell in &mut columns[n][min..=max] {
+                *cell = Some(Cell { symbol: Symbol::Cross, up: link, down: link });
+            }
+            for (row, symbol) in cells {
+                let up = if row > min { link } else { Link::None };
+                let down = if row < max { link } else { Link::None };
+                columns[n][row] = Some(Cell { symbol, up, down });
+            }
+        }
+
+        let names: Vec<_> = (0..circ.num_qubit()).map(|n| format!("q{n}"))
+            .chain((0..circ.num_ancillas()).map(|n| format!("a{n}")))
+            .chain((0..circ.num_bits()).map(|n| format!("c{n}")))
+            .collect();
+        let prefix = names.iter().map(String::len).max().unwrap_or(0) + 2;
+        let widths: Vec<_> = columns.iter()
+            .map(|column| column.iter().flatten().map(Cell::width).max().unwrap_or(3))
+            .collect();
+
+        // Splits the columns into pages fitting in the width, each page starting at a break.
+        let mut breaks = vec![0];
+        let mut used = prefix + 1;
+        for (n, &width) in widths.iter().enumerate() {
+            if !breaks.ends_with(&[n]) && self.width.is_some_and(|max| used + width + 1 > max) {
+                breaks.push(n);
+                used = prefix + 1;
+            }
+            used += width + 1;
+        }
+        breaks.push(widths.len());
+
+        let glyphs = self.glyphs();
+        let mut res = String::new();
+
+        for (index, page) in breaks.windows(2).enumerate() {
+            if index > 0 {
+                res.push('\n');
+            }
+
+            for (row, name) in names.iter().enumerate() {
+                let wire = if row < circ.width() { glyphs.wire } else { glyphs.bit_wire };
+                let mut lines = [" ".repeat(prefix + 1), format!("{name:>0$}: {wire}", prefix - 2), " ".repeat(prefix + 1)];
+
+                for n in page[0]..page[1] {
+                    let cell = self.cell(columns[n][row].as_ref(), widths[n], row >= circ.width());
+                    for (line, part) in lines.iter_mut().zip(cell) {
+                        line.push_str(&part);
+                    }
+                    lines[0].push(' ');
+                    lines[1].push(wire);
+                    lines[2].push(' ');
+                }
+
+                for line in lines {
+                    res.push_str(line.trim_end());
+                    res.push('\n');
+                }
+            }
+        }
+
+        res
+    }
+
+    /// Returns the symbols drawn for the instruction, along with their rows.
+    fn cells(&self, circ: &QuantumCircuit, instr: &Instr<'_>) -> Vec<(usize, Symbol)> {
+        let glyphs = self.glyphs();
+        let qubit = |n: usize| instr.qubits[n].id() as usize;
+        let bit = |bit: Bit<'_>| circ.width() + bit.id() as usize;
+        let annotate = |label: &str| format!("{label}{}", self.annotations(circ, instr));
+        let mut res = Vec::new();
+
+        let (controls, states) = instr.controls();
+        res.extend(controls.iter().zip(states).map(|(control, state)| (control.id() as usize, Symbol::Dot(state))));
+
+        // The number of leading qubits of the operation which are controls, and the label of
+        // the operation on the remaining ones.
+        let controlled = match &instr.op {
+            OpKind::CX | OpKind::CCX => Some("X".to_string()),
+            OpKind::CY => Some("Y".to_string()),
+            OpKind::CZ => Some("Z".to_string()),
+            OpKind::CPhase => Some(format!("P({})", parameter(circ, instr.parameters[0]))),
+            _ => None,
+        };
+
+        match (&instr.op, controlled) {
+            (_, Some(label)) => {
+                let last = instr.qubits.len() - 1;
+                res.extend((0..last).map(|n| (qubit(n), Symbol::Dot(true))));
+                res.push((qubit(last), Symbol::Box(annotate(&label))));
+            }
+            (OpKind::Swap, _) if instr.modifiers.iter().all(|modifier| matches!(modifier, Modifier::Controlled(_))) => {
+                res.extend((0..2).map(|n| (qubit(n), Symbol::Swap)));
+            }
+            (OpKind::Measure, _) => {
+                res.push((qubit(0), Symbol::Box(annotate("M"))));
+                res.push((bit(instr.bits[0]), Symbol::Arrow));
+            }
+            (op, _) if op.blocks().is_some() || matches!(op, OpKind::Compute(_)) => {
+                let label = match op {
+                    OpKind::For(repeat) => format!("for {}", repeat.count),
+                    OpKind::IfElse(_) => format!("if {}", bits(instr.bits)),
+                    OpKind::While(_) => format!("while {}", bits(instr.bits)),
+                    OpKind::Switch(_) => format!("switch {}", bits(instr.bits)),
+                    OpKind::Compute(compute) => format!("{} = f({})", bits(instr.bits), bits(compute.bits)),
+                    op => op.label().to_string(),
+                };
+                let wires = dag::wires(instr);
+                res.extend(wires.iter().enumerate().map(|(n, &wire)| {
+                    (row(circ, wire), Symbol::Box(if n == 0 { annotate(&label) } else { String::new() }))
+                }));
+            }
+            (op, _) => {
+                let label = match op {
+                    OpKind::Sdg => format!("S{}", glyphs.dagger),
+                    OpKind::Tdg => format!("T{}", glyphs.dagger),
+                    OpKind::SXdg => format!("SX{}", glyphs.dagger),
+                    OpKind::Reset => "|0>".to_string(),
+                    OpKind::Call(gate) => circ.definition(*gate).name().to_string(),
+                    op => op.label().to_uppercase(),
+                };
+                let label = match instr.parameters {
+                    [] => label,
+                    parameters => {
+                        let parameters: Vec<_> = parameters.iter().map(|&param| parameter(circ, param)).collect();
+                        format!("{label}({})", parameters.join(", "))
+                    }
+                };
+
+                if instr.qubits.is_empty() {
+                    if let Some((_, symbol)) = res.last_mut() {
+                        *symbol = Symbol::Box(annotate(&label));
+                    }
+                } else {
+                    res.push((qubit(0), Symbol::Box(annotate(&label))));
+                    res.extend((1..instr.qubits.len()).map(|n| (qubit(n), Symbol::Box(format!("{label}:{n}")))));
+                }
+                res.extend(instr.bits.iter().map(|&b| (bit(b), Symbol::Box(String::new()))));
+            }
+        }
+
+        res
+    }
+
+    /// Returns the annotations of the modifiers of the instruction, other than controls.
+    fn annotations(&self, circ: &QuantumCircuit, instr: &Instr<'_>) -> String {
+        instr.modifiers.iter()
+            .map(|modifier| match modifier {
+                Modifier::Controlled(_) => String::new(),
+                Modifier::Inverse => self.glyphs().dagger.to_string(),
+                Modifier::Power(exponent) => format!("^{}", parameter(circ, *exponent)),
+                Modifier::IfBit(bit) => format!(" if c{}", bit.id()),
+                Modifier::IfCompute(compute) => format!(" if f({})", bits(compute.bits)),
+                Modifier::WhileBit(bit) => format!(" while c{}", bit.id()),
+                Modifier::WhileCompute(compute) => format!(" while f({})", bits(compute.bits)),
+                Modifier::ForConst(count) => format!(" for {count}"),
+                Modifier::ForCompute(compute) => format!(" for f({})", bits(compute.bits)),
+            })
+            .collect()
+    }
+
+    /// Returns the three lines of the cell, of the given width.
+    fn cell(&self, cell: Option<&Cell>, width: usize, bit: bool) -> [String; 3] {
+        let glyphs = self.glyphs();
+        let wire = if bit { glyphs.bit_wire } else { glyphs.wire };
+        let center = width / 2;
+        let line = |fill: char, middle: Option<char>| -> String {
+            (0..width).map(|n| if n == center { middle.unwrap_or(fill) } else { fill }).collect()
+        };
+        let vertical = |link: Link| match link {
+            Link::None => None,
+            Link::Single => Some(glyphs.vertical),
+            Link::Double => Some(glyphs.double_vertical),
+        };
+
+        let Some(cell) = cell else {
+            return [line(' ', None), line(wire, None), line(' ', None)];
+        };
+
+        let symbol = match &cell.symbol {
+            Symbol::Box(label) => {
+                let [top_left, top_right, bottom_left, bottom_right] = glyphs.corners;
+                let (left, right) = if bit { (glyphs.sides[2], glyphs.sides[3]) } else { (glyphs.sides[0], glyphs.sides[1]) };
+                let tee = |link: Link, n: usize| match link {
+                    Link::None => None,
+                    Link::Single => Some(glyphs.tees[n]),
+                    Link::Double => Some(glyphs.tees[n + 1]),
+                };
+                let boxed = |first: char, last: char, fill: String| {
+                    let inner: String = fill.chars().skip(1).take(width - 2).collect();
+                    format!("{first}{inner}{last}")
+                };
+
+                return [
+                    boxed(top_left, top_right, line(glyphs.box_horizontal, tee(cell.up, 0))),
+                    format!("{left}{label:^0$}{right}", width - 2),
+                    boxed(bottom_left, bottom_right, line(glyphs.box_horizontal, tee(cell.down, 2))),
+                ];
+            }
+            Symbol::Dot(true) => glyphs.dot,
+            Symbol::Dot(false) => glyphs.open_dot,
+            Symbol::Swap => glyphs.swap,
+            Symbol::Arrow => glyphs.arrow,
+            Symbol::Cross => {
+                let double = cell.up == Link::Double || cell.down == Link::Double;
+                glyphs.crossings[usize::from(double) + 2 * usize::from(bit)]
+            }
+        };
+
+        [line(' ', vertical(cell.up)), line(wire, Some(symbol)), line(' ', vertical(cell.down))]
+    }
+}
+
+/// Returns the row of the wire.
+fn row(circ: &QuantumCircuit, wire: Wire<'_>) -> usize {
+    match wire {
+        Wire::Qubit(qubit) => qubit.id() as usize,
+        Wire::Bit(bit) => circ.width() + bit.id() as usize,
+    }
+}
+
+/// Formats the bits, such as `c0,c2`.
+fn bits(bits: &[Bit<'_>]) -> String {
+    bits.iter().map(|bit| format!("c{}", bit.id())).collect::<Vec<_>>().join(",")
+}
+
+/// Formats the parameter, values being rounded to three decimals.
+fn parameter(circ: &QuantumCircuit, param: Parameter<'_>) -> String {
+    if let Some(value) = param.as_value() {
+        let res = format!("{value:.3}");
+        let res = res.trim_end_matches('0').trim_end_matches('.');
+        if res == "-0" { "0".to_string() } else { res.to_string() }
+    } else if let Some(formal) = param.as_formal() {
+        format!("p{}", formal.id())
+    } else {
+        circ.expr(param).map_or_else(String::new, |expr| expr.to_string())
+    }
+}
+
+/// Draws the circuit with the default [`Drawer`].
+impl fmt::Display for QuantumCircuit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(Drawer::default().draw(self).trim_end())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::circuit::QuantumCircuit;
+    use crate::definition::GateDefinition;
+    use crate::instruction::Modifier;
+    use crate::operation::OpKind;
+    use crate::parameter::Parameter;
+
+    use super::{Charset, Drawer};
+
+    fn circuit() -> QuantumCircuit {
+        QuantumCircuit::new(|b| {
+            let [a, c] = b.qubits()?;
+            let bit = b.bit()?;
+            b.h(a).cx(a, c).rz(0.5, c).measure(a, bit);
+            b.instructions_mut().push_modified(OpKind::T, &[c], &[], &[], Modifier::Power(Parameter::from(2.0)))?;
+            b.instructions_mut().push_modified(OpKind::S, &[c], &[], &[], Modifier::Inverse)?;
+            Ok(())
+        }).unwrap()
+    }
+
+    #[test]
+    fn draws_in_both_charsets() {
+        let ascii = [
+            "     +---+                   +---+",
+            "q0: -| H |---*---------------| M |-----------------",
+            "     +---+   |               +-+-+",
+            "           +-+-+ +---------+   |   +-----+ +-----+",
+            "q1: -------| X |-| RZ(0.5) |---+---| T^2 |-| Sdg |-",
+            "           +---+ +---------+   |   +-----+ +-----+",
+            "                               |",
+            "c0: ===========================v===================",
+            "",
+            "",
+        ];
+        let unicode = [
+            "     ┌───┐                   ┌───┐",
+            "q0: ─┤ H ├───■───────────────┤ M ├────────────────",
+            "     └───┘   │               └─╥─┘",
+            "           ┌─┴─┐ ┌─────────┐   ║   ┌─────┐ ┌────┐",
+            "q1: ───────┤ X ├─┤ RZ(0.5) ├───╫───┤ T^2 ├─┤ S† ├─",
+            "           └───┘ └─────────┘   ║   └─────┘ └────┘",
+            "                               ║",
+            "c0: ═══════════════════════════▼══════════════════",
+            "",
+            "",
+        ];
+
+        let circ = circuit();
+        assert_eq!(Drawer { charset: Charset::Ascii, width: None }.draw(&circ), ascii.join("\n"));
+        assert_eq!(Drawer { charset: Charset::Unicode, width: None }.draw(&circ), unicode.join("\n"));
+    }
+
+    #[test]
+    fn wraps_at_the_width() {
+        // The measurement does not fit on the first page, which is followed by an empty line.
+        let expected = [
+            "     +---+",
+            "q0: -| H |---*---------------",
+            "     +---+   |",
+            "           +-+-+ +---------+",
+            "q1: -------| X |-| RZ(0.5) |-",
+            "           +---+ +---------+",
+            "",
+            "c0: =========================",
+            "",
+            "",
+            "     +---+",
+            "q0: -| M |-----------------",
+            "     +-+-+",
+            "       |   +-----+ +-----+",
+            "q1: ---+---| T^2 |-| Sdg |-",
+            "       |   +-----+ +-----+",
+            "       |",
+            "c0: ===v===================",
+            "",
+            "",
+        ];
+        let drawer = Drawer { charset: Charset::Ascii, width: Some(30) };
+        assert_eq!(drawer.draw(&circuit()), expected.join("\n"));
+    }
+
+    #[test]
+    fn draws_instructions_without_qubits() {
+        let circ = QuantumCircuit::new(|b| {
+            let [a, c] = b.qubits()?;
+            let phase = OpKind::Call(b.define(GateDefinition::new("phase", QuantumCircuit::new(|_| Ok(()))?)?)?);
+            b.h(a);
+            b.for_loop(3, |_| Ok(()))?;
+            b.instructions_mut().push(phase.clone(), &[], &[], &[]);
+            b.instructions_mut().push_controlled(phase, &[a, c], &[false, true], &[], &[]);
+            Ok(())
+        }).unwrap();
+
+        // The empty loop and the uncontrolled call are not drawn, and the controlled call is
+        // drawn on it's last control.
+        let expected = [
+            "     +---+",
+            "q0: -| H |-----o-----",
+            "     +---+     |",
+            "           +---+---+",
+            "q1: -------| phase |-",
+            "           +-------+",
+            "",
+        ];
+        let drawer = Drawer { charset: Charset::Ascii, width: None };
+        assert_eq!(drawer.draw(&circ), expected.join("\n"));
+    }
+}
@@ -0,0 +1,443 @@
+//! Equivalence checking on decision diagrams, for circuits too wide for dense unitaries.
+//!
+//! A matrix over `n` qubits is represented by a quasi-reduced diagram: a node of level `q`
+//! splits the matrix in four blocks, according to the row and column bits of qubit `q - 1`,
+//! and points to nodes of level `q - 1` through weighted edges. Nodes are normalized and
+//! shared through a unique table, so that repeated structure is only stored once.
+//!
+//! Equivalence is checked on the miter $ U_1 U_2^\dagger $, built from the identity by
+//! applying the gates of the first circuit from the left and the adjoints of the gates of the
+//! second circuit from the right, alternately. The diagram stays close to the identity as
+//! long as both circuits are similar, which keeps it small even for wide circuits.
+
+use std::collections::HashMap;
+
+use crate::linalg::{c64, DMatrix};
+use crate::operation::OpKind;
+
+/// Index of a node in the diagram, `0` being the terminal.
+type NodeId = u32;
+
+const TERMINAL: NodeId = 0;
+
+/// Weights smaller than this are considered to be zero.
+const ZERO: f64 = 1E-12;
+
+/// The step with which weights are quantized to find equal nodes in the unique table.
+const GRID: f64 = 1E-9;
+
+/// The tolerance on the weights of the miter when comparing it to the identity.
+const TOLERANCE: f64 = 1E-6;
+
+/// A weighted edge to a node.
+#[derive(Copy, Clone, Debug)]
+struct Edge {
+    weight: c64,
+    node: NodeId,
+}
+
+impl Edge {
+    const ZERO: Self = Self { weight: c64::ZERO, node: TERMINAL };
+
+    fn terminal(weight: c64) -> Self {
+        if weight.abs() < ZERO { Self::ZERO } else { Self { weight, node: TERMINAL } }
+    }
+
+    fn is_zero(&self) -> bool {
+        self.weight.abs() < ZERO
+    }
+
+    fn scale(self, factor: c64) -> Self {
+        Self { weight: self.weight * factor, ..self }
+    }
+}
+
+/// Quantizes a weight, to be used as a key of the unique and compute tables.
+fn quantize(weight: c64) -> (i64, i64) {
+    ((weight.re / GRID).round() as i64, (weight.im / GRID).round() as i64)
+}
+
+/// A gate, given by it's matrix acting on target qubits, the first one being the most
+/// significant in the matrix's basis, under the control of qubits in the given states.
+#[derive(Clone, Debug)]
+pub(super) struct Gate {
+    pub matrix: DMatrix,
+    pub targets: Vec<usize>,
+    pub controls: Vec<(usize, bool)>,
+}
+
+impl Gate {
+    fn adjoint(&self) -> Self {
+        Self { matrix: self.matrix.adjoint(), ..self.clone() }
+    }
+}
+
+/// What a qubit is to a gate, when building it's diagram.
+#[derive(Copy, Clone)]
+enum Role {
+    Idle,
+    /// The index of the qubit in the gate's targets.
+    Target(usize),
+    /// The state the qubit must be in for the gate to apply.
+    Control(bool),
+}
+
+/// A store of decision diagrams over a fixed number of qubits.
+#[derive(Default)]
+struct Diagrams {
+    nodes: Vec<[Edge; 4]>,
+    unique: HashMap<[(i64, i64, NodeId); 4], NodeId>,
+    adds: HashMap<(NodeId, NodeId, (i64, i64)), Edge>,
+    muls: HashMap<(NodeId, NodeId), Edge>,
+}
+
+impl Diagrams {
+    fn new() -> Self {
+        // The terminal has no children, it's entry is never read.
+        Self { nodes: vec![[Edge::ZERO; 4]], ..Self::default() }
+    }
+
+    /// Returns the normalized edge to the node with the given children, creating it if needed.
+    fn node(&mut self, edges: [Edge; 4]) -> Edge {
+        let edges = edges.map(|edge| if edge.is_zero() { Edge::ZERO } else { edge });
+
+        // Normalize by the first edge of largest magnitude, for numerical stability.
+        let max = edges.iter().map(|edge| edge.weight.abs()).fold(0.0, f64::max);
+        if max < ZERO {
+            return Edge::ZERO;
+        }
+        let norm = edges.iter().find(|edge| edge.weight.abs() > max - GRID).unwrap().weight;
+
+        let edges = edges.map(|edge| Edge { weight: edge.weight / norm, ..edge });
+        let key = edges.map(|edge| {
+            let (re, im) = quantize(edge.weight);
+            (re, im, edge.node)
+        });
+
+        let node = match self.unique.get(&key) {
+            Some(&node) => node,
+            None => {
+                let node = self.nodes.len() as NodeId;
+                self.nodes.push(edges);
+                self.unique.insert(key, node);
+                node
+            }
+        };
+
+        Edge { weight: norm, node }
+    }
+
+    /// Returns the identity over `n` qubits.
+    fn identity(&mut self, n: usize) -> Edge {
+        (0..n).fold(Edge::terminal(c64::ONE), |edge, _| self.node([edge, Edge::ZERO, Edge::ZERO, edge]))
+    }
+
+    /// Returns the sum of two matrices of the same level.
+    fn add(&mut self, lhs: Edge, rhs: Edge) -> Edge {
+        if lhs.is_zero() {
+            return rhs;
+        }
+        if rhs.is_zero() {
+            return lhs;
+        }
+        if lhs.node == rhs.node {
+            let weight = lhs.weight + rhs.weight;
+            return if weight.abs() < ZERO { Edge::ZERO } else { Edge { weight, node: lhs.node } };
+        }
+
+        // Factor out the left weight, so that the result may be cached for any scaling.
+        let ratio = rhs.weight / lhs.weight;
+        let key = (lhs.node, rhs.node, quantize(ratio));
+        let res = match self.adds.get(&key) {
+            Some(&res) => res,
+            None => {
+                let (a, b) = (self.nodes[lhs.node as usize], self.nodes[rhs.node as usize]);
+                let edges = [0, 1, 2, 3].map(|i| self.add(a[i], b[i].scale(ratio)));
+                let res = self.node(edges);
+                self.adds.insert(key, res);
+                res
+            }
+        };
+
+        res.scale(lhs.weight)
+    }
+
+    /// Returns the product of two matrices of the same level.
+    fn mul(&mut self, lhs: Edge, rhs: Edge) -> Edge {
+        if lhs.is_zero() || rhs.is_zero() {
+            return Edge::ZERO;
+        }
+        if lhs.node == TERMINAL {
+            return Edge::terminal(lhs.weight * rhs.weight);
+        }
+
+        let key = (lhs.node, rhs.node);
+        let res = match self.muls.get(&key) {
+            Some(&res) => res,
+            None => {
+                let (a, b) = (self.nodes[lhs.node as usize], self.nodes[rhs.node as usize]);
+                let edges = [0, 1, 2, 3].map(|i| {
+                    let (row, col) = (i >> 1, i & 1);
+                    let first = self.mul(a[2 * row], b[col]);
+                    let second = self.mul(a[2 * row + 1], b[2 + col]);
+                    self.add(first, second)
+                });
+                let res = self.node(edges);
+                self.muls.insert(key, res);
+                res
+            }
+        };
+
+        res.scale(lhs.weight * rhs.weight)
+    }
+
+    /// Returns the diagram of the gate over `n` qubits.
+    fn gate(&mut self, n: usize, gate: &Gate) -> Edge {
+        let mut roles = vec![Role::Idle; n];
+        gate.targets.iter().enumerate().for_each(|(k, &qubit)| roles[qubit] = Role::Target(k));
+        gate.controls.iter().for_each(|&(qubit, state)| roles[qubit] = Role::Control(state));
+
+        let mut memo = HashMap::new();
+        self.build(gate, &roles, n, 0, 0, false, &mut memo)
+    }
+
+    /// Builds the diagram of the gate from the given level, the bits of the rows and columns
+    /// of the gate's matrix being selected by the targets above, and `inactive` being `true`
+    /// when a control above is in the wrong state.
+    #[allow(clippy::too_many_arguments)]
+    fn build(
+        &mut self,
+        gate: &Gate,
+        roles: &[Role],
+        level: usize,
+        row: usize,
+        col: usize,
+        inactive: bool,
+        memo: &mut HashMap<(usize, usize, usize, bool), Edge>,
+    ) -> Edge {
+        if level == 0 {
+            return match inactive {
+                true => Edge::terminal(if row == col { c64::ONE } else { c64::ZERO }),
+                false => Edge::terminal(gate.matrix[(row, col)]),
+            };
+        }
+
+        if let Some(&edge) = memo.get(&(level, row, col, inactive)) {
+            return edge;
+        }
+
+        let edges = match roles[level - 1] {
+            Role::Idle => {
+                let edge = self.build(gate, roles, level - 1, row, col, inactive, memo);
+                [edge, Edge::ZERO, Edge::ZERO, edge]
+            }
+            Role::Target(k) => {
+                let shift = gate.targets.len() - 1 - k;
+                [0, 1, 2, 3].map(|i| {
+                    let (r, c) = (i >> 1, i & 1);
+                    self.build(gate, roles, level - 1, row | (r << shift), col | (c << shift), inactive, memo)
+                })
+            }
+            Role::Control(state) => {
+                let off = self.build(gate, roles, level - 1, row, col, inactive || state, memo);
+                let on = self.build(gate, roles, level - 1, row, col, inactive || !state, memo);
+                [off, Edge::ZERO, Edge::ZERO, on]
+            }
+        };
+
+        let res = self.node(edges);
+        memo.insert((level, row, col, inactive), res);
+        res
+    }
+
+    /// Returns `true` if the matrix is the identity up to a global phase.
+    fn is_identity(&self, edge: Edge) -> bool {
+        let mut memo = HashMap::new();
+        (edge.weight.abs() - 1.0).abs() < TOLERANCE && self.is_identity_node(edge.node, &mut memo)
+    }
+
+    fn is_identity_node(&self, node: NodeId, memo: &mut HashMap<NodeId, bool>) -> bool {
+        if node == TERMINAL {
+            return true;
+        }
+        if let Some(&res) = memo.get(&node) {
+            return res;
+        }
+
+        let [diag1, off1, off2, diag2] = self.nodes[node as usize];
+        let is_one = |edge: Edge| !edge.is_zero() && (edge.weight - c64::ONE).abs() < TOLERANCE;
+        let res = off1.is_zero() && off2.is_zero() && is_one(diag1) && is_one(diag2)
+            && self.is_identity_node(diag1.node, memo)
+            && self.is_identity_node(diag2.node, memo);
+
+        memo.insert(node, res);
+        res
+    }
+}
+
+/// Returns `true` if the two sequences of gates over `n` qubits implement the same unitary up
+/// to a global phase, and to the final permutation of the qubits of the second sequence, as
+/// for [`super::equivalent_permuted`].
+pub(super) fn equivalent(n: usize, gates1: &[Gate], gates2: &[Gate], permutation: &[usize]) -> bool {
+    let mut diagrams = Diagrams::new();
+    let mut miter = diagrams.identity(n);
+
+    // Interleaves both circuits proportionally to their lengths.
+    let (mut i, mut j) = (0, 0);
+    while i < gates1.len() || j < gates2.len() {
+        if j == gates2.len() || (i < gates1.len() && i * gates2.len() <= j * gates1.len()) {
+            let gate = diagrams.gate(n, &gates1[i]);
+            miter = diagrams.mul(gate, miter);
+            i += 1;
+        } else {
+            let gate = diagrams.gate(n, &gates2[j].adjoint());
+            miter = diagrams.mul(miter, gate);
+            j += 1;
+        }
+    }
+
+    // Relabels the outputs of the second circuit, through swaps applied from the right.
+    let mut current: Vec<usize> = (0..n).collect();
+    for (i, &target) in permutation.iter().enumerate() {
+        let j = current.iter().position(|&qubit| qubit == target).unwrap();
+        if i != j {
+            current.swap(i, j);
+            let swap = Gate { matrix: OpKind::Swap.matrix(&[]).unwrap(), targets: vec![i, j], controls: Vec::new() };
+            let gate = diagrams.gate(n, &swap);
+            miter = diagrams.mul(miter, gate);
+        }
+    }
+
+    diagrams.is_identity(miter)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::circuit::QuantumCircuit;
+    use crate::equivalence::{self, gates};
+    use crate::operation::OpKind;
+    use crate::symbol::Qubit;
+
+    /// Returns a circuit of `depth` layers of gates, picked from the seed with a xorshift
+    /// generator, each layer having one gate per qubit.
+    fn random(num_qubits: usize, depth: usize, seed: u64) -> QuantumCircuit {
+        let mut state = seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1;
+        let mut next = move |n: usize| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % n as u64) as usize
+        };
+
+        QuantumCircuit::new(|b| {
+            let qubits = (0..num_qubits).map(|_| b.qubit()).collect::<Result<Vec<_>, _>>()?;
+            for _ in 0..depth {
+                for n in 0..num_qubits {
+                    let a = qubits[n];
+                    let c = qubits[(n + 1 + next(num_qubits - 1)) % num_qubits];
+                    let angle = next(100) as f32 / 10.0;
+                    match next(10) {
+                        0 => b.h(a),
+                        1 => b.x(a),
+                        2 => b.sx(a),
+                        3 => b.t(a),
+                        4 => b.rz(angle, a),
+                        5 => b.u(angle, 0.5, -angle, a),
+                        6 => b.cx(a, c),
+                        7 => b.cz(a, c),
+                        8 => b.cp(angle, a, c),
+                        _ => b.swap(a, c),
+                    };
+                }
+            }
+            Ok(())
+        }).unwrap()
+    }
+
+    /// Checks the diagrams against the dense unitaries.
+    fn check(circ1: &QuantumCircuit, circ2: &QuantumCircuit, permutation: &[usize]) -> bool {
+        let expected = equivalence::equivalent_permuted(circ1, circ2, permutation).unwrap();
+        let width = circ1.width();
+        assert_eq!(super::equivalent(width, &gates(circ1).unwrap(), &gates(circ2).unwrap(), permutation), expected);
+        expected
+    }
+
+    #[test]
+    fn matches_dense_unitaries() {
+        let identity: Vec<_> = (0..5).collect();
+        for seed in 0..8 {
+            let circ = random(5, 6, seed);
+            assert!(check(&circ, &circ, &identity));
+            assert!(!check(&circ, &random(5, 6, seed + 100), &identity));
+        }
+    }
+
+    #[test]
+    fn matches_dense_permutations() {
+        let circ = random(5, 6, 0);
+        let routed = circ.clone().edit(|b| {
+            let [q0, q1, q2] = [0, 1, 2].map(Qubit::new_unchecked);
+            b.swap(q0, q1).swap(q1, q2);
+            Ok(())
+        }).unwrap();
+
+        assert!(check(&circ, &routed, &[2, 0, 1, 3, 4]));
+        assert!(!check(&circ, &routed, &[1, 2, 0, 3, 4]));
+        assert!(!check(&circ, &routed, &[0, 1, 2, 3, 4]));
+    }
+
+    #[test]
+    fn checks_wide_circuits() {
+        let circ = random(40, 8, 0);
+        let tweaked = circ.clone().edit(|b| {
+            b.t(Qubit::new_unchecked(17));
+            Ok(())
+        }).unwrap();
+
+        assert!(equivalence::equivalent(&circ, &circ).unwrap());
+        assert!(!equivalence::equivalent(&circ, &tweaked).unwrap());
+    }
+
+    #[test]
+    fn checks_wide_rewrites() {
+        let ladder = |conjugated: bool| QuantumCircuit::new(|b| {
+            let qubits = (0..40).map(|_| b.qubit()).collect::<Result<Vec<_>, _>>()?;
+            b.h(qubits[0]);
+            for pair in qubits.windows(2) {
+                if conjugated {
+                    b.h(pair[1]).cz(pair[0], pair[1]).h(pair[1]);
+                } else {
+                    b.cx(pair[0], pair[1]);
+                }
+            }
+            Ok(())
+        }).unwrap();
+
+        assert!(equivalence::equivalent(&ladder(false), &ladder(true)).unwrap());
+    }
+
+    #[test]
+    fn checks_control_states() {
+        let controlled = |flipped: bool| QuantumCircuit::new(|b| {
+            let qubits = (0..30).map(|_| b.qubit()).collect::<Result<Vec<_>, _>>()?;
+            let (target, controls) = qubits.split_last().unwrap();
+            let mut states = vec![true; controls.len()];
+            if flipped {
+                b.x(controls[3]);
+                b.instructions_mut().push_controlled(OpKind::X, controls, &states, &[*target], &[]);
+                b.x(controls[3]);
+            } else {
+                states[3] = false;
+                b.instructions_mut().push_controlled(OpKind::X, controls, &states, &[*target], &[]);
+            }
+            Ok(())
+        }).unwrap();
+
+        assert!(equivalence::equivalent(&controlled(false), &controlled(true)).unwrap());
+        assert!(!equivalence::equivalent(&controlled(false), &QuantumCircuit::new(|b| {
+            let qubits = (0..30).map(|_| b.qubit()).collect::<Result<Vec<_>, _>>()?;
+            b.instructions_mut().push_controlled(OpKind::X, &qubits[..29], &[true; 29], &qubits[29..], &[]);
+            Ok(())
+        }).unwrap()).unwrap());
+    }
+}
@@ -0,0 +1,175 @@
+//! Equivalence checking of quantum circuits, up to a global phase.
+//!
+//! Circuits of at most [`MAX_QUBITS`] qubits are compared through their dense unitary
+//! matrices. Wider circuits are compared on decision diagrams of the miter of both
+//! circuits, which stay compact as long as the circuits are similar.
+
+use thiserror::Error;
+
+use crate::circuit::{CircuitError, QuantumCircuit};
+use crate::instruction::{Instr, InstrIter, Modifier};
+use crate::linalg::DMatrix;
+use crate::operation::OpKind;
+
+mod dd;
+
+/// The maximum width of the circuits whose dense unitaries may be computed, wider
+/// circuits are checked on decision diagrams.
+pub const MAX_QUBITS: usize = 10;
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Error)]
+pub enum EquivalenceError {
+    #[error("circuits have different widths: {0} and {1}")]
+    WidthMismatch(usize, usize),
+    #[error("circuit of width {0} is too wide for it's unitary to be computed, the maximum is {MAX_QUBITS}")]
+    TooWide(usize),
+    #[error("circuit has unbound formal parameters")]
+    NotConcrete,
+    #[error("operation `{0}` is not unitary")]
+    NotUnitary(&'static str),
+    #[error("instructions with classical modifiers other than repetitions are not unitary")]
+    Modifier,
+    #[error("the qubit permutation is invalid")]
+    InvalidPermutation,
+    #[error("circuit could not be inlined: {0}")]
+    Inline(CircuitError),
+}
+
+/// Computes the unitary matrix implemented by the circuit.
+///
+/// Qubit `q` corresponds to the `q`th bit of the indices of the basis states.
+pub fn unitary(circ: &QuantumCircuit) -> Result<DMatrix, EquivalenceError> {
+    let width = circ.width();
+    if width > MAX_QUBITS {
+        return Err(EquivalenceError::TooWide(width));
+    }
+
+    let inlined = circ.inlined().map_err(EquivalenceError::Inline)?;
+    let circ = &*inlined;
+
+    let mut res = DMatrix::eye(1 << width);
+    unroll(circ.iter(), &mut |instr| {
+        let (parameters, exponents) = values(instr)?;
+        let gate = instr.matrix(&parameters, &exponents).ok_or(EquivalenceError::NotUnitary(instr.op.label()))?;
+        let qubits: Vec<_> = instr.controls().0.iter().chain(instr.qubits).map(|qubit| qubit.id() as usize).collect();
+
+        res.apply(&gate, &qubits);
+        Ok(())
+    })?;
+
+    Ok(res)
+}
+
+/// Calls `f` on each instruction in order, repeating the instructions with a constant number
+/// of iterations, such as the bodies of for loops and inlined powers of gates, and skipping
+/// the no-ops.
+fn unroll<'a, F>(instrs: impl Iterator<Item = Instr<'a>>, f: &mut F) -> Result<(), EquivalenceError>
+where
+    F: FnMut(&Instr<'a>) -> Result<(), EquivalenceError>
+{
+    for instr in instrs {
+        let mut count = 1u64;
+        for modifier in &instr.modifiers {
+            match modifier {
+                Modifier::ForConst(n) => count *= u64::from(*n),
+                modifier if modifier.is_classical() => return Err(EquivalenceError::Modifier),
+                _ => (),
+            }
+        }
+
+        for _ in 0..count {
+            match &instr.op {
+                OpKind::Nop => (),
+                OpKind::For(repeat) => {
+                    let body = repeat.body.bodies().next().unwrap();
+                    (0..repeat.count).try_for_each(|_| unroll(InstrIter::new(body), f))?;
+                }
+                _ => f(&instr)?,
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the values of the parameters and exponents of the instruction.
+fn values(instr: &Instr<'_>) -> Result<(Vec<f64>, Vec<f64>), EquivalenceError> {
+    let parameters = instr.parameters.iter()
+        .map(|param| param.as_value().map(f64::from).ok_or(EquivalenceError::NotConcrete))
+        .collect::<Result<Vec<_>, _>>()?;
+    let exponents = instr.exponents()
+        .map(|exponent| exponent.as_value().map(f64::from).ok_or(EquivalenceError::NotConcrete))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok((parameters, exponents))
+}
+
+/// Returns the gates of the circuit, with their controls kept apart from their matrices
+/// so that they may be built on decision diagrams without a dense matrix of all the qubits.
+fn gates(circ: &QuantumCircuit) -> Result<Vec<dd::Gate>, EquivalenceError> {
+    let inlined = circ.inlined().map_err(EquivalenceError::Inline)?;
+
+    let mut res = Vec::new();
+    unroll(inlined.iter(), &mut |instr| {
+        let (parameters, exponents) = values(instr)?;
+        let matrix = instr.target_matrix(&parameters, &exponents).ok_or(EquivalenceError::NotUnitary(instr.op.label()))?;
+        let (qubits, states) = instr.controls();
+
+        res.push(dd::Gate {
+            matrix,
+            targets: instr.qubits.iter().map(|qubit| qubit.id() as usize).collect(),
+            controls: qubits.iter().map(|qubit| qubit.id() as usize).zip(states).collect(),
+        });
+        Ok(())
+    })?;
+
+    Ok(res)
+}
+
+/// Returns `true` if the two circuits implement the same unitary, up to a global phase.
+pub fn equivalent(circ1: &QuantumCircuit, circ2: &QuantumCircuit) -> Result<bool, EquivalenceError> {
+    let width = check_widths(circ1, circ2)?;
+    if width > MAX_QUBITS {
+        let identity: Vec<_> = (0..width).collect();
+        return Ok(dd::equivalent(width, &gates(circ1)?, &gates(circ2)?, &identity));
+    }
+
+    Ok(unitary(circ1)?.eq_up_to_phase(&unitary(circ2)?))
+}
+
+/// Returns `true` if the two circuits implement the same unitary, up to a global phase
+/// and a final permutation of the qubits of the second circuit, as returned by routing.
+///
+/// `permutation[i]` is the qubit of `circ2` holding the state of qubit `i` of `circ1`
+/// at the end of the circuit.
+pub fn equivalent_permuted(circ1: &QuantumCircuit, circ2: &QuantumCircuit, permutation: &[usize]) -> Result<bool, EquivalenceError> {
+    let width = check_widths(circ1, circ2)?;
+
+    let mut seen = vec![false; width];
+    let valid = permutation.len() == width && permutation.iter().all(|&q| q < width && !std::mem::replace(&mut seen[q], true));
+    if !valid {
+        return Err(EquivalenceError::InvalidPermutation);
+    }
+
+    if width > MAX_QUBITS {
+        return Ok(dd::equivalent(width, &gates(circ1)?, &gates(circ2)?, permutation));
+    }
+
+    let unitary2 = unitary(circ2)?;
+
+    // Relabel the rows of the second unitary, so that they are expressed in the basis of the first circuit.
+    let mut permuted = DMatrix::zeros(unitary2.dim());
+    for row in 0..unitary2.dim() {
+        let new_row = (0..width).fold(0, |acc, i| acc | (((row >> permutation[i]) & 1) << i));
+        (0..unitary2.dim()).for_each(|col| permuted[(new_row, col)] = unitary2[(row, col)]);
+    }
+
+    Ok(unitary(circ1)?.eq_up_to_phase(&permuted))
+}
+
+/// Checks that both circuits have the same width, and returns it.
+fn check_widths(circ1: &QuantumCircuit, circ2: &QuantumCircuit) -> Result<usize, EquivalenceError> {
+    (circ1.width() == circ2.width())
+        .then(|| circ1.width())
+        .ok_or(EquivalenceError::WidthMismatch(circ1.width(), circ2.width()))
+}
@@ -0,0 +1,469 @@
+//! Estimation of expectation values of observables from measurement histograms.
+//!
+//! The terms of the observable are grouped into sets of commuting Pauli strings, which
+//! can be measured together. For each group, a basis change mapping every string of the
+//! group to a diagonal one is appended to the circuit, followed by measurements of the
+//! qubits the group acts on. The histograms returned by the backend are then combined
+//! into an estimate of the expectation value, along with it's standard error.
+
+use thiserror::Error;
+
+use crate::circuit::{CircuitError, QuantumCircuit};
+use crate::linalg::c64;
+use crate::pauli::{Pauli, PauliString, SparsePauliOp};
+use crate::provider::Histogram;
+use crate::symbol::Qubit;
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Error)]
+pub enum EstimationError {
+    #[error("observable acts on {0} qubits, but the circuit only has {1}")]
+    TooFewQubits(usize, usize),
+    #[error("expected {0} histograms, got {1}")]
+    WrongNumberOfHistograms(usize, usize),
+    #[error("histogram has {0} bits, but at least {1} are required")]
+    TooFewBits(usize, usize),
+    #[error("histogram has no shots")]
+    NoShots,
+    #[error(transparent)]
+    Circuit(#[from] CircuitError),
+}
+
+/// The strategy used to group the terms of an observable.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Grouping {
+    /// Strings commuting qubit by qubit are grouped together. The basis change only
+    /// uses single qubit gates.
+    QubitWise,
+    /// Commuting strings are grouped together, resulting in fewer groups. The basis
+    /// change is a Clifford circuit which may use two-qubit gates.
+    Commuting,
+}
+
+/// The Clifford gates used in basis changes.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+enum Gate {
+    H(usize),
+    S(usize),
+    Sdg(usize),
+    CX(usize, usize),
+    CZ(usize, usize),
+}
+
+impl Gate {
+    /// Conjugates the string by the gate.
+    fn conjugate(self, pauli: &mut PauliString) {
+        match self {
+            Self::H(q) => pauli.conjugate_h(q),
+            Self::S(q) => pauli.conjugate_s(q),
+            Self::Sdg(q) => pauli.conjugate_sdg(q),
+            Self::CX(c, t) => pauli.conjugate_cx(c, t),
+            Self::CZ(a, b) => pauli.conjugate_cz(a, b),
+        }
+    }
+}
+
+/// A group of commuting terms, measured together.
+#[derive(Clone, Debug)]
+struct Group {
+    /// The coefficients of the terms, along with their strings after the basis change.
+    terms: Vec<(c64, PauliString)>,
+    /// The gates of the basis change.
+    gates: Vec<Gate>,
+    /// The qubits to measure, in order.
+    measured: Vec<usize>,
+}
+
+impl Group {
+    /// Computes the basis change of the group, whose strings all commute.
+    fn new(terms: Vec<(c64, PauliString)>, num_qubits: usize, grouping: Grouping) -> Self {
+        let mut res = Self { terms, gates: Vec::new(), measured: Vec::new() };
+
+        match grouping {
+            Grouping::QubitWise => for q in 0..num_qubits {
+                match res.terms.iter().map(|(_, pauli)| pauli.get(q)).find(|&pauli| pauli != Pauli::I) {
+                    Some(Pauli::X) => res.apply(Gate::H(q)),
+                    Some(Pauli::Y) => {
+                        res.apply(Gate::Sdg(q));
+                        res.apply(Gate::H(q));
+                    }
+                    _ => (),
+                }
+            },
+            // Each string is mapped in turn to a single Z on a pivot qubit. The following
+            // strings commute with that Z, so they don't act with X or Y on the pivot, and
+            // the gates applied to diagonalize them leave it untouched.
+            Grouping::Commuting => for i in 0..res.terms.len() {
+                let Some(pivot) = res.terms[i].1.x().ones().next() else {
+                    continue;
+                };
+
+                let others: Vec<_> = res.terms[i].1.x().ones().filter(|&q| q != pivot).collect();
+                others.into_iter().for_each(|q| res.apply(Gate::CX(pivot, q)));
+
+                if res.terms[i].1.get(pivot) == Pauli::Y {
+                    res.apply(Gate::S(pivot));
+                }
+
+                let others: Vec<_> = res.terms[i].1.z().ones().filter(|&q| q != pivot).collect();
+                others.into_iter().for_each(|q| res.apply(Gate::CZ(pivot, q)));
+
+                res.apply(Gate::H(pivot));
+            },
+        }
+
+        debug_assert!(res.terms.iter().all(|(_, pauli)| pauli.is_diagonal()));
+
+        res.measured = (0..num_qubits)
+            .filter(|&q| res.terms.iter().any(|(_, pauli)| pauli.get(q) != Pauli::I))
+            .collect();
+
+        res
+    }
+
+    /// Appends the gate to the basis change, conjugating every string of the group by it.
+    fn apply(&mut self, gate: Gate) {
+        self.gates.push(gate);
+        self.terms.iter_mut().for_each(|(_, pauli)| gate.conjugate(pauli));
+    }
+
+    /// Returns the mean and the variance of the mean of the group's terms sum, from the histogram.
+    fn estimate(&self, histogram: &Histogram) -> Result<(f64, f64), EstimationError> {
+        let num_bits = histogram.num_bits();
+        let offset = num_bits.checked_sub(self.measured.len()).ok_or(EstimationError::TooFewBits(num_bits, self.measured.len()))?;
+        let shots = histogram.shots();
+        if shots == 0 {
+            return Err(EstimationError::NoShots);
+        }
+
+        let (mut sum, mut sum_sqr) = (0.0, 0.0);
+        for (outcome, count) in histogram.iter() {
+            let value: f64 = self.terms.iter()
+                .map(|(coeff, pauli)| {
+                    let parity = self.measured.iter().enumerate()
+                        .filter(|&(_, &q)| pauli.get(q) == Pauli::Z)
+                        .filter(|&(k, _)| outcome.get(offset + k).unwrap())
+                        .count();
+                    let sign = if pauli.phase() == 2 { -1.0 } else { 1.0 };
+                    let eigenvalue = if parity % 2 == 0 { sign } else { -sign };
+                    coeff.re * eigenvalue
+                })
+                .sum();
+
+            sum += value * count as f64;
+            sum_sqr += value * value * count as f64;
+        }
+
+        let n = shots as f64;
+        let mean = sum / n;
+        let variance = if shots > 1 { (sum_sqr - n * mean * mean).max(0.0) / (n - 1.0) } else { 0.0 };
+
+        Ok((mean, variance / n))
+    }
+}
+
+/// An estimate of an expectation value, with it's standard error.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Estimate {
+    pub value: f64,
+    pub std_error: f64,
+}
+
+/// Estimates the expectation value of an hermitian observable, given as a sum of Pauli strings.
+#[derive(Clone, Debug)]
+pub struct Estimator {
+    num_qubits: usize,
+    /// The sum of the coefficients of the identity terms.
+    constant: f64,
+    groups: Vec<Group>,
+}
+
+impl Estimator {
+    /// Groups the terms of the observable with the given strategy, and computes the basis
+    /// changes of each group. Only the real parts of the coefficients are considered.
+    pub fn new(observable: &SparsePauliOp, grouping: Grouping) -> Self {
+        let num_qubits = observable.num_qubits();
+        let observable = observable.simplify();
+
+        let mut constant = 0.0;
+        let mut groups: Vec<Vec<(c64, PauliString)>> = Vec::new();
+
+        for (pauli, coeff) in observable.terms() {
+            if pauli.is_identity() {
+                constant += coeff.re;
+                continue;
+            }
+
+            let commutes = |other: &PauliString| match grouping {
+                Grouping::QubitWise => pauli.qubit_wise_commutes(other),
+                Grouping::Commuting => pauli.commutes(other),
+            };
+
+            match groups.iter_mut().find(|group| group.iter().all(|(_, other)| commutes(other))) {
+                Some(group) => group.push((*coeff, pauli.clone())),
+                None => groups.push(vec![(*coeff, pauli.clone())]),
+            }
+        }
+
+        let groups = groups.into_iter().map(|terms| Group::new(terms, num_qubits, grouping)).collect();
+        Self { num_qubits, constant, groups }
+    }
+
+    /// Returns the number of groups, which is the number of circuits to run.
+    pub fn num_groups(&self) -> usize {
+        self.groups.len()
+    }
+
+    /// Returns one circuit per group, made of the given circuit followed by the basis change
+    /// of the group and the measurements of the qubits it acts on into new bits. The `n`th
+    /// qubit of the circuit is the `n`th qubit of the observable.
+    pub fn circuits(&self, circ: &QuantumCircuit) -> Result<Vec<QuantumCircuit>, EstimationError> {
+        if circ.width() < self.num_qubits {
+            return Err(EstimationError::TooFewQubits(self.num_qubits, circ.width()));
+        }
+
+        self.groups.iter()
+            .map(|group| {
+                let res = circ.clone().edit(|builder| {
+                    let qubit = |q: usize| Qubit::new_unchecked(q as u32);
+
+                    for &gate in &group.gates {
+                        match gate {
+                            Gate::H(q) => builder.h(qubit(q)),
+                            Gate::S(q) => builder.s(qubit(q)),
+                            Gate::Sdg(q) => builder.sdg(qubit(q)),
+                            Gate::CX(c, t) => builder.cx(qubit(c), qubit(t)),
+                            Gate::CZ(a, b) => builder.cz(qubit(a), qubit(b)),
+                        };
+                    }
+
+                    for &q in &group.measured {
+                        let bit = builder.bit()?;
+                        builder.measure(qubit(q), bit);
+                    }
+
+                    Ok(())
+                })?;
+
+                Ok(res)
+            })
+            .collect()
+    }
+
+    /// Combines the histograms obtained by running the circuits returned by
+    /// [`Estimator::circuits`], in the same order, into an estimate of the expectation value.
+    pub fn estimate(&self, histograms: &[Histogram]) -> Result<Estimate, EstimationError> {
+        if histograms.len() != self.groups.len() {
+            return Err(EstimationError::WrongNumberOfHistograms(self.groups.len(), histograms.len()));
+        }
+
+        let (mut value, mut variance) = (self.constant, 0.0);
+        for (group, histogram) in self.groups.iter().zip(histograms) {
+            let (mean, var) = group.estimate(histogram)?;
+            value += mean;
+            variance += var;
+        }
+
+        Ok(Estimate { value, std_error: variance.sqrt() })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bitset::BitSet;
+    use crate::circuit::QuantumCircuit;
+    use crate::linalg::{c64, DMatrix};
+    use crate::pauli::{Pauli, PauliString, SparsePauliOp};
+    use crate::provider::Histogram;
+    use crate::simulator::density::DensityMatrixSimulator;
+
+    use super::{Estimator, EstimationError, Gate, Grouping};
+
+    /// Returns the matrix of the gate on `num_qubits` qubits, conjugating the strings as the
+    /// gate does.
+    fn gate_matrix(num_qubits: usize, gate: Gate) -> DMatrix {
+        let h = c64::from(std::f64::consts::FRAC_1_SQRT_2);
+        let mut res = DMatrix::zeros(1 << num_qubits);
+
+        for col in 0..1usize << num_qubits {
+            let bit = |q: usize| col >> q & 1 == 1;
+            match gate {
+                Gate::H(q) => {
+                    res[(col & !(1 << q), col)] = h;
+                    res[(col | 1 << q, col)] = if bit(q) { -h } else { h };
+                }
+                Gate::S(q) => res[(col, col)] = if bit(q) { c64::I } else { c64::ONE },
+                Gate::Sdg(q) => res[(col, col)] = if bit(q) { -c64::I } else { c64::ONE },
+                Gate::CX(c, t) => res[(if bit(c) { col ^ 1 << t } else { col }, col)] = c64::ONE,
+                Gate::CZ(a, b) => res[(col, col)] = if bit(a) && bit(b) { -c64::ONE } else { c64::ONE },
+            }
+        }
+
+        res
+    }
+
+    fn histogram(num_bits: usize, counts: &[(&[usize], u64)]) -> Histogram {
+        let mut res = Histogram::new(num_bits);
+        for &(ones, count) in counts {
+            let mut outcome = BitSet::new(num_bits);
+            ones.iter().for_each(|&bit| outcome.set(bit, true).unwrap());
+            res.record(outcome, count);
+        }
+        res
+    }
+
+    /// Returns the instructions of the circuit as labels and qubit indices.
+    fn instructions(circ: &QuantumCircuit) -> Vec<(&'static str, Vec<u32>)> {
+        circ.iter().map(|instr| (instr.op.label(), instr.qubits.iter().map(|qubit| qubit.id()).collect())).collect()
+    }
+
+    fn bell() -> QuantumCircuit {
+        QuantumCircuit::new(|b| {
+            let [q0, q1] = b.qubits()?;
+            b.h(q0).cx(q0, q1);
+            Ok(())
+        }).unwrap()
+    }
+
+    #[test]
+    fn basis_changes_diagonalize_the_groups() {
+        let observable = SparsePauliOp::from_labels(&[
+            ("XXI", 1.0), ("YYI", -0.5), ("ZZI", 2.0), ("IXY", 0.25), ("ZIZ", 1.5), ("YXZ", -1.0), ("III", 0.5),
+        ]).unwrap();
+
+        for grouping in [Grouping::QubitWise, Grouping::Commuting] {
+            let estimator = Estimator::new(&observable, grouping);
+            assert_eq!(estimator.constant, 0.5);
+
+            // Undoing the basis changes gives back the observable.
+            let mut sum = DMatrix::eye(8).scale(c64::from(estimator.constant));
+            for group in &estimator.groups {
+                assert!(group.terms.iter().all(|(_, pauli)| pauli.is_diagonal()));
+
+                let basis = group.gates.iter().fold(DMatrix::eye(8), |acc, &gate| &gate_matrix(3, gate) * &acc);
+                let diagonal = SparsePauliOp::new(3, group.terms.iter().map(|(coeff, pauli)| (pauli.clone(), *coeff)));
+                sum = &sum + &(&(&basis.adjoint() * &diagonal.to_matrix()) * &basis);
+
+                if grouping == Grouping::QubitWise {
+                    assert!(group.gates.iter().all(|gate| matches!(gate, Gate::H(_) | Gate::S(_) | Gate::Sdg(_))));
+                }
+            }
+
+            assert!((&sum - &observable.to_matrix()).norm() < 1E-12, "{grouping:?}");
+        }
+
+        let observable = SparsePauliOp::from_labels(&[("XX", 1.0), ("YY", 1.0), ("ZZ", 1.0)]).unwrap();
+        assert_eq!(Estimator::new(&observable, Grouping::QubitWise).num_groups(), 3);
+        assert_eq!(Estimator::new(&observable, Grouping::Commuting).num_groups(), 1);
+    }
+
+    #[test]
+    fn circuits_append_basis_changes_and_measurements() {
+        let observable = SparsePauliOp::from_labels(&[("YX", 1.0)]).unwrap();
+        let circs = Estimator::new(&observable, Grouping::QubitWise).circuits(&bell()).unwrap();
+        assert_eq!(circs.len(), 1);
+        assert_eq!(circs[0].num_bits(), 2);
+        assert_eq!(instructions(&circs[0]), [
+            ("h", vec![0]), ("cx", vec![0, 1]),
+            ("h", vec![0]), ("sdg", vec![1]), ("h", vec![1]),
+            ("measure", vec![0]), ("measure", vec![1]),
+        ]);
+
+        let observable = SparsePauliOp::from_labels(&[("XX", 1.0), ("ZZ", 1.0)]).unwrap();
+        let circs = Estimator::new(&observable, Grouping::Commuting).circuits(&bell()).unwrap();
+        assert_eq!(circs.len(), 1);
+        assert_eq!(instructions(&circs[0]), [
+            ("h", vec![0]), ("cx", vec![0, 1]),
+            ("cx", vec![0, 1]), ("h", vec![0]),
+            ("measure", vec![0]), ("measure", vec![1]),
+        ]);
+
+        let observable = SparsePauliOp::from_labels(&[("ZII", 1.0)]).unwrap();
+        let err = Estimator::new(&observable, Grouping::Commuting).circuits(&bell()).unwrap_err();
+        assert_eq!(err, EstimationError::TooFewQubits(3, 2));
+    }
+
+    #[test]
+    fn estimates_bell_state_correlations() {
+        let observable = SparsePauliOp::from_labels(&[("XX", 1.0), ("ZZ", 1.0)]).unwrap();
+
+        // Measuring the Bell state in the X or Z basis gives perfectly correlated outcomes.
+        let qubit_wise = Estimator::new(&observable, Grouping::QubitWise);
+        let histograms = [histogram(2, &[(&[], 50), (&[0, 1], 50)]), histogram(2, &[(&[], 40), (&[0, 1], 60)])];
+        let estimate = qubit_wise.estimate(&histograms).unwrap();
+        assert!((estimate.value - 2.0).abs() < 1E-12);
+        assert!(estimate.std_error.abs() < 1E-12);
+
+        // The basis change maps the Bell state to $ |00\rangle $.
+        let commuting = Estimator::new(&observable, Grouping::Commuting);
+        let estimate = commuting.estimate(&[histogram(2, &[(&[], 100)])]).unwrap();
+        assert!((estimate.value - 2.0).abs() < 1E-12);
+        assert!(estimate.std_error.abs() < 1E-12);
+
+        // Anticorrelated outcomes in the Z basis flip the sign of $ ZZ $ only.
+        let estimate = qubit_wise.estimate(&[histogram(2, &[(&[], 100)]), histogram(2, &[(&[0], 100)])]).unwrap();
+        assert!(estimate.value.abs() < 1E-12);
+    }
+
+    #[test]
+    fn estimates_values_and_standard_errors() {
+        // $ 2 Z - Y + 0.5 $, measured on two circuits with a bit already used.
+        let mut observable = SparsePauliOp::from_labels(&[("Z", 2.0), ("I", 0.5)]).unwrap();
+        observable = &observable + &SparsePauliOp::from(PauliString::from_sparse(1, &[(0, Pauli::Y)])).scale(c64::from(-1.0));
+        let estimator = Estimator::new(&observable, Grouping::QubitWise);
+        assert_eq!(estimator.num_groups(), 2);
+
+        // $ \langle Z \rangle = 0.5 $ with a sample variance of $ 30/39 $, and $ Y $ is always $ +1 $.
+        let histograms = [histogram(2, &[(&[0], 30), (&[0, 1], 10)]), histogram(2, &[(&[], 20)])];
+        let estimate = estimator.estimate(&histograms).unwrap();
+        assert!((estimate.value - (2.0 * 0.5 - 1.0 + 0.5)).abs() < 1E-12);
+        assert!((estimate.std_error - 2.0 * (30.0 / 39.0 / 40.0f64).sqrt()).abs() < 1E-12);
+
+        assert_eq!(estimator.estimate(&histograms[..1]).unwrap_err(), EstimationError::WrongNumberOfHistograms(2, 1));
+        assert_eq!(estimator.estimate(&[histogram(0, &[(&[], 1)]), histogram(1, &[])]).unwrap_err(), EstimationError::TooFewBits(0, 1));
+        assert_eq!(estimator.estimate(&[histogram(1, &[]), histogram(1, &[])]).unwrap_err(), EstimationError::NoShots);
+    }
+
+    /// Runs the circuits of the estimator on the density matrix simulator, turning the exact
+    /// probabilities of the outcomes into histograms of a million shots.
+    fn simulate(estimator: &Estimator, circ: &QuantumCircuit) -> Vec<Histogram> {
+        estimator.circuits(circ).unwrap().iter()
+            .map(|circ| {
+                let mut res = Histogram::new(circ.num_bits());
+                for branch in DensityMatrixSimulator::new(0).run(circ).unwrap() {
+                    res.record(branch.bits, (branch.probability * 1E6).round() as u64);
+                }
+                res
+            })
+            .collect()
+    }
+
+    #[test]
+    fn estimates_simulated_expectation_values() {
+        let observable = SparsePauliOp::from_labels(&[("XX", 1.0), ("ZZ", 1.0)]).unwrap();
+        for grouping in [Grouping::QubitWise, Grouping::Commuting] {
+            let estimator = Estimator::new(&observable, grouping);
+            let estimate = estimator.estimate(&simulate(&estimator, &bell())).unwrap();
+            assert!((estimate.value - 2.0).abs() < 1E-9, "{grouping:?}");
+            assert!(estimate.std_error.abs() < 1E-9, "{grouping:?}");
+        }
+
+        // On $ R_x(b) |0\rangle \otimes R_y(a) |0\rangle $, the expectations of $ X $, $ Y $
+        // and $ Z $ are $ (\sin a, 0, \cos a) $ and $ (0, -\sin b, \cos b) $.
+        let (a, b) = (0.7f32, 1.9f32);
+        let circ = QuantumCircuit::new(|builder| {
+            let [q0, q1] = builder.qubits()?;
+            builder.ry(a, q0).rx(b, q1);
+            Ok(())
+        }).unwrap();
+        let observable = SparsePauliOp::from_labels(&[("ZX", 1.0), ("YZ", 0.5), ("IZ", -2.0), ("XX", 0.7), ("II", 0.25)]).unwrap();
+        let (a, b) = (f64::from(a), f64::from(b));
+        let expected = b.cos() * a.sin() - 0.5 * b.sin() * a.cos() - 2.0 * a.cos() + 0.25;
+
+        for grouping in [Grouping::QubitWise, Grouping::Commuting] {
+            let estimator = Estimator::new(&observable, grouping);
+            let estimate = estimator.estimate(&simulate(&estimator, &circ)).unwrap();
+            assert!((estimate.value - expected).abs() < 1E-5, "{grouping:?}: {} != {expected}", estimate.value);
+            assert!(estimate.std_error > 0.0 && estimate.std_error < 1E-2);
+        }
+    }
+}
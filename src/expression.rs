@@ -0,0 +1,396 @@
+//! Symbolic parameter expressions, such as $ 2 \theta + \pi / 4 $.
+//!
+//! Expressions are stored in a table held by the circuit, and are referenced
+//! from instructions by a [`Parameter`](crate::parameter::Parameter) in it's
+//! compact form.
+
+use std::fmt;
+use std::ops::{Add, Sub, Mul, Div, Neg};
+
+use crate::symbol::FormalParameter;
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Expr<'id> {
+    /// A constant value.
+    Value(f64),
+    /// A formal parameter, to be bound later.
+    Formal(FormalParameter<'id>),
+    /// The opposite of the expression.
+    Neg(Box<Expr<'id>>),
+    /// The sum of two expressions.
+    Add(Box<Expr<'id>>, Box<Expr<'id>>),
+    /// The product of two expressions.
+    Mul(Box<Expr<'id>>, Box<Expr<'id>>),
+    /// The quotient of two expressions.
+    Div(Box<Expr<'id>>, Box<Expr<'id>>),
+    /// The sine of the expression.
+    Sin(Box<Expr<'id>>),
+    /// The cosine of the expression.
+    Cos(Box<Expr<'id>>),
+    /// The exponential of the expression.
+    Exp(Box<Expr<'id>>),
+}
+
+impl<'id> Expr<'id> {
+    pub fn sin(self) -> Self {
+        Self::Sin(Box::new(self))
+    }
+
+    pub fn cos(self) -> Self {
+        Self::Cos(Box::new(self))
+    }
+
+    pub fn exp(self) -> Self {
+        Self::Exp(Box::new(self))
+    }
+
+    pub fn as_value(&self) -> Option<f64> {
+        match self {
+            Self::Value(x) => Some(*x),
+            _ => None,
+        }
+    }
+
+    /// Returns `true` if the expression can't evaluate to NaN or an infinite value, provided
+    /// the formal parameters take finite values. Sums and products are excluded, as they
+    /// may overflow.
+    fn is_finite(&self) -> bool {
+        match self {
+            Self::Value(x) => x.is_finite(),
+            Self::Formal(_) => true,
+            Self::Neg(x) | Self::Sin(x) | Self::Cos(x) => x.is_finite(),
+            Self::Add(..) | Self::Mul(..) | Self::Div(..) | Self::Exp(_) => false,
+        }
+    }
+
+    /// Evaluates the expression, where the `n`th formal parameter takes the value `values[n]`.
+    /// Returns `None` if a formal parameter has no value.
+    pub fn eval(&self, values: &[f64]) -> Option<f64> {
+        Some(match self {
+            Self::Value(x) => *x,
+            Self::Formal(formal) => *values.get(formal.id() as usize)?,
+            Self::Neg(x) => -x.eval(values)?,
+            Self::Add(x, y) => x.eval(values)? + y.eval(values)?,
+            Self::Mul(x, y) => x.eval(values)? * y.eval(values)?,
+            Self::Div(x, y) => x.eval(values)? / y.eval(values)?,
+            Self::Sin(x) => x.eval(values)?.sin(),
+            Self::Cos(x) => x.eval(values)?.cos(),
+            Self::Exp(x) => x.eval(values)?.exp(),
+        })
+    }
+
+    /// Returns `true` if the expression depends on the formal parameter.
+    pub fn depends_on(&self, formal: FormalParameter<'id>) -> bool {
+        match self {
+            Self::Value(_) => false,
+            Self::Formal(other) => *other == formal,
+            Self::Neg(x) | Self::Sin(x) | Self::Cos(x) | Self::Exp(x) => x.depends_on(formal),
+            Self::Add(x, y) | Self::Mul(x, y) | Self::Div(x, y) => x.depends_on(formal) || y.depends_on(formal),
+        }
+    }
+
+    /// Returns the formal parameters the expression depends on, sorted and without duplicates.
+    pub fn formals(&self) -> Vec<FormalParameter<'id>> {
+        fn collect<'id>(expr: &Expr<'id>, res: &mut Vec<FormalParameter<'id>>) {
+            match expr {
+                Expr::Value(_) => (),
+                Expr::Formal(formal) => res.push(*formal),
+                Expr::Neg(x) | Expr::Sin(x) | Expr::Cos(x) | Expr::Exp(x) => collect(x, res),
+                Expr::Add(x, y) | Expr::Mul(x, y) | Expr::Div(x, y) => {
+                    collect(x, res);
+                    collect(y, res);
+                }
+            }
+        }
+
+        let mut res = Vec::new();
+        collect(self, &mut res);
+        res.sort();
+        res.dedup();
+        res
+    }
+
+    /// Returns a simplified version of the expression, by folding constants and
+    /// removing neutral and absorbing elements. Quotients are kept unless the divisor
+    /// is one, as they may be undefined, and absorbing elements only apply to operands
+    /// which are always finite.
+    pub fn simplify(&self) -> Self {
+        use Expr::*;
+
+        match self {
+            Value(_) | Formal(_) => self.clone(),
+            Neg(x) => match x.simplify() {
+                Value(x) => Value(-x),
+                Neg(x) => *x,
+                x => -x,
+            },
+            Add(x, y) => match (x.simplify(), y.simplify()) {
+                (Value(x), Value(y)) => Value(x + y),
+                (Value(z), other) | (other, Value(z)) if z == 0.0 => other,
+                (x, Neg(y)) if x == *y && x.is_finite() => Value(0.0),
+                (x, y) => x + y,
+            },
+            Mul(x, y) => match (x.simplify(), y.simplify()) {
+                (Value(x), Value(y)) => Value(x * y),
+                (Value(z), other) | (other, Value(z)) if z == 0.0 && other.is_finite() => Value(0.0),
+                (Value(z), other) | (other, Value(z)) if z == 1.0 => other,
+                (Value(z), other) | (other, Value(z)) if z == -1.0 => -other,
+                (x, y) => x * y,
+            },
+            Div(x, y) => match (x.simplify(), y.simplify()) {
+                (Value(x), Value(y)) => Value(x / y),
+                (x, Value(1.0)) => x,
+                (x, y) => x / y,
+            },
+            Sin(x) => match x.simplify() {
+                Value(x) => Value(x.sin()),
+                x => x.sin(),
+            },
+            Cos(x) => match x.simplify() {
+                Value(x) => Value(x.cos()),
+                x => x.cos(),
+            },
+            Exp(x) => match x.simplify() {
+                Value(x) => Value(x.exp()),
+                x => x.exp(),
+            },
+        }
+    }
+
+    /// Returns the simplified derivative of the expression with respect to the formal parameter.
+    pub fn derivative(&self, formal: FormalParameter<'id>) -> Self {
+        use Expr::*;
+
+        let res = match self {
+            Value(_) => Value(0.0),
+            Formal(other) => Value(if *other == formal { 1.0 } else { 0.0 }),
+            Neg(x) => -x.derivative(formal),
+            Add(x, y) => x.derivative(formal) + y.derivative(formal),
+            Mul(x, y) => x.derivative(formal) * (**y).clone() + (**x).clone() * y.derivative(formal),
+            Div(x, y) => {
+                let numerator = x.derivative(formal) * (**y).clone() - (**x).clone() * y.derivative(formal);
+                numerator / ((**y).clone() * (**y).clone())
+            }
+            Sin(x) => x.derivative(formal) * (**x).clone().cos(),
+            Cos(x) => -(x.derivative(formal) * (**x).clone().sin()),
+            Exp(x) => x.derivative(formal) * self.clone(),
+        };
+
+        res.simplify()
+    }
+
+    /// Returns the expression with every formal parameter replaced by the expression returned
+    /// by `substitution`, possibly of another circuit.
+    pub fn substitute<'a, F>(&self, substitution: &F) -> Expr<'a>
+    where
+        F: Fn(FormalParameter<'id>) -> Expr<'a>
+    {
+        let substitute = |x: &Expr<'id>| Box::new(x.substitute(substitution));
+
+        match self {
+            Self::Value(x) => Expr::Value(*x),
+            Self::Formal(formal) => substitution(*formal),
+            Self::Neg(x) => Expr::Neg(substitute(x)),
+            Self::Add(x, y) => Expr::Add(substitute(x), substitute(y)),
+            Self::Mul(x, y) => Expr::Mul(substitute(x), substitute(y)),
+            Self::Div(x, y) => Expr::Div(substitute(x), substitute(y)),
+            Self::Sin(x) => Expr::Sin(substitute(x)),
+            Self::Cos(x) => Expr::Cos(substitute(x)),
+            Self::Exp(x) => Expr::Exp(substitute(x)),
+        }
+    }
+
+    /// Returns the same expression, with a different brand.
+    pub(crate) fn rebrand<'a>(&self) -> Expr<'a> {
+        let rebrand = |x: &Expr<'id>| Box::new(x.rebrand());
+
+        match self {
+            Self::Value(x) => Expr::Value(*x),
+            Self::Formal(formal) => Expr::Formal(FormalParameter::new_unchecked(formal.id())),
+            Self::Neg(x) => Expr::Neg(rebrand(x)),
+            Self::Add(x, y) => Expr::Add(rebrand(x), rebrand(y)),
+            Self::Mul(x, y) => Expr::Mul(rebrand(x), rebrand(y)),
+            Self::Div(x, y) => Expr::Div(rebrand(x), rebrand(y)),
+            Self::Sin(x) => Expr::Sin(rebrand(x)),
+            Self::Cos(x) => Expr::Cos(rebrand(x)),
+            Self::Exp(x) => Expr::Exp(rebrand(x)),
+        }
+    }
+}
+
+impl fmt::Display for Expr<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Value(x) => write!(f, "{x}"),
+            Self::Formal(formal) => write!(f, "p{}", formal.id()),
+            Self::Neg(x) => write!(f, "-({x})"),
+            Self::Add(x, y) => write!(f, "({x} + {y})"),
+            Self::Mul(x, y) => write!(f, "({x} * {y})"),
+            Self::Div(x, y) => write!(f, "({x} / {y})"),
+            Self::Sin(x) => write!(f, "sin({x})"),
+            Self::Cos(x) => write!(f, "cos({x})"),
+            Self::Exp(x) => write!(f, "exp({x})"),
+        }
+    }
+}
+
+impl From<f64> for Expr<'_> {
+    fn from(x: f64) -> Self {
+        Self::Value(x)
+    }
+}
+
+impl<'id> From<FormalParameter<'id>> for Expr<'id> {
+    fn from(formal: FormalParameter<'id>) -> Self {
+        Self::Formal(formal)
+    }
+}
+
+impl<'id> Neg for Expr<'id> {
+    type Output = Expr<'id>;
+
+    fn neg(self) -> Self::Output {
+        Expr::Neg(Box::new(self))
+    }
+}
+
+impl<'id> Neg for FormalParameter<'id> {
+    type Output = Expr<'id>;
+
+    fn neg(self) -> Self::Output {
+        -Expr::from(self)
+    }
+}
+
+// Implements the binary operation $op for expressions, formal parameters and floats.
+macro_rules! expr_op {
+    { $op: ident: $fn: ident, $build: expr } => {
+        impl<'id, T: Into<Expr<'id>>> $op<T> for Expr<'id> {
+            type Output = Expr<'id>;
+
+            fn $fn(self, rhs: T) -> Expr<'id> {
+                $build(self, rhs.into())
+            }
+        }
+
+        impl<'id, T: Into<Expr<'id>>> $op<T> for FormalParameter<'id> {
+            type Output = Expr<'id>;
+
+            fn $fn(self, rhs: T) -> Expr<'id> {
+                $build(Expr::from(self), rhs.into())
+            }
+        }
+
+        impl<'id> $op<Expr<'id>> for f64 {
+            type Output = Expr<'id>;
+
+            fn $fn(self, rhs: Expr<'id>) -> Expr<'id> {
+                $build(Expr::from(self), rhs)
+            }
+        }
+
+        impl<'id> $op<FormalParameter<'id>> for f64 {
+            type Output = Expr<'id>;
+
+            fn $fn(self, rhs: FormalParameter<'id>) -> Expr<'id> {
+                $build(Expr::from(self), Expr::from(rhs))
+            }
+        }
+    }
+}
+
+expr_op! { Add: add, |x, y| Expr::Add(Box::new(x), Box::new(y)) }
+expr_op! { Sub: sub, |x, y: Expr<'id>| Expr::Add(Box::new(x), Box::new(-y)) }
+expr_op! { Mul: mul, |x, y| Expr::Mul(Box::new(x), Box::new(y)) }
+expr_op! { Div: div, |x, y| Expr::Div(Box::new(x), Box::new(y)) }
+
+#[cfg(test)]
+mod tests {
+    use crate::symbol::FormalParameter;
+
+    use super::Expr;
+
+    #[test]
+    fn simplify_keeps_undefined_quotients() {
+        let x = Expr::from(FormalParameter::new_unchecked(0));
+
+        assert!((0.0 / x.clone()).simplify().eval(&[0.0]).unwrap().is_nan());
+        assert!((x.clone() / x.clone()).simplify().eval(&[0.0]).unwrap().is_nan());
+        assert!((Expr::from(0.0) / 0.0).simplify().as_value().unwrap().is_nan());
+        assert_eq!((x.clone() / 1.0).simplify(), x);
+    }
+
+    #[test]
+    fn simplify_folds_finite_operands() {
+        let x = Expr::from(FormalParameter::new_unchecked(0));
+
+        assert_eq!((0.0 * x.clone().sin()).simplify(), Expr::Value(0.0));
+        assert_eq!((x.clone() * 0.0).simplify(), Expr::Value(0.0));
+        assert_eq!((x.clone().cos() - x.clone().cos()).simplify(), Expr::Value(0.0));
+        assert_eq!((x.clone() * 1.0 + 0.0).simplify(), x);
+        assert_eq!((-1.0 * x.clone()).simplify(), -x.clone());
+        assert_eq!((-(-x.clone())).simplify(), x);
+    }
+
+    #[test]
+    fn simplify_keeps_products_and_differences_of_undefined_operands() {
+        let x = Expr::from(FormalParameter::new_unchecked(0));
+        let inverse = 1.0 / x.clone();
+
+        let product = (0.0 * inverse.clone()).simplify();
+        assert_ne!(product, Expr::Value(0.0));
+        assert!(product.eval(&[0.0]).unwrap().is_nan());
+        assert_eq!(product.eval(&[2.0]), Some(0.0));
+
+        let difference = (inverse.clone() - inverse).simplify();
+        assert_ne!(difference, Expr::Value(0.0));
+        assert!(difference.eval(&[0.0]).unwrap().is_nan());
+        assert_eq!(difference.eval(&[2.0]), Some(0.0));
+
+        let overflow = (x.clone().exp() - x.exp()).simplify();
+        assert!(overflow.eval(&[1000.0]).unwrap().is_nan());
+    }
+
+    #[test]
+    fn derivative_follows_the_usual_rules() {
+        let x = FormalParameter::new_unchecked(0);
+        let y = FormalParameter::new_unchecked(1);
+        let approx = |expr: Expr<'_>, values: &[f64], expected: f64| {
+            assert!((expr.eval(values).unwrap() - expected).abs() < 1e-12, "{expr} at {values:?}");
+        };
+
+        assert_eq!(Expr::from(2.0).derivative(x), Expr::Value(0.0));
+        assert_eq!(Expr::from(x).derivative(x), Expr::Value(1.0));
+        assert_eq!(Expr::from(y).derivative(x), Expr::Value(0.0));
+        assert_eq!((2.0 * x + y).derivative(x), Expr::Value(2.0));
+        assert_eq!((x * y).derivative(x), Expr::from(y));
+        assert_eq!((-x).derivative(x), Expr::Value(-1.0));
+
+        approx((x * x).derivative(x), &[3.0], 6.0);
+        approx((1.0 / x).derivative(x), &[2.0], -0.25);
+        approx((x / y).derivative(y), &[3.0, 2.0], -0.75);
+        approx(Expr::from(x).sin().derivative(x), &[0.5], 0.5f64.cos());
+        approx(Expr::from(x).cos().derivative(x), &[0.5], -0.5f64.sin());
+        approx((2.0 * x).exp().derivative(x), &[0.5], 2.0 * 1f64.exp());
+        approx((x * y).sin().derivative(y), &[2.0, 0.5], 2.0 * 1f64.cos());
+    }
+
+    #[test]
+    fn substitute_replaces_every_formal_parameter() {
+        let x = Expr::from(FormalParameter::new_unchecked(0));
+        let y = Expr::from(FormalParameter::new_unchecked(1));
+        let expr = (x.clone() * 2.0 + y.clone().sin()) / (-x.clone()).exp() - y.cos();
+
+        // The formal parameters are swapped, then replaced by expressions of a single one.
+        let swapped = expr.substitute(&|formal| Expr::from(FormalParameter::new_unchecked(1 - formal.id())));
+        assert_eq!(swapped.eval(&[0.7, -0.3]), expr.eval(&[-0.3, 0.7]));
+
+        let z = Expr::from(FormalParameter::new_unchecked(0));
+        let composed = expr.substitute(&|formal| if formal.id() == 0 { z.clone() * 3.0 } else { z.clone() + 1.0 });
+        assert!((composed.eval(&[0.4]).unwrap() - expr.eval(&[0.4 * 3.0, 0.4 + 1.0]).unwrap()).abs() < 1E-12);
+
+        // Values are kept, and substituting a parameter by itself keeps the expression.
+        assert_eq!(Expr::from(1.5).substitute(&|_| x.clone()), Expr::from(1.5));
+        assert_eq!(expr.substitute(&|formal| Expr::from(formal)), expr);
+    }
+}
@@ -1,9 +1,11 @@
-use std::cell::Cell;
 use std::marker::PhantomData;
 
 /// This is a type with an invariant lifetime. It is used to
 /// emulate genericity. It enables trusted indexing.
+///
+/// The lifetime is made invariant by a function pointer rather than a `Cell`,
+/// so that branded types remain `Send` and `Sync`.
 #[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Default, Debug)]
 pub(crate) struct Id<'id> {
-    _phantom: PhantomData<Cell<&'id ()>>
-}
\ No newline at end of file
+    _phantom: PhantomData<fn(&'id ()) -> &'id ()>
+}
@@ -0,0 +1,376 @@
+//! Analytic gradients of expectation values, with the
+//! [parameter-shift rule](https://arxiv.org/abs/1811.11184).
+//!
+//! The gradient of the expectation value of an observable with respect to the formal
+//! parameters of a circuit is obtained by evaluating the observable on shifted copies of
+//! the circuit. [`ParameterShift`] builds these circuits, and combines the expectation
+//! values returned by the backend into the gradient.
+
+use std::f64::consts::PI;
+
+use thiserror::Error;
+
+use crate::circuit::{CircuitError, ConcreteCircuit, QuantumCircuit};
+use crate::instruction::{self, Modifier};
+use crate::parameter::Parameter;
+use crate::symbol::FormalParameter;
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Error)]
+pub enum GradientError {
+    #[error("expected {0} parameter values, got {1}")]
+    WrongNumberOfValues(usize, usize),
+    #[error("operation `{0}` has no parameter-shift rule")]
+    NoShiftRule(&'static str),
+    #[error("parametrized instructions under a loop modifier can't be differentiated")]
+    LoopModifier,
+    #[error("parametrized instructions under a power modifier must have a single parameter")]
+    Power,
+    #[error("exponents of power modifiers can't depend on formal parameters")]
+    ParametrizedExponent,
+    #[error("parametrized instructions inside control flow can't be differentiated")]
+    ControlFlow,
+    #[error("circuit could not be inlined: {0}")]
+    Inline(CircuitError),
+}
+
+/// A parameter-shift rule: the derivative of $ f $ at $ x $ is
+/// $ \sum_k c_k f(x + s_k) $, for the coefficients $ c_k $ and shifts $ s_k $.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ShiftRule {
+    terms: Vec<(f64, f64)>,
+}
+
+impl ShiftRule {
+    /// The generalized shift rule for gates $ e^{-i x G} $ whose generator $ G $ has
+    /// equidistant eigenvalues, with the `frequencies` differences $ 1, \dots, R $. See
+    /// [this paper](https://arxiv.org/abs/2107.12390), equation (24).
+    pub fn equidistant(frequencies: usize) -> Self {
+        let r = frequencies as f64;
+        let terms = (1..=2 * frequencies)
+            .map(|mu| {
+                let shift = (2 * mu - 1) as f64 * PI / (2.0 * r);
+                let sign = if mu % 2 == 1 { 1.0 } else { -1.0 };
+                (sign / (4.0 * r * (shift / 2.0).sin().powi(2)), shift)
+            })
+            .collect();
+        Self { terms }
+    }
+
+    /// The usual two-term shift rule, for generators with two eigenvalues whose
+    /// difference is $ 1 $, such as $ X / 2 $ for the `RX` gate.
+    pub fn two_term() -> Self {
+        Self::equidistant(1)
+    }
+
+    /// Returns the rule for the generator $ \omega G $, whose eigenvalue differences are
+    /// scaled by $ \omega > 0 $.
+    pub fn scaled(&self, omega: f64) -> Self {
+        let terms = self.terms.iter().map(|&(coeff, shift)| (coeff * omega, shift / omega)).collect();
+        Self { terms }
+    }
+
+    /// Returns the pairs of coefficients and shifts of the rule.
+    pub fn terms(&self) -> &[(f64, f64)] {
+        &self.terms
+    }
+}
+
+/// The spectrum of the generator $ G $ of a gate $ e^{-i x G} $, up to a global phase.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Generator {
+    /// Eigenvalues $ \pm 1/2 $, as for Pauli rotations.
+    Rotation,
+    /// Eigenvalues $ 0 $ and $ 1 $, as for phase shifts.
+    Phase,
+}
+
+impl Generator {
+    /// Returns the shift rule of the gate, possibly under controls and raised to a power.
+    ///
+    /// Controls add the eigenvalue $ 0 $ to the spectrum of the generator, which gives a rotation
+    /// the frequencies $ 1/2 $ and $ 1 $ and requires the four-term rule, while a power scales
+    /// the frequencies by its exponent.
+    pub fn shift_rule(self, controlled: bool, exponent: f64) -> ShiftRule {
+        let omega = exponent.abs();
+        if omega == 0.0 {
+            return ShiftRule { terms: Vec::new() };
+        }
+
+        match (self, controlled) {
+            (Self::Rotation, true) => ShiftRule::equidistant(2).scaled(omega / 2.0),
+            _ => ShiftRule::two_term().scaled(omega),
+        }
+    }
+}
+
+/// A single shifted circuit, contributing to the derivative of a formal parameter.
+#[derive(Copy, Clone, PartialEq, Debug)]
+struct Term {
+    formal: usize,
+    circuit: usize,
+    weight: f64,
+}
+
+/// The circuits required to compute the gradient of an expectation value with the
+/// parameter-shift rule, at a given point.
+///
+/// Parameters referencing expressions are differentiated with the chain rule.
+#[derive(Clone, Debug)]
+pub struct ParameterShift {
+    num_formals: usize,
+    circuits: Vec<ConcreteCircuit>,
+    terms: Vec<Term>,
+}
+
+impl ParameterShift {
+    /// Builds the shifted circuits of the circuit, for the given values of it's formal parameters.
+    pub fn new(circ: &QuantumCircuit, values: &[f32]) -> Result<Self, GradientError> {
+        if values.len() != circ.num_formals() {
+            return Err(GradientError::WrongNumberOfValues(circ.num_formals(), values.len()));
+        }
+
+        // Calls are inlined, so that the parameters of each gate are shifted on their own.
+        let inlined = circ.inlined().map_err(GradientError::Inline)?;
+        let circ = &*inlined;
+
+        let point: Vec<f64> = values.iter().map(|&x| x.into()).collect();
+
+        // The instructions, with all of their parameters bound to their values.
+        let mut instrs = Vec::new();
+        for instr in circ.iter() {
+            // The bodies of control flow operations are kept as is, so they must not be parametrized.
+            if let Some(blocks) = instr.op.blocks() {
+                blocks.bodies().try_for_each(|body| instruction::visit(body, &mut |instr| {
+                    match instr.parameters.iter().copied().chain(instr.exponents()).all(Parameter::is_value) {
+                        true => Ok(()),
+                        false => Err(GradientError::ControlFlow),
+                    }
+                }))?;
+            }
+
+            if !instr.exponents().all(Parameter::is_value) {
+                return Err(GradientError::ParametrizedExponent);
+            }
+
+            let bound: Vec<_> = instr.parameters.iter()
+                .map(|&param| circ.parameter_value(param, &point).unwrap())
+                .collect();
+            instrs.push((instr, bound));
+        }
+
+        let mut res = Self { num_formals: circ.num_formals(), circuits: Vec::new(), terms: Vec::new() };
+
+        for (index, (instr, bound)) in instrs.iter().enumerate() {
+            for (param_index, &param) in instr.parameters.iter().enumerate() {
+                // The derivatives of the parameter with respect to each formal parameter it depends on.
+                let derivatives: Vec<_> = if let Some(formal) = param.as_formal() {
+                    vec![(formal.id() as usize, 1.0)]
+                } else if let Some(expr) = circ.expr(param) {
+                    expr.formals().into_iter()
+                        .map(|formal| (formal.id() as usize, expr.derivative(formal).eval(&point).unwrap()))
+                        .filter(|&(_, derivative)| derivative != 0.0)
+                        .collect()
+                } else {
+                    continue;
+                };
+
+                if derivatives.is_empty() {
+                    continue;
+                }
+
+                // An inverse keeps the spectrum of the generator, up to it's sign.
+                let (mut controlled, mut exponent) = (false, 1.0);
+                for modifier in &instr.modifiers {
+                    match modifier {
+                        Modifier::Controlled(_) => controlled = true,
+                        Modifier::Power(power) => exponent *= f64::from(power.as_value().unwrap()),
+                        Modifier::Inverse | Modifier::IfBit(_) | Modifier::IfCompute(_) => (),
+                        _ => return Err(GradientError::LoopModifier),
+                    }
+                }
+
+                // Powers scale the parameter of single parameter gates only, see `OpKind::power`.
+                if instr.exponents().next().is_some() && instr.parameters.len() != 1 {
+                    return Err(GradientError::Power);
+                }
+
+                let generator = instr.op.generator(param_index).ok_or(GradientError::NoShiftRule(instr.op.label()))?;
+                let rule = generator.shift_rule(controlled, exponent);
+
+                for &(coeff, shift) in rule.terms() {
+                    let mut data = Vec::new();
+                    for (other, (instr, bound)) in instrs.iter().enumerate() {
+                        let params: Vec<_> = bound.iter().enumerate()
+                            .map(|(i, &value)| {
+                                let shifted = if other == index && i == param_index { value + shift } else { value };
+                                Parameter::from(shifted as f32)
+                            })
+                            .collect();
+                        instruction::write_parts(&mut data, &instr.op, instr.qubits, instr.bits, &params, &instr.modifiers);
+                    }
+
+                    let circuit = res.circuits.len();
+                    res.circuits.push(circ.with_concrete_data(data));
+                    res.terms.extend(derivatives.iter().map(|&(formal, derivative)| Term { formal, circuit, weight: coeff * derivative }));
+                }
+            }
+        }
+
+        Ok(res)
+    }
+
+    /// Returns the shifted circuits, on which the observable should be evaluated.
+    pub fn circuits(&self) -> &[ConcreteCircuit] {
+        &self.circuits
+    }
+
+    /// Combines the expectation values of the observable on each of the shifted circuits,
+    /// in the order returned by [`ParameterShift::circuits`], into the gradient with respect
+    /// to each formal parameter. Returns `None` if the number of expectation values is wrong.
+    pub fn gradient(&self, expectations: &[f64]) -> Option<Vec<f64>> {
+        (expectations.len() == self.circuits.len()).then(|| {
+            let mut res = vec![0.0; self.num_formals];
+            self.terms.iter().for_each(|term| res[term.formal] += term.weight * expectations[term.circuit]);
+            res
+        })
+    }
+
+    /// Returns the derivative with respect to a single formal parameter, see [`ParameterShift::gradient`].
+    pub fn derivative(&self, formal: FormalParameter<'_>, expectations: &[f64]) -> Option<f64> {
+        self.gradient(expectations)?.get(formal.id() as usize).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::circuit::{CircuitBuilder, CircuitError, QuantumCircuit};
+    use crate::expression::Expr;
+    use crate::instruction::Modifier;
+    use crate::operation::OpKind;
+    use crate::parameter::Parameter;
+    use crate::simulator::density::DensityMatrixSimulator;
+    use crate::symbol::{FormalParameter, Qubit};
+
+    use super::{Generator, GradientError, ParameterShift};
+
+    /// Returns the exact expectation value of $ Z $ on the first bit.
+    fn expectation(circ: &QuantumCircuit) -> f64 {
+        DensityMatrixSimulator::new(0).run(circ).unwrap().iter()
+            .map(|branch| if branch.bits.get(0).unwrap() { -branch.probability } else { branch.probability })
+            .sum()
+    }
+
+    /// Builds a circuit preparing two qubits, applying the gate parametrized by the formal
+    /// parameter, then measuring the first qubit in a basis entangled with the second, so that
+    /// the expectation depends on the coherences between the branches of a control.
+    fn circuit<F>(gate: F) -> QuantumCircuit
+    where
+        F: for<'id> Fn(&mut CircuitBuilder<'id>, [Qubit<'id>; 2], FormalParameter<'id>)
+    {
+        QuantumCircuit::new(|b| {
+            let [a, c] = b.qubits()?;
+            let [bit] = b.bits()?;
+            let theta = b.formal()?;
+            b.h(a).ry(0.4, a).rx(0.9, c);
+            gate(b, [a, c], theta);
+            b.cx(c, a).h(a).measure(a, bit);
+            Ok::<_, CircuitError>(())
+        }).unwrap()
+    }
+
+    /// Checks the parameter-shift gradient against finite differences.
+    fn check_gradient(circ: &QuantumCircuit, theta: f32) {
+        let shift = ParameterShift::new(circ, &[theta]).unwrap();
+        let expectations: Vec<_> = shift.circuits().iter().map(|circ| expectation(circ)).collect();
+        let gradient = shift.gradient(&expectations).unwrap()[0];
+
+        let h = 1E-2;
+        let plus = expectation(&circ.bind_copy(&[theta + h]).unwrap());
+        let minus = expectation(&circ.bind_copy(&[theta - h]).unwrap());
+        let expected = (plus - minus) / (2.0 * f64::from(h));
+
+        // The central difference has an error of order $ h^2 $.
+        assert!((gradient - expected).abs() < 1E-3, "{gradient} != {expected}");
+    }
+
+    /// Checks the rule against the derivative of a trigonometric polynomial with the given
+    /// frequencies, as expectation values are for gates with such generators.
+    fn check(generator: Generator, controlled: bool, exponent: f64, frequencies: &[f64]) {
+        let rule = generator.shift_rule(controlled, exponent);
+        let f = |x: f64| frequencies.iter().enumerate()
+            .map(|(k, &omega)| (k as f64 + 0.3) * (omega * x).cos() + (k as f64 - 0.7) * (omega * x).sin())
+            .sum::<f64>();
+        let derivative = |x: f64| frequencies.iter().enumerate()
+            .map(|(k, &omega)| omega * (-(k as f64 + 0.3) * (omega * x).sin() + (k as f64 - 0.7) * (omega * x).cos()))
+            .sum::<f64>();
+
+        for x in [-1.3, 0.0, 0.4, 2.1] {
+            let estimate: f64 = rule.terms().iter().map(|&(coeff, shift)| coeff * f(x + shift)).sum();
+            assert!((estimate - derivative(x)).abs() < 1E-10, "{estimate} != {}", derivative(x));
+        }
+    }
+
+    #[test]
+    fn rules_match_the_spectrum_of_the_generator() {
+        check(Generator::Rotation, false, 1.0, &[1.0]);
+        check(Generator::Phase, false, 1.0, &[1.0]);
+        check(Generator::Rotation, true, 1.0, &[0.5, 1.0]);
+        check(Generator::Phase, true, 1.0, &[1.0]);
+        check(Generator::Rotation, false, -1.5, &[1.5]);
+        check(Generator::Rotation, true, 0.5, &[0.25, 0.5]);
+        assert!(Generator::Phase.shift_rule(false, 0.0).terms().is_empty());
+    }
+
+    #[test]
+    fn differentiates_rotations() {
+        check_gradient(&circuit(|b, [a, c], t| { b.rx(t, c).cp(t, a, c).ry(t, c); }), 0.3);
+        check_gradient(&circuit(|b, [a, c], t| { b.u(0.2, t, t, c).cx(a, c).u(t, 0.4, 0.1, c); }), 1.1);
+    }
+
+    #[test]
+    fn differentiates_expressions() {
+        let circ = circuit(|b, [a, c], t| {
+            let double = b.expr(2.0 * t).unwrap();
+            let sine = b.expr(Expr::from(t).sin()).unwrap();
+            b.rx(double, c).cp(sine, a, c);
+        });
+        check_gradient(&circ, 0.6);
+    }
+
+    #[test]
+    fn differentiates_controlled_rotations() {
+        for label in ["rx", "ry", "rz", "p"] {
+            let circ = circuit(|b, [a, c], t| {
+                let op = OpKind::from_label(label).unwrap();
+                b.instructions_mut().push_controlled(op, &[a], &[true], &[c], &[t.into()]);
+            });
+            check_gradient(&circ, 0.7);
+        }
+
+        let circ = circuit(|b, [a, c], t| {
+            b.instructions_mut().push_controlled(OpKind::U, &[a], &[false], &[c], &[t.into(), Parameter::from(0.3), t.into()]);
+        });
+        check_gradient(&circ, -0.5);
+    }
+
+    #[test]
+    fn differentiates_powers_and_inverses() {
+        let circ = circuit(|b, [a, c], t| {
+            b.instructions_mut().push_modified(OpKind::RY, &[c], &[], &[t.into()], Modifier::Power(Parameter::from(0.5))).unwrap();
+            b.cx(a, c);
+            b.instructions_mut().push_modified(OpKind::RX, &[c], &[], &[t.into()], Modifier::Inverse).unwrap();
+            b.instructions_mut().push_modified(OpKind::Phase, &[c], &[], &[t.into()], Modifier::Power(Parameter::from(-1.5))).unwrap();
+            b.instructions_mut().push_with_modifiers(OpKind::RX, &[c], &[], &[t.into()], &[Modifier::Inverse, Modifier::Power(Parameter::from(-1.5))]).unwrap();
+            b.instructions_mut().push_controlled_modified(OpKind::RZ, &[a], &[true], &[c], &[t.into()], &[Modifier::Power(Parameter::from(2.0))]).unwrap();
+        });
+        check_gradient(&circ, 0.8);
+    }
+
+    #[test]
+    fn rejects_powers_of_generic_gates() {
+        let circ = circuit(|b, [_, c], t| {
+            b.instructions_mut().push_modified(OpKind::U, &[c], &[], &[t.into(), Parameter::from(0.0), Parameter::from(0.0)], Modifier::Power(Parameter::from(0.5))).unwrap();
+        });
+        assert_eq!(ParameterShift::new(&circ, &[0.1]).unwrap_err(), GradientError::Power);
+    }
+}
@@ -1,9 +1,18 @@
+use std::mem;
+use std::iter::FusedIterator;
+use std::ops::{Bound, Range, RangeBounds};
+use std::sync::OnceLock;
+
 use bitflags::bitflags;
 
 use crate::bitset::BitSet;
+use crate::circuit::CircuitError;
+use crate::definition::GateRef;
 use crate::genericity::Id;
+use crate::linalg::DMatrix;
+use crate::transpiler::Gate;
 
-use super::operation::OpKind;
+use super::operation::{self, OpKind};
 use super::storage;
 use super::parameter::Parameter;
 use super::symbol::{Qubit, Bit};
@@ -13,23 +22,39 @@ bitflags! {
     /// instruction.
     #[repr(transparent)]
     pub(crate) struct InstrFlags: u16 {
-        /// Wether or not the instruction has a modifier.
+        /// Wether or not the instruction has a single modifier.
         const HAS_MODIFIER = 1;
+        /// Wether or not the instruction has several modifiers, preceded by their number.
+        const HAS_MODIFIERS = 2;
     }
 }
 
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, Debug)]
 pub struct Compute<'id, T> {
     pub bits: &'id [Bit<'id>],
     pub func: fn(BitSet) -> T,
 }
 
+impl<T> PartialEq for Compute<'_, T> {
+    fn eq(&self, rhs: &Self) -> bool {
+        // Two computes are equal when they read the same bits through the same function pointer.
+        self.bits == rhs.bits && self.func as usize == rhs.func as usize
+    }
+}
+
+impl<T> Eq for Compute<'_, T> {}
+
 impl<'id, T> Compute<'id, T> {
+    /// Writes a compute to the destination from it's parts.
+    pub(crate) fn write_parts(dest: &mut Vec<u32>, bits: &[Bit<'id>], func: fn(BitSet) -> T) {
+        storage::write(dest, bits.len() as u32);
+        storage::write_slice(dest, bits);
+        storage::write(dest, func);
+    }
+
     /// Writes the compute to the destination.
     pub(crate) fn write(&self, dest: &mut Vec<u32>) {
-        storage::write(dest, self.bits.len() as u32);
-        storage::write_slice(dest, self.bits);
-        storage::write(dest, self.func);
+        Self::write_parts(dest, self.bits, self.func);
     }
 
     /// Reads the compute from the destination.
@@ -44,6 +69,161 @@ impl<'id, T> Compute<'id, T> {
     }
 }
 
+/// The quantum controls of an instruction, which is only applied on the subspace where each
+/// control qubit is in it's given state.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Controls<'id> {
+    /// The control qubits.
+    pub qubits: &'id [Qubit<'id>],
+    /// The states of the control qubits, packed in words: the `n`th control qubit must be in
+    /// the state given by the `n % 32`th bit of the `n / 32`th word.
+    pub states: &'id [u32],
+}
+
+impl<'id> Controls<'id> {
+    /// Returns the state the `n`th control qubit must be in.
+    ///
+    /// Panics if there are less than `n + 1` control qubits.
+    pub fn state(&self, n: usize) -> bool {
+        assert!(n < self.qubits.len(), "control index out of range");
+        (self.states[n / 32] >> (n % 32)) & 1 == 1
+    }
+
+    /// Returns the matrix of the controlled operation, given the matrix of the operation. The
+    /// control qubits are the most significant ones, followed by the targets of the operation.
+    pub fn matrix(&self, target: &DMatrix) -> DMatrix {
+        let dim = target.dim();
+        let active = (0..self.qubits.len()).fold(0, |acc, n| (acc << 1) | usize::from(self.state(n)));
+
+        let mut res = DMatrix::eye(dim << self.qubits.len());
+        (0..dim).for_each(|r| (0..dim).for_each(|c| res[(active * dim + r, active * dim + c)] = target[(r, c)]));
+        res
+    }
+
+    /// Packs the states of the control qubits in words, see [`Controls::states`].
+    pub(crate) fn pack(states: &[bool]) -> Vec<u32> {
+        states.chunks(32)
+            .map(|chunk| chunk.iter().enumerate().fold(0, |acc, (i, &state)| acc | (u32::from(state) << i)))
+            .collect()
+    }
+
+    /// Writes controls to the destination from their parts, the states being packed.
+    pub(crate) fn write_parts(dest: &mut Vec<u32>, qubits: &[Qubit<'id>], states: &[u32]) {
+        storage::write(dest, qubits.len() as u32);
+        storage::write_slice(dest, qubits);
+        storage::write_slice(dest, states);
+    }
+
+    /// Writes the controls to the destination.
+    pub(crate) fn write(&self, dest: &mut Vec<u32>) {
+        Self::write_parts(dest, self.qubits, self.states);
+    }
+
+    /// Reads the controls from the destination.
+    pub(crate) fn read(src: &mut &'id [u32]) -> Self {
+        let len: u32 = storage::read(src);
+        Self {
+            qubits: storage::read_slice(src, len),
+            states: storage::read_slice(src, len.div_ceil(32)),
+        }
+    }
+}
+
+/// The bodies of a control flow operation, each being a sequence of instructions.
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub struct Blocks<'id> {
+    len: u32,
+    /// The bodies, each preceded by it's length.
+    data: &'id [u32],
+}
+
+impl<'id> Blocks<'id> {
+    /// Returns the number of bodies.
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns an iterator over the instructions of the `n`th body, if there is one.
+    pub fn get(&self, n: usize) -> Option<InstrIter<'id>> {
+        self.bodies().nth(n).map(InstrIter::new)
+    }
+
+    /// Returns an iterator over the instructions of each body, in order.
+    pub fn iter(&self) -> impl Iterator<Item = InstrIter<'id>> {
+        self.bodies().map(InstrIter::new)
+    }
+
+    /// Returns the compact representation of each body.
+    pub(crate) fn bodies(&self) -> impl Iterator<Item = &'id [u32]> {
+        let mut src = self.data;
+        (0..self.len).map(move |_| {
+            let len: u32 = storage::read(&mut src);
+            let (body, rest) = src.split_at(len as usize);
+            src = rest;
+            body
+        })
+    }
+
+    /// Writes bodies to the destination from their compact representations.
+    pub(crate) fn write_parts(dest: &mut Vec<u32>, bodies: &[&[u32]]) {
+        storage::write(dest, bodies.len() as u32);
+        for body in bodies {
+            storage::write(dest, body.len() as u32);
+            dest.extend(*body);
+        }
+    }
+
+    /// Writes the bodies to the destination.
+    pub(crate) fn write(&self, dest: &mut Vec<u32>) {
+        storage::write(dest, self.len);
+        dest.extend(self.data);
+    }
+
+    /// Reads the bodies from the source.
+    pub(crate) fn read(src: &mut &'id [u32]) -> Self {
+        let len: u32 = storage::read(src);
+
+        let mut rest = *src;
+        for _ in 0..len {
+            let body: u32 = storage::read(&mut rest);
+            rest = &rest[body as usize..];
+        }
+
+        let (data, rest) = src.split_at(src.len() - rest.len());
+        *src = rest;
+        Self { len, data }
+    }
+}
+
+/// The payload of a [`OpKind::For`] loop.
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub struct ForLoop<'id> {
+    /// The number of iterations.
+    pub count: u32,
+    /// The body of the loop.
+    pub body: Blocks<'id>,
+}
+
+impl<'id> ForLoop<'id> {
+    /// Writes the loop to the destination.
+    pub(crate) fn write(&self, dest: &mut Vec<u32>) {
+        storage::write(dest, self.count);
+        self.body.write(dest);
+    }
+
+    /// Reads the loop from the source.
+    pub(crate) fn read(src: &mut &'id [u32]) -> Self {
+        Self {
+            count: storage::read(src),
+            body: Blocks::read(src),
+        }
+    }
+}
+
 macro_rules! modifiers {
     {
         $(
@@ -65,6 +245,22 @@ macro_rules! modifiers {
         }
 
         impl<'id> Modifier<'id> {
+            /// Returns `true` if the modifier depends on classical bits or repeats the
+            /// instruction, that is for every modifier but [`Modifier::Controlled`],
+            /// [`Modifier::Inverse`] and [`Modifier::Power`].
+            pub fn is_classical(&self) -> bool {
+                !matches!(self, Self::Controlled(_) | Self::Inverse | Self::Power(_))
+            }
+
+            /// Returns the integer identifying the kind of the modifier in it's compact
+            /// representation.
+            #[allow(unused_variables)]
+            pub(crate) fn discriminant(&self) -> u32 {
+                match self {
+                    $(Self::$name $(($inner))? => $int,)*
+                }
+            }
+
             /// Writes the modifier to the destination.
             pub(crate) fn write(&self, dest: &mut Vec<u32>) {
                 match self {
@@ -94,7 +290,7 @@ modifiers! {
     /// Only perform the instruction if the bit is `true`.
     IfBit = 0 {
         inner: Bit<'id>,
-        write: |dest| storage::write(dest, inner),
+        write: |dest| storage::write(dest, *inner),
         read: storage::read,
     },
     /// Only perform the instruction if the result of the compute is `true`.
@@ -106,7 +302,7 @@ modifiers! {
     /// Perform the instruction while the bit is `true`.
     WhileBit = 2 {
         inner: Bit<'id>,
-        write: |dest| storage::write(dest, inner),
+        write: |dest| storage::write(dest, *inner),
         read: storage::read,
     },
     /// Perform the instruction while the result of the compute is `true`.
@@ -118,7 +314,7 @@ modifiers! {
     /// Perform the instruction as many times as the provided integer.
     ForConst = 4 {
         inner: u32,
-        write: |dest| storage::write(dest, inner),
+        write: |dest| storage::write(dest, *inner),
         read: storage::read,
     },
     /// Perform the instruction as many times as the result of the compute.
@@ -127,8 +323,40 @@ modifiers! {
         write: |dest| inner.write(dest),
         read: Compute::read,
     },
+    /// Only perform the unitary instruction on the subspace where the control qubits are in
+    /// their given states.
+    Controlled = 6 {
+        inner: Controls<'id>,
+        write: |dest| inner.write(dest),
+        read: Controls::read,
+    },
+    /// Perform the adjoint of the unitary instruction.
+    Inverse = 7,
+    /// Perform the unitary instruction to the given real power, see [`OpKind::power`].
+    Power = 8 {
+        inner: Parameter<'id>,
+        write: |dest| storage::write(dest, *inner),
+        read: storage::read,
+    },
+}
+
+/// Writes a [`Modifier::Controlled`] to the destination from the parts of it's controls, the
+/// states being packed.
+fn write_controlled<'id>(dest: &mut Vec<u32>, qubits: &[Qubit<'id>], states: &[u32]) {
+    // The discriminant of `Modifier::Controlled`.
+    storage::write(dest, 6u32);
+    Controls::write_parts(dest, qubits, states);
 }
 
+/// An instruction, as read from an [`InstrVec`].
+///
+/// The modifiers of an instruction are ordered from the innermost to the outermost: the first
+/// one applies to the operation, and each of the following ones to the result of the previous
+/// ones. The quantum modifiers, see [`Modifier::is_classical`], come before the classical ones.
+///
+/// [`Modifier::Inverse`] and [`Modifier::Power`] apply in order to the operation, while the
+/// controls of every [`Modifier::Controlled`] are merged, see [`Instr::controls`]: the inverse
+/// and powers of a controlled operation are the controlled inverse and powers of the operation.
 #[derive(Clone, PartialEq, Eq, Default, Debug)]
 pub struct Instr<'id> {
     /// The base operation type.
@@ -139,39 +367,14 @@ pub struct Instr<'id> {
     pub bits: &'id [Bit<'id>],
     /// The parameters this operation depends on.
     pub parameters: &'id [Parameter<'id>],
-    /// This operation's modifier, if there is one.
-    pub modifier: Option<Modifier<'id>>,
+    /// This operation's modifiers, from the innermost to the outermost.
+    pub modifiers: Vec<Modifier<'id>>,
 }
 
 impl<'id> Instr<'id> {
     /// Writes the instruction to the destination.
     pub(crate) fn write(&self, dest: &mut Vec<u32>) {
-        let flags = {
-            let mut res = InstrFlags::empty();
-
-            if self.has_modifier() {
-                res |= InstrFlags::HAS_MODIFIER;
-            }
-
-            res
-        };
-
-        self.op.write(dest, flags);
-
-        macro_rules! write_slices {
-            ( $($name: ident),* ) => {
-                $(
-                    if self.op.$name().is_variadic() {
-                        storage::write(dest, self.$name.len() as u32);
-                    }
-                    storage::write_slice(dest, self.$name);
-                )*
-            }
-        }
-
-        write_slices!(qubits, bits, parameters);
-
-        self.modifier.as_ref().map(|modifier| modifier.write(dest));
+        write_parts(dest, &self.op, self.qubits, self.bits, self.parameters, &self.modifiers);
     }
 
     /// Reads the instruction from the source.
@@ -191,11 +394,113 @@ impl<'id> Instr<'id> {
 
         read_slices!(qubits, bits, parameters);
 
-        self.modifier = flags.contains(InstrFlags::HAS_MODIFIER).then(|| Modifier::read(src));
+        let len = if flags.contains(InstrFlags::HAS_MODIFIERS) {
+            storage::read(src)
+        } else {
+            u32::from(flags.contains(InstrFlags::HAS_MODIFIER))
+        };
+
+        self.modifiers.clear();
+        self.modifiers.extend((0..len).map(|_| Modifier::read(src)));
     }
 
     pub fn has_modifier(&self) -> bool {
-        self.modifier.is_some()
+        !self.modifiers.is_empty()
+    }
+
+    /// Returns `true` if the instruction has a classical modifier, see [`Modifier::is_classical`].
+    pub fn has_classical_modifier(&self) -> bool {
+        self.modifiers.iter().any(Modifier::is_classical)
+    }
+
+    /// Returns `true` if the instruction has a quantum modifier, see [`Modifier::is_classical`].
+    pub fn has_quantum_modifier(&self) -> bool {
+        self.modifiers.iter().any(|modifier| !modifier.is_classical())
+    }
+
+    /// Returns the control qubits of every [`Modifier::Controlled`] of the instruction, along
+    /// with the states they must be in, the controls of the outermost modifiers coming first.
+    pub fn controls(&self) -> (Vec<Qubit<'id>>, Vec<bool>) {
+        self.modifiers.iter().rev()
+            .filter_map(|modifier| match modifier {
+                Modifier::Controlled(controls) => Some(controls),
+                _ => None,
+            })
+            .flat_map(|controls| controls.qubits.iter().enumerate().map(|(n, &qubit)| (qubit, controls.state(n))))
+            .unzip()
+    }
+
+    /// Returns the exponents of the [`Modifier::Power`]s of the instruction, in order.
+    pub fn exponents(&self) -> impl Iterator<Item = Parameter<'id>> + '_ {
+        self.modifiers.iter().filter_map(|modifier| match modifier {
+            Modifier::Power(exponent) => Some(*exponent),
+            _ => None,
+        })
+    }
+
+    /// Returns the gates implementing the instruction's operation under it's inverse and power
+    /// modifiers, given the values of it's parameters and of it's [`Instr::exponents`]. The
+    /// gates act on the indices of the qubits of the instruction, controls are not included.
+    ///
+    /// Returns `None` if the operation is not unitary, or if a power applies to several gates,
+    /// as the power of a product of gates can only be computed from it's matrix.
+    ///
+    /// Panics if the number of parameters or exponents is wrong.
+    pub fn gates(&self, parameters: &[f64], exponents: &[f64]) -> Option<Vec<Gate<'id>>> {
+        self.fold(parameters, exponents)?.ok()
+    }
+
+    /// Returns the matrix of the instruction's unitary operation under it's quantum modifiers,
+    /// given the values of it's parameters and of it's [`Instr::exponents`], or `None` if the
+    /// operation is not unitary. The control qubits, as given by [`Instr::controls`], are the
+    /// most significant ones, followed by the qubits of the instruction.
+    ///
+    /// Panics if the number of parameters or exponents is wrong.
+    pub fn matrix(&self, parameters: &[f64], exponents: &[f64]) -> Option<DMatrix> {
+        Some(self.modifiers.iter().fold(self.target_matrix(parameters, exponents)?, |matrix, modifier| match modifier {
+            Modifier::Controlled(controls) => controls.matrix(&matrix),
+            _ => matrix,
+        }))
+    }
+
+    /// Returns the matrix of the instruction's unitary operation under it's inverse and power
+    /// modifiers, acting on the qubits of the instruction only, or `None` if the operation
+    /// is not unitary. Controls are not included, see [`Instr::matrix`].
+    ///
+    /// Panics if the number of parameters or exponents is wrong.
+    pub fn target_matrix(&self, parameters: &[f64], exponents: &[f64]) -> Option<DMatrix> {
+        match self.fold(parameters, exponents)? {
+            Ok(gates) => operation::gates_matrix(self.qubits.len(), &gates),
+            Err(matrix) => Some(matrix),
+        }
+    }
+
+    /// Applies the inverse and power modifiers to the operation, as gates for as long as
+    /// possible, then falling back to the matrix once a power applies to several gates.
+    fn fold(&self, parameters: &[f64], exponents: &[f64]) -> Option<Result<Vec<Gate<'id>>, DMatrix>> {
+        assert_eq!(exponents.len(), self.exponents().count(), "wrong number of exponents");
+
+        let mut res: Result<Vec<Gate<'id>>, DMatrix> = Ok(vec![(self.op.clone(), (0..self.qubits.len()).collect(), parameters.to_vec())]);
+        let mut exponents = exponents.iter();
+
+        for modifier in &self.modifiers {
+            res = match (modifier, res) {
+                (Modifier::Inverse, Ok(gates)) => Ok(gates.into_iter().rev()
+                    .map(|(op, qubits, parameters)| op.adjoint(&parameters).map(|(op, parameters)| (op, qubits, parameters)))
+                    .collect::<Option<_>>()?),
+                (Modifier::Inverse, Err(matrix)) => Err(matrix.adjoint()),
+                (Modifier::Power(_), Ok(mut gates)) if gates.len() == 1 => {
+                    let (op, qubits, parameters) = gates.pop().unwrap();
+                    let power = op.power(&parameters, *exponents.next().unwrap())?;
+                    Ok(power.into_iter().map(|(op, indices, parameters)| (op, indices.iter().map(|&i| qubits[i]).collect(), parameters)).collect())
+                }
+                (Modifier::Power(_), Ok(gates)) => Err(operation::gates_matrix(self.qubits.len(), &gates)?.pow_unitary(*exponents.next().unwrap()).ok()?),
+                (Modifier::Power(_), Err(matrix)) => Err(matrix.pow_unitary(*exponents.next().unwrap()).ok()?),
+                (_, res) => res,
+            };
+        }
+
+        Some(res)
     }
 
     pub fn is_concrete(&self) -> bool {
@@ -203,55 +508,922 @@ impl<'id> Instr<'id> {
     }
 }
 
+/// Writes an instruction to the destination from it's parts, without requiring
+/// them to be borrowed for `'id`.
+pub(crate) fn write_parts<'id>(
+    dest: &mut Vec<u32>,
+    op: &OpKind<'id>,
+    qubits: &[Qubit<'id>],
+    bits: &[Bit<'id>],
+    parameters: &[Parameter<'id>],
+    modifiers: &[Modifier<'id>],
+) {
+    write_head(dest, op, None, qubits, bits, parameters, modifiers.len());
+    modifiers.iter().for_each(|modifier| modifier.write(dest));
+}
+
+/// Writes the instruction to the destination, with an additional [`Modifier::Controlled`] after
+/// it's quantum modifiers.
+pub(crate) fn write_with_controls<'id>(dest: &mut Vec<u32>, instr: &Instr<'id>, controls: &[Qubit<'id>], states: &[bool]) {
+    let quantum = instr.modifiers.iter().take_while(|modifier| !modifier.is_classical()).count();
+    write_head(dest, &instr.op, None, instr.qubits, instr.bits, instr.parameters, instr.modifiers.len() + 1);
+    instr.modifiers[..quantum].iter().for_each(|modifier| modifier.write(dest));
+    write_controlled(dest, controls, &Controls::pack(states));
+    instr.modifiers[quantum..].iter().for_each(|modifier| modifier.write(dest));
+}
+
+/// Writes a control flow instruction to the destination from it's parts, the given bodies
+/// replacing those of the operation, see [`OpKind::blocks`].
+pub(crate) fn write_control_flow<'id>(
+    dest: &mut Vec<u32>,
+    op: &OpKind<'id>,
+    bodies: &[&[u32]],
+    bits: &[Bit<'id>],
+    modifiers: &[Modifier<'id>],
+) {
+    write_head(dest, op, Some(&|dest| op.write_blocks(dest, bodies)), &[], bits, &[], modifiers.len());
+    modifiers.iter().for_each(|modifier| modifier.write(dest));
+}
+
+/// Writes the payload of an operation to the destination.
+type Payload<'a> = &'a dyn Fn(&mut Vec<u32>);
+
+/// Writes the operation, qubits, bits and parameters of an instruction to the destination,
+/// along with the number of modifiers, which are written afterwards. The payload of the
+/// operation may be replaced by the one written by `payload`.
+fn write_head<'id>(
+    dest: &mut Vec<u32>,
+    op: &OpKind<'id>,
+    payload: Option<Payload<'_>>,
+    qubits: &[Qubit<'id>],
+    bits: &[Bit<'id>],
+    parameters: &[Parameter<'id>],
+    num_modifiers: usize,
+) {
+    let flags = match num_modifiers {
+        0 => InstrFlags::empty(),
+        1 => InstrFlags::HAS_MODIFIER,
+        _ => InstrFlags::HAS_MODIFIERS,
+    };
+
+    match payload {
+        Some(payload) => {
+            storage::write(dest, (flags, op.discriminant()));
+            payload(dest);
+        }
+        None => op.write(dest, flags),
+    }
+
+    macro_rules! write_slices {
+        ( $($name: ident),* ) => {
+            $(
+                match op.$name().get() {
+                    Some(n) => assert_eq!(n as usize, $name.len(), concat!("wrong number of ", stringify!($name), " for operation")),
+                    None => storage::write(dest, $name.len() as u32),
+                }
+                storage::write_slice(dest, $name);
+            )*
+        }
+    }
+
+    write_slices!(qubits, bits, parameters);
+
+    if num_modifiers > 1 {
+        storage::write(dest, num_modifiers as u32);
+    }
+}
+
+/// Checks that the modifiers can be applied to the operation on the qubits, see [`Instr`].
+fn check_modifiers<'id>(op: &OpKind<'id>, qubits: &[Qubit<'id>], modifiers: &[Modifier<'id>]) -> Result<(), CircuitError> {
+    let quantum = modifiers.iter().take_while(|modifier| !modifier.is_classical()).count();
+    assert!(modifiers[quantum..].iter().all(Modifier::is_classical), "quantum modifiers must come before classical ones");
+    assert!(quantum == 0 || op.is_unitary(), "only unitary operations can have quantum modifiers");
+
+    let mut used = qubits.to_vec();
+    for modifier in modifiers {
+        if let Modifier::Controlled(controls) = modifier {
+            assert!(controls.qubits.iter().all(|control| !used.contains(control)), "control qubit is also a target");
+            used.extend(controls.qubits);
+        }
+    }
+
+    // The body of a gate can only be repeated, see `CircuitBuilder::inline`.
+    let repeated = |modifier: &Modifier<'id>| match modifier {
+        Modifier::Power(exponent) => call_exponent(*exponent).is_some(),
+        _ => true,
+    };
+    match op {
+        OpKind::Call(_) if !modifiers.iter().all(repeated) => Err(CircuitError::CallPower),
+        _ => Ok(()),
+    }
+}
+
+/// Returns the number of times the body of a call is repeated for the exponent of a
+/// [`Modifier::Power`], negative for it's inverse, or `None` if the exponent is not a constant
+/// integer of at most [`u32::MAX`] in magnitude.
+pub(crate) fn call_exponent(exponent: Parameter<'_>) -> Option<i64> {
+    // `u32::MAX` rounds up to $ 2^{32} $, so the bound is strict.
+    exponent.as_value()
+        .filter(|value| value.fract() == 0.0 && value.abs() < u32::MAX as f32)
+        .map(|value| value as i64)
+}
+
+/// An iterator over the instructions of a compact representation, decoding each of them.
+///
+/// Decoding is cheap, as the qubits, bits and parameters of the instructions borrow the
+/// representation: only their modifiers, if any, are allocated.
 #[derive(Clone, Debug)]
 pub struct InstrIter<'id> {
-    instr: Instr<'id>,
     src: &'id [u32],
 }
 
 impl<'id> InstrIter<'id> {
     /// Creates a new instruction iterator from the given source.
     pub(crate) fn new(src: &'id [u32]) -> Self {
-        Self { instr: Instr::default(), src }
+        Self { src }
     }
-    
-    pub fn next(&mut self) -> Option<&Instr<'id>> {
-        // Implementing `Iterator` is impossible because of the struct's internal buffer `self.instr`.
+}
+
+impl<'id> Iterator for InstrIter<'id> {
+    type Item = Instr<'id>;
+
+    fn next(&mut self) -> Option<Self::Item> {
         (!self.src.is_empty()).then(|| {
-            self.instr.read(&mut self.src);
-            &self.instr
+            let mut instr = Instr::default();
+            instr.read(&mut self.src);
+            instr
         })
     }
 }
 
+impl FusedIterator for InstrIter<'_> {}
+
+/// Rewrites the instructions of the source to the destination with `rewrite`, recursing into
+/// the bodies of control flow operations, which are kept along with their modifiers.
+pub(crate) fn rewrite<'id, E, F>(src: &'id [u32], dest: &mut Vec<u32>, rewrite: &mut F) -> Result<(), E>
+where
+    F: FnMut(&Instr<'id>, &mut Vec<u32>) -> Result<(), E>
+{
+    for instr in InstrIter::new(src) {
+        let Some(blocks) = instr.op.blocks() else {
+            rewrite(&instr, dest)?;
+            continue;
+        };
+
+        let bodies = blocks.bodies()
+            .map(|body| {
+                let mut res = Vec::with_capacity(body.len());
+                self::rewrite(body, &mut res, rewrite).map(|_| res)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let bodies: Vec<_> = bodies.iter().map(Vec::as_slice).collect();
+        write_control_flow(dest, &instr.op, &bodies, instr.bits, &instr.modifiers);
+    }
+
+    Ok(())
+}
+
+/// Replaces the symbols referenced by instructions, see [`remap`]. Symbols are kept by default.
+pub(crate) trait Remap<'id> {
+    type Error;
+
+    fn qubit(&self, qubit: Qubit<'id>) -> Qubit<'id> {
+        qubit
+    }
+
+    fn bit(&self, bit: Bit<'id>) -> Bit<'id> {
+        bit
+    }
+
+    fn parameter(&mut self, parameter: Parameter<'id>) -> Result<Parameter<'id>, Self::Error> {
+        Ok(parameter)
+    }
+
+    fn gate(&self, gate: GateRef<'id>) -> GateRef<'id> {
+        gate
+    }
+}
+
+/// Writes the instructions of the source to the destination, with every qubit, bit, parameter
+/// and gate they reference replaced by `remap`, including those of their payloads, modifiers
+/// and bodies.
+pub(crate) fn remap<'id, R: Remap<'id>>(src: &'id [u32], dest: &mut Vec<u32>, remap: &mut R) -> Result<(), R::Error> {
+    /// Writes a modifier depending on a compute to the destination, with the bits of the
+    /// compute replaced.
+    fn write_compute<'id, T, R: Remap<'id>>(dest: &mut Vec<u32>, modifier: &Modifier<'id>, compute: &Compute<'id, T>, remap: &R) {
+        let bits: Vec<_> = compute.bits.iter().map(|&bit| remap.bit(bit)).collect();
+        storage::write(dest, modifier.discriminant());
+        Compute::write_parts(dest, &bits, compute.func);
+    }
+
+    for instr in InstrIter::new(src) {
+        let bodies = match instr.op.blocks() {
+            Some(blocks) => blocks.bodies()
+                .map(|body| {
+                    let mut res = Vec::with_capacity(body.len());
+                    self::remap(body, &mut res, remap).map(|_| res)
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            None => Vec::new(),
+        };
+        let bodies: Vec<_> = bodies.iter().map(Vec::as_slice).collect();
+
+        let qubits: Vec<_> = instr.qubits.iter().map(|&qubit| remap.qubit(qubit)).collect();
+        let bits: Vec<_> = instr.bits.iter().map(|&bit| remap.bit(bit)).collect();
+        let parameters = instr.parameters.iter().map(|&param| remap.parameter(param)).collect::<Result<Vec<_>, _>>()?;
+
+        let (op, computed) = match &instr.op {
+            OpKind::Call(gate) => (OpKind::Call(remap.gate(*gate)), Vec::new()),
+            OpKind::Compute(compute) => (instr.op.clone(), compute.bits.iter().map(|&bit| remap.bit(bit)).collect()),
+            op => (op.clone(), Vec::new()),
+        };
+        let payload = |dest: &mut Vec<u32>| match &op {
+            OpKind::Compute(compute) => Compute::write_parts(dest, &computed, compute.func),
+            op => op.write_blocks(dest, &bodies),
+        };
+        let payload = (op.blocks().is_some() || matches!(op, OpKind::Compute(_))).then_some(&payload as Payload<'_>);
+        write_head(dest, &op, payload, &qubits, &bits, &parameters, instr.modifiers.len());
+
+        for modifier in &instr.modifiers {
+            match modifier {
+                Modifier::IfBit(bit) => Modifier::IfBit(remap.bit(*bit)).write(dest),
+                Modifier::WhileBit(bit) => Modifier::WhileBit(remap.bit(*bit)).write(dest),
+                Modifier::IfCompute(compute) | Modifier::WhileCompute(compute) => write_compute(dest, modifier, compute, remap),
+                Modifier::ForCompute(compute) => write_compute(dest, modifier, compute, remap),
+                Modifier::Controlled(controls) => {
+                    let qubits: Vec<_> = controls.qubits.iter().map(|&qubit| remap.qubit(qubit)).collect();
+                    write_controlled(dest, &qubits, controls.states);
+                }
+                Modifier::Power(exponent) => Modifier::Power(remap.parameter(*exponent)?).write(dest),
+                modifier => modifier.write(dest),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Calls `visit` on every instruction of the source, recursing into the bodies of control flow
+/// operations after visiting them.
+pub(crate) fn visit<'id, E, F>(src: &'id [u32], visit: &mut F) -> Result<(), E>
+where
+    F: FnMut(&Instr<'id>) -> Result<(), E>
+{
+    for instr in InstrIter::new(src) {
+        visit(&instr)?;
+
+        if let Some(blocks) = instr.op.blocks() {
+            blocks.bodies().try_for_each(|body| self::visit(body, visit))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// An iterator over the instructions of an indexed [`InstrVec`], from the last one to the
+/// first one, see [`InstrVec::iter_rev`].
+#[derive(Clone, Debug)]
+pub struct InstrRevIter<'id> {
+    src: &'id [u32],
+    offsets: &'id [usize],
+}
+
+impl<'id> Iterator for InstrRevIter<'id> {
+    type Item = Instr<'id>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (&offset, offsets) = self.offsets.split_last()?;
+        self.offsets = offsets;
+
+        let mut instr = Instr::default();
+        instr.read(&mut &self.src[offset..]);
+        Some(instr)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.offsets.len(), Some(self.offsets.len()))
+    }
+}
+
+impl ExactSizeIterator for InstrRevIter<'_> {}
+
+impl FusedIterator for InstrRevIter<'_> {}
+
+/// Returns the offsets of the instructions of the source.
+fn read_offsets(src: &[u32]) -> Vec<usize> {
+    let mut instr = Instr::default();
+    let mut rest = src;
+    let mut res = Vec::new();
+
+    while !rest.is_empty() {
+        res.push(src.len() - rest.len());
+        instr.read(&mut rest);
+    }
+
+    res
+}
+
+/// Returns the bounds of the range of instructions, out of `len`.
+///
+/// Panics if the range is out of bounds.
+fn resolve(range: impl RangeBounds<usize>, len: usize) -> Range<usize> {
+    let start = match range.start_bound() {
+        Bound::Included(&start) => start,
+        Bound::Excluded(&start) => start + 1,
+        Bound::Unbounded => 0,
+    };
+    let end = match range.end_bound() {
+        Bound::Included(&end) => end + 1,
+        Bound::Excluded(&end) => end,
+        Bound::Unbounded => len,
+    };
+
+    assert!(start <= end && end <= len, "instruction range {start}..{end} out of bounds for length {len}");
+    start..end
+}
+
+/// A vector of instructions, in their compact representation.
+///
+/// As instructions have a variable length, they can only be read in order. The vector holds
+/// an index of the offsets of it's instructions, built the first time they are counted,
+/// accessed at random or read in reverse order, for these to take constant time after that,
+/// see [`InstrVec::build_index`].
+/// Instructions may be inserted, removed or replaced without re-encoding the others, see
+/// [`InstrVec::splice`].
 #[derive(Clone, Default, Debug)]
 pub struct InstrVec<'id> {
     _id: Id<'id>,
     data: Vec<u32>,
+    index: OnceLock<Vec<usize>>,
 }
 
 impl<'id> InstrVec<'id> {
     pub(crate) fn new(data: Vec<u32>) -> Self {
-        Self { _id: Id::default(), data }
+        Self { _id: Id::default(), data, index: OnceLock::new() }
     }
 
     pub(crate) fn take(self) -> Vec<u32> {
         self.data
     }
 
+    /// Records the offset of the instruction about to be written, if the vector is indexed.
+    fn start(&mut self) {
+        if let Some(index) = self.index.get_mut() {
+            index.push(self.data.len());
+        }
+    }
+
+    /// Builds the index of the offsets of the instructions, if the vector has none. It is
+    /// kept up to date as instructions are added to the vector.
+    pub fn build_index(&mut self) {
+        self.index();
+    }
+
+    /// Drops the index of the vector, if it has one, for it to be built again when needed.
+    pub fn drop_index(&mut self) {
+        self.index.take();
+    }
+
+    pub fn is_indexed(&self) -> bool {
+        self.index.get().is_some()
+    }
+
+    /// Returns the index of the vector, building it if the vector has none.
+    fn index(&self) -> &[usize] {
+        self.index.get_or_init(|| read_offsets(&self.data))
+    }
+
+    /// Returns the number of instructions in the vector, building the index if the vector
+    /// has none.
+    pub fn len(&self) -> usize {
+        self.index().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Returns the `n`th instruction, or `None` if it is out of bounds.
+    ///
+    /// The instruction borrows the vector, and has it's symbols branded by the borrow rather
+    /// than the circuit: to add it back to the vector, see [`InstrVec::copy`].
+    pub fn get(&self, n: usize) -> Option<Instr<'_>> {
+        let mut instr = Instr::default();
+        instr.read(&mut &self.data[*self.index().get(n)?..]);
+        Some(instr)
+    }
+
+    /// Returns an iterator over the instructions, from the last one to the first one.
+    pub fn iter_rev(&self) -> InstrRevIter<'_> {
+        InstrRevIter { src: &self.data, offsets: self.index() }
+    }
+
+    /// Returns a vector holding a copy of the `n`th instruction, or `None` if it is out of
+    /// bounds. Unlike the instruction returned by [`InstrVec::get`], the copy keeps the brand of
+    /// the circuit, for it to be added back to the vector with [`InstrVec::splice`] or
+    /// [`InstrVec::extend`].
+    pub fn copy(&self, n: usize) -> Option<InstrVec<'id>> {
+        (n < self.len()).then(|| self.slice(n..=n))
+    }
+
+    /// Returns an indexed vector holding the instructions in the range.
+    ///
+    /// Panics if the range is out of bounds.
+    pub fn slice(&self, range: impl RangeBounds<usize>) -> InstrVec<'id> {
+        let offsets = self.index();
+        let range = resolve(range, offsets.len());
+
+        let from = offsets.get(range.start).copied().unwrap_or(self.data.len());
+        let to = offsets.get(range.end).copied().unwrap_or(self.data.len());
+        Self {
+            _id: Id::default(),
+            data: self.data[from..to].to_vec(),
+            index: OnceLock::from(offsets[range].iter().map(|offset| offset - from).collect::<Vec<_>>()),
+        }
+    }
+
+    /// Replaces the instructions in the range by those of the given vector, copying their
+    /// compact representation as is. The index of the vector is kept up to date.
+    ///
+    /// Panics if the range is out of bounds.
+    pub fn splice(&mut self, range: impl RangeBounds<usize>, instructions: &InstrVec<'id>) {
+        let index = self.index();
+        let range = resolve(range, index.len());
+        let from = index.get(range.start).copied().unwrap_or(self.data.len());
+        let to = index.get(range.end).copied().unwrap_or(self.data.len());
+
+        let shift = |offset: &usize| offset - to + from + instructions.data.len();
+        let index: Vec<_> = index[..range.start].iter().copied()
+            .chain(instructions.index().iter().map(|offset| offset + from))
+            .chain(index[range.end..].iter().map(shift))
+            .collect();
+
+        self.data.splice(from..to, instructions.data.iter().copied());
+        self.index = OnceLock::from(index);
+    }
+
+    /// Inserts the instruction at position `n`, shifting the following ones.
+    ///
+    /// Panics if `n` is greater than the number of instructions.
+    pub fn insert(&mut self, n: usize, instruction: &Instr<'id>) {
+        let mut instructions = InstrVec::default();
+        instructions.append(instruction);
+        self.splice(n..n, &instructions);
+    }
+
+    /// Removes the `n`th instruction, shifting the following ones.
+    ///
+    /// Panics if `n` is out of bounds.
+    pub fn remove(&mut self, n: usize) {
+        self.splice(n..=n, &InstrVec::default());
+    }
+
+    /// Replaces the `n`th instruction by the given one.
+    ///
+    /// Panics if `n` is out of bounds.
+    pub fn replace(&mut self, n: usize, instruction: &Instr<'id>) {
+        let mut instructions = InstrVec::default();
+        instructions.append(instruction);
+        self.splice(n..=n, &instructions);
+    }
+
+    /// Keeps only the instructions for which `keep` returns `true`, in a single pass.
+    pub fn retain<F: FnMut(&Instr<'_>) -> bool>(&mut self, mut keep: F) {
+        let data = mem::take(&mut self.data);
+        let mut instr = Instr::default();
+        let mut src = data.as_slice();
+        let mut index = self.index.take().map(|_| Vec::new());
+
+        while !src.is_empty() {
+            let start = data.len() - src.len();
+            instr.read(&mut src);
+
+            if keep(&instr) {
+                if let Some(index) = &mut index {
+                    index.push(self.data.len());
+                }
+                self.data.extend(&data[start..data.len() - src.len()]);
+            }
+        }
+
+        if let Some(index) = index {
+            self.index = OnceLock::from(index);
+        }
+    }
+
     pub fn append(&mut self, instruction: &Instr<'id>) {
+        self.start();
         instruction.write(&mut self.data);
     }
 
+    /// Appends an instruction to the vector from it's parts.
+    /// 
+    /// Panics if the number of qubits, bits or parameters does not match
+    /// the arity of the operation.
+    pub fn push(&mut self, op: OpKind<'id>, qubits: &[Qubit<'id>], bits: &[Bit<'id>], parameters: &[Parameter<'id>]) {
+        self.start();
+        write_parts(&mut self.data, &op, qubits, bits, parameters, &[]);
+    }
+
+    /// Appends an instruction with a modifier to the vector from it's parts.
+    /// 
+    /// Panics if the number of qubits, bits or parameters does not match
+    /// the arity of the operation, or if a quantum modifier is applied to
+    /// an operation which is not unitary. Fails if a call is raised to a
+    /// power which is not a constant integer, see [`CircuitError::CallPower`].
+    pub fn push_modified(&mut self, op: OpKind<'id>, qubits: &[Qubit<'id>], bits: &[Bit<'id>], parameters: &[Parameter<'id>], modifier: Modifier<'id>) -> Result<(), CircuitError> {
+        self.push_with_modifiers(op, qubits, bits, parameters, &[modifier])
+    }
+
+    /// Appends an instruction with modifiers to the vector from it's parts, the modifiers
+    /// being ordered from the innermost to the outermost, see [`Instr`].
+    /// 
+    /// Panics if the number of qubits, bits or parameters does not match
+    /// the arity of the operation, if a quantum modifier comes after a
+    /// classical one or is applied to an operation which is not unitary,
+    /// or if a control qubit is used twice. Fails if a call is raised to a
+    /// power which is not a constant integer, see [`CircuitError::CallPower`].
+    pub fn push_with_modifiers(&mut self, op: OpKind<'id>, qubits: &[Qubit<'id>], bits: &[Bit<'id>], parameters: &[Parameter<'id>], modifiers: &[Modifier<'id>]) -> Result<(), CircuitError> {
+        check_modifiers(&op, qubits, modifiers)?;
+        self.start();
+        write_parts(&mut self.data, &op, qubits, bits, parameters, modifiers);
+        Ok(())
+    }
+
+    /// Appends a unitary instruction controlled by the given qubits to the vector, the `n`th
+    /// control qubit having to be in the state `states[n]` for the operation to be applied.
+    ///
+    /// Panics if the operation is not unitary, if the number of states is not the number of
+    /// control qubits, if a control qubit is also a target, or if the number of qubits or
+    /// parameters does not match the arity of the operation.
+    pub fn push_controlled(&mut self, op: OpKind<'id>, controls: &[Qubit<'id>], states: &[bool], qubits: &[Qubit<'id>], parameters: &[Parameter<'id>]) {
+        self.write_controlled(op, controls, states, qubits, parameters, &[]);
+    }
+
+    /// Appends a controlled unitary instruction to the vector as with
+    /// [`InstrVec::push_controlled`], the controls being followed by the given modifiers, from
+    /// the innermost to the outermost, see [`Instr`].
+    ///
+    /// Panics and fails in the same cases as [`InstrVec::push_controlled`] and
+    /// [`InstrVec::push_with_modifiers`].
+    pub fn push_controlled_modified(&mut self, op: OpKind<'id>, controls: &[Qubit<'id>], states: &[bool], qubits: &[Qubit<'id>], parameters: &[Parameter<'id>], modifiers: &[Modifier<'id>]) -> Result<(), CircuitError> {
+        check_modifiers(&op, &[qubits, controls].concat(), modifiers)?;
+        self.write_controlled(op, controls, states, qubits, parameters, modifiers);
+        Ok(())
+    }
+
+    fn write_controlled(&mut self, op: OpKind<'id>, controls: &[Qubit<'id>], states: &[bool], qubits: &[Qubit<'id>], parameters: &[Parameter<'id>], modifiers: &[Modifier<'id>]) {
+        assert!(op.is_unitary(), "only unitary operations can be controlled");
+        assert_eq!(controls.len(), states.len(), "wrong number of control states");
+        assert!(controls.iter().all(|control| !qubits.contains(control)), "control qubit is also a target");
+
+        self.start();
+        write_head(&mut self.data, &op, None, qubits, &[], parameters, 1 + modifiers.len());
+        write_controlled(&mut self.data, controls, &Controls::pack(states));
+        modifiers.iter().for_each(|modifier| modifier.write(&mut self.data));
+    }
+
+    /// Appends an if/else block to the vector, running `then` if the bit is `true` and
+    /// `otherwise` if it is `false`.
+    pub fn push_if_else(&mut self, bit: Bit<'id>, then: &InstrVec<'id>, otherwise: &InstrVec<'id>) {
+        self.start();
+        write_control_flow(&mut self.data, &OpKind::IfElse(Blocks::default()), &[&then.data, &otherwise.data], &[bit], &[]);
+    }
+
+    /// Appends a while loop to the vector, running the body while the bit is `true`.
+    pub fn push_while(&mut self, bit: Bit<'id>, body: &InstrVec<'id>) {
+        self.start();
+        write_control_flow(&mut self.data, &OpKind::While(Blocks::default()), &[&body.data], &[bit], &[]);
+    }
+
+    /// Appends a for loop to the vector, running the body `count` times.
+    pub fn push_for(&mut self, count: u32, body: &InstrVec<'id>) {
+        let op = OpKind::For(ForLoop { count, body: Blocks::default() });
+        self.start();
+        write_control_flow(&mut self.data, &op, &[&body.data], &[], &[]);
+    }
+
+    /// Appends a switch to the vector, running `cases[n]` when the value of the register, the
+    /// first bit being the least significant, is `n`, and `default` if there is no such case.
+    ///
+    /// Panics if the register has more than 32 bits.
+    pub fn push_switch(&mut self, register: &[Bit<'id>], cases: &[&InstrVec<'id>], default: &InstrVec<'id>) {
+        assert!(register.len() <= 32, "switch register has more than 32 bits");
+
+        let bodies: Vec<_> = cases.iter().chain([&default]).map(|body| body.data.as_slice()).collect();
+        self.start();
+        write_control_flow(&mut self.data, &OpKind::Switch(Blocks::default()), &bodies, register, &[]);
+    }
+
     pub fn extend(&mut self, instructions: &InstrVec<'id>) {
+        if let Some(index) = self.index.get_mut() {
+            index.extend(instructions.index().iter().map(|offset| offset + self.data.len()));
+        }
+
         self.data.extend(&instructions.data);
     }
 
     pub fn clear(&mut self) {
-        self.data.clear()
+        self.data.clear();
+        if let Some(index) = self.index.get_mut() {
+            index.clear();
+        }
     }
 
-    pub fn iter(&'id self) -> InstrIter<'id> {
+    /// Returns an iterator over the instructions, which borrow the vector, see
+    /// [`InstrVec::get`].
+    pub fn iter(&self) -> InstrIter<'_> {
         InstrIter::new(&self.data)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::circuit::QuantumCircuit;
+    use crate::linalg::DMatrix;
+    use crate::operation::OpKind;
+    use crate::parameter::Parameter;
+    use crate::simulator::DensityMatrixSimulator;
+    use crate::storage;
+    use crate::symbol::{Bit, Qubit};
+
+    use super::{read_offsets, write_parts, Instr, InstrFlags, InstrVec, Modifier};
+
+    /// Writes the instruction as before instructions could have several modifiers, with a
+    /// single flag telling if it is followed by a modifier.
+    fn write_single<'id>(dest: &mut Vec<u32>, op: &OpKind<'id>, qubits: &[Qubit<'id>], bits: &[Bit<'id>], parameters: &[Parameter<'id>], modifier: Option<&Modifier<'id>>) {
+        op.write(dest, if modifier.is_some() { InstrFlags::HAS_MODIFIER } else { InstrFlags::empty() });
+
+        macro_rules! write_slices {
+            ( $($name: ident),* ) => {
+                $(
+                    if op.$name().get().is_none() {
+                        storage::write(dest, $name.len() as u32);
+                    }
+                    storage::write_slice(dest, $name);
+                )*
+            }
+        }
+
+        write_slices!(qubits, bits, parameters);
+        if let Some(modifier) = modifier {
+            modifier.write(dest);
+        }
+    }
+
+    /// Returns the encoding of the modifiers, to compare modifiers read from a vector with the
+    /// ones it was written from.
+    fn encode(modifiers: &[Modifier<'_>]) -> Vec<u32> {
+        let mut res = Vec::new();
+        modifiers.iter().for_each(|modifier| modifier.write(&mut res));
+        res
+    }
+
+    #[test]
+    fn decodes_single_modifier_streams() {
+        QuantumCircuit::new(|b| {
+            let [a, c] = b.qubits()?;
+            let [bit] = b.bits()?;
+
+            let instrs = [
+                (OpKind::H, vec![a], vec![], vec![], None),
+                (OpKind::RZ, vec![c], vec![], vec![Parameter::from(0.5)], Some(Modifier::IfBit(bit))),
+                (OpKind::CX, vec![a, c], vec![], vec![], Some(Modifier::ForConst(3))),
+                (OpKind::S, vec![c], vec![], vec![], Some(Modifier::Inverse)),
+                (OpKind::Measure, vec![a], vec![bit], vec![], Some(Modifier::WhileBit(bit))),
+            ];
+
+            let mut old = Vec::new();
+            let mut new = Vec::new();
+            for (op, qubits, bits, parameters, modifier) in &instrs {
+                write_single(&mut old, op, qubits, bits, parameters, modifier.as_ref());
+                write_parts(&mut new, op, qubits, bits, parameters, modifier.as_slice());
+            }
+
+            // Instructions with at most one modifier are encoded as they used to be.
+            assert_eq!(old, new);
+
+            let vec = InstrVec::new(old);
+            let mut iter = vec.iter();
+            for (op, qubits, bits, parameters, modifier) in &instrs {
+                let instr = iter.next().unwrap();
+                assert_eq!(instr.op.label(), op.label());
+                assert!(instr.qubits.iter().map(|qubit| qubit.id()).eq(qubits.iter().map(|qubit| qubit.id())));
+                assert!(instr.bits.iter().map(|bit| bit.id()).eq(bits.iter().map(|bit| bit.id())));
+                assert!(instr.parameters.iter().map(|param| param.as_value()).eq(parameters.iter().map(|param| param.as_value())));
+                assert_eq!(encode(&instr.modifiers), encode(modifier.as_slice()));
+            }
+            assert!(iter.next().is_none());
+
+            Ok(())
+        }).unwrap();
+    }
+
+    #[test]
+    fn keeps_the_order_of_modifiers() {
+        QuantumCircuit::new(|b| {
+            let [a, c, d] = b.qubits()?;
+            let [bit] = b.bits()?;
+
+            let modifiers = [Modifier::Inverse, Modifier::Power(Parameter::from(0.5)), Modifier::IfBit(bit), Modifier::ForConst(4)];
+            let mut vec = InstrVec::default();
+            vec.push_controlled_modified(OpKind::RY, &[a, d], &[true, false], &[c], &[Parameter::from(0.3)], &modifiers)?;
+            vec.push_with_modifiers(OpKind::T, &[c], &[], &[], &modifiers[1..])?;
+            vec.push(OpKind::X, &[d], &[], &[]);
+
+            let instrs: Vec<_> = vec.iter().collect();
+            assert_eq!(instrs.len(), 3);
+
+            let (controls, states) = instrs[0].controls();
+            assert!(controls.iter().map(|qubit| qubit.id()).eq([a.id(), d.id()]));
+            assert_eq!(states, [true, false]);
+            assert!(matches!(instrs[0].modifiers[0], Modifier::Controlled(_)));
+            assert_eq!(encode(&instrs[0].modifiers[1..]), encode(&modifiers));
+            assert_eq!(instrs[0].exponents().map(|exponent| exponent.as_value()).collect::<Vec<_>>(), [Some(0.5)]);
+            assert_eq!(encode(&instrs[1].modifiers), encode(&modifiers[1..]));
+            assert!(!instrs[2].has_modifier());
+
+            Ok(())
+        }).unwrap();
+    }
+
+    #[test]
+    fn composes_quantum_modifiers() {
+        QuantumCircuit::new(|b| {
+            let [a, c] = b.qubits()?;
+
+            // The square of the inverse of $ S $ is $ Z $, controlled on $ |0 \rangle $.
+            let mut vec = InstrVec::default();
+            vec.push_controlled_modified(OpKind::S, &[a], &[false], &[c], &[], &[Modifier::Inverse, Modifier::Power(Parameter::from(2.0))])?;
+            let instr = vec.iter().next().unwrap();
+
+            let z = OpKind::Z.matrix(&[]).unwrap();
+            let mut expected = DMatrix::eye(4);
+            (0..2).for_each(|r| (0..2).for_each(|c| expected[(r, c)] = z[(r, c)]));
+            assert_eq!(instr.matrix(&[], &[2.0]).unwrap(), expected);
+
+            Ok(())
+        }).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "quantum modifiers must come before classical ones")]
+    fn rejects_quantum_modifiers_after_classical_ones() {
+        QuantumCircuit::new(|b| {
+            let qubit = b.qubit()?;
+            let [bit] = b.bits()?;
+            b.instructions_mut().push_with_modifiers(OpKind::X, &[qubit], &[], &[], &[Modifier::IfBit(bit), Modifier::Inverse])?;
+            Ok(())
+        }).unwrap();
+    }
+
+    #[test]
+    fn runs_stacked_modifiers() {
+        // The controlled $ \sqrt{X} $ is conditioned on a set bit and repeated, which flips the
+        // target when it is repeated twice, but not four times.
+        for (count, flipped) in [(4, false), (2, true)] {
+            let circ = QuantumCircuit::new(|b| {
+                let [a, c] = b.qubits()?;
+                let bits = b.bits::<2>()?;
+                b.x(a).measure(a, bits[0]);
+                b.instructions_mut().push_controlled_modified(OpKind::SX, &[a], &[true], &[c], &[], &[Modifier::IfBit(bits[0]), Modifier::ForConst(count)])?;
+                b.measure(c, bits[1]);
+                Ok(())
+            }).unwrap();
+
+            let branches = DensityMatrixSimulator::new(0).run(&circ).unwrap();
+            assert_eq!(branches.len(), 1);
+            assert_eq!(branches[0].bits.get(1), Some(flipped));
+        }
+    }
+
+    #[test]
+    fn builds_the_index_on_demand() {
+        QuantumCircuit::new(|b| {
+            let [a, c] = b.qubits()?;
+            let bit = b.bit()?;
+            b.h(a).cx(a, c).rz(0.5, c).measure(c, bit);
+
+            let instrs = b.instructions_mut();
+            let labels = ["h", "cx", "rz", "measure"];
+            for build in [false, true] {
+                instrs.drop_index();
+                if build {
+                    instrs.build_index();
+                }
+
+                assert_eq!(instrs.is_indexed(), build);
+                assert_eq!(instrs.get(2).unwrap().op.label(), "rz");
+                assert!(instrs.get(4).is_none());
+                assert!(instrs.is_indexed());
+                assert!(instrs.iter_rev().map(|instr| instr.op.label()).eq(labels.into_iter().rev()));
+                assert!(instrs.slice(1..3).iter_rev().map(|instr| instr.op.label()).eq(["rz", "cx"]));
+
+                instrs.drop_index();
+                assert_eq!(instrs.len(), 4);
+                assert!(instrs.is_indexed());
+            }
+            Ok(())
+        }).unwrap();
+    }
+
+    #[test]
+    fn slices_empty_ranges() {
+        QuantumCircuit::new(|b| {
+            let [a, c] = b.qubits()?;
+            b.h(a).cx(a, c).rz(0.5, c).x(a);
+
+            let instrs = b.instructions_mut();
+            for range in [0..0, 2..2, 4..4] {
+                let slice = instrs.slice(range);
+                assert!(slice.is_empty());
+                assert_eq!(slice.len(), 0);
+                assert!(slice.get(0).is_none());
+            }
+            assert!(instrs.slice(4..).is_empty());
+            Ok(())
+        }).unwrap();
+    }
+
+    /// Checks the labels of the instructions, in both directions, and that the index of the
+    /// vector, if any, matches it's instructions.
+    fn check(instrs: &InstrVec<'_>, labels: &[&str]) {
+        if let Some(index) = instrs.index.get() {
+            assert_eq!(index, &read_offsets(&instrs.data));
+        }
+        assert!(instrs.iter().map(|instr| instr.op.label()).eq(labels.iter().copied()));
+        assert!(instrs.iter_rev().map(|instr| instr.op.label()).eq(labels.iter().rev().copied()));
+    }
+
+    #[test]
+    fn keeps_the_index_up_to_date() {
+        let [a, c] = [Qubit::new_unchecked(0), Qubit::new_unchecked(1)];
+        let bit = Bit::new_unchecked(0);
+        let targets = [c];
+        let x = Instr { op: OpKind::X, qubits: &targets, ..Instr::default() };
+
+        for indexed in [false, true] {
+            let mut body = InstrVec::default();
+            body.push(OpKind::T, &[a], &[], &[]);
+            body.push(OpKind::CX, &[c, a], &[], &[]);
+
+            let mut instrs = InstrVec::default();
+            instrs.push(OpKind::H, &[a], &[], &[]);
+            instrs.push(OpKind::CX, &[a, c], &[], &[]);
+            instrs.push(OpKind::RZ, &[c], &[], &[Parameter::from(0.5)]);
+            instrs.push(OpKind::Measure, &[c], &[bit], &[]);
+            instrs.push_for(2, &body);
+
+            match indexed {
+                true => instrs.build_index(),
+                false => instrs.drop_index(),
+            }
+            instrs.retain(|instr| instr.op != OpKind::RZ);
+            assert_eq!(instrs.is_indexed(), indexed);
+            check(&instrs, &["h", "cx", "measure", "for"]);
+
+            instrs.insert(1, &x);
+            check(&instrs, &["h", "x", "cx", "measure", "for"]);
+            instrs.replace(3, &x);
+            check(&instrs, &["h", "x", "cx", "x", "for"]);
+
+            let block = instrs.slice(2..);
+            instrs.splice(0..2, &block);
+            check(&instrs, &["cx", "x", "for", "cx", "x", "for"]);
+            instrs.remove(2);
+            check(&instrs, &["cx", "x", "cx", "x", "for"]);
+            instrs.splice(1..4, &InstrVec::default());
+            check(&instrs, &["cx", "for"]);
+            instrs.extend(&block);
+            check(&instrs, &["cx", "for", "cx", "x", "for"]);
+
+            instrs.push(OpKind::RZ, &[a], &[], &[Parameter::from(0.5)]);
+            check(&instrs, &["cx", "for", "cx", "x", "for", "rz"]);
+            instrs.clear();
+            instrs.push(OpKind::H, &[c], &[], &[]);
+            check(&instrs, &["h"]);
+        }
+    }
+
+    #[test]
+    fn copies_instructions_back_to_the_vector() {
+        QuantumCircuit::new(|b| {
+            let [a, c] = b.qubits()?;
+            b.h(a).cx(a, c).rz(0.5, c);
+
+            let instrs = b.instructions_mut();
+            let rotations: Vec<_> = instrs.iter().enumerate()
+                .filter(|(_, instr)| instr.op == OpKind::RZ)
+                .map(|(n, _)| instrs.copy(n).unwrap())
+                .collect();
+            assert!(instrs.copy(3).is_none());
+
+            instrs.splice(0..0, &rotations[0]);
+            let cx = instrs.copy(2).unwrap();
+            instrs.extend(&cx);
+            assert!(instrs.iter().map(|instr| instr.op.label()).eq(["rz", "h", "cx", "rz", "cx"]));
+            assert_eq!(instrs.get(0), instrs.get(3));
+            Ok(())
+        }).unwrap();
+    }
+}
@@ -7,11 +7,23 @@ mod storage;
 
 pub mod bitset;
 pub mod circuit;
+pub mod dag;
+pub mod definition;
+pub mod draw;
+pub mod equivalence;
+pub mod estimation;
+pub mod expression;
+pub mod gradient;
 pub mod instruction;
 pub mod linalg;
 pub mod operation;
 pub mod parameter;
+pub mod pauli;
+pub mod random;
+pub mod simulator;
+pub mod stats;
 pub mod symbol;
+pub mod transpiler;
 pub mod provider;
 
 pub mod prelude {
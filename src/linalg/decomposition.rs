@@ -0,0 +1,607 @@
+//! Matrix decompositions and matrix functions, implemented in pure rust.
+
+use std::f64::consts::PI;
+
+use thiserror::Error;
+
+use crate::linalg::{c64, DMatrix, Matrix, NotUnitaryError, UnitaryMatrix};
+
+/// The maximum number of sweeps performed by the Jacobi algorithms.
+const MAX_SWEEPS: usize = 100;
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Error)]
+#[error("matrix is not hermitian")]
+pub struct NotHermitianError;
+
+/// Returns the 2x2 unitary $ G $ such that $ G^\dagger M G $ is diagonal, where
+/// $ M $ is the hermitian matrix `[[a, b], [b*, d]]`.
+fn jacobi_rotation(a: f64, d: f64, b: c64) -> [[c64; 2]; 2] {
+    let phase = c64::cis(-b.arg());
+    let theta = 0.5 * (2.0 * b.abs()).atan2(d - a);
+    let (s, c) = theta.sin_cos();
+    [[c64::from(c), c64::from(s)], [-s * phase, c * phase]]
+}
+
+/// Right-multiplies the columns `p` and `q` of the matrix by the 2x2 matrix `g`.
+fn rotate_columns(mat: &mut DMatrix, p: usize, q: usize, g: &[[c64; 2]; 2]) {
+    for k in 0..mat.dim() {
+        let (x, y) = (mat[(k, p)], mat[(k, q)]);
+        mat[(k, p)] = x * g[0][0] + y * g[1][0];
+        mat[(k, q)] = x * g[0][1] + y * g[1][1];
+    }
+}
+
+/// Left-multiplies the rows `p` and `q` of the matrix by the adjoint of the 2x2 matrix `g`.
+fn rotate_rows(mat: &mut DMatrix, p: usize, q: usize, g: &[[c64; 2]; 2]) {
+    for k in 0..mat.dim() {
+        let (x, y) = (mat[(p, k)], mat[(q, k)]);
+        mat[(p, k)] = g[0][0].conj() * x + g[1][0].conj() * y;
+        mat[(q, k)] = g[0][1].conj() * x + g[1][1].conj() * y;
+    }
+}
+
+/// Completes the given orthonormal columns of the matrix into an orthonormal basis,
+/// by Gram-Schmidt orthogonalization of the canonical basis vectors.
+fn complete_basis(mat: &mut DMatrix, filled: &[bool]) {
+    let dim = mat.dim();
+    let mut candidates = 0..dim;
+
+    for col in (0..dim).filter(|&col| !filled[col]) {
+        loop {
+            let e = candidates.next().expect("failed to complete basis");
+            let mut v: Vec<c64> = (0..dim).map(|i| if i == e { c64::ONE } else { c64::ZERO }).collect();
+
+            for other in (0..dim).filter(|&other| other != col && (filled[other] || other < col)) {
+                let dot: c64 = (0..dim).map(|i| mat[(i, other)].conj() * v[i]).sum();
+                v.iter_mut().enumerate().for_each(|(i, z)| *z -= dot * mat[(i, other)]);
+            }
+
+            let norm = v.iter().map(c64::abs_sqr).sum::<f64>().sqrt();
+            if norm > 1E-6 {
+                v.iter().enumerate().for_each(|(i, &z)| mat[(i, col)] = z / norm);
+                break;
+            }
+        }
+    }
+}
+
+/// Returns the argument of the complex number in $ (-\pi, \pi] $.
+fn principal_arg(z: c64) -> f64 {
+    let arg = z.arg();
+    if arg <= -PI { arg + 2.0 * PI } else { arg }
+}
+
+impl DMatrix {
+    /// Returns `true` if the matrix is equal to it's conjugate transpose.
+    pub fn is_hermitian(&self) -> bool {
+        (0..self.dim()).all(|i| (i..self.dim()).all(|j| self[(i, j)] == self[(j, i)].conj()))
+    }
+
+    /// Returns the [determinant](https://en.wikipedia.org/wiki/Determinant) of the matrix,
+    /// computed by LU decomposition with partial pivoting.
+    pub fn det(&self) -> c64 {
+        let n = self.dim();
+        let mut lu = self.clone();
+        let mut det = c64::ONE;
+
+        for k in 0..n {
+            let pivot = (k..n).max_by(|&a, &b| lu[(a, k)].abs_sqr().total_cmp(&lu[(b, k)].abs_sqr())).unwrap();
+            if lu[(pivot, k)].abs_sqr() == 0.0 {
+                return c64::ZERO;
+            }
+
+            if pivot != k {
+                (0..n).for_each(|j| lu.raw_mut().swap(k * n + j, pivot * n + j));
+                det = -det;
+            }
+
+            det *= lu[(k, k)];
+
+            for i in k + 1..n {
+                let factor = lu[(i, k)] / lu[(k, k)];
+                (k..n).for_each(|j| {
+                    let sub = factor * lu[(k, j)];
+                    lu[(i, j)] -= sub;
+                });
+            }
+        }
+
+        det
+    }
+
+    /// Returns the [QR decomposition](https://en.wikipedia.org/wiki/QR_decomposition)
+    /// $ (Q, R) $ of the matrix, where $ Q $ is unitary and $ R $ is upper triangular,
+    /// computed with Householder reflections.
+    pub fn qr(&self) -> (DMatrix, DMatrix) {
+        let n = self.dim();
+        let mut q = DMatrix::eye(n);
+        let mut r = self.clone();
+
+        for k in 0..n.saturating_sub(1) {
+            let norm = (k..n).map(|i| r[(i, k)].abs_sqr()).sum::<f64>().sqrt();
+            if norm == 0.0 {
+                continue;
+            }
+
+            // Reflect the column onto -e^{i arg x_k} |x| e_k, to avoid cancellations.
+            let alpha = -c64::euler(norm, r[(k, k)].arg());
+            let mut v: Vec<c64> = (k..n).map(|i| r[(i, k)]).collect();
+            v[0] -= alpha;
+
+            let v_norm = v.iter().map(c64::abs_sqr).sum::<f64>().sqrt();
+            if v_norm == 0.0 {
+                continue;
+            }
+            v.iter_mut().for_each(|z| *z /= v_norm);
+
+            // R <- (I - 2vv†) R
+            for j in 0..n {
+                let dot: c64 = v.iter().enumerate().map(|(i, z)| z.conj() * r[(k + i, j)]).sum();
+                v.iter().enumerate().for_each(|(i, z)| r[(k + i, j)] -= 2.0 * z * dot);
+            }
+
+            // Q <- Q (I - 2vv†)
+            for i in 0..n {
+                let dot: c64 = v.iter().enumerate().map(|(j, z)| q[(i, k + j)] * z).sum();
+                v.iter().enumerate().for_each(|(j, z)| q[(i, k + j)] -= 2.0 * dot * z.conj());
+            }
+        }
+
+        (q, r)
+    }
+
+    /// Returns the eigenvalues, in ascending order, and the corresponding eigenvectors,
+    /// as the columns of a unitary matrix, of the hermitian matrix. They are computed with
+    /// the [Jacobi eigenvalue algorithm](https://en.wikipedia.org/wiki/Jacobi_eigenvalue_algorithm).
+    pub fn eigh(&self) -> Result<(Vec<f64>, DMatrix), NotHermitianError> {
+        if !self.is_hermitian() {
+            return Err(NotHermitianError);
+        }
+
+        let n = self.dim();
+        let mut a = self.clone();
+        let mut v = DMatrix::eye(n);
+        let tolerance = f64::EPSILON * self.norm().max(f64::MIN_POSITIVE);
+
+        for _ in 0..MAX_SWEEPS {
+            let off = (0..n).flat_map(|i| (0..n).filter(move |&j| j != i).map(move |j| (i, j)))
+                .map(|(i, j)| a[(i, j)].abs_sqr())
+                .sum::<f64>()
+                .sqrt();
+            if off <= tolerance {
+                break;
+            }
+
+            for p in 0..n {
+                for q in p + 1..n {
+                    if a[(p, q)].abs() <= tolerance / n as f64 {
+                        continue;
+                    }
+
+                    let g = jacobi_rotation(a[(p, p)].re, a[(q, q)].re, a[(p, q)]);
+                    rotate_columns(&mut a, p, q, &g);
+                    rotate_rows(&mut a, p, q, &g);
+                    rotate_columns(&mut v, p, q, &g);
+                }
+            }
+        }
+
+        let mut order: Vec<usize> = (0..n).collect();
+        order.sort_by(|&i, &j| a[(i, i)].re.total_cmp(&a[(j, j)].re));
+
+        let values = order.iter().map(|&i| a[(i, i)].re).collect();
+        let mut vectors = DMatrix::zeros(n);
+        order.iter().enumerate().for_each(|(col, &i)| (0..n).for_each(|row| vectors[(row, col)] = v[(row, i)]));
+
+        Ok((values, vectors))
+    }
+
+    /// Returns the [singular value decomposition](https://en.wikipedia.org/wiki/Singular_value_decomposition)
+    /// $ (U, \Sigma, V) $ of the matrix, such that $ A = U \Sigma V^\dagger $. The singular values are
+    /// returned in descending order. They are computed with the one-sided Jacobi algorithm.
+    pub fn svd(&self) -> (DMatrix, Vec<f64>, DMatrix) {
+        let n = self.dim();
+        let mut w = self.clone();
+        let mut v = DMatrix::eye(n);
+        let tolerance = f64::EPSILON * self.norm().max(f64::MIN_POSITIVE);
+
+        let column_dot = |w: &DMatrix, p: usize, q: usize| (0..n).map(|k| w[(k, p)].conj() * w[(k, q)]).sum::<c64>();
+
+        for _ in 0..MAX_SWEEPS {
+            let mut rotated = false;
+
+            for p in 0..n {
+                for q in p + 1..n {
+                    let gamma = column_dot(&w, p, q);
+                    if gamma.abs() <= tolerance * tolerance {
+                        continue;
+                    }
+
+                    let alpha = column_dot(&w, p, p).re;
+                    let beta = column_dot(&w, q, q).re;
+                    if gamma.abs() <= f64::EPSILON * (alpha * beta).sqrt() {
+                        continue;
+                    }
+
+                    let g = jacobi_rotation(alpha, beta, gamma);
+                    rotate_columns(&mut w, p, q, &g);
+                    rotate_columns(&mut v, p, q, &g);
+                    rotated = true;
+                }
+            }
+
+            if !rotated {
+                break;
+            }
+        }
+
+        let norms: Vec<f64> = (0..n).map(|k| column_dot(&w, k, k).re.sqrt()).collect();
+        let mut order: Vec<usize> = (0..n).collect();
+        order.sort_by(|&i, &j| norms[j].total_cmp(&norms[i]));
+
+        let mut u = DMatrix::zeros(n);
+        let mut v_sorted = DMatrix::zeros(n);
+        let mut filled = vec![false; n];
+        let mut values = Vec::with_capacity(n);
+
+        for (col, &k) in order.iter().enumerate() {
+            values.push(norms[k]);
+            (0..n).for_each(|row| v_sorted[(row, col)] = v[(row, k)]);
+
+            if norms[k] > tolerance {
+                (0..n).for_each(|row| u[(row, col)] = w[(row, k)] / norms[k]);
+                filled[col] = true;
+            }
+        }
+
+        complete_basis(&mut u, &filled);
+
+        (u, values, v_sorted)
+    }
+
+    /// Returns the [exponential](https://en.wikipedia.org/wiki/Matrix_exponential) of the matrix,
+    /// computed by scaling and squaring of it's Taylor series. For example, the time evolution
+    /// operator of an hamiltonian $ H $ is `h.scale(c64::new(0.0, -t)).exp()`.
+    pub fn exp(&self) -> DMatrix {
+        const TERMS: usize = 24;
+
+        let norm = self.norm();
+        let squarings = if norm > 0.5 { (norm / 0.5).log2().ceil() as u32 } else { 0 };
+        let scaled = self.scale(c64::from(0.5f64.powi(squarings as i32)));
+
+        let mut res = DMatrix::eye(self.dim());
+        let mut term = DMatrix::eye(self.dim());
+        for k in 1..=TERMS {
+            term = (&term * &scaled).scale(c64::from(1.0 / k as f64));
+            res = &res + &term;
+        }
+
+        (0..squarings).for_each(|_| res = &res * &res);
+        res
+    }
+
+    /// Returns the eigenvalues and the eigenvectors, as the columns of a unitary matrix,
+    /// of the unitary matrix.
+    ///
+    /// The matrix is split into the commuting hermitian matrices $ H = (U + U^\dagger) / 2 $
+    /// and $ K = (U - U^\dagger) / 2i $. $ H $ is diagonalized first, then $ K $ is diagonalized
+    /// within each eigenspace of $ H $, which separates the conjugate eigenvalues of $ U $
+    /// sharing the same real part.
+    pub fn eig_unitary(&self) -> Result<(Vec<c64>, DMatrix), NotUnitaryError> {
+        // Eigenvalues of $ H $ closer than this are treated as a single eigenspace.
+        const CLUSTER: f64 = 1E-6;
+
+        if !self.is_unitary() {
+            return Err(NotUnitaryError);
+        }
+
+        let n = self.dim();
+        let adjoint = self.adjoint();
+        let h = (self + &adjoint).scale(c64::from(0.5));
+        let k = (self - &adjoint).scale(c64::new(0.0, -0.5));
+
+        // Both parts are hermitian by construction, up to rounding errors.
+        let hermitian = |mat: &DMatrix| (mat + &mat.adjoint()).scale(c64::from(0.5));
+        let (h_values, mut vectors) = hermitian(&h).eigh().map_err(|_| NotUnitaryError)?;
+
+        // $ K $ is block diagonal in the eigenbasis of $ H $, each block is diagonalized on it's own.
+        let kv = &k * &vectors;
+        let mut start = 0;
+        while start < n {
+            let end = (start + 1..n).find(|&i| h_values[i] - h_values[i - 1] > CLUSTER).unwrap_or(n);
+            let m = end - start;
+
+            if m > 1 {
+                let mut block = DMatrix::zeros(m);
+                for a in 0..m {
+                    for b in 0..m {
+                        block[(a, b)] = (0..n).map(|i| vectors[(i, start + a)].conj() * kv[(i, start + b)]).sum();
+                    }
+                }
+
+                let (_, rotation) = hermitian(&block).eigh().map_err(|_| NotUnitaryError)?;
+                for i in 0..n {
+                    let row: Vec<c64> = (0..m).map(|b| (0..m).map(|a| vectors[(i, start + a)] * rotation[(a, b)]).sum()).collect();
+                    row.into_iter().enumerate().for_each(|(b, z)| vectors[(i, start + b)] = z);
+                }
+            }
+
+            start = end;
+        }
+
+        let diag = &(&vectors.adjoint() * self) * &vectors;
+        let values = (0..n).map(|i| diag[(i, i)]).collect();
+
+        Ok((values, vectors))
+    }
+
+    /// Returns the principal logarithm of the unitary matrix, whose eigenvalues
+    /// have imaginary parts in $ (-\pi, \pi] $.
+    pub fn log_unitary(&self) -> Result<DMatrix, NotUnitaryError> {
+        let (values, vectors) = self.eig_unitary()?;
+        Ok(Self::map_eigenvalues(&values, &vectors, |z| c64::new(z.abs().ln(), principal_arg(z))))
+    }
+
+    /// Returns the principal square root of the unitary matrix.
+    pub fn sqrt_unitary(&self) -> Result<DMatrix, NotUnitaryError> {
+        self.pow_unitary(0.5)
+    }
+
+    /// Returns the unitary matrix to the real power `t`, using the principal branch
+    /// of the logarithm.
+    pub fn pow_unitary(&self, t: f64) -> Result<DMatrix, NotUnitaryError> {
+        let (values, vectors) = self.eig_unitary()?;
+        Ok(Self::map_eigenvalues(&values, &vectors, |z| c64::euler(z.abs().powf(t), t * principal_arg(z))))
+    }
+
+    /// Applies the function `f` to the eigenvalues of the normal matrix, given it's
+    /// eigendecomposition.
+    fn map_eigenvalues(values: &[c64], vectors: &DMatrix, f: impl Fn(c64) -> c64) -> DMatrix {
+        let diag = DMatrix::diag(&values.iter().map(|&z| f(z)).collect::<Vec<_>>());
+        &(vectors * &diag) * &vectors.adjoint()
+    }
+}
+
+/// Converts a dynamically sized matrix to a statically sized one, returning
+/// the matrix back when it's dimension is not `N`.
+impl<const N: usize> TryFrom<DMatrix> for Matrix<N> {
+    type Error = DMatrix;
+
+    fn try_from(mat: DMatrix) -> Result<Self, Self::Error> {
+        if mat.dim() != N {
+            return Err(mat);
+        }
+
+        let mut res = Matrix::default();
+        (0..N).for_each(|i| (0..N).for_each(|j| res[i][j] = mat[(i, j)]));
+        Ok(res)
+    }
+}
+
+/// The message of the panics on matrices checked to be unitary on construction.
+const UNITARY: &str = "the matrix of a UnitaryMatrix must be unitary";
+
+/// Converts a matrix known to be of dimension `N`.
+fn to_matrix<const N: usize>(mat: DMatrix) -> Matrix<N> {
+    mat.try_into().expect("matrix dimension changed")
+}
+
+impl<const N: usize> Matrix<N> {
+    pub fn is_hermitian(&self) -> bool {
+        DMatrix::from(self.clone()).is_hermitian()
+    }
+
+    /// See [`DMatrix::det`].
+    pub fn det(&self) -> c64 {
+        DMatrix::from(self.clone()).det()
+    }
+
+    /// See [`DMatrix::qr`].
+    pub fn qr(&self) -> (UnitaryMatrix<N>, Matrix<N>) {
+        let (q, r) = DMatrix::from(self.clone()).qr();
+        (UnitaryMatrix::new_unchecked(to_matrix(q)), to_matrix(r))
+    }
+
+    /// See [`DMatrix::eigh`].
+    pub fn eigh(&self) -> Result<([f64; N], UnitaryMatrix<N>), NotHermitianError> {
+        let (values, vectors) = DMatrix::from(self.clone()).eigh()?;
+        Ok((values.try_into().unwrap(), UnitaryMatrix::new_unchecked(to_matrix(vectors))))
+    }
+
+    /// See [`DMatrix::svd`].
+    pub fn svd(&self) -> (UnitaryMatrix<N>, [f64; N], UnitaryMatrix<N>) {
+        let (u, values, v) = DMatrix::from(self.clone()).svd();
+        (
+            UnitaryMatrix::new_unchecked(to_matrix(u)),
+            values.try_into().unwrap(),
+            UnitaryMatrix::new_unchecked(to_matrix(v)),
+        )
+    }
+
+    /// See [`DMatrix::exp`].
+    pub fn exp(&self) -> Matrix<N> {
+        to_matrix(DMatrix::from(self.clone()).exp())
+    }
+}
+
+impl<const N: usize> UnitaryMatrix<N> {
+    /// See [`DMatrix::eig_unitary`].
+    pub fn eig(&self) -> ([c64; N], UnitaryMatrix<N>) {
+        let (values, vectors) = DMatrix::from(self.clone().take()).eig_unitary().expect(UNITARY);
+        (values.try_into().unwrap(), UnitaryMatrix::new_unchecked(to_matrix(vectors)))
+    }
+
+    /// See [`DMatrix::log_unitary`].
+    pub fn log(&self) -> Matrix<N> {
+        to_matrix(DMatrix::from(self.clone().take()).log_unitary().expect(UNITARY))
+    }
+
+    /// See [`DMatrix::sqrt_unitary`].
+    pub fn sqrt(&self) -> UnitaryMatrix<N> {
+        UnitaryMatrix::new_unchecked(to_matrix(DMatrix::from(self.clone().take()).sqrt_unitary().expect(UNITARY)))
+    }
+
+    /// See [`DMatrix::pow_unitary`].
+    pub fn pow(&self, t: f64) -> UnitaryMatrix<N> {
+        UnitaryMatrix::new_unchecked(to_matrix(DMatrix::from(self.clone().take()).pow_unitary(t).expect(UNITARY)))
+    }
+
+    pub fn det(&self) -> c64 {
+        Matrix::det(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use crate::linalg::{c64, DMatrix};
+
+    /// Returns a matrix with entries in $ [-1, 1) + i [-1, 1) $, drawn from the seed with a
+    /// xorshift generator.
+    fn random_matrix(dim: usize, seed: u64) -> DMatrix {
+        let mut state = seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 11) as f64 / (1u64 << 52) as f64 - 1.0
+        };
+
+        let data = (0..dim * dim).map(|_| c64::new(next(), next())).collect();
+        DMatrix::new(dim, data).unwrap()
+    }
+
+    /// Returns the unitary factor of the QR decomposition of a random matrix.
+    fn random_unitary(dim: usize, seed: u64) -> DMatrix {
+        random_matrix(dim, seed).qr().0
+    }
+
+    /// Returns $ V D V^\dagger $ for a random unitary $ V $, with the given eigenphases in $ D $.
+    fn with_phases(phases: &[f64], seed: u64) -> DMatrix {
+        let v = random_unitary(phases.len(), seed);
+        let d = DMatrix::diag(&phases.iter().map(|&phase| c64::cis(phase)).collect::<Vec<_>>());
+        &(&v * &d) * &v.adjoint()
+    }
+
+    fn assert_close(a: &DMatrix, b: &DMatrix) {
+        assert_eq!(a.dim(), b.dim());
+        assert!(a.raw().iter().zip(b.raw()).all(|(x, y)| (x - y).abs() < 1E-8), "{a:?} != {b:?}");
+    }
+
+    fn is_upper_triangular(mat: &DMatrix) -> bool {
+        (0..mat.dim()).all(|i| (0..i).all(|j| mat[(i, j)].abs() < 1E-10))
+    }
+
+    #[test]
+    fn qr_is_unitary_times_triangular() {
+        let a = random_matrix(5, 0);
+        let (q, r) = a.qr();
+
+        assert!(q.is_unitary());
+        assert!(is_upper_triangular(&r));
+        assert_close(&(&q * &r), &a);
+    }
+
+    #[test]
+    fn det_is_product_of_triangular_diagonal() {
+        let a = random_matrix(4, 1);
+        let (q, r) = a.qr();
+        let expected = q.det() * (0..4).map(|i| r[(i, i)]).product::<c64>();
+
+        assert!((a.det() - expected).abs() < 1E-10);
+        assert!((DMatrix::eye(3).det() - c64::ONE).abs() < 1E-12);
+        assert!(DMatrix::zeros(3).det().abs() < 1E-12);
+    }
+
+    #[test]
+    fn eigh_diagonalizes_hermitian() {
+        let a = random_matrix(6, 2);
+        let h = (&a + &a.adjoint()).scale(c64::from(0.5));
+        let (values, vectors) = h.eigh().unwrap();
+
+        assert!(vectors.is_unitary());
+        assert!(values.windows(2).all(|pair| pair[0] <= pair[1]));
+        let diag = DMatrix::diag(&values.iter().map(|&value| c64::from(value)).collect::<Vec<_>>());
+        assert_close(&(&(&vectors * &diag) * &vectors.adjoint()), &h);
+
+        assert!(a.eigh().is_err());
+    }
+
+    #[test]
+    fn eigh_handles_degenerate_eigenvalues() {
+        let v = random_unitary(4, 3);
+        let diag = DMatrix::diag(&[1.0, 1.0, -2.0, 1.0].map(c64::from));
+        let h = &(&v * &diag) * &v.adjoint();
+        let (values, vectors) = h.eigh().unwrap();
+
+        assert!(values.iter().zip([-2.0, 1.0, 1.0, 1.0]).all(|(a, b)| (a - b).abs() < 1E-10));
+        assert!(vectors.is_unitary());
+    }
+
+    #[test]
+    fn svd_reconstructs_matrix() {
+        for (seed, rank) in [(4, 5), (5, 2)] {
+            // A matrix of the given rank, as a product of random factors.
+            let (a, b) = (random_matrix(5, seed), random_matrix(5, seed + 10));
+            let projector = DMatrix::diag(&(0..5).map(|i| c64::from(f64::from(u8::from(i < rank)))).collect::<Vec<_>>());
+            let m = &(&a * &projector) * &b;
+
+            let (u, values, v) = m.svd();
+            assert!(u.is_unitary() && v.is_unitary());
+            assert!(values.windows(2).all(|pair| pair[0] >= pair[1]));
+            assert!(values[rank..].iter().all(|&value| value < 1E-8));
+
+            let sigma = DMatrix::diag(&values.iter().map(|&value| c64::from(value)).collect::<Vec<_>>());
+            assert_close(&(&(&u * &sigma) * &v.adjoint()), &m);
+        }
+    }
+
+    #[test]
+    fn exp_of_hamiltonian_is_unitary() {
+        let a = random_matrix(4, 6);
+        let h = (&a + &a.adjoint()).scale(c64::from(2.0));
+        let (values, vectors) = h.eigh().unwrap();
+
+        let t = 0.7;
+        let u = h.scale(c64::new(0.0, -t)).exp();
+        let expected = DMatrix::diag(&values.iter().map(|&value| c64::cis(-t * value)).collect::<Vec<_>>());
+
+        assert!(u.is_unitary());
+        assert_close(&u, &(&(&vectors * &expected) * &vectors.adjoint()));
+        assert_close(&DMatrix::zeros(3).exp(), &DMatrix::eye(3));
+    }
+
+    #[test]
+    fn pow_unitary_composes() {
+        let u = random_unitary(4, 7);
+        let sqrt = u.sqrt_unitary().unwrap();
+
+        assert_close(&(&sqrt * &sqrt), &u);
+        assert_close(&u.pow_unitary(1.0).unwrap(), &u);
+        assert_close(&(&u.pow_unitary(0.3).unwrap() * &u.pow_unitary(0.7).unwrap()), &u);
+        assert_close(&u.log_unitary().unwrap().exp(), &u);
+    }
+
+    fn check_eig(u: &DMatrix) {
+        let (values, vectors) = u.eig_unitary().unwrap();
+        assert!(vectors.is_unitary());
+
+        let rebuilt = &(&vectors * &DMatrix::diag(&values)) * &vectors.adjoint();
+        assert!(rebuilt.raw().iter().zip(u.raw()).all(|(a, b)| (a - b).abs() < 1E-8));
+    }
+
+    #[test]
+    fn eig_unitary_separates_colliding_phases() {
+        // Phases whose sum is $ \pi / 3 $, and conjugate phases sharing the same real part.
+        check_eig(&with_phases(&[0.3, PI / 3.0 - 0.3, 1.1], 0));
+        check_eig(&with_phases(&[0.7, -0.7, 2.0, -2.0], 1));
+        check_eig(&with_phases(&[PI, 0.0, PI, 0.0], 2));
+        check_eig(&with_phases(&[0.5, 0.5, -0.5, 1.5], 3));
+    }
+
+    #[test]
+    fn eig_unitary_rejects_non_unitary() {
+        assert!(DMatrix::diag(&[c64::ONE, c64::from(2.0)]).eig_unitary().is_err());
+    }
+}
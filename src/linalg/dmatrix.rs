@@ -0,0 +1,204 @@
+use std::ops::{Index, IndexMut, Add, Sub, Mul, Neg};
+
+use crate::linalg::{c64, Matrix};
+
+/// A square matrix of complex numbers, whose dimension is only known at runtime.
+///
+/// Elements are stored in row-major order.
+#[derive(Clone, Debug)]
+pub struct DMatrix {
+    dim: usize,
+    data: Vec<c64>,
+}
+
+impl Index<(usize, usize)> for DMatrix {
+    type Output = c64;
+
+    fn index(&self, (i, j): (usize, usize)) -> &Self::Output {
+        &self.data[i * self.dim + j]
+    }
+}
+
+impl IndexMut<(usize, usize)> for DMatrix {
+    fn index_mut(&mut self, (i, j): (usize, usize)) -> &mut Self::Output {
+        &mut self.data[i * self.dim + j]
+    }
+}
+
+impl DMatrix {
+    /// Creates a new matrix of the given dimension from it's elements, in row-major order.
+    /// Returns `None` if the number of elements is not the square of the dimension.
+    pub fn new(dim: usize, data: Vec<c64>) -> Option<Self> {
+        (data.len() == dim * dim).then_some(Self { dim, data })
+    }
+
+    pub fn zeros(dim: usize) -> Self {
+        Self { dim, data: vec![c64::ZERO; dim * dim] }
+    }
+
+    pub fn eye(dim: usize) -> Self {
+        let mut res = Self::zeros(dim);
+        (0..dim).for_each(|i| res[(i, i)] = c64::ONE);
+        res
+    }
+
+    /// Creates a diagonal matrix from the elements of it's diagonal.
+    pub fn diag(diag: &[c64]) -> Self {
+        let mut res = Self::zeros(diag.len());
+        diag.iter().enumerate().for_each(|(i, &z)| res[(i, i)] = z);
+        res
+    }
+
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    pub fn raw(&self) -> &[c64] {
+        &self.data
+    }
+
+    pub fn raw_mut(&mut self) -> &mut [c64] {
+        &mut self.data
+    }
+
+    /// Returns the conjugate transpose of the matrix.
+    pub fn adjoint(&self) -> Self {
+        let mut res = Self::zeros(self.dim);
+        (0..self.dim).for_each(|i| (0..self.dim).for_each(|j| {
+            res[(i, j)] = self[(j, i)].conj();
+        }));
+        res
+    }
+
+    pub fn trace(&self) -> c64 {
+        (0..self.dim).map(|i| self[(i, i)]).sum()
+    }
+
+    pub fn scale(&self, factor: c64) -> Self {
+        Self { dim: self.dim, data: self.data.iter().map(|&z| z * factor).collect() }
+    }
+
+    /// Returns the [Frobenius norm](https://en.wikipedia.org/wiki/Matrix_norm#Frobenius_norm) of the matrix.
+    pub fn norm(&self) -> f64 {
+        self.data.iter().map(c64::abs_sqr).sum::<f64>().sqrt()
+    }
+
+    pub fn kronecker(&self, rhs: &Self) -> Self {
+        let dim = self.dim * rhs.dim;
+        let mut res = Self::zeros(dim);
+        (0..self.dim).for_each(|i| (0..self.dim).for_each(|j| {
+            let coeff = self[(i, j)];
+            (0..rhs.dim).for_each(|p| (0..rhs.dim).for_each(|q| {
+                res[(i * rhs.dim + p, j * rhs.dim + q)] = coeff * rhs[(p, q)];
+            }));
+        }));
+        res
+    }
+
+    pub fn is_unitary(&self) -> bool {
+        (0..self.dim).all(|i| (i..self.dim).all(|j| {
+            let target = if i == j { c64::ONE } else { c64::ZERO };
+            (0..self.dim).map(|k| self[(i, k)] * self[(j, k)].conj()).sum::<c64>() == target
+        }))
+    }
+
+    /// Returns `true` if the two matrices are equal up to a global phase, i.e. there
+    /// exists $ \theta $ such that $ A = e^{i \theta} B $.
+    pub fn eq_up_to_phase(&self, rhs: &Self) -> bool {
+        if self.dim != rhs.dim {
+            return false;
+        }
+
+        // Find the phase from the largest element of rhs, for numerical stability.
+        let Some(k) = (0..rhs.data.len()).max_by(|&a, &b| rhs.data[a].abs_sqr().total_cmp(&rhs.data[b].abs_sqr())) else {
+            return true;
+        };
+
+        let phase = self.data[k] / rhs.data[k];
+        if (phase.abs() - 1.0).abs() > c64::PRECISION.sqrt() {
+            return false;
+        }
+
+        self.data.iter().zip(&rhs.data).all(|(&a, &b)| a == phase * b)
+    }
+
+    /// Left-multiplies the matrix by the gate `gate`, acting on the given qubits.
+    ///
+    /// The matrix is seen as an operator on `log2(dim)` qubits, where qubit `q` corresponds to
+    /// the `q`th bit of the basis states' indices. The first qubit of `qubits` is the most
+    /// significant one in the gate's own basis.
+    ///
+    /// Panics if the dimension of the gate is not `2^qubits.len()`.
+    pub fn apply(&mut self, gate: &Self, qubits: &[usize]) {
+        assert_eq!(gate.dim, 1 << qubits.len(), "gate dimension does not match its number of qubits");
+
+        let k = qubits.len();
+        let mask = qubits.iter().fold(0, |acc, &q| acc | (1 << q));
+
+        // Maps an index of the gate's basis to the corresponding bits of the full basis.
+        let spread = |sub: usize| (0..k).fold(0, |acc, j| acc | (((sub >> (k - 1 - j)) & 1) << qubits[j]));
+        let offsets: Vec<usize> = (0..gate.dim).map(spread).collect();
+
+        let mut buf = vec![c64::ZERO; gate.dim];
+        for col in 0..self.dim {
+            for base in (0..self.dim).filter(|i| i & mask == 0) {
+                buf.iter_mut().enumerate().for_each(|(r, z)| {
+                    *z = (0..gate.dim).map(|c| gate[(r, c)] * self[(base | offsets[c], col)]).sum();
+                });
+                buf.iter().enumerate().for_each(|(r, &z)| self[(base | offsets[r], col)] = z);
+            }
+        }
+    }
+}
+
+impl PartialEq for DMatrix {
+    fn eq(&self, rhs: &Self) -> bool {
+        self.dim == rhs.dim && self.data == rhs.data
+    }
+}
+
+impl<const N: usize> From<Matrix<N>> for DMatrix {
+    fn from(mat: Matrix<N>) -> Self {
+        Self { dim: N, data: mat.raw().iter().flatten().cloned().collect() }
+    }
+}
+
+impl Add<Self> for &DMatrix {
+    type Output = DMatrix;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        assert_eq!(self.dim, rhs.dim, "matrices must have the same dimension");
+        DMatrix { dim: self.dim, data: self.data.iter().zip(&rhs.data).map(|(a, b)| a + b).collect() }
+    }
+}
+
+impl Sub<Self> for &DMatrix {
+    type Output = DMatrix;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        assert_eq!(self.dim, rhs.dim, "matrices must have the same dimension");
+        DMatrix { dim: self.dim, data: self.data.iter().zip(&rhs.data).map(|(a, b)| a - b).collect() }
+    }
+}
+
+impl Mul<Self> for &DMatrix {
+    type Output = DMatrix;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        assert_eq!(self.dim, rhs.dim, "matrices must have the same dimension");
+        let mut res = DMatrix::zeros(self.dim);
+        (0..self.dim).for_each(|i| (0..self.dim).for_each(|k| {
+            let coeff = self[(i, k)];
+            (0..self.dim).for_each(|j| res[(i, j)] += coeff * rhs[(k, j)]);
+        }));
+        res
+    }
+}
+
+impl Neg for &DMatrix {
+    type Output = DMatrix;
+
+    fn neg(self) -> Self::Output {
+        DMatrix { dim: self.dim, data: self.data.iter().map(Neg::neg).collect() }
+    }
+}
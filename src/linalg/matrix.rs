@@ -56,7 +56,7 @@ impl<const N: usize> Matrix<N> {
     pub fn is_unitary(&self) -> bool {
         (0..N).all(|i| (i..N).all(|j| {
             let target = if i == j { c64::ONE } else { c64::ZERO };
-            (0..N).map(|k| self[i][k] * self[j][k]).sum::<c64>() == target
+            (0..N).map(|k| self[i][k] * self[j][k].conj()).sum::<c64>() == target
         }))
     }
 
@@ -2,4 +2,11 @@ mod complex;
 pub use complex::*;
 
 mod matrix;
-pub use matrix::*;
\ No newline at end of file
+pub use matrix::*;
+mod dmatrix;
+pub use dmatrix::*;
+
+mod decomposition;
+pub use decomposition::*;
+
+mod random;
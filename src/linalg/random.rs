@@ -0,0 +1,111 @@
+//! Random matrices, drawn from the [Haar measure](https://en.wikipedia.org/wiki/Haar_measure)
+//! on the unitary groups.
+
+use std::f64::consts::TAU;
+
+use rand::Rng;
+
+use crate::linalg::{c64, DMatrix, Matrix, Su2, UnitaryMatrix};
+
+/// Draws a complex number whose real and imaginary parts are independent
+/// standard normal variables, with the Box-Muller transform.
+fn normal<R: Rng + ?Sized>(rng: &mut R) -> c64 {
+    let u: f64 = 1.0 - rng.gen::<f64>();
+    let v: f64 = rng.gen();
+    c64::euler((-2.0 * u.ln()).sqrt(), TAU * v)
+}
+
+impl DMatrix {
+    /// Returns a random unitary matrix of the given dimension, drawn from the Haar measure.
+    ///
+    /// The matrix is obtained from the QR decomposition of a matrix of complex gaussians,
+    /// whose phases are fixed such that $ R $ has a positive real diagonal, see
+    /// [this paper](https://arxiv.org/abs/math-ph/0609050).
+    pub fn random_unitary<R: Rng + ?Sized>(dim: usize, rng: &mut R) -> Self {
+        let gaussian = DMatrix::new(dim, (0..dim * dim).map(|_| normal(rng)).collect()).unwrap();
+        let (mut q, r) = gaussian.qr();
+
+        for j in 0..dim {
+            let phase = c64::cis(r[(j, j)].arg());
+            (0..dim).for_each(|i| q[(i, j)] *= phase);
+        }
+
+        q
+    }
+}
+
+impl<const N: usize> UnitaryMatrix<N> {
+    /// Returns a random unitary matrix, drawn from the Haar measure.
+    pub fn random<R: Rng + ?Sized>(rng: &mut R) -> Self {
+        let mat = Matrix::try_from(DMatrix::random_unitary(N, rng)).unwrap();
+        Self::new_unchecked(mat)
+    }
+}
+
+impl Su2 {
+    /// Returns a random special unitary matrix, drawn from the Haar measure.
+    ///
+    /// $ (\alpha, \beta) $ is drawn uniformly from the unit sphere of $ \mathbb{C}^2 $.
+    pub fn random<R: Rng + ?Sized>(rng: &mut R) -> Self {
+        let (alpha, beta) = (normal(rng), normal(rng));
+        let norm = (alpha.abs_sqr() + beta.abs_sqr()).sqrt();
+        Self::new_unchecked(alpha / norm, beta / norm)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use crate::linalg::{c64, DMatrix, Su2};
+
+    const SAMPLES: usize = 4000;
+
+    #[test]
+    fn random_unitaries_are_unitary() {
+        let mut rng = StdRng::seed_from_u64(0);
+        assert!((1..6).all(|dim| DMatrix::random_unitary(dim, &mut rng).is_unitary()));
+    }
+
+    #[test]
+    fn random_unitaries_follow_haar_moments() {
+        // For the Haar measure, $ E[|U_{ij}|^2] = 1/d $ and $ E[|U_{ij}|^4] = 2/(d(d+1)) $.
+        let dim = 4;
+        let mut rng = StdRng::seed_from_u64(1);
+        let (mut second, mut fourth, mut trace) = (0.0, 0.0, c64::ZERO);
+
+        for _ in 0..SAMPLES {
+            let u = DMatrix::random_unitary(dim, &mut rng);
+            let p = u[(1, 2)].abs_sqr();
+            second += p;
+            fourth += p * p;
+            trace += u.trace();
+        }
+
+        let n = SAMPLES as f64;
+        assert!((second / n - 0.25).abs() < 0.01);
+        assert!((fourth / n - 0.1).abs() < 0.01);
+        // The trace has mean zero and unit variance.
+        assert!((trace / n).abs() < 0.05);
+    }
+
+    #[test]
+    fn random_su2_is_uniform_on_the_sphere() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let mut mean = c64::ZERO;
+        let mut second = 0.0;
+
+        for _ in 0..SAMPLES {
+            let su2 = Su2::random(&mut rng);
+            let (alpha, beta) = (su2.alpha(), su2.beta());
+            assert!((alpha.abs_sqr() + beta.abs_sqr() - 1.0).abs() < 1E-12);
+            mean += alpha;
+            second += alpha.abs_sqr();
+        }
+
+        let n = SAMPLES as f64;
+        assert!((mean / n).abs() < 0.03);
+        assert!((second / n - 0.5).abs() < 0.02);
+    }
+}
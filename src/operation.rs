@@ -1,6 +1,13 @@
+use std::f64::consts::{FRAC_1_SQRT_2, FRAC_PI_2, FRAC_PI_4, PI};
+use std::ops::Neg;
+
 use crate::bitset::BitSet;
+use crate::definition::GateRef;
+use crate::gradient::{Generator, ShiftRule};
+use crate::linalg::{c64, DMatrix};
+use crate::transpiler;
 
-use super::instruction::{Compute, InstrFlags};
+use super::instruction::{Blocks, Compute, ForLoop, InstrFlags};
 use super::storage;
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Debug)]
@@ -8,7 +15,7 @@ pub struct Arity(u32);
 
 impl Arity {
     pub fn new(n: u32) -> Option<Self> {
-        (n != u32::MAX).then(|| Self(n))
+        (n != u32::MAX).then_some(Self(n))
     }
 
     pub fn variadic() -> Self {
@@ -24,7 +31,7 @@ impl Arity {
     }
 
     pub fn get(self) -> Option<u32> {
-        self.is_definite().then(|| self.0)
+        self.is_definite().then_some(self.0)
     }
 }
 
@@ -34,6 +41,12 @@ impl From<u32> for Arity {
     }
 }
 
+/// Expands to `Some(OpKind::$name)` if the operation has no payload, or `None` otherwise.
+macro_rules! without_payload {
+    ($name: ident) => { Some(OpKind::$name) };
+    ($name: ident $payload: ty) => { None };
+}
+
 macro_rules! operations {
     {
         $(
@@ -77,6 +90,15 @@ macro_rules! operations {
                 }
             }
 
+            /// Returns the integer identifying the kind of the operation in it's compact
+            /// representation.
+            #[allow(unused_variables)]
+            pub(crate) fn discriminant(&self) -> u16 {
+                match self {
+                    $(Self::$name $(($inner))? => $int,)*
+                }
+            }
+
             /// Reads the operation kind along with it's associated flags from the destination.
             pub(crate) fn read(src: &mut &'id [u32]) -> (Self, InstrFlags) {
                 let (flags, id): (InstrFlags, u16) = storage::read(src);
@@ -125,6 +147,15 @@ macro_rules! operations {
                     $(Self::$name $(($inner))? => $label,)*
                 }
             }
+
+            /// Returns the operation with the given label, if there is one and it
+            /// has no payload.
+            pub fn from_label(label: &str) -> Option<Self> {
+                match label {
+                    $($label => without_payload!($name $($payload)?),)*
+                    _ => None,
+                }
+            }
         }
     }
 }
@@ -146,6 +177,250 @@ operations! {
         unitary: true,
         label: "h",
     },
+    /// Pauli X gate, or NOT gate.
+    X = 2 {
+        qubits: 1,
+        bits: 0,
+        parameters: 0,
+        unitary: true,
+        label: "x",
+    },
+    /// Pauli Y gate.
+    Y = 3 {
+        qubits: 1,
+        bits: 0,
+        parameters: 0,
+        unitary: true,
+        label: "y",
+    },
+    /// Pauli Z gate.
+    Z = 4 {
+        qubits: 1,
+        bits: 0,
+        parameters: 0,
+        unitary: true,
+        label: "z",
+    },
+    /// Phase gate, square root of Z.
+    S = 5 {
+        qubits: 1,
+        bits: 0,
+        parameters: 0,
+        unitary: true,
+        label: "s",
+    },
+    /// Adjoint of the S gate.
+    Sdg = 6 {
+        qubits: 1,
+        bits: 0,
+        parameters: 0,
+        unitary: true,
+        label: "sdg",
+    },
+    /// T gate, fourth root of Z.
+    T = 7 {
+        qubits: 1,
+        bits: 0,
+        parameters: 0,
+        unitary: true,
+        label: "t",
+    },
+    /// Adjoint of the T gate.
+    Tdg = 8 {
+        qubits: 1,
+        bits: 0,
+        parameters: 0,
+        unitary: true,
+        label: "tdg",
+    },
+    /// Square root of X.
+    SX = 9 {
+        qubits: 1,
+        bits: 0,
+        parameters: 0,
+        unitary: true,
+        label: "sx",
+    },
+    /// Adjoint of the SX gate.
+    SXdg = 10 {
+        qubits: 1,
+        bits: 0,
+        parameters: 0,
+        unitary: true,
+        label: "sxdg",
+    },
+    /// Rotation of angle $ \theta $ around the X axis.
+    RX = 11 {
+        qubits: 1,
+        bits: 0,
+        parameters: 1,
+        unitary: true,
+        label: "rx",
+    },
+    /// Rotation of angle $ \theta $ around the Y axis.
+    RY = 12 {
+        qubits: 1,
+        bits: 0,
+        parameters: 1,
+        unitary: true,
+        label: "ry",
+    },
+    /// Rotation of angle $ \theta $ around the Z axis.
+    RZ = 13 {
+        qubits: 1,
+        bits: 0,
+        parameters: 1,
+        unitary: true,
+        label: "rz",
+    },
+    /// Phase shift of angle $ \lambda $ on the $ |1 \rangle $ state.
+    Phase = 14 {
+        qubits: 1,
+        bits: 0,
+        parameters: 1,
+        unitary: true,
+        label: "p",
+    },
+    /// Generic single qubit gate, parametrized by the euler angles $ \theta, \phi, \lambda $.
+    U = 15 {
+        qubits: 1,
+        bits: 0,
+        parameters: 3,
+        unitary: true,
+        label: "u",
+    },
+    /// Controlled X gate, the first qubit is the control.
+    CX = 20 {
+        qubits: 2,
+        bits: 0,
+        parameters: 0,
+        unitary: true,
+        label: "cx",
+    },
+    /// Controlled Y gate, the first qubit is the control.
+    CY = 21 {
+        qubits: 2,
+        bits: 0,
+        parameters: 0,
+        unitary: true,
+        label: "cy",
+    },
+    /// Controlled Z gate.
+    CZ = 22 {
+        qubits: 2,
+        bits: 0,
+        parameters: 0,
+        unitary: true,
+        label: "cz",
+    },
+    /// Swaps the state of two qubits.
+    Swap = 23 {
+        qubits: 2,
+        bits: 0,
+        parameters: 0,
+        unitary: true,
+        label: "swap",
+    },
+    /// Controlled phase shift of angle $ \lambda $.
+    CPhase = 24 {
+        qubits: 2,
+        bits: 0,
+        parameters: 1,
+        unitary: true,
+        label: "cp",
+    },
+    /// Toffoli gate, the first two qubits are the controls.
+    CCX = 30 {
+        qubits: 3,
+        bits: 0,
+        parameters: 0,
+        unitary: true,
+        label: "ccx",
+    },
+    /// Measures the qubit in the computational basis and stores the result in the bit.
+    Measure = 50 {
+        qubits: 1,
+        bits: 1,
+        parameters: 0,
+        unitary: false,
+        label: "measure",
+    },
+    /// Resets the qubit to the $ |0 \rangle $ state.
+    Reset = 51 {
+        qubits: 1,
+        bits: 0,
+        parameters: 0,
+        unitary: false,
+        label: "reset",
+    },
+    /// Runs the first body if the bit is `true`, and the second one otherwise.
+    IfElse = 70 {
+        qubits: 0,
+        bits: 1,
+        parameters: 0,
+        unitary: false,
+        label: "if_else",
+        payload: {
+            inner: Blocks<'id>,
+            write: |dest| inner.write(dest),
+            read: Blocks::read,
+        },
+    },
+    /// Runs the body while the bit is `true`.
+    While = 71 {
+        qubits: 0,
+        bits: 1,
+        parameters: 0,
+        unitary: false,
+        label: "while",
+        payload: {
+            inner: Blocks<'id>,
+            write: |dest| inner.write(dest),
+            read: Blocks::read,
+        },
+    },
+    /// Runs the body a constant number of times.
+    For = 72 {
+        qubits: 0,
+        bits: 0,
+        parameters: 0,
+        unitary: false,
+        label: "for",
+        payload: {
+            inner: ForLoop<'id>,
+            write: |dest| inner.write(dest),
+            read: ForLoop::read,
+        },
+    },
+    /// Runs the `n`th body when the value of the bits, the first one being the least
+    /// significant, is `n`, and the last body, the default case, when there is no such body.
+    Switch = 73 {
+        qubits: 0,
+        bits: Arity::variadic(),
+        parameters: 0,
+        unitary: false,
+        label: "switch",
+        payload: {
+            inner: Blocks<'id>,
+            write: |dest| inner.write(dest),
+            read: Blocks::read,
+        },
+    },
+    /// Calls a gate of the circuit's definition table on the qubits, with the parameters, see
+    /// [`GateDefinition`](crate::definition::GateDefinition). Gates are unitary, but can only
+    /// be raised to constant integer powers.
+    Call = 90 {
+        qubits: Arity::variadic(),
+        bits: 0,
+        parameters: Arity::variadic(),
+        unitary: true,
+        label: "call",
+        payload: {
+            inner: GateRef<'id>,
+            write: |dest| storage::write(dest, *inner),
+            read: storage::read,
+        },
+    },
     /// Compute node, performs an arbitrary classical compute on bits,
     /// as defined by a custom function.
     Compute = 100 {
@@ -160,4 +435,259 @@ operations! {
             read: Compute::read,
         },
     },
-}
\ No newline at end of file
+}
+
+impl<'id> OpKind<'id> {
+    /// Returns the bodies of the operation, if it's a control flow operation.
+    pub fn blocks(&self) -> Option<&Blocks<'id>> {
+        match self {
+            Self::IfElse(blocks) | Self::While(blocks) | Self::Switch(blocks) => Some(blocks),
+            Self::For(repeat) => Some(&repeat.body),
+            _ => None,
+        }
+    }
+
+    /// Writes the payload of the control flow operation to the destination, it's bodies being
+    /// replaced by the given ones.
+    ///
+    /// Panics if the operation is not a control flow operation.
+    pub(crate) fn write_blocks(&self, dest: &mut Vec<u32>, bodies: &[&[u32]]) {
+        match self {
+            Self::IfElse(_) | Self::While(_) | Self::Switch(_) => (),
+            Self::For(repeat) => storage::write(dest, repeat.count),
+            op => panic!("operation `{}` is not a control flow operation", op.label()),
+        }
+
+        Blocks::write_parts(dest, bodies);
+    }
+
+    /// Returns the matrix of the operation, given the values of it's parameters, or `None`
+    /// if the operation is not unitary.
+    /// 
+    /// The first qubit the operation is applied to is the most significant one in the
+    /// matrix's basis.
+    /// 
+    /// Panics if the number of parameters does not match the arity of the operation.
+    pub fn matrix(&self, parameters: &[f64]) -> Option<DMatrix> {
+        if let Some(n) = self.parameters().get() {
+            assert_eq!(n as usize, parameters.len(), "wrong number of parameters for operation");
+        }
+
+        let z = |re, im| c64::new(re, im);
+        let (o, l, i) = (c64::ZERO, c64::ONE, c64::I);
+        let h = FRAC_1_SQRT_2;
+
+        let data = match self {
+            Self::H => vec![z(h, 0.0), z(h, 0.0), z(h, 0.0), z(-h, 0.0)],
+            Self::X => vec![o, l, l, o],
+            Self::Y => vec![o, -i, i, o],
+            Self::Z => vec![l, o, o, -l],
+            Self::S => vec![l, o, o, i],
+            Self::Sdg => vec![l, o, o, -i],
+            Self::T => vec![l, o, o, z(h, h)],
+            Self::Tdg => vec![l, o, o, z(h, -h)],
+            Self::SX => vec![z(0.5, 0.5), z(0.5, -0.5), z(0.5, -0.5), z(0.5, 0.5)],
+            Self::SXdg => vec![z(0.5, -0.5), z(0.5, 0.5), z(0.5, 0.5), z(0.5, -0.5)],
+            Self::RX => {
+                let (sin, cos) = (parameters[0] / 2.0).sin_cos();
+                vec![z(cos, 0.0), z(0.0, -sin), z(0.0, -sin), z(cos, 0.0)]
+            }
+            Self::RY => {
+                let (sin, cos) = (parameters[0] / 2.0).sin_cos();
+                vec![z(cos, 0.0), z(-sin, 0.0), z(sin, 0.0), z(cos, 0.0)]
+            }
+            Self::RZ => {
+                let half = parameters[0] / 2.0;
+                vec![c64::cis(-half), o, o, c64::cis(half)]
+            }
+            Self::Phase => vec![l, o, o, c64::cis(parameters[0])],
+            Self::U => {
+                let (theta, phi, lambda) = (parameters[0], parameters[1], parameters[2]);
+                let (sin, cos) = (theta / 2.0).sin_cos();
+                vec![
+                    z(cos, 0.0), -c64::euler(sin, lambda), 
+                    c64::euler(sin, phi), c64::euler(cos, phi + lambda),
+                ]
+            }
+            Self::CX => vec![
+                l, o, o, o,
+                o, l, o, o,
+                o, o, o, l,
+                o, o, l, o,
+            ],
+            Self::CY => vec![
+                l, o, o, o,
+                o, l, o, o,
+                o, o, o, -i,
+                o, o, i, o,
+            ],
+            Self::CZ => vec![
+                l, o, o, o,
+                o, l, o, o,
+                o, o, l, o,
+                o, o, o, -l,
+            ],
+            Self::Swap => vec![
+                l, o, o, o,
+                o, o, l, o,
+                o, l, o, o,
+                o, o, o, l,
+            ],
+            Self::CPhase => return Some(DMatrix::diag(&[l, l, l, c64::cis(parameters[0])])),
+            Self::CCX => {
+                let mut res = DMatrix::eye(8);
+                res[(6, 6)] = o;
+                res[(7, 7)] = o;
+                res[(6, 7)] = l;
+                res[(7, 6)] = l;
+                return Some(res);
+            }
+            _ => return None,
+        };
+
+        DMatrix::new(1 << self.qubits().get()?, data)
+    }
+
+    /// Returns the adjoint of the operation along with the values of it's parameters, given
+    /// their current values, or `None` if the operation is not unitary.
+    ///
+    /// The parameters may be numeric values as well as symbolic expressions, as they are
+    /// only ever negated and permuted.
+    ///
+    /// Panics if the number of parameters does not match the arity of the operation.
+    pub fn adjoint<T: Clone + Neg<Output = T>>(&self, parameters: &[T]) -> Option<(Self, Vec<T>)> {
+        if let Some(n) = self.parameters().get() {
+            assert_eq!(n as usize, parameters.len(), "wrong number of parameters for operation");
+        }
+
+        let negated = || parameters.iter().cloned().map(Neg::neg).collect();
+
+        Some(match self {
+            Self::H | Self::X | Self::Y | Self::Z | Self::CX | Self::CY | Self::CZ | Self::Swap | Self::CCX => (self.clone(), vec![]),
+            Self::S => (Self::Sdg, vec![]),
+            Self::Sdg => (Self::S, vec![]),
+            Self::T => (Self::Tdg, vec![]),
+            Self::Tdg => (Self::T, vec![]),
+            Self::SX => (Self::SXdg, vec![]),
+            Self::SXdg => (Self::SX, vec![]),
+            Self::RX | Self::RY | Self::RZ | Self::Phase | Self::CPhase => (self.clone(), negated()),
+            Self::U => (Self::U, vec![-parameters[0].clone(), -parameters[2].clone(), -parameters[1].clone()]),
+            _ => return None,
+        })
+    }
+
+    /// Returns gates implementing the operation to the real power `exponent`, or `None` if
+    /// the operation is not unitary. Each gate is given by it's operation, the indices of the
+    /// qubits of the operation it acts on and the values of it's parameters.
+    ///
+    /// Rotations and phase shifts have their angle scaled by the exponent, while the power
+    /// of other operations is taken on the principal branch, with the phases of the
+    /// eigenvalues in $ (-\pi, \pi] $. The gates are exact, including the global phase, so
+    /// that the power may be controlled.
+    ///
+    /// Panics if the number of parameters does not match the arity of the operation.
+    pub fn power(&self, parameters: &[f64], exponent: f64) -> Option<Vec<transpiler::Gate<'id>>> {
+        use OpKind::*;
+
+        if let Some(n) = self.parameters().get() {
+            assert_eq!(n as usize, parameters.len(), "wrong number of parameters for operation");
+        }
+
+        let t = exponent;
+        let gate = |op, qubits: &[usize], parameters: &[f64]| (op, qubits.to_vec(), parameters.to_vec());
+
+        // $ X^t = H Z^t H $, and $ Z^t $ is a phase shift.
+        let x = |angle: f64| vec![gate(H, &[0], &[]), gate(Phase, &[0], &[angle]), gate(H, &[0], &[])];
+        // $ CX^t $ is conjugated in the same way.
+        let cx = |c: usize, t: usize, angle: f64| vec![gate(H, &[t], &[]), gate(CPhase, &[c, t], &[angle]), gate(H, &[t], &[])];
+
+        Some(match self {
+            // $ H = R_y(\pi / 4) Z R_y(-\pi / 4) $.
+            H => vec![gate(RY, &[0], &[-FRAC_PI_4]), gate(Phase, &[0], &[PI * t]), gate(RY, &[0], &[FRAC_PI_4])],
+            X => x(PI * t),
+            // $ Y = S X S^\dagger $.
+            Y => [vec![gate(Sdg, &[0], &[])], x(PI * t), vec![gate(S, &[0], &[])]].concat(),
+            Z => vec![gate(Phase, &[0], &[PI * t])],
+            S => vec![gate(Phase, &[0], &[FRAC_PI_2 * t])],
+            Sdg => vec![gate(Phase, &[0], &[-FRAC_PI_2 * t])],
+            T => vec![gate(Phase, &[0], &[FRAC_PI_4 * t])],
+            Tdg => vec![gate(Phase, &[0], &[-FRAC_PI_4 * t])],
+            SX => x(FRAC_PI_2 * t),
+            SXdg => x(-FRAC_PI_2 * t),
+            RX | RY | RZ | Phase | CPhase => vec![gate(self.clone(), &self.qubit_indices(), &[parameters[0] * t])],
+            U => {
+                // $ e^{i \alpha} R_z(\beta) R_y(\gamma) R_z(\delta) $, the phase being carried by
+                // a phase shift, as $ P(2 \alpha) = e^{i \alpha} R_z(2 \alpha) $.
+                let (alpha, beta, gamma, delta) = transpiler::zyz(&self.matrix(parameters)?.pow_unitary(t).ok()?);
+                vec![
+                    gate(RZ, &[0], &[delta]), gate(RY, &[0], &[gamma]),
+                    gate(RZ, &[0], &[beta - 2.0 * alpha]), gate(Phase, &[0], &[2.0 * alpha]),
+                ]
+            }
+            CX => cx(0, 1, PI * t),
+            CY => [vec![gate(Sdg, &[1], &[])], cx(0, 1, PI * t), vec![gate(S, &[1], &[])]].concat(),
+            CZ => vec![gate(CPhase, &[0, 1], &[PI * t])],
+            // $ SWAP = CX_{01} CX_{10} CX_{01} $, and $ CX_{01} $ is it's own inverse.
+            Swap => [vec![gate(CX, &[0, 1], &[])], cx(1, 0, PI * t), vec![gate(CX, &[0, 1], &[])]].concat(),
+            CCX => {
+                // The doubly controlled phase shift, as $ 2ab = a + b - (a \oplus b) $.
+                let half = PI * t / 2.0;
+                vec![
+                    gate(H, &[2], &[]),
+                    gate(CPhase, &[1, 2], &[half]), gate(CX, &[0, 1], &[]), gate(CPhase, &[1, 2], &[-half]),
+                    gate(CX, &[0, 1], &[]), gate(CPhase, &[0, 2], &[half]),
+                    gate(H, &[2], &[]),
+                ]
+            }
+            _ => return None,
+        })
+    }
+
+    /// Returns the matrix of the operation to the real power `exponent`, see [`OpKind::power`],
+    /// or `None` if the operation is not unitary.
+    ///
+    /// Panics if the number of parameters does not match the arity of the operation.
+    pub fn power_matrix(&self, parameters: &[f64], exponent: f64) -> Option<DMatrix> {
+        gates_matrix(self.qubits().get()? as usize, &self.power(parameters, exponent)?)
+    }
+
+    /// Returns the indices of the qubits of an operation of definite arity.
+    fn qubit_indices(&self) -> Vec<usize> {
+        (0..self.qubits().get().unwrap_or(0) as usize).collect()
+    }
+
+    /// Returns the generator $ G $ of the operation, seen as a function $ e^{-i x G} $ of its
+    /// `index`th parameter $ x $ up to fixed gates, if it is one.
+    pub fn generator(&self, index: usize) -> Option<Generator> {
+        if index >= self.parameters().get()? as usize {
+            return None;
+        }
+
+        match (self, index) {
+            // $ U(\theta, \phi, \lambda) = P(\phi) R_y(\theta) P(\lambda) $.
+            (Self::RX | Self::RY | Self::RZ, _) | (Self::U, 0) => Some(Generator::Rotation),
+            (Self::Phase | Self::CPhase | Self::U, _) => Some(Generator::Phase),
+            _ => None,
+        }
+    }
+
+    /// Returns the parameter-shift rule giving the derivative of expectation values with
+    /// respect to the `index`th parameter of the operation, if there is one.
+    pub fn shift_rule(&self, index: usize) -> Option<ShiftRule> {
+        self.generator(index).map(|generator| generator.shift_rule(false, 1.0))
+    }
+}
+
+/// Returns the matrix of the product of the gates on `n` qubits, given by the indices of the
+/// qubits they act on, or `None` if one of them is not unitary. The first qubit is the most
+/// significant one.
+pub(crate) fn gates_matrix(n: usize, gates: &[transpiler::Gate<'_>]) -> Option<DMatrix> {
+    let mut res = DMatrix::eye(1 << n);
+
+    for (op, qubits, parameters) in gates {
+        let positions: Vec<_> = qubits.iter().map(|q| n - 1 - q).collect();
+        res.apply(&op.matrix(parameters)?, &positions);
+    }
+
+    Some(res)
+}
@@ -5,6 +5,9 @@ use crate::linalg::c64;
 
 use super::symbol::{FormalParameter, Symbol};
 
+/// Mask of the mantissa bits of an `f32`.
+const MANTISSA_MASK: u32 = (1 << (f32::MANTISSA_DIGITS - 1)) - 1;
+
 #[repr(transparent)]
 #[derive(Copy, Clone, Eq, Debug)]
 pub struct Parameter<'id> {
@@ -17,17 +20,42 @@ impl<'id> Parameter<'id> {
     /// can't reach this level of precision nowadays.
     pub const PRECISION: f32 = 1E-5;
 
+    /// The maximum number of expressions that may be referenced by parameters.
+    pub const MAX_EXPRS: u32 = 1 << (f32::MANTISSA_DIGITS - 1);
+
     /// Returns a new `Parameter` from it's bits.
     fn new(bits: u32) -> Self {
         Self { bits, _id: Id::default() }
     }
 
+    /// Returns a new `Parameter` referencing the `index`th expression of the circuit's
+    /// expression table. Expressions are boxed in the negative non-finite floats, while
+    /// formal parameters are boxed in the positive ones.
+    pub(crate) fn expr(index: u32) -> Self {
+        Self::new((index & MANTISSA_MASK) | f32::NEG_INFINITY.to_bits())
+    }
+
+    /// Returns the same parameter, with a different brand.
+    pub(crate) fn rebrand<'a>(self) -> Parameter<'a> {
+        Parameter::new(self.bits)
+    }
+
     pub fn is_value(self) -> bool {
         f32::from_bits(self.bits).is_finite()
     }
 
     pub fn is_formal(self) -> bool {
-        !self.is_value()
+        !self.is_value() && f32::from_bits(self.bits).is_sign_positive()
+    }
+
+    pub fn is_expr(self) -> bool {
+        !self.is_value() && f32::from_bits(self.bits).is_sign_negative()
+    }
+
+    /// Returns the index of the expression referenced by the parameter in the
+    /// circuit's expression table, if it is an expression.
+    pub(crate) fn as_expr(self) -> Option<u32> {
+        self.is_expr().then_some(self.bits & MANTISSA_MASK)
     }
 
     pub fn as_value(self) -> Option<f32> {
@@ -39,6 +67,8 @@ impl<'id> Parameter<'id> {
     }
 }
 
+/// Non-finite values are reserved to formal parameters and expressions, and are
+/// converted to zero.
 impl<'id> From<f32> for Parameter<'id> {
     fn from(mut value: f32) -> Self {
         if !value.is_finite() {
@@ -51,7 +81,7 @@ impl<'id> From<f32> for Parameter<'id> {
 
 impl<'id> From<FormalParameter<'id>> for Parameter<'id> {
     fn from(formal: FormalParameter<'id>) -> Self {
-        Self::new(u32::from(formal.id()) | f32::INFINITY.to_bits())
+        Self::new(formal.id() | f32::INFINITY.to_bits())
     }
 }
 
@@ -93,7 +123,6 @@ impl<'id> TryFrom<Parameter<'id>> for FormalParameter<'id> {
     type Error = NotFormal;
 
     fn try_from(param: Parameter) -> Result<Self, Self::Error> {
-        const MANTISSA_MASK: u32 = (1 << f32::MANTISSA_DIGITS) - 1;
         param.is_formal().then(|| Self::new_unchecked(param.bits & MANTISSA_MASK)).ok_or(NotFormal)
     }
 }
\ No newline at end of file
@@ -0,0 +1,615 @@
+//! Pauli strings and weighted sums of Pauli strings, used as observables and hamiltonians.
+//!
+//! Pauli strings are stored in the symplectic representation: one bit vector for the
+//! X components and one for the Z components, with $ Y $ having both bits set.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::{Add, Mul};
+use std::str::FromStr;
+
+use thiserror::Error;
+
+use crate::bitset::BitSet;
+use crate::linalg::{c64, DMatrix};
+
+/// The single qubit Pauli operators.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Pauli {
+    I,
+    X,
+    Y,
+    Z,
+}
+
+impl Pauli {
+    /// Returns the pauli operator from it's X and Z components.
+    fn from_bits(x: bool, z: bool) -> Self {
+        match (x, z) {
+            (false, false) => Self::I,
+            (true, false) => Self::X,
+            (true, true) => Self::Y,
+            (false, true) => Self::Z,
+        }
+    }
+
+    /// Returns the X and Z components of the pauli operator.
+    fn bits(self) -> (bool, bool) {
+        match self {
+            Self::I => (false, false),
+            Self::X => (true, false),
+            Self::Y => (true, true),
+            Self::Z => (false, true),
+        }
+    }
+
+    fn label(self) -> char {
+        match self {
+            Self::I => 'I',
+            Self::X => 'X',
+            Self::Y => 'Y',
+            Self::Z => 'Z',
+        }
+    }
+}
+
+/// Returns the power of $ i $ in the product of the single qubit Pauli operators
+/// $ \sigma_1 \sigma_2 = i^g \sigma_3 $, from their X and Z components.
+fn product_phase((x1, z1): (bool, bool), (x2, z2): (bool, bool)) -> i32 {
+    let (x2, z2) = (x2 as i32, z2 as i32);
+    match (x1, z1) {
+        (false, false) => 0,
+        (true, true) => z2 - x2,
+        (true, false) => z2 * (2 * x2 - 1),
+        (false, true) => x2 * (1 - 2 * z2),
+    }
+}
+
+/// An n-qubit Pauli string with a phase, $ i^k P_{n-1} \otimes \dots \otimes P_0 $.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct PauliString {
+    x: BitSet,
+    z: BitSet,
+    phase: u8,
+}
+
+impl PauliString {
+    /// Returns the identity on `num_qubits` qubits.
+    pub fn identity(num_qubits: usize) -> Self {
+        Self { x: BitSet::new(num_qubits), z: BitSet::new(num_qubits), phase: 0 }
+    }
+
+    /// Returns the Pauli string on `num_qubits` qubits, acting with the given
+    /// operators on the given qubits and as the identity elsewhere.
+    ///
+    /// Panics if a qubit is out of range.
+    pub fn from_sparse(num_qubits: usize, paulis: &[(usize, Pauli)]) -> Self {
+        let mut res = Self::identity(num_qubits);
+        paulis.iter().for_each(|&(qubit, pauli)| res.set(qubit, pauli));
+        res
+    }
+
+    pub fn num_qubits(&self) -> usize {
+        self.x.len()
+    }
+
+    /// Returns the power of $ i $ of the global phase of the string.
+    pub fn phase(&self) -> u8 {
+        self.phase
+    }
+
+    pub fn set_phase(&mut self, phase: u8) {
+        self.phase = phase % 4;
+    }
+
+    /// Returns the phase as a complex number.
+    pub fn phase_factor(&self) -> c64 {
+        [c64::ONE, c64::I, -c64::ONE, -c64::I][self.phase as usize]
+    }
+
+    /// Returns the X components of the string.
+    pub fn x(&self) -> &BitSet {
+        &self.x
+    }
+
+    /// Returns the Z components of the string.
+    pub fn z(&self) -> &BitSet {
+        &self.z
+    }
+
+    /// Returns the Pauli operator acting on the qubit.
+    ///
+    /// Panics if the qubit is out of range.
+    pub fn get(&self, qubit: usize) -> Pauli {
+        Pauli::from_bits(self.x.get(qubit).unwrap(), self.z.get(qubit).unwrap())
+    }
+
+    /// Sets the Pauli operator acting on the qubit.
+    ///
+    /// Panics if the qubit is out of range.
+    pub fn set(&mut self, qubit: usize, pauli: Pauli) {
+        let (x, z) = pauli.bits();
+        self.x.set(qubit, x).unwrap();
+        self.z.set(qubit, z).unwrap();
+    }
+
+    /// Returns the number of qubits the string acts non-trivially on.
+    pub fn weight(&self) -> usize {
+        (&self.x | &self.z).count_ones()
+    }
+
+    /// Returns the qubits the string acts non-trivially on.
+    pub fn support(&self) -> Vec<usize> {
+        (&self.x | &self.z).ones().collect()
+    }
+
+    pub fn is_identity(&self) -> bool {
+        !self.x.any() && !self.z.any()
+    }
+
+    /// Returns `true` if the string is diagonal in the computational basis.
+    pub fn is_diagonal(&self) -> bool {
+        !self.x.any()
+    }
+
+    /// Returns `true` if the two strings commute.
+    pub fn commutes(&self, rhs: &Self) -> bool {
+        ((&self.x & &rhs.z).count_ones() + (&self.z & &rhs.x).count_ones()).is_multiple_of(2)
+    }
+
+    /// Returns `true` if the two strings commute on every qubit.
+    pub fn qubit_wise_commutes(&self, rhs: &Self) -> bool {
+        (0..self.num_qubits()).all(|q| {
+            let (a, b) = (self.get(q), rhs.get(q));
+            a == Pauli::I || b == Pauli::I || a == b
+        })
+    }
+
+    /// Returns the same string, without it's phase.
+    pub fn without_phase(&self) -> Self {
+        Self { phase: 0, ..self.clone() }
+    }
+
+    /// Flips the sign of the string if `flip` is `true`.
+    fn flip_sign(&mut self, flip: bool) {
+        if flip {
+            self.phase = (self.phase + 2) % 4;
+        }
+    }
+
+    /// Conjugates the string by the Hadamard gate on the qubit, $ P \mapsto H P H^\dagger $.
+    pub fn conjugate_h(&mut self, qubit: usize) {
+        let (x, z) = self.get(qubit).bits();
+        self.flip_sign(x && z);
+        self.set(qubit, Pauli::from_bits(z, x));
+    }
+
+    /// Conjugates the string by the S gate on the qubit, $ P \mapsto S P S^\dagger $.
+    pub fn conjugate_s(&mut self, qubit: usize) {
+        let (x, z) = self.get(qubit).bits();
+        self.flip_sign(x && z);
+        self.set(qubit, Pauli::from_bits(x, z ^ x));
+    }
+
+    /// Conjugates the string by the S† gate on the qubit, $ P \mapsto S^\dagger P S $.
+    pub fn conjugate_sdg(&mut self, qubit: usize) {
+        let (x, z) = self.get(qubit).bits();
+        self.flip_sign(x && !z);
+        self.set(qubit, Pauli::from_bits(x, z ^ x));
+    }
+
+    /// Conjugates the string by the CX gate, $ P \mapsto CX P CX^\dagger $.
+    pub fn conjugate_cx(&mut self, control: usize, target: usize) {
+        let (xc, zc) = self.get(control).bits();
+        let (xt, zt) = self.get(target).bits();
+        self.flip_sign(xc && zt && !(xt ^ zc));
+        self.set(control, Pauli::from_bits(xc, zc ^ zt));
+        self.set(target, Pauli::from_bits(xt ^ xc, zt));
+    }
+
+    /// Conjugates the string by the CZ gate, $ P \mapsto CZ P CZ^\dagger $.
+    pub fn conjugate_cz(&mut self, qubit1: usize, qubit2: usize) {
+        self.conjugate_h(qubit2);
+        self.conjugate_cx(qubit1, qubit2);
+        self.conjugate_h(qubit2);
+    }
+
+    /// Returns the matrix of the string. Qubit `q` corresponds to the `q`th bit of the
+    /// indices of the basis states.
+    pub fn to_matrix(&self) -> DMatrix {
+        let n = self.num_qubits();
+        let as_int = |bits: &BitSet| bits.ones().fold(0usize, |acc, q| acc | (1 << q));
+        let (x, z) = (as_int(&self.x), as_int(&self.z));
+        let num_y = (&self.x & &self.z).count_ones();
+        let phase = [c64::ONE, c64::I, -c64::ONE, -c64::I][(self.phase as usize + num_y) % 4];
+
+        let mut res = DMatrix::zeros(1 << n);
+        for col in 0..1 << n {
+            let sign = if (col & z).count_ones().is_multiple_of(2) { 1.0 } else { -1.0 };
+            res[(col ^ x, col)] = phase * sign;
+        }
+        res
+    }
+}
+
+impl Mul<Self> for &PauliString {
+    type Output = PauliString;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        assert_eq!(self.num_qubits(), rhs.num_qubits(), "pauli strings must act on the same number of qubits");
+
+        let phase = (0..self.num_qubits())
+            .map(|q| product_phase(self.get(q).bits(), rhs.get(q).bits()))
+            .sum::<i32>() + self.phase as i32 + rhs.phase as i32;
+
+        PauliString {
+            x: &self.x ^ &rhs.x,
+            z: &self.z ^ &rhs.z,
+            phase: phase.rem_euclid(4) as u8,
+        }
+    }
+}
+
+/// Formats the string with qubit `0` as the rightmost character, e.g. `-iXIZ`.
+impl fmt::Display for PauliString {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(["", "i", "-", "-i"][self.phase as usize])?;
+        (0..self.num_qubits()).rev().try_for_each(|q| write!(f, "{}", self.get(q).label()))
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Error)]
+#[error("invalid pauli string")]
+pub struct ParsePauliError;
+
+/// Parses a string such as `-iXIZ`, with qubit `0` as the rightmost character.
+impl FromStr for PauliString {
+    type Err = ParsePauliError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (phase, labels) = [("-i", 3), ("+i", 1), ("i", 1), ("-", 2), ("+", 0)]
+            .iter()
+            .find_map(|&(prefix, phase)| s.strip_prefix(prefix).map(|rest| (phase, rest)))
+            .unwrap_or((0, s));
+
+        let mut res = Self::identity(labels.len());
+        res.phase = phase;
+
+        for (q, label) in labels.chars().rev().enumerate() {
+            let pauli = match label {
+                'I' => Pauli::I,
+                'X' => Pauli::X,
+                'Y' => Pauli::Y,
+                'Z' => Pauli::Z,
+                _ => return Err(ParsePauliError),
+            };
+            res.set(q, pauli);
+        }
+
+        Ok(res)
+    }
+}
+
+/// A weighted sum of Pauli strings, $ \sum_k c_k P_k $, with complex coefficients.
+///
+/// The Pauli strings of the terms are kept without phase, the phases being absorbed
+/// in the coefficients.
+#[derive(Clone, PartialEq, Debug)]
+pub struct SparsePauliOp {
+    num_qubits: usize,
+    terms: Vec<(PauliString, c64)>,
+}
+
+impl SparsePauliOp {
+    /// The magnitude under which coefficients are dropped by [`SparsePauliOp::simplify`].
+    pub const TOLERANCE: f64 = 1E-12;
+
+    /// Returns the null operator on `num_qubits` qubits.
+    pub fn zero(num_qubits: usize) -> Self {
+        Self { num_qubits, terms: Vec::new() }
+    }
+
+    /// Returns the operator from it's terms.
+    ///
+    /// Panics if a string does not act on `num_qubits` qubits.
+    pub fn new(num_qubits: usize, terms: impl IntoIterator<Item = (PauliString, c64)>) -> Self {
+        let terms = terms.into_iter()
+            .map(|(pauli, coeff)| {
+                assert_eq!(pauli.num_qubits(), num_qubits, "pauli strings must act on the same number of qubits");
+                (pauli.without_phase(), coeff * pauli.phase_factor())
+            })
+            .collect();
+        Self { num_qubits, terms }
+    }
+
+    /// Parses the operator from a list of labels, such as `[("XX", 0.5), ("ZI", -1.0)]`.
+    pub fn from_labels(labels: &[(&str, f64)]) -> Result<Self, ParsePauliError> {
+        let terms = labels.iter()
+            .map(|&(label, coeff)| Ok((label.parse::<PauliString>()?, c64::from(coeff))))
+            .collect::<Result<Vec<_>, _>>()?;
+        let num_qubits = terms.first().map_or(0, |(pauli, _)| pauli.num_qubits());
+
+        if terms.iter().any(|(pauli, _)| pauli.num_qubits() != num_qubits) {
+            return Err(ParsePauliError);
+        }
+
+        Ok(Self::new(num_qubits, terms))
+    }
+
+    pub fn num_qubits(&self) -> usize {
+        self.num_qubits
+    }
+
+    pub fn terms(&self) -> &[(PauliString, c64)] {
+        &self.terms
+    }
+
+    pub fn len(&self) -> usize {
+        self.terms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    /// Returns `true` if every coefficient is real, which makes the operator hermitian.
+    pub fn is_hermitian(&self) -> bool {
+        self.simplify().terms.iter().all(|(_, coeff)| coeff.im.abs() <= Self::TOLERANCE)
+    }
+
+    pub fn scale(&self, factor: c64) -> Self {
+        Self {
+            num_qubits: self.num_qubits,
+            terms: self.terms.iter().map(|(pauli, coeff)| (pauli.clone(), coeff * factor)).collect(),
+        }
+    }
+
+    /// Returns the operator with duplicate strings merged and null terms removed.
+    /// The first occurence of each string determines the order of the terms.
+    pub fn simplify(&self) -> Self {
+        let mut indices: HashMap<PauliString, usize> = HashMap::new();
+        let mut terms: Vec<(PauliString, c64)> = Vec::new();
+
+        for (pauli, coeff) in &self.terms {
+            match indices.get(pauli) {
+                Some(&index) => terms[index].1 += coeff,
+                None => {
+                    indices.insert(pauli.clone(), terms.len());
+                    terms.push((pauli.clone(), *coeff));
+                }
+            }
+        }
+
+        terms.retain(|(_, coeff)| coeff.abs() > Self::TOLERANCE);
+        Self { num_qubits: self.num_qubits, terms }
+    }
+
+    /// Returns `true` if the two operators commute, i.e. their commutator simplifies to zero.
+    pub fn commutes(&self, rhs: &Self) -> bool {
+        // Only pairs of anticommuting strings contribute to the commutator, with twice their product.
+        let commutator = Self::new(self.num_qubits, self.terms.iter().flat_map(|(p, a)| {
+            rhs.terms.iter()
+                .filter(|(q, _)| !p.commutes(q))
+                .map(move |(q, b)| (p * q, 2.0 * a * b))
+        }));
+        commutator.simplify().is_empty()
+    }
+
+    /// Returns the matrix of the operator, see [`PauliString::to_matrix`].
+    pub fn to_matrix(&self) -> DMatrix {
+        self.terms.iter().fold(DMatrix::zeros(1 << self.num_qubits), |acc, (pauli, coeff)| {
+            &acc + &pauli.to_matrix().scale(*coeff)
+        })
+    }
+}
+
+impl Add<Self> for &SparsePauliOp {
+    type Output = SparsePauliOp;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        assert_eq!(self.num_qubits, rhs.num_qubits, "operators must act on the same number of qubits");
+        let terms = self.terms.iter().chain(&rhs.terms).cloned().collect();
+        SparsePauliOp { num_qubits: self.num_qubits, terms }.simplify()
+    }
+}
+
+impl Mul<Self> for &SparsePauliOp {
+    type Output = SparsePauliOp;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        assert_eq!(self.num_qubits, rhs.num_qubits, "operators must act on the same number of qubits");
+        let terms = self.terms.iter().flat_map(|(p, a)| rhs.terms.iter().map(move |(q, b)| (p * q, a * b)));
+        SparsePauliOp::new(self.num_qubits, terms).simplify()
+    }
+}
+
+impl From<PauliString> for SparsePauliOp {
+    fn from(pauli: PauliString) -> Self {
+        Self::new(pauli.num_qubits(), [(pauli, c64::ONE)])
+    }
+}
+
+impl fmt::Display for SparsePauliOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.terms.is_empty() {
+            return f.write_str("0");
+        }
+
+        self.terms.iter().enumerate().try_for_each(|(k, (pauli, coeff))| {
+            if k > 0 {
+                f.write_str(" + ")?;
+            }
+            write!(f, "({coeff}) {pauli}")
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::linalg::{c64, DMatrix};
+
+    use super::{Pauli, PauliString, SparsePauliOp};
+
+    /// Returns every Pauli string on `num_qubits` qubits, without phase.
+    fn strings(num_qubits: usize) -> Vec<PauliString> {
+        (0..1 << (2 * num_qubits))
+            .map(|index: usize| {
+                let paulis: Vec<_> = (0..num_qubits)
+                    .map(|q| (q, [Pauli::I, Pauli::X, Pauli::Y, Pauli::Z][(index >> (2 * q)) & 3]))
+                    .collect();
+                PauliString::from_sparse(num_qubits, &paulis)
+            })
+            .collect()
+    }
+
+    fn string(label: &str) -> PauliString {
+        label.parse().unwrap()
+    }
+
+    fn assert_close(lhs: &DMatrix, rhs: &DMatrix) {
+        assert!((lhs - rhs).norm() < 1E-12);
+    }
+
+    /// Returns the matrix of the single qubit gate on the qubit, among `num_qubits` qubits.
+    fn single(num_qubits: usize, gate: [[c64; 2]; 2], qubit: usize) -> DMatrix {
+        let mut res = DMatrix::zeros(1 << num_qubits);
+        for row in 0..1 << num_qubits {
+            for col in 0..1 << num_qubits {
+                if (row ^ col) & !(1 << qubit) == 0 {
+                    res[(row, col)] = gate[(row >> qubit) & 1][(col >> qubit) & 1];
+                }
+            }
+        }
+        res
+    }
+
+    /// Returns the matrix of the controlled Pauli on the target, among `num_qubits` qubits.
+    fn controlled(num_qubits: usize, pauli: Pauli, control: usize, target: usize) -> DMatrix {
+        let mut res = DMatrix::eye(1 << num_qubits);
+        let op = PauliString::from_sparse(num_qubits, &[(target, pauli)]).to_matrix();
+        for row in 0..1 << num_qubits {
+            for col in (0..1 << num_qubits).filter(|col| col >> control & 1 == 1) {
+                res[(row, col)] = op[(row, col)];
+            }
+        }
+        res
+    }
+
+    #[test]
+    fn clifford_conjugations_match_matrices() {
+        let h = c64::from(std::f64::consts::FRAC_1_SQRT_2);
+        let (zero, one) = (c64::from(0.0), c64::ONE);
+        let hadamard = |q| single(3, [[h, h], [h, -h]], q);
+        let s = |q| single(3, [[one, zero], [zero, c64::I]], q);
+        let sdg = |q| single(3, [[one, zero], [zero, -c64::I]], q);
+
+        for mut p in strings(3) {
+            p.set_phase(1);
+
+            for (q, r) in [(0, 1), (2, 0), (1, 2)] {
+                let check = |conjugate: &dyn Fn(&mut PauliString), gate: &DMatrix| {
+                    let mut conjugated = p.clone();
+                    conjugate(&mut conjugated);
+                    assert_close(&conjugated.to_matrix(), &(&(gate * &p.to_matrix()) * &gate.adjoint()));
+                };
+
+                check(&|p| p.conjugate_h(q), &hadamard(q));
+                check(&|p| p.conjugate_s(q), &s(q));
+                check(&|p| p.conjugate_sdg(q), &sdg(q));
+                check(&|p| p.conjugate_cx(q, r), &controlled(3, Pauli::X, q, r));
+                check(&|p| p.conjugate_cz(q, r), &controlled(3, Pauli::Z, q, r));
+            }
+        }
+    }
+
+    #[test]
+    fn products_match_matrices() {
+        for p in strings(2) {
+            for q in strings(2) {
+                assert_eq!((&p * &q).to_matrix(), &p.to_matrix() * &q.to_matrix(), "{p} * {q}");
+            }
+        }
+
+        let mut p = string("XYZ");
+        p.set_phase(3);
+        let q = string("iZYX");
+        assert_eq!((&p * &q).to_matrix(), &p.to_matrix() * &q.to_matrix());
+    }
+
+    #[test]
+    fn products_have_the_right_phase() {
+        assert_eq!(&string("X") * &string("Y"), string("iZ"));
+        assert_eq!(&string("Y") * &string("X"), string("-iZ"));
+        assert_eq!(&string("Z") * &string("X"), string("iY"));
+        assert_eq!(&string("Y") * &string("Z"), string("iX"));
+        assert_eq!(&string("XX") * &string("YY"), string("-ZZ"));
+        assert_eq!(&string("iXZ") * &string("-iXZ"), string("II"));
+        assert_eq!(&string("Y") * &string("Y"), string("I"));
+    }
+
+    #[test]
+    fn commutation_matches_matrices() {
+        for p in strings(2) {
+            for q in strings(2) {
+                let (a, b) = (p.to_matrix(), q.to_matrix());
+                let commutes = &a * &b == &b * &a;
+                assert_eq!(p.commutes(&q), commutes, "{p} and {q}");
+
+                let qubit_wise = (0..2).all(|k| {
+                    let (a, b) = (PauliString::from_sparse(1, &[(0, p.get(k))]), PauliString::from_sparse(1, &[(0, q.get(k))]));
+                    a.commutes(&b)
+                });
+                assert_eq!(p.qubit_wise_commutes(&q), qubit_wise, "{p} and {q}");
+                assert!(!qubit_wise || commutes);
+            }
+        }
+
+        assert!(string("XX").commutes(&string("ZZ")));
+        assert!(!string("XX").qubit_wise_commutes(&string("ZZ")));
+        assert!(string("XI").qubit_wise_commutes(&string("XZ")));
+    }
+
+    #[test]
+    fn operators_commute_like_their_matrices() {
+        let ops = [
+            SparsePauliOp::from_labels(&[("XX", 1.0), ("ZZ", 1.0)]).unwrap(),
+            SparsePauliOp::from_labels(&[("YY", 0.5)]).unwrap(),
+            SparsePauliOp::from_labels(&[("XI", 1.0), ("IZ", -2.0)]).unwrap(),
+            SparsePauliOp::from_labels(&[("ZI", 0.3), ("IX", 1.0)]).unwrap(),
+        ];
+
+        for p in &ops {
+            for q in &ops {
+                let (a, b) = (p.to_matrix(), q.to_matrix());
+                assert_eq!(p.commutes(q), (&(&a * &b) - &(&b * &a)).norm() < 1E-12, "{p} and {q}");
+            }
+        }
+    }
+
+    #[test]
+    fn simplify_merges_and_drops_terms() {
+        let op = SparsePauliOp::new(2, [
+            (string("XZ"), c64::from(1.0)),
+            (string("-XZ"), c64::from(0.5)),
+            (string("YI"), c64::from(2.0)),
+            (string("iZZ"), c64::from(1.0)),
+            (string("YI"), c64::from(-2.0)),
+        ]);
+        let simplified = op.simplify();
+
+        assert_eq!(simplified.terms(), &[(string("XZ"), c64::from(0.5)), (string("ZZ"), c64::I)]);
+        assert_close(&simplified.to_matrix(), &op.to_matrix());
+        assert!(!simplified.is_hermitian());
+        assert!((&op + &op.scale(-c64::ONE)).is_empty());
+    }
+
+    #[test]
+    fn operator_products_match_matrices() {
+        let p = SparsePauliOp::from_labels(&[("XY", 0.5), ("ZI", -1.0), ("IZ", 2.0)]).unwrap();
+        let q = SparsePauliOp::from_labels(&[("YY", 1.5), ("XZ", 0.25)]).unwrap();
+
+        assert_close(&(&p * &q).to_matrix(), &(&p.to_matrix() * &q.to_matrix()));
+        assert_close(&(&p + &q).to_matrix(), &(&p.to_matrix() + &q.to_matrix()));
+        assert!((&p * &p).is_hermitian());
+    }
+}
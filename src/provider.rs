@@ -1,12 +1,66 @@
+use std::collections::HashMap;
+
 use async_trait::async_trait;
 
+use crate::bitset::BitSet;
 use crate::circuit::TranspiledCircuit;
 use crate::instruction::{Instr, InstrVec};
 use crate::linalg::UnitaryMatrix;
 use crate::symbol::Ancillas;
 
+/// The number of occurences of each outcome of the bits of a circuit, over many shots.
+#[derive(Clone, PartialEq, Eq, Default, Debug)]
 pub struct Histogram {
-    // TODO
+    num_bits: usize,
+    shots: u64,
+    counts: HashMap<BitSet, u64>,
+}
+
+impl Histogram {
+    /// Creates an empty histogram, for outcomes of `num_bits` bits.
+    pub fn new(num_bits: usize) -> Self {
+        Self { num_bits, shots: 0, counts: HashMap::new() }
+    }
+
+    pub fn num_bits(&self) -> usize {
+        self.num_bits
+    }
+
+    /// Returns the total number of shots recorded.
+    pub fn shots(&self) -> u64 {
+        self.shots
+    }
+
+    /// Records `count` occurences of the outcome.
+    /// 
+    /// Panics if the outcome is not of `num_bits` bits.
+    pub fn record(&mut self, outcome: BitSet, count: u64) {
+        assert_eq!(outcome.len(), self.num_bits, "outcome has the wrong number of bits");
+        *self.counts.entry(outcome).or_default() += count;
+        self.shots += count;
+    }
+
+    /// Returns the number of occurences of the outcome.
+    pub fn count(&self, outcome: &BitSet) -> u64 {
+        self.counts.get(outcome).cloned().unwrap_or(0)
+    }
+
+    /// Returns the frequency of the outcome.
+    pub fn probability(&self, outcome: &BitSet) -> f64 {
+        self.count(outcome) as f64 / self.shots.max(1) as f64
+    }
+
+    /// Returns an iterator over the outcomes and their number of occurences.
+    pub fn iter(&self) -> impl Iterator<Item = (&BitSet, u64)> + '_ {
+        self.counts.iter().map(|(outcome, &count)| (outcome, count))
+    }
+
+    /// Adds the counts of the other histogram to this one.
+    /// 
+    /// Panics if the histograms are not over the same number of bits.
+    pub fn merge(&mut self, other: &Histogram) {
+        other.iter().for_each(|(outcome, count)| self.record(outcome.clone(), count));
+    }
 }
 
 pub trait Architecture {
@@ -22,6 +76,12 @@ pub trait Architecture {
 
     fn supports<'id>(&self, instr: &Instr<'id>) -> Result<(), Self::TranspileError>;
 
+    /// Returns the time taken by the instruction on the architecture, in arbitrary units.
+    /// Every instruction takes unit time by default.
+    fn duration<'id>(&self, instr: &Instr<'id>) -> f64 {
+        1.0
+    }
+
     fn transpile<'id>(&self, instructions: InstrVec<'id>, ancillas: Option<Ancillas<'id>>) -> Result<InstrVec<'id>, Self::TranspileError>;
 }
 
@@ -0,0 +1,202 @@
+//! Generation of random quantum circuits, for fuzzing and benchmarking.
+
+use std::f32::consts::TAU;
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use thiserror::Error;
+
+use crate::circuit::{CircuitError, QuantumCircuit};
+use crate::instruction::Modifier;
+use crate::operation::OpKind;
+use crate::parameter::Parameter;
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Error)]
+pub enum RandomCircuitError {
+    #[error("unknown gate `{0}`")]
+    UnknownGate(&'static str),
+    #[error("operation `{0}` is not a unitary gate of definite arity")]
+    NotAGate(&'static str),
+    #[error("the gate set is empty or has no gate acting on few enough qubits")]
+    EmptyGateSet,
+    #[error("the modifier probability is not in [0, 1]")]
+    InvalidProbability,
+    #[error(transparent)]
+    Circuit(#[from] CircuitError),
+}
+
+/// A generator of random quantum circuits.
+///
+/// Each layer of the circuit is made of gates acting on disjoint qubits, drawn uniformly
+/// from the gate set. Parameters are drawn uniformly in $ [0, 2\pi) $.
+#[derive(Clone, PartialEq, Debug)]
+pub struct RandomCircuit {
+    /// The number of qubits of the circuit.
+    pub num_qubits: usize,
+    /// The number of layers of gates of the circuit.
+    pub depth: usize,
+    /// The labels of the gates to draw from.
+    pub gates: Vec<&'static str>,
+    /// Wether or not to measure every qubit into it's own bit at the end of the circuit.
+    pub measure: bool,
+    /// The probability for each gate to be given a random classical modifier.
+    pub modifier_probability: f64,
+}
+
+impl RandomCircuit {
+    /// Creates a new generator, with a default gate set of `h`, `x`, `s`, `t`, `rz` and `cx`,
+    /// without measurements nor modifiers.
+    pub fn new(num_qubits: usize, depth: usize) -> Self {
+        Self {
+            num_qubits,
+            depth,
+            gates: vec!["h", "x", "s", "t", "rz", "cx"],
+            measure: false,
+            modifier_probability: 0.0,
+        }
+    }
+
+    /// Generates a random circuit, deterministically from the given seed.
+    pub fn generate_seeded(&self, seed: u64) -> Result<QuantumCircuit, RandomCircuitError> {
+        self.generate(&mut StdRng::seed_from_u64(seed))
+    }
+
+    /// Generates a random circuit, using the given random number generator.
+    pub fn generate<R: Rng + ?Sized>(&self, rng: &mut R) -> Result<QuantumCircuit, RandomCircuitError> {
+        if !(0.0..=1.0).contains(&self.modifier_probability) {
+            return Err(RandomCircuitError::InvalidProbability);
+        }
+
+        // Checks the gate set beforehand, collecting each gate's arity.
+        let gates = self.gates.iter()
+            .map(|&label| {
+                let op = OpKind::from_label(label).ok_or(RandomCircuitError::UnknownGate(label))?;
+                match op.qubits().get() {
+                    Some(n) if op.is_unitary() && op.parameters().is_definite() => Ok((label, n as usize)),
+                    _ => Err(RandomCircuitError::NotAGate(label)),
+                }
+            })
+            .filter(|res| !matches!(res, Ok((_, n)) if *n > self.num_qubits))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if gates.is_empty() && self.depth > 0 {
+            return Err(RandomCircuitError::EmptyGateSet);
+        }
+
+        let with_bits = self.measure || self.modifier_probability > 0.0;
+
+        let circ = QuantumCircuit::new(|builder| {
+            let qubits = (0..self.num_qubits).map(|_| builder.qubit()).collect::<Result<Vec<_>, _>>()?;
+            let bits = if with_bits {
+                (0..self.num_qubits).map(|_| builder.bit()).collect::<Result<Vec<_>, _>>()?
+            } else {
+                Vec::new()
+            };
+
+            let mut order = qubits.clone();
+            for _ in 0..self.depth {
+                order.shuffle(rng);
+                let mut free = &order[..];
+
+                loop {
+                    let candidates: Vec<_> = gates.iter().filter(|(_, n)| *n <= free.len()).collect();
+                    let Some(&&(label, n)) = candidates.choose(rng) else {
+                        break;
+                    };
+
+                    let op = OpKind::from_label(label).unwrap();
+                    let parameters: Vec<_> = (0..op.parameters().get().unwrap())
+                        .map(|_| Parameter::from(rng.gen_range(0.0..TAU)))
+                        .collect();
+                    let (targets, rest) = free.split_at(n);
+                    free = rest;
+
+                    if !bits.is_empty() && rng.gen_bool(self.modifier_probability) {
+                        let modifier = if rng.gen() {
+                            Modifier::IfBit(*bits.choose(rng).unwrap())
+                        } else {
+                            Modifier::ForConst(rng.gen_range(1..=3))
+                        };
+                        builder.push_modified(op, targets, &[], &parameters, modifier)?;
+                    } else {
+                        builder.push(op, targets, &[], &parameters);
+                    }
+                }
+            }
+
+            if self.measure {
+                qubits.iter().zip(&bits).for_each(|(&qubit, &bit)| {
+                    builder.measure(qubit, bit);
+                });
+            }
+
+            Ok(())
+        })?;
+
+        Ok(circ)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::operation::OpKind;
+
+    use super::{RandomCircuit, RandomCircuitError};
+
+    #[test]
+    fn generation_is_deterministic() {
+        let generator = RandomCircuit::new(5, 8);
+        let (a, b) = (generator.generate_seeded(3).unwrap(), generator.generate_seeded(3).unwrap());
+
+        assert!(a.iter().eq(b.iter()));
+        assert!(!a.iter().eq(generator.generate_seeded(4).unwrap().iter()));
+    }
+
+    #[test]
+    fn layers_act_on_disjoint_qubits() {
+        let generator = RandomCircuit::new(4, 1);
+        let circ = generator.generate_seeded(0).unwrap();
+
+        let mut qubits: Vec<_> = circ.iter().flat_map(|instr| instr.qubits.to_vec()).collect();
+        let count = qubits.len();
+        qubits.sort_by_key(|qubit| qubit.id());
+        qubits.dedup();
+
+        assert!(count > 0);
+        assert_eq!(qubits.len(), count);
+    }
+
+    #[test]
+    fn measures_every_qubit() {
+        let mut generator = RandomCircuit::new(3, 2);
+        generator.measure = true;
+        let circ = generator.generate_seeded(0).unwrap();
+
+        assert_eq!(circ.num_bits(), 3);
+        assert_eq!(circ.iter().filter(|instr| instr.op == OpKind::Measure).count(), 3);
+    }
+
+    #[test]
+    fn checks_the_gate_set() {
+        let mut generator = RandomCircuit::new(1, 2);
+        generator.gates = vec!["h", "foo"];
+        assert_eq!(generator.generate_seeded(0).unwrap_err(), RandomCircuitError::UnknownGate("foo"));
+
+        generator.gates = vec!["measure"];
+        assert_eq!(generator.generate_seeded(0).unwrap_err(), RandomCircuitError::NotAGate("measure"));
+
+        // Two qubit gates are dropped from the gate set of a single qubit circuit.
+        generator.gates = vec!["cx"];
+        assert_eq!(generator.generate_seeded(0).unwrap_err(), RandomCircuitError::EmptyGateSet);
+    }
+
+    #[test]
+    fn rejects_invalid_probabilities() {
+        for probability in [-0.1, 1.5, f64::NAN, f64::INFINITY] {
+            let mut generator = RandomCircuit::new(3, 4);
+            generator.modifier_probability = probability;
+            assert_eq!(generator.generate_seeded(0).unwrap_err(), RandomCircuitError::InvalidProbability);
+        }
+    }
+}
@@ -0,0 +1,586 @@
+//! Exact simulation of noisy circuits, with density matrices.
+
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use rand::Rng;
+
+use crate::bitset::BitSet;
+use crate::circuit::{QuantumCircuit, TranspiledCircuit};
+use crate::instruction::{Instr, InstrIter, Modifier};
+use crate::linalg::{c64, DMatrix};
+use crate::operation::OpKind;
+use crate::provider::{Backend, Histogram};
+
+use super::{FullyConnected, KrausChannel, NoiseModel, SimulationError};
+
+/// Branches whose probability falls below this threshold are dropped.
+const PRUNE: f64 = 1E-14;
+
+/// The state of the simulation for a given outcome of the measurements so far.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Branch {
+    /// The values of the bits of the circuit.
+    pub bits: BitSet,
+    /// The probability of the branch.
+    pub probability: f64,
+    /// The normalized density matrix of the qubits.
+    pub state: DMatrix,
+}
+
+/// A simulator evolving the density matrix of the qubits, subject to the noise described by
+/// a [`NoiseModel`].
+///
+/// The simulation is exact: the state is split into one branch for each outcome of the bits,
+/// and shots are only sampled at the end of the circuit. As the density matrix of $ n $ qubits
+/// has $ 4^n $ entries, it is limited to [`DensityMatrixSimulator::MAX_QUBITS`] qubits.
+#[derive(Clone, Debug)]
+pub struct DensityMatrixSimulator {
+    /// The number of shots to sample.
+    pub shots: u64,
+    /// The seed of the random number generator used to sample shots, taken from the
+    /// operating system if `None`.
+    pub seed: Option<u64>,
+    /// The noise applied during the simulation.
+    pub noise: NoiseModel,
+}
+
+/// A branch, with an unnormalized density matrix whose trace is the probability of the branch.
+type RawBranch = (BitSet, DMatrix);
+
+impl DensityMatrixSimulator {
+    /// The maximum number of qubits of the simulated circuits.
+    pub const MAX_QUBITS: usize = 10;
+
+    /// Creates a simulator without noise.
+    pub fn new(shots: u64) -> Self {
+        Self { shots, seed: None, noise: NoiseModel::new() }
+    }
+
+    /// Runs the circuit, returning the final state of each branch of non-zero probability.
+    pub fn run(&self, circ: &QuantumCircuit) -> Result<Vec<Branch>, SimulationError> {
+        let n = circ.width();
+        if n > Self::MAX_QUBITS {
+            return Err(SimulationError::TooManyQubits(n, Self::MAX_QUBITS));
+        }
+
+        let inlined = circ.inlined().map_err(SimulationError::Inline)?;
+        let circ = &*inlined;
+
+        let mut rho = DMatrix::zeros(1 << n);
+        rho.raw_mut()[0] = c64::ONE;
+        let mut branches = vec![(BitSet::new(circ.num_bits()), rho)];
+
+        for instr in circ.iter() {
+            branches = self.apply_modified(circ, &instr, &instr.modifiers, branches)?;
+        }
+
+        Ok(branches.into_iter()
+            .map(|(bits, state)| {
+                let probability = state.trace().re;
+                Branch { bits, probability, state: state.scale((1.0 / probability).into()) }
+            })
+            .collect())
+    }
+
+    /// Applies the instruction to each branch, according to the given modifiers, from the
+    /// outermost to the innermost classical one.
+    fn apply_modified(&self, circ: &QuantumCircuit, instr: &Instr<'_>, modifiers: &[Modifier<'_>], branches: Vec<RawBranch>) -> Result<Vec<RawBranch>, SimulationError> {
+        let Some((modifier, inner)) = modifiers.split_last().filter(|(modifier, _)| modifier.is_classical()) else {
+            return self.apply(circ, instr, branches);
+        };
+        let apply = |branches| self.apply_modified(circ, instr, inner, branches);
+
+        let condition = |bits: &BitSet| match modifier {
+            Modifier::IfBit(bit) | Modifier::WhileBit(bit) => bits.get(bit.id() as usize).unwrap(),
+            Modifier::IfCompute(compute) | Modifier::WhileCompute(compute) => super::eval(bits, compute),
+            _ => true,
+        };
+
+        match modifier {
+            Modifier::IfBit(_) | Modifier::IfCompute(_) => {
+                let (active, mut res): (Vec<_>, Vec<_>) = branches.into_iter().partition(|(bits, _)| condition(bits));
+                res.extend(apply(active)?);
+                Ok(res)
+            }
+            Modifier::WhileBit(_) | Modifier::WhileCompute(_) => {
+                let mut res = Vec::new();
+                let mut pending = branches;
+                for _ in 0..=super::MAX_ITERATIONS {
+                    if pending.is_empty() {
+                        return Ok(merge(res));
+                    }
+
+                    let (active, done): (Vec<_>, Vec<_>) = pending.into_iter().partition(|(bits, _)| condition(bits));
+                    res.extend(done);
+                    pending = apply(active)?;
+                }
+                Err(SimulationError::IterationLimit(super::MAX_ITERATIONS))
+            }
+            Modifier::ForConst(n) => (0..*n).try_fold(branches, |branches, _| apply(branches)),
+            Modifier::ForCompute(compute) => {
+                let mut res = Vec::new();
+                for branch in branches {
+                    let n = super::eval(&branch.0, compute);
+                    res.extend((0..n).try_fold(vec![branch], |branches, _| apply(branches))?);
+                }
+                Ok(merge(res))
+            }
+            _ => unreachable!("quantum modifier after a classical one"),
+        }
+    }
+
+    /// Applies the operation of the instruction to each branch, along with it's noise.
+    fn apply(&self, circ: &QuantumCircuit, instr: &Instr<'_>, mut branches: Vec<RawBranch>) -> Result<Vec<RawBranch>, SimulationError> {
+        let qubits = super::qubits(instr);
+        let label = instr.op.label();
+
+        match &instr.op {
+            OpKind::Nop => (),
+            OpKind::Measure => {
+                self.apply_noise(label, &qubits, &mut branches)?;
+
+                let (qubit, bit) = (qubits[0], instr.bits[0].id() as usize);
+                let readout = self.noise.readout_error(qubit);
+
+                let mut res = Vec::new();
+                for (bits, rho) in branches {
+                    for outcome in [false, true] {
+                        let projected = project(&rho, qubit, outcome);
+                        let flip = readout.map_or(0.0, |error| error.flip_probability(outcome));
+
+                        for (value, p) in [(outcome, 1.0 - flip), (!outcome, flip)] {
+                            if p > 0.0 {
+                                let mut bits = bits.clone();
+                                bits.set(bit, value);
+                                res.push((bits, projected.scale(p.into())));
+                            }
+                        }
+                    }
+                }
+
+                branches = merge(res);
+            }
+            OpKind::Reset => {
+                let reset = reset_channel();
+                branches.iter_mut().for_each(|(_, rho)| *rho = apply_channel(rho, &reset, &qubits));
+                self.apply_noise(label, &qubits, &mut branches)?;
+            }
+            OpKind::Compute(compute) => {
+                branches.iter_mut().for_each(|(bits, _)| super::run_compute(bits, compute, instr.bits));
+                branches = merge(branches);
+            }
+            op if op.blocks().is_some() => branches = self.apply_control_flow(circ, instr, branches)?,
+            op if op.is_unitary() => {
+                let (gate, gate_qubits) = super::gate(circ, instr)?;
+                branches.iter_mut().for_each(|(_, rho)| *rho = conjugate(rho, &gate, &gate_qubits));
+                self.apply_noise(label, &qubits, &mut branches)?;
+            }
+            op => return Err(SimulationError::UnsupportedOp(op.label())),
+        }
+
+        Ok(branches)
+    }
+
+    /// Runs the bodies of the control flow instruction selected by the bits of each branch.
+    fn apply_control_flow(&self, circ: &QuantumCircuit, instr: &Instr<'_>, branches: Vec<RawBranch>) -> Result<Vec<RawBranch>, SimulationError> {
+        let run = |body, branches| self.apply_body(circ, body, branches);
+        let condition = |bits: &BitSet| bits.get(instr.bits[0].id() as usize).unwrap();
+
+        match &instr.op {
+            OpKind::IfElse(blocks) => {
+                let (then, otherwise): (Vec<_>, Vec<_>) = branches.into_iter().partition(|(bits, _)| condition(bits));
+                let mut res = run(blocks.get(0).unwrap(), then)?;
+                res.extend(run(blocks.get(1).unwrap(), otherwise)?);
+                Ok(merge(res))
+            }
+            OpKind::While(blocks) => {
+                let mut res = Vec::new();
+                let mut pending = branches;
+                for _ in 0..=super::MAX_ITERATIONS {
+                    if pending.is_empty() {
+                        return Ok(merge(res));
+                    }
+
+                    let (active, done): (Vec<_>, Vec<_>) = pending.into_iter().partition(|(bits, _)| condition(bits));
+                    res.extend(done);
+                    pending = run(blocks.get(0).unwrap(), active)?;
+                }
+                Err(SimulationError::IterationLimit(super::MAX_ITERATIONS))
+            }
+            OpKind::For(repeat) => (0..repeat.count).try_fold(branches, |branches, _| run(repeat.body.get(0).unwrap(), branches)),
+            OpKind::Switch(blocks) => {
+                let mut cases: Vec<Vec<RawBranch>> = (0..blocks.len()).map(|_| Vec::new()).collect();
+                for branch in branches {
+                    cases[super::case(&branch.0, instr.bits, blocks.len())].push(branch);
+                }
+
+                let mut res = Vec::new();
+                for (body, branches) in blocks.iter().zip(cases) {
+                    res.extend(run(body, branches)?);
+                }
+                Ok(merge(res))
+            }
+            op => unreachable!("`{}` is not a control flow operation", op.label()),
+        }
+    }
+
+    /// Applies the instructions of a body to each branch.
+    fn apply_body(&self, circ: &QuantumCircuit, body: InstrIter<'_>, mut branches: Vec<RawBranch>) -> Result<Vec<RawBranch>, SimulationError> {
+        for instr in body {
+            branches = self.apply_modified(circ, &instr, &instr.modifiers, branches)?;
+        }
+        Ok(branches)
+    }
+
+    /// Applies the channels attached to the operation with the given label to each branch.
+    fn apply_noise(&self, label: &'static str, qubits: &[usize], branches: &mut [RawBranch]) -> Result<(), SimulationError> {
+        for channel in self.noise.errors(label, qubits) {
+            for targets in channel.targets(label, qubits)? {
+                branches.iter_mut().for_each(|(_, rho)| *rho = apply_channel(rho, channel, targets));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Samples the shots from the final branches of the simulation.
+    fn sample(&self, num_bits: usize, branches: &[Branch]) -> Histogram {
+        let mut rng = super::rng(self.seed);
+
+        let cumulative: Vec<f64> = branches.iter()
+            .scan(0.0, |acc, branch| {
+                *acc += branch.probability;
+                Some(*acc)
+            })
+            .collect();
+        let total = cumulative.last().cloned().unwrap_or(0.0);
+
+        let mut counts = vec![0; branches.len()];
+        for _ in 0..self.shots {
+            let x = rng.gen::<f64>() * total;
+            let index = cumulative.partition_point(|&c| c <= x).min(branches.len() - 1);
+            counts[index] += 1;
+        }
+
+        let mut histogram = Histogram::new(num_bits);
+        branches.iter().zip(counts)
+            .filter(|&(_, count)| count > 0)
+            .for_each(|(branch, count)| histogram.record(branch.bits.clone(), count));
+        histogram
+    }
+}
+
+#[async_trait]
+impl Backend for DensityMatrixSimulator {
+    type Architecture = FullyConnected;
+
+    type RuntimeError = SimulationError;
+
+    fn execute(&self, circ: &TranspiledCircuit<Self::Architecture>) -> Result<Histogram, Self::RuntimeError> {
+        let branches = self.run(circ)?;
+        Ok(self.sample(circ.num_bits(), &branches))
+    }
+
+    async fn execute_async(&self, circ: &TranspiledCircuit<Self::Architecture>) -> Result<Histogram, Self::RuntimeError> {
+        self.execute(circ)
+    }
+}
+
+/// Returns $ K \rho K^\dagger $, for the hermitian matrix $ \rho $ and the operator $ K $
+/// acting on the given qubits.
+fn conjugate(rho: &DMatrix, op: &DMatrix, qubits: &[usize]) -> DMatrix {
+    let mut res = rho.clone();
+    res.apply(op, qubits);
+    let mut res = res.adjoint();
+    res.apply(op, qubits);
+    res
+}
+
+/// Applies the channel on the given qubits to the hermitian matrix.
+fn apply_channel(rho: &DMatrix, channel: &KrausChannel, qubits: &[usize]) -> DMatrix {
+    channel.operators().iter()
+        .map(|op| conjugate(rho, op, qubits))
+        .reduce(|acc, term| &acc + &term)
+        .unwrap()
+}
+
+/// The channel resetting a qubit to $ |0 \rangle $.
+fn reset_channel() -> KrausChannel {
+    let (o, l) = (c64::ZERO, c64::ONE);
+    KrausChannel::new(vec![
+        DMatrix::new(2, vec![l, o, o, o]).unwrap(),
+        DMatrix::new(2, vec![o, l, o, o]).unwrap(),
+    ]).unwrap()
+}
+
+/// Projects the matrix onto the subspace where the qubit has the given value.
+fn project(rho: &DMatrix, qubit: usize, value: bool) -> DMatrix {
+    let mut res = rho.clone();
+    let dim = res.dim();
+    let keep = |i: usize| ((i >> qubit) & 1 == 1) == value;
+
+    res.raw_mut().iter_mut().enumerate()
+        .filter(|&(index, _)| !keep(index / dim) || !keep(index % dim))
+        .for_each(|(_, z)| *z = c64::ZERO);
+    res
+}
+
+/// Merges the branches with the same bits, and drops the ones with a negligible probability.
+fn merge(branches: Vec<RawBranch>) -> Vec<RawBranch> {
+    let mut indices: HashMap<BitSet, usize> = HashMap::new();
+    let mut res: Vec<RawBranch> = Vec::new();
+
+    for (bits, rho) in branches {
+        if rho.trace().re < PRUNE {
+            continue;
+        }
+
+        match indices.get(&bits) {
+            Some(&index) => res[index].1 = &res[index].1 + &rho,
+            None => {
+                indices.insert(bits.clone(), res.len());
+                res.push((bits, rho));
+            }
+        }
+    }
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::circuit::QuantumCircuit;
+    use crate::instruction::Modifier;
+    use crate::operation::OpKind;
+    use crate::simulator::{KrausChannel, NoiseModel, ReadoutError, SimulationError, MAX_ITERATIONS};
+
+    use super::DensityMatrixSimulator;
+
+    /// Returns the probability that each bit of the circuit is set.
+    fn marginals(simulator: &DensityMatrixSimulator, circ: &QuantumCircuit) -> Vec<f64> {
+        let branches = simulator.run(circ).unwrap();
+        assert!((branches.iter().map(|branch| branch.probability).sum::<f64>() - 1.0).abs() < 1E-9);
+
+        (0..circ.num_bits())
+            .map(|bit| branches.iter().filter(|branch| branch.bits.get(bit).unwrap()).map(|branch| branch.probability).sum())
+            .collect()
+    }
+
+    /// A circuit flipping a qubit, then measuring it.
+    fn flip() -> QuantumCircuit {
+        QuantumCircuit::new(|b| {
+            let qubit = b.qubit()?;
+            let [bit] = b.bits()?;
+            b.x(qubit).measure(qubit, bit);
+            Ok(())
+        }).unwrap()
+    }
+
+    #[test]
+    fn applies_noise_after_gates() {
+        let mut simulator = DensityMatrixSimulator::new(0);
+        simulator.noise.add_error("x", KrausChannel::amplitude_damping(0.3));
+        assert!((marginals(&simulator, &flip())[0] - 0.7).abs() < 1E-9);
+
+        // $ (1 - p) |1 \rangle \langle 1 | + p I / 2 $.
+        let mut simulator = DensityMatrixSimulator::new(0);
+        simulator.noise.add_error("x", KrausChannel::depolarizing(0.4, 1));
+        assert!((marginals(&simulator, &flip())[0] - 0.8).abs() < 1E-9);
+    }
+
+    #[test]
+    fn applies_noise_before_measurements() {
+        let mut simulator = DensityMatrixSimulator::new(0);
+        simulator.noise.add_error("measure", KrausChannel::pauli(0.25, 0.0, 0.0));
+        assert!((marginals(&simulator, &flip())[0] - 0.75).abs() < 1E-9);
+
+        let mut simulator = DensityMatrixSimulator::new(0);
+        simulator.noise.add_readout_error(ReadoutError::new(0.0, 0.1));
+        assert!((marginals(&simulator, &flip())[0] - 0.9).abs() < 1E-9);
+    }
+
+    #[test]
+    fn damps_coherences() {
+        // After $ T_2 $, the coherences of $ |+ \rangle $ decay by $ 1 / e $.
+        let circ = QuantumCircuit::new(|b| {
+            let qubit = b.qubit()?;
+            b.h(qubit);
+            Ok(())
+        }).unwrap();
+
+        let mut simulator = DensityMatrixSimulator::new(0);
+        simulator.noise.add_error("h", KrausChannel::thermal_relaxation(100.0, 80.0, 80.0));
+        let state = &simulator.run(&circ).unwrap()[0].state;
+
+        assert!((state[(0, 1)].abs() - 0.5 / 1f64.exp()).abs() < 1E-9);
+        assert!((state[(1, 1)].re - 0.5 * (-0.8f64).exp()).abs() < 1E-9);
+    }
+
+    #[test]
+    fn applies_qubit_specific_noise() {
+        let circ = QuantumCircuit::new(|b| {
+            let [a, c] = b.qubits()?;
+            let bits = b.bits::<2>()?;
+            b.x(a).x(c).measure(a, bits[0]).measure(c, bits[1]);
+            Ok(())
+        }).unwrap();
+
+        let mut simulator = DensityMatrixSimulator::new(0);
+        simulator.noise.add_qubit_error("x", &[1], KrausChannel::amplitude_damping(1.0));
+        let marginals = marginals(&simulator, &circ);
+        assert!((marginals[0] - 1.0).abs() < 1E-9 && marginals[1].abs() < 1E-9);
+
+        let mut model = NoiseModel::new();
+        model.add_error("x", KrausChannel::depolarizing(0.1, 2));
+        simulator.noise = model;
+        assert_eq!(simulator.run(&circ).unwrap_err(), SimulationError::NoiseArity("x", 2, 1));
+    }
+
+    #[test]
+    fn resets_and_conditions_on_bits() {
+        // The second qubit is flipped when the first one is measured in $ |1 \rangle $, and
+        // both flips are damped.
+        let circ = QuantumCircuit::new(|b| {
+            let [a, c] = b.qubits()?;
+            let bits = b.bits::<2>()?;
+            b.x(a).measure(a, bits[0]).reset(a);
+            b.instructions_mut().push_modified(OpKind::X, &[c], &[], &[], Modifier::IfBit(bits[0]))?;
+            b.measure(c, bits[1]);
+            Ok(())
+        }).unwrap();
+
+        let mut simulator = DensityMatrixSimulator::new(0);
+        simulator.noise.add_error("x", KrausChannel::amplitude_damping(0.2));
+        let branches = simulator.run(&circ).unwrap();
+
+        let probability = |bits: [bool; 2]| branches.iter()
+            .find(|branch| branch.bits.get(0) == Some(bits[0]) && branch.bits.get(1) == Some(bits[1]))
+            .map_or(0.0, |branch| branch.probability);
+        assert!((probability([false, false]) - 0.2).abs() < 1E-9);
+        assert!((probability([true, false]) - 0.16).abs() < 1E-9);
+        assert!((probability([true, true]) - 0.64).abs() < 1E-9);
+        assert_eq!(probability([false, true]), 0.0);
+
+        // The first qubit is always reset to $ |0 \rangle $.
+        for branch in &branches {
+            let excited: f64 = (0..4).filter(|i| i & 1 == 1).map(|i| branch.state[(i, i)].re).sum();
+            assert!(excited.abs() < 1E-9);
+        }
+    }
+
+    #[test]
+    fn runs_both_branches_of_conditionals() {
+        // The second qubit is flipped when the first one is measured in $ |1 \rangle $, and put
+        // in $ |+ \rangle $ otherwise, while the third one is only flipped with the first one.
+        let circ = QuantumCircuit::new(|b| {
+            let [a, c, t] = b.qubits()?;
+            let bits = b.bits::<3>()?;
+            b.h(a).measure(a, bits[0]);
+            b.if_else(bits[0], |b| { b.x(c); Ok(()) }, |b| { b.h(c); Ok(()) })?;
+            b.if_then(bits[0], |b| { b.x(t); Ok(()) })?;
+            b.measure(c, bits[1]).measure(t, bits[2]);
+            Ok(())
+        }).unwrap();
+
+        let simulator = DensityMatrixSimulator::new(0);
+        let marginals = marginals(&simulator, &circ);
+        assert!((marginals[0] - 0.5).abs() < 1E-9);
+        assert!((marginals[1] - 0.75).abs() < 1E-9);
+        assert!((marginals[2] - 0.5).abs() < 1E-9);
+
+        for branch in simulator.run(&circ).unwrap() {
+            assert_eq!(branch.bits.get(0), branch.bits.get(2));
+        }
+    }
+
+    #[test]
+    fn terminates_while_loops() {
+        // The loop runs three times when the first bit is set, as it reads the second qubit
+        // and shifts the third one into it, and flips the counter qubit on each iteration.
+        let circ = QuantumCircuit::new(|b| {
+            let [a, q, r, counter] = b.qubits()?;
+            let bits = b.bits::<3>()?;
+            b.x(q).x(r).h(a).measure(a, bits[0]).measure(a, bits[1]);
+            b.while_loop(bits[0], |b| {
+                b.x(counter).measure(q, bits[0]).reset(q).cx(r, q).reset(r);
+                Ok(())
+            })?;
+            b.measure(counter, bits[2]);
+            Ok(())
+        }).unwrap();
+
+        let simulator = DensityMatrixSimulator::new(0);
+        let marginals = marginals(&simulator, &circ);
+        assert_eq!(marginals[0], 0.0);
+        assert!((marginals[2] - 0.5).abs() < 1E-9);
+
+        for branch in simulator.run(&circ).unwrap() {
+            assert_eq!(branch.bits.get(1), branch.bits.get(2));
+        }
+
+        // The bit read by the loop is never cleared.
+        let circ = QuantumCircuit::new(|b| {
+            let qubit = b.qubit()?;
+            let [bit] = b.bits()?;
+            b.x(qubit).measure(qubit, bit);
+            b.while_loop(bit, |b| { b.measure(qubit, bit); Ok(()) })?;
+            Ok(())
+        }).unwrap();
+        assert_eq!(DensityMatrixSimulator::new(0).run(&circ).unwrap_err(), SimulationError::IterationLimit(MAX_ITERATIONS));
+    }
+
+    #[test]
+    fn selects_switch_cases() {
+        // The value 0 flips the first target, the value 1 runs an empty case and the values
+        // 2 and 3 run the default body, flipping the second target.
+        let circ = QuantumCircuit::new(|b| {
+            let [a, c, t, u] = b.qubits()?;
+            let bits = b.bits::<4>()?;
+            b.h(a).h(c).measure(a, bits[0]).measure(c, bits[1]);
+            b.switch(&bits[..2], 2, |b, case| {
+                match case {
+                    Some(0) => { b.x(t); }
+                    Some(_) => (),
+                    None => { b.x(u); }
+                }
+                Ok(())
+            })?;
+            b.measure(t, bits[2]).measure(u, bits[3]);
+            Ok(())
+        }).unwrap();
+
+        let simulator = DensityMatrixSimulator::new(0);
+        let marginals = marginals(&simulator, &circ);
+        assert!((marginals[2] - 0.25).abs() < 1E-9);
+        assert!((marginals[3] - 0.5).abs() < 1E-9);
+
+        for branch in simulator.run(&circ).unwrap() {
+            let value = u8::from(branch.bits.get(0).unwrap()) + 2 * u8::from(branch.bits.get(1).unwrap());
+            assert_eq!(branch.bits.get(2), Some(value == 0));
+            assert_eq!(branch.bits.get(3), Some(value >= 2));
+        }
+    }
+
+    #[test]
+    fn runs_nested_bodies() {
+        // The inner conditional flips the qubit on each iteration of the loop, in the branch
+        // taken by the outer conditional only.
+        let circ = QuantumCircuit::new(|b| {
+            let [a, c, t] = b.qubits()?;
+            let bits = b.bits::<3>()?;
+            b.x(a).measure(a, bits[0]);
+            b.if_else(bits[0], |b| {
+                b.for_loop(3, |b| {
+                    b.if_then(bits[0], |b| { b.x(c); Ok(()) })?;
+                    Ok(())
+                })?;
+                Ok(())
+            }, |b| { b.x(t); Ok(()) })?;
+            b.measure(c, bits[1]).measure(t, bits[2]);
+            Ok(())
+        }).unwrap();
+
+        assert_eq!(marginals(&DensityMatrixSimulator::new(0), &circ), [1.0, 1.0, 0.0]);
+    }
+}
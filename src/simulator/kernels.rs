@@ -0,0 +1,475 @@
+//! Kernels applying gates to the amplitudes of a statevector.
+//!
+//! Gates are classified once as diagonal, permutation or dense matrices, each with it's own
+//! kernel. Kernels walk the amplitudes in contiguous runs whenever the layout allows it, so
+//! that the inner loops can be vectorized, and are spread over several threads for large states.
+
+use std::ops::Range;
+use std::thread;
+
+use crate::linalg::{c64, DMatrix};
+
+/// The minimum number of amplitudes for a kernel to be spread over several threads.
+pub(crate) const PARALLEL_THRESHOLD: usize = 1 << 14;
+
+/// A gate, in the form it is the fastest to apply in.
+#[derive(Clone, Debug)]
+pub(crate) enum Kernel {
+    /// A diagonal matrix, given by it's diagonal.
+    Diagonal(Vec<c64>),
+    /// A matrix with a single nonzero element in each row and column. Column `c` is sent
+    /// to row `rows[c].0`, multiplied by `rows[c].1`.
+    Permutation(Vec<(usize, c64)>),
+    /// Any other matrix.
+    Dense(DMatrix),
+}
+
+impl Kernel {
+    /// Chooses the kernel of the matrix.
+    pub(crate) fn new(matrix: &DMatrix) -> Self {
+        let dim = matrix.dim();
+        let is_zero = |z: c64| z.re == 0.0 && z.im == 0.0;
+
+        if (0..dim).all(|r| (0..dim).all(|c| r == c || is_zero(matrix[(r, c)]))) {
+            return Self::Diagonal((0..dim).map(|i| matrix[(i, i)]).collect());
+        }
+
+        let rows: Option<Vec<_>> = (0..dim)
+            .map(|c| {
+                let mut nonzero = (0..dim).filter(|&r| !is_zero(matrix[(r, c)]));
+                match (nonzero.next(), nonzero.next()) {
+                    (Some(r), None) => Some((r, matrix[(r, c)])),
+                    _ => None,
+                }
+            })
+            .collect();
+
+        match rows {
+            Some(rows) if (0..dim).all(|r| rows.iter().any(|&(row, _)| row == r)) => Self::Permutation(rows),
+            _ => Self::Dense(matrix.clone()),
+        }
+    }
+
+    /// Returns a rough estimate of the cost of the kernel, relative to a single pass over the
+    /// amplitudes, as measured by the `statevector` benchmark.
+    pub(crate) fn cost(&self) -> f64 {
+        match self {
+            Self::Diagonal(_) => 1.0,
+            Self::Permutation(rows) => if rows.len() == 2 { 1.0 } else { 2.0 },
+            Self::Dense(matrix) if matrix.dim() == 2 => 2.0,
+            // Gathering the amplitudes of the groups costs about a pass, the products add up.
+            Self::Dense(matrix) => 1.0 + 0.75 * matrix.dim() as f64,
+        }
+    }
+
+    /// Left-multiplies the amplitudes by the gate, acting on the given qubits, the first qubit
+    /// being the most significant one in the gate's basis.
+    ///
+    /// Panics if the qubits are not distinct, or out of the range of the amplitudes.
+    pub(crate) fn apply(&self, amplitudes: &mut [c64], qubits: &[usize], threads: usize) {
+        assert!(qubits.iter().all(|&q| 1 << q < amplitudes.len()), "qubit out of range");
+        assert!(qubits.iter().enumerate().all(|(i, q)| !qubits[..i].contains(q)), "qubits are not distinct");
+
+        match (self, qubits) {
+            (Self::Diagonal(diag), &[q]) => {
+                let (d0, d1) = (diag[0], diag[1]);
+                pairs(amplitudes, q, threads, |lo, hi| {
+                    if !is_one(d0) {
+                        lo.iter_mut().for_each(|z| *z *= d0);
+                    }
+                    hi.iter_mut().for_each(|z| *z *= d1);
+                });
+            }
+            (Self::Diagonal(diag), _) => {
+                // The factor is the same for runs of amplitudes below the least significant qubit.
+                let run = 1 << qubits.iter().min().copied().unwrap_or(0);
+                chunks(amplitudes, run, threads, |offset, chunk| {
+                    chunk.chunks_exact_mut(run).enumerate().for_each(|(i, run_amplitudes)| {
+                        let index = offset + i * run;
+                        let factor = diag[qubits.iter().fold(0, |acc, &q| (acc << 1) | ((index >> q) & 1))];
+                        run_amplitudes.iter_mut().for_each(|z| *z *= factor);
+                    });
+                });
+            }
+            (Self::Permutation(rows), &[q]) => {
+                // The only permutation of a qubit which is not diagonal swaps it's basis states.
+                let (p0, p1) = (rows[0].1, rows[1].1);
+                pairs(amplitudes, q, threads, |lo, hi| {
+                    if is_one(p0) && is_one(p1) {
+                        lo.swap_with_slice(hi);
+                    } else {
+                        lo.iter_mut().zip(hi).for_each(|(a, b)| (*a, *b) = (p1 * *b, p0 * *a));
+                    }
+                });
+            }
+            (Self::Permutation(rows), _) => match qubits.len() {
+                2 => permutation(amplitudes, qubits, threads, rows, || [c64::ZERO; 4]),
+                3 => permutation(amplitudes, qubits, threads, rows, || [c64::ZERO; 8]),
+                k => permutation(amplitudes, qubits, threads, rows, || vec![c64::ZERO; 1 << k]),
+            },
+            (Self::Dense(matrix), &[q]) => {
+                let m = matrix.raw();
+                let (m00, m01, m10, m11) = (m[0], m[1], m[2], m[3]);
+                pairs(amplitudes, q, threads, |lo, hi| {
+                    lo.iter_mut().zip(hi).for_each(|(a, b)| {
+                        let (x, y) = (*a, *b);
+                        *a = m00 * x + m01 * y;
+                        *b = m10 * x + m11 * y;
+                    });
+                });
+            }
+            // Fixed size buffers let the compiler unroll the products of the most common gates.
+            (Self::Dense(matrix), _) => match qubits.len() {
+                2 => dense::<4>(amplitudes, qubits, threads, matrix),
+                3 => dense::<8>(amplitudes, qubits, threads, matrix),
+                4 => dense::<16>(amplitudes, qubits, threads, matrix),
+                k => {
+                    let (m, dim) = (matrix.raw(), matrix.dim());
+                    groups(amplitudes, qubits, threads, || vec![c64::ZERO; 1 << k], |buf| {
+                        let input = buf.clone();
+                        buf.iter_mut().zip(m.chunks_exact(dim)).for_each(|(z, row)| {
+                            *z = row.iter().zip(&input).fold(c64::ZERO, |acc, (&a, &b)| acc + a * b);
+                        });
+                    });
+                }
+            },
+        }
+    }
+}
+
+/// Returns `true` if the number is exactly $ 1 $, unlike the comparison of complex numbers
+/// which is up to some precision.
+fn is_one(z: c64) -> bool {
+    z.re == 1.0 && z.im == 0.0
+}
+
+/// Returns the number of threads to spread a kernel over.
+fn num_threads(amplitudes: &[c64], threads: usize) -> usize {
+    if amplitudes.len() < PARALLEL_THRESHOLD { 1 } else { threads.max(1) }
+}
+
+/// Calls `f` on each range of a partition of `0..len` in at most `threads` ranges, in parallel.
+fn parallel(len: usize, threads: usize, f: impl Fn(Range<usize>) + Sync) {
+    if threads <= 1 {
+        return f(0..len);
+    }
+
+    let size = len.div_ceil(threads);
+    thread::scope(|scope| {
+        for start in (0..len).step_by(size) {
+            let f = &f;
+            scope.spawn(move || f(start..len.min(start + size)));
+        }
+    });
+}
+
+/// Splits the amplitudes in contiguous chunks whose length is a multiple of `block`, and calls
+/// `f` on each of them along with the index of their first amplitude, in parallel.
+pub(crate) fn chunks(amplitudes: &mut [c64], block: usize, threads: usize, f: impl Fn(usize, &mut [c64]) + Sync) {
+    let threads = num_threads(amplitudes, threads).min(amplitudes.len() / block);
+    if threads <= 1 {
+        return f(0, amplitudes);
+    }
+
+    let size = (amplitudes.len() / block).div_ceil(threads) * block;
+    thread::scope(|scope| {
+        for (n, chunk) in amplitudes.chunks_mut(size).enumerate() {
+            let f = &f;
+            scope.spawn(move || f(n * size, chunk));
+        }
+    });
+}
+
+/// Calls `f` on pairs of slices of the amplitudes, the first one of the basis states where
+/// the qubit is $ |0 \rangle $, and the second one of the same states with the qubit flipped.
+/// Together, the pairs cover all of the amplitudes.
+fn pairs(amplitudes: &mut [c64], qubit: usize, threads: usize, f: impl Fn(&mut [c64], &mut [c64]) + Sync) {
+    let half = 1 << qubit;
+    let blocks = amplitudes.len() / (2 * half);
+    let threads = num_threads(amplitudes, threads);
+
+    let split = |block: &mut [c64]| {
+        let (lo, hi) = block.split_at_mut(half);
+        f(lo, hi)
+    };
+
+    if blocks >= threads {
+        chunks(amplitudes, 2 * half, threads, |_, chunk| chunk.chunks_exact_mut(2 * half).for_each(split));
+    } else {
+        // The qubit is one of the most significant ones, there are less blocks than threads
+        // so the halves of each block are split between the threads.
+        let size = half.div_ceil(threads / blocks);
+        thread::scope(|scope| {
+            for block in amplitudes.chunks_exact_mut(2 * half) {
+                let (lo, hi) = block.split_at_mut(half);
+                for (lo, hi) in lo.chunks_mut(size).zip(hi.chunks_mut(size)) {
+                    let f = &f;
+                    scope.spawn(move || f(lo, hi));
+                }
+            }
+        });
+    }
+}
+
+/// The amplitudes, shared between threads which access disjoint sets of them.
+#[derive(Copy, Clone)]
+struct Shared(*mut c64);
+
+// SAFETY: the threads sharing the pointer access disjoint amplitudes, see `groups`.
+unsafe impl Send for Shared {}
+unsafe impl Sync for Shared {}
+
+impl Shared {
+    fn ptr(self) -> *mut c64 {
+        self.0
+    }
+}
+
+/// Calls `f` on the amplitudes of each group of basis states which only differ on the given
+/// qubits, ordered with the first qubit as the most significant one. The amplitudes are
+/// gathered in a buffer created by `new`, and updated with the buffer once `f` modified it.
+///
+/// The qubits must be distinct and in the range of the amplitudes.
+fn groups<B: AsMut<[c64]>>(amplitudes: &mut [c64], qubits: &[usize], threads: usize, new: impl Fn() -> B + Sync, f: impl Fn(&mut B) + Sync) {
+    let k = qubits.len();
+    let offsets: Vec<usize> = (0..1 << k)
+        .map(|sub| (0..k).fold(0, |acc, j| acc | (((sub >> (k - 1 - j)) & 1) << qubits[j])))
+        .collect();
+
+    let mut sorted = qubits.to_vec();
+    sorted.sort_unstable();
+    let mask = qubits.iter().fold(0, |acc, &q| acc | (1 << q));
+
+    let threads = num_threads(amplitudes, threads);
+    let shared = Shared(amplitudes.as_mut_ptr());
+    parallel(amplitudes.len() >> k, threads, |range| {
+        let ptr = shared.ptr();
+        let mut buf = new();
+
+        // The index of the first basis state of the group, where all of the qubits are $ |0 \rangle $.
+        let mut base = sorted.iter().fold(range.start, |acc, &q| ((acc >> q) << (q + 1)) | (acc & ((1 << q) - 1)));
+        for _ in range {
+            // SAFETY: the qubits are distinct and in range, so the indices are in bounds, and
+            // the groups are disjoint, each of them being accessed by a single thread.
+            unsafe {
+                offsets.iter().zip(buf.as_mut()).for_each(|(&offset, z)| *z = *ptr.add(base | offset));
+                f(&mut buf);
+                offsets.iter().zip(buf.as_mut()).for_each(|(&offset, z)| *ptr.add(base | offset) = *z);
+            }
+            base = ((base | mask) + 1) & !mask;
+        }
+    });
+}
+
+/// The kernel of permutation gates on several qubits.
+fn permutation<B: AsMut<[c64]> + AsRef<[c64]> + Clone>(amplitudes: &mut [c64], qubits: &[usize], threads: usize, rows: &[(usize, c64)], new: impl Fn() -> B + Sync) {
+    groups(amplitudes, qubits, threads, new, |buf| {
+        let input = buf.clone();
+        let output = buf.as_mut();
+        rows.iter().zip(input.as_ref()).for_each(|(&(r, factor), &z)| output[r] = factor * z);
+    });
+}
+
+/// The kernel of dense gates on several qubits, of dimension `D`.
+fn dense<const D: usize>(amplitudes: &mut [c64], qubits: &[usize], threads: usize, matrix: &DMatrix) {
+    let mut m = [[c64::ZERO; D]; D];
+    m.iter_mut().enumerate().for_each(|(r, row)| row.iter_mut().enumerate().for_each(|(c, z)| *z = matrix[(r, c)]));
+
+    groups(amplitudes, qubits, threads, || [c64::ZERO; D], |buf| {
+        let input = *buf;
+        buf.iter_mut().zip(&m).for_each(|(z, row)| {
+            *z = row.iter().zip(&input).fold(c64::ZERO, |acc, (&a, &b)| acc + a * b);
+        });
+    });
+}
+
+/// Unitary gates fused together into a single gate, as long as it is cheaper than applying
+/// them one after the other.
+#[derive(Clone, Debug)]
+pub(crate) struct Fusion {
+    qubits: Vec<usize>,
+    matrix: DMatrix,
+    /// The cost of the fused gates, when applied on their own.
+    cost: f64,
+}
+
+impl Fusion {
+    pub(crate) fn new() -> Self {
+        Self { qubits: Vec::new(), matrix: DMatrix::eye(1), cost: 0.0 }
+    }
+
+    /// Applies the gate after the fused ones. Returns `false` and leaves the fusion unchanged if
+    /// the fused gate would act on more than `max` qubits or be slower than applying the gate
+    /// on it's own, unless the fusion was empty.
+    pub(crate) fn push(&mut self, gate: &DMatrix, qubits: &[usize], max: usize) -> bool {
+        let new: Vec<usize> = qubits.iter().copied().filter(|q| !self.qubits.contains(q)).collect();
+        if !self.qubits.is_empty() && self.qubits.len() + new.len() > max {
+            return false;
+        }
+
+        // The new qubits are the least significant ones of the fused gate.
+        let mut fused = self.qubits.clone();
+        fused.extend(new.iter().copied());
+        let mut matrix = self.matrix.kronecker(&DMatrix::eye(1 << new.len()));
+
+        let k = fused.len();
+        let positions: Vec<usize> = qubits.iter()
+            .map(|q| k - 1 - fused.iter().position(|p| p == q).unwrap())
+            .collect();
+        matrix.apply(gate, &positions);
+
+        let cost = self.cost + Kernel::new(gate).cost();
+        if !self.qubits.is_empty() && Kernel::new(&matrix).cost() > cost {
+            return false;
+        }
+
+        *self = Self { qubits: fused, matrix, cost };
+        true
+    }
+
+    /// Returns the qubits and kernel of the fused gate, if any, and empties the fusion.
+    pub(crate) fn take(&mut self) -> Option<(Vec<usize>, Kernel)> {
+        let fused = std::mem::replace(self, Self::new());
+        (!fused.qubits.is_empty()).then(|| (fused.qubits, Kernel::new(&fused.matrix)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::seq::SliceRandom;
+    use rand::{Rng, SeedableRng};
+
+    use crate::linalg::{c64, DMatrix};
+    use crate::operation::OpKind;
+
+    use super::{Fusion, Kernel, PARALLEL_THRESHOLD};
+
+    fn random_state(n: usize, rng: &mut StdRng) -> Vec<c64> {
+        (0..1 << n).map(|_| c64::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0))).collect()
+    }
+
+    /// Applies the matrix with a plain matrix-vector product over the whole basis.
+    fn reference(amplitudes: &[c64], matrix: &DMatrix, qubits: &[usize]) -> Vec<c64> {
+        let k = qubits.len();
+        let sub = |index: usize| qubits.iter().fold(0, |acc, &q| (acc << 1) | ((index >> q) & 1));
+        let mask = qubits.iter().fold(0, |acc, &q| acc | (1 << q));
+
+        (0..amplitudes.len())
+            .map(|i| {
+                (0..1usize << k)
+                    .map(|c| {
+                        let j = (0..k).fold(i & !mask, |acc, n| acc | (((c >> (k - 1 - n)) & 1) << qubits[n]));
+                        matrix[(sub(i), c)] * amplitudes[j]
+                    })
+                    .sum()
+            })
+            .collect()
+    }
+
+    /// Returns random diagonal, permutation and dense matrices on `k` qubits.
+    fn random_gates(k: usize, rng: &mut StdRng) -> [DMatrix; 3] {
+        let dim = 1 << k;
+        let mut diagonal = DMatrix::zeros(dim);
+        let mut permutation = DMatrix::zeros(dim);
+        let mut rows: Vec<usize> = (0..dim).collect();
+        rows.shuffle(rng);
+        for i in 0..dim {
+            diagonal[(i, i)] = c64::cis(rng.gen_range(0.0..6.0));
+            permutation[(rows[i], i)] = c64::cis(rng.gen_range(0.0..6.0));
+        }
+        [diagonal, permutation, DMatrix::random_unitary(dim, rng)]
+    }
+
+    fn assert_close(a: &[c64], b: &[c64]) {
+        assert!(a.iter().zip(b).all(|(x, y)| (x - y).abs() < 1E-10));
+    }
+
+    #[test]
+    fn classifies_gates() {
+        let kernel = |op: OpKind<'_>, parameters: &[f64]| Kernel::new(&op.matrix(parameters).unwrap());
+        assert!(matches!(kernel(OpKind::Z, &[]), Kernel::Diagonal(_)));
+        assert!(matches!(kernel(OpKind::CPhase, &[0.3]), Kernel::Diagonal(_)));
+        assert!(matches!(kernel(OpKind::X, &[]), Kernel::Permutation(_)));
+        assert!(matches!(kernel(OpKind::CX, &[]), Kernel::Permutation(_)));
+        assert!(matches!(kernel(OpKind::Swap, &[]), Kernel::Permutation(_)));
+        assert!(matches!(kernel(OpKind::H, &[]), Kernel::Dense(_)));
+        assert!(matches!(kernel(OpKind::RX, &[0.1]), Kernel::Dense(_)));
+    }
+
+    #[test]
+    fn kernels_match_matrix_products() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let n = 6;
+
+        for k in 1..=5 {
+            for _ in 0..4 {
+                let mut qubits: Vec<usize> = (0..n).collect();
+                qubits.shuffle(&mut rng);
+                qubits.truncate(k);
+
+                for gate in random_gates(k, &mut rng) {
+                    let state = random_state(n, &mut rng);
+                    let mut res = state.clone();
+                    Kernel::new(&gate).apply(&mut res, &qubits, 1);
+                    assert_close(&res, &reference(&state, &gate, &qubits));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn parallel_kernels_match_sequential_ones() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let n = PARALLEL_THRESHOLD.trailing_zeros() as usize + 1;
+        let state = random_state(n, &mut rng);
+
+        // The most significant qubit has fewer blocks than threads.
+        for qubits in [vec![0], vec![n - 1], vec![n - 1, 2], vec![3, 0, n - 2]] {
+            for gate in random_gates(qubits.len(), &mut rng) {
+                let kernel = Kernel::new(&gate);
+                let (mut sequential, mut parallel) = (state.clone(), state.clone());
+                kernel.apply(&mut sequential, &qubits, 1);
+                kernel.apply(&mut parallel, &qubits, 3);
+                assert_eq!(sequential, parallel);
+            }
+        }
+    }
+
+    #[test]
+    fn fused_gates_match_sequential_gates() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let n = 5;
+        let state = random_state(n, &mut rng);
+
+        let gates: Vec<(DMatrix, Vec<usize>)> = vec![
+            (DMatrix::random_unitary(2, &mut rng), vec![3]),
+            (OpKind::CX.matrix(&[]).unwrap(), vec![3, 1]),
+            (DMatrix::random_unitary(4, &mut rng), vec![1, 4]),
+            (OpKind::RZ.matrix(&[0.4]).unwrap(), vec![4]),
+            (DMatrix::random_unitary(2, &mut rng), vec![0]),
+        ];
+
+        let mut expected = state.clone();
+        gates.iter().for_each(|(gate, qubits)| expected = reference(&expected, gate, qubits));
+
+        for max in [1, 2, 3, 5] {
+            let mut fusion = Fusion::new();
+            let mut res = state.clone();
+            for (gate, qubits) in &gates {
+                if !fusion.push(gate, qubits, max) {
+                    let (fused, kernel) = fusion.take().unwrap();
+                    // A gate on more qubits than the maximum is applied on it's own.
+                    assert!(fused.len() <= max.max(2));
+                    kernel.apply(&mut res, &fused, 1);
+                    assert!(fusion.push(gate, qubits, max));
+                }
+            }
+
+            let (fused, kernel) = fusion.take().unwrap();
+            kernel.apply(&mut res, &fused, 1);
+            assert!(fusion.take().is_none());
+            assert_close(&res, &expected);
+        }
+    }
+}
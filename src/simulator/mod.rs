@@ -0,0 +1,282 @@
+//! Classical simulators of quantum circuits, implementing [`Backend`](crate::provider::Backend).
+//!
+//! Every simulator runs on the [`FullyConnected`] architecture, which accepts any instruction.
+
+mod kernels;
+
+pub mod density;
+pub mod mps;
+pub mod noise;
+pub mod stabilizer;
+pub mod statevector;
+
+pub use density::DensityMatrixSimulator;
+pub use mps::{Mps, MpsSimulator};
+pub use noise::{KrausChannel, NoiseModel, ReadoutError};
+pub use stabilizer::{StabilizerSimulator, Tableau};
+pub use statevector::{Statevector, StatevectorSimulator};
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::thread;
+
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use thiserror::Error;
+
+use crate::bitset::BitSet;
+use crate::circuit::{CircuitError, QuantumCircuit};
+use crate::instruction::{self, Compute, Instr, InstrIter, InstrVec, Modifier};
+use crate::linalg::{DMatrix, UnitaryMatrix};
+use crate::operation::OpKind;
+use crate::provider::{Architecture, Histogram};
+use crate::symbol::{Ancillas, Bit};
+
+/// The maximum number of iterations of a while loop, after which the simulation is aborted.
+pub const MAX_ITERATIONS: usize = 1 << 16;
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Error)]
+pub enum SimulationError {
+    #[error("circuit has {0} qubits, but the simulator supports at most {1}")]
+    TooManyQubits(usize, usize),
+    #[error("circuit has an unbound formal parameter")]
+    NotConcrete,
+    #[error("operation `{0}` is not supported by the simulator")]
+    UnsupportedOp(&'static str),
+    #[error("operation `{0}` is not a Clifford operation")]
+    NotClifford(&'static str),
+    #[error("loop exceeded {0} iterations")]
+    IterationLimit(usize),
+    #[error("circuit could not be inlined: {0}")]
+    Inline(CircuitError),
+    #[error("noise channel attached to `{0}` acts on {1} qubits, but the operation acts on {2}")]
+    NoiseArity(&'static str, usize, usize),
+}
+
+/// An architecture with any number of qubits, all connected to each other and supporting
+/// any instruction, as is the case for simulators.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct FullyConnected {
+    num_qubits: usize,
+}
+
+impl FullyConnected {
+    pub fn new(num_qubits: usize) -> Self {
+        Self { num_qubits }
+    }
+}
+
+impl Architecture for FullyConnected {
+    type TranspileError = Infallible;
+
+    fn num_qubits(&self) -> usize {
+        self.num_qubits
+    }
+
+    fn connected(&self, qubit1: usize, qubit2: usize) -> bool {
+        qubit1 != qubit2 && qubit1 < self.num_qubits && qubit2 < self.num_qubits
+    }
+
+    fn decompose_su2(&self, unitary: UnitaryMatrix<2>) {}
+
+    fn non_local(&self) {}
+
+    fn supports<'id>(&self, instr: &Instr<'id>) -> Result<(), Self::TranspileError> {
+        Ok(())
+    }
+
+    fn transpile<'id>(&self, instructions: InstrVec<'id>, ancillas: Option<Ancillas<'id>>) -> Result<InstrVec<'id>, Self::TranspileError> {
+        Ok(instructions)
+    }
+}
+
+/// Returns the values of the given bits, in order.
+pub(crate) fn gather(state: &BitSet, bits: &[Bit<'_>]) -> BitSet {
+    let mut res = BitSet::new(bits.len());
+    bits.iter().enumerate().for_each(|(i, bit)| {
+        res.set(i, state.get(bit.id() as usize).unwrap());
+    });
+    res
+}
+
+/// Evaluates the compute on the given bits.
+pub(crate) fn eval<T>(state: &BitSet, compute: &Compute<'_, T>) -> T {
+    (compute.func)(gather(state, compute.bits))
+}
+
+/// Runs the compute operation, storing the `n`th bit of it's result in the `n`th output bit.
+pub(crate) fn run_compute(state: &mut BitSet, compute: &Compute<'_, BitSet>, outputs: &[Bit<'_>]) {
+    let res = eval(state, compute);
+    outputs.iter().enumerate().for_each(|(i, bit)| {
+        state.set(bit.id() as usize, res.get(i).unwrap_or(false));
+    });
+}
+
+/// Returns the values of the parameters of the instruction.
+pub(crate) fn parameters(circ: &QuantumCircuit, instr: &Instr<'_>) -> Result<Vec<f64>, SimulationError> {
+    instr.parameters.iter()
+        .map(|&param| circ.parameter_value(param, &[]).ok_or(SimulationError::NotConcrete))
+        .collect()
+}
+
+/// Returns the matrix of the instruction's unitary operation, under it's quantum modifiers, and
+/// the qubits it acts on, the control qubits coming first, see [`Instr::matrix`].
+pub(crate) fn gate(circ: &QuantumCircuit, instr: &Instr<'_>) -> Result<(DMatrix, Vec<usize>), SimulationError> {
+    let matrix = instr.matrix(&parameters(circ, instr)?, &exponents(circ, instr)?)
+        .ok_or(SimulationError::UnsupportedOp(instr.op.label()))?;
+    let qubits = instr.controls().0.iter().chain(instr.qubits).map(|qubit| qubit.id() as usize).collect();
+
+    Ok((matrix, qubits))
+}
+
+/// Returns the values of the exponents of the instruction's [`Modifier::Power`]s.
+pub(crate) fn exponents(circ: &QuantumCircuit, instr: &Instr<'_>) -> Result<Vec<f64>, SimulationError> {
+    instr.exponents()
+        .map(|exponent| circ.parameter_value(exponent, &[]).ok_or(SimulationError::NotConcrete))
+        .collect()
+}
+
+/// Returns the indices of the control qubits of the instruction, along with their states, see
+/// [`Instr::controls`].
+pub(crate) fn controls(instr: &Instr<'_>) -> (Vec<usize>, Vec<bool>) {
+    let (qubits, states) = instr.controls();
+    (qubits.iter().map(|qubit| qubit.id() as usize).collect(), states)
+}
+
+/// Returns the indices of the qubits of the instruction.
+pub(crate) fn qubits(instr: &Instr<'_>) -> Vec<usize> {
+    instr.qubits.iter().map(|qubit| qubit.id() as usize).collect()
+}
+
+/// Runs `apply` on the bits as many times as required by the classical modifiers, from the
+/// outermost to the innermost, for simulators running shot by shot. Quantum modifiers are left
+/// to `apply`.
+pub(crate) fn run_modified<F>(modifiers: &[Modifier<'_>], bits: &mut BitSet, apply: &mut F) -> Result<(), SimulationError>
+where
+    F: FnMut(&mut BitSet) -> Result<(), SimulationError>
+{
+    fn repeat<F, C>(bits: &mut BitSet, apply: &mut F, condition: C) -> Result<(), SimulationError>
+    where
+        F: FnMut(&mut BitSet) -> Result<(), SimulationError>,
+        C: Fn(&BitSet) -> bool,
+    {
+        for _ in 0..MAX_ITERATIONS {
+            if !condition(bits) {
+                return Ok(());
+            }
+            apply(bits)?;
+        }
+        Err(SimulationError::IterationLimit(MAX_ITERATIONS))
+    }
+
+    let Some((modifier, inner)) = modifiers.split_last().filter(|(modifier, _)| modifier.is_classical()) else {
+        return apply(bits);
+    };
+    let mut apply = |bits: &mut BitSet| run_modified(inner, bits, apply);
+
+    match modifier {
+        Modifier::IfBit(bit) => if bits.get(bit.id() as usize).unwrap() { apply(bits) } else { Ok(()) },
+        Modifier::IfCompute(compute) => if eval(bits, compute) { apply(bits) } else { Ok(()) },
+        Modifier::WhileBit(bit) => repeat(bits, &mut apply, |bits| bits.get(bit.id() as usize).unwrap()),
+        Modifier::WhileCompute(compute) => repeat(bits, &mut apply, |bits| eval(bits, compute)),
+        Modifier::ForConst(n) => (0..*n).try_for_each(|_| apply(bits)),
+        Modifier::ForCompute(compute) => (0..eval(bits, compute)).try_for_each(|_| apply(bits)),
+        _ => unreachable!("quantum modifier after a classical one"),
+    }
+}
+
+/// Returns `Ok` if `check` accepts the operation of the instruction or, for control flow
+/// operations, the operations of every instruction of their bodies.
+pub(crate) fn check_nested<F>(instr: &Instr<'_>, check: &F) -> Result<(), SimulationError>
+where
+    F: Fn(&OpKind<'_>) -> Result<(), SimulationError>
+{
+    let Some(blocks) = instr.op.blocks() else {
+        return check(&instr.op);
+    };
+
+    blocks.bodies().try_for_each(|body| instruction::visit(body, &mut |instr| match instr.op.blocks() {
+        Some(_) => Ok(()),
+        None => check(&instr.op),
+    }))
+}
+
+/// Returns the index of the body of a [`OpKind::Switch`] run for the values of the register,
+/// the last body being run if the value has no case.
+pub(crate) fn case(state: &BitSet, register: &[Bit<'_>], num_bodies: usize) -> usize {
+    let value = register.iter().enumerate()
+        .fold(0u64, |acc, (i, bit)| acc | (u64::from(state.get(bit.id() as usize).unwrap()) << i));
+    value.min(num_bodies as u64 - 1) as usize
+}
+
+/// Runs the bodies of the control flow instruction selected by the bits with `run`, for
+/// simulators running shot by shot.
+pub(crate) fn run_control_flow<'id, F>(instr: &Instr<'id>, bits: &mut BitSet, run: &mut F) -> Result<(), SimulationError>
+where
+    F: FnMut(InstrIter<'id>, &mut BitSet) -> Result<(), SimulationError>
+{
+    let condition = |bits: &BitSet| bits.get(instr.bits[0].id() as usize).unwrap();
+
+    match &instr.op {
+        OpKind::IfElse(blocks) => {
+            let body = if condition(bits) { 0 } else { 1 };
+            run(blocks.get(body).unwrap(), bits)
+        }
+        OpKind::While(blocks) => {
+            for _ in 0..MAX_ITERATIONS {
+                if !condition(bits) {
+                    return Ok(());
+                }
+                run(blocks.get(0).unwrap(), bits)?;
+            }
+            Err(SimulationError::IterationLimit(MAX_ITERATIONS))
+        }
+        OpKind::For(repeat) => (0..repeat.count).try_for_each(|_| run(repeat.body.get(0).unwrap(), bits)),
+        OpKind::Switch(blocks) => {
+            let body = case(bits, instr.bits, blocks.len());
+            run(blocks.get(body).unwrap(), bits)
+        }
+        op => unreachable!("`{}` is not a control flow operation", op.label()),
+    }
+}
+
+/// Returns a random number generator seeded with the seed, or by the operating system if `None`.
+pub(crate) fn rng(seed: Option<u64>) -> StdRng {
+    match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    }
+}
+
+/// Runs `shot` once per shot, spreading the shots over `threads` threads, and collects their
+/// outcomes. The `n`th thread's random number generator is seeded with `seed + n`, so the
+/// results are reproducible for a given seed and number of threads.
+pub(crate) fn parallel_shots<F>(shots: u64, seed: Option<u64>, threads: usize, num_bits: usize, shot: F) -> Result<Histogram, SimulationError>
+where
+    F: Fn(&mut StdRng) -> Result<BitSet, SimulationError> + Sync
+{
+    let threads = threads.clamp(1, shots.max(1) as usize) as u64;
+    let shot = &shot;
+
+    let counts = thread::scope(|scope| {
+        let handles: Vec<_> = (0..threads)
+            .map(|n| {
+                let count = shots / threads + u64::from(n < shots % threads);
+                scope.spawn(move || {
+                    let mut rng = rng(seed.map(|seed| seed.wrapping_add(n)));
+                    let mut counts: HashMap<BitSet, u64> = HashMap::new();
+                    for _ in 0..count {
+                        *counts.entry(shot(&mut rng)?).or_default() += 1;
+                    }
+                    Ok(counts)
+                })
+            })
+            .collect();
+
+        handles.into_iter().map(|handle| handle.join().unwrap()).collect::<Result<Vec<_>, _>>()
+    })?;
+
+    let mut histogram = Histogram::new(num_bits);
+    counts.into_iter().flatten().for_each(|(outcome, count)| histogram.record(outcome, count));
+    Ok(histogram)
+}
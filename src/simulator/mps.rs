@@ -0,0 +1,607 @@
+//! Simulation of weakly entangled circuits, with
+//! [matrix product states](https://en.wikipedia.org/wiki/Matrix_product_state).
+
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use rand::Rng;
+
+use crate::bitset::BitSet;
+use crate::circuit::{QuantumCircuit, TranspiledCircuit};
+use crate::instruction::Instr;
+use crate::linalg::{c64, DMatrix};
+use crate::operation::OpKind;
+use crate::provider::{Backend, Histogram};
+use crate::transpiler;
+
+use super::{FullyConnected, SimulationError};
+
+/// The tensor of a site, with a left bond, a physical index and a right bond.
+#[derive(Clone, PartialEq, Debug)]
+struct Site {
+    left: usize,
+    right: usize,
+    /// The entries, the entry $ (l, s, r) $ being at index $ (2l + s) \cdot right + r $.
+    data: Vec<c64>,
+}
+
+impl Site {
+    fn index(&self, l: usize, s: usize, r: usize) -> usize {
+        (2 * l + s) * self.right + r
+    }
+}
+
+/// Returns the thin singular value decomposition $ U \Sigma V^\dagger $ of the `rows` by `cols`
+/// matrix given in row-major order, as $ U $, the singular values in descending order and
+/// $ V^\dagger $. Null singular values are dropped, but at least one is kept.
+fn svd(data: &[c64], rows: usize, cols: usize) -> (Vec<c64>, Vec<f64>, Vec<c64>) {
+    let dim = rows.max(cols);
+    let mut padded = DMatrix::zeros(dim);
+    for i in 0..rows {
+        for j in 0..cols {
+            padded[(i, j)] = data[i * cols + j];
+        }
+    }
+
+    let (u, values, v) = padded.svd();
+    let rank = values.iter()
+        .take(rows.min(cols))
+        .take_while(|&&value| value > values[0] * 1E-14)
+        .count()
+        .max(1);
+
+    let u = (0..rows).flat_map(|i| (0..rank).map(move |k| (i, k))).map(|(i, k)| u[(i, k)]).collect();
+    let vh = (0..rank).flat_map(|k| (0..cols).map(move |j| (k, j))).map(|(k, j)| v[(j, k)].conj()).collect();
+    (u, values[..rank].to_vec(), vh)
+}
+
+/// A matrix product state of qubits, where each qubit is a site of the chain.
+///
+/// The state is kept in mixed canonical form around an orthogonality center, so that the
+/// weights of the singular values discarded when truncating bonds are exact.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Mps {
+    sites: Vec<Site>,
+    center: usize,
+    max_bond: usize,
+    threshold: f64,
+    truncation_error: f64,
+}
+
+impl Mps {
+    /// Returns the state $ |0 \dots 0 \rangle $. Bonds are truncated to at most `max_bond`
+    /// singular values, and singular values whose relative weight is below `threshold` are
+    /// discarded.
+    ///
+    /// Panics if `max_bond` is zero.
+    pub fn new(num_qubits: usize, max_bond: usize, threshold: f64) -> Self {
+        assert!(max_bond > 0, "bond dimension must be positive");
+
+        let site = Site { left: 1, right: 1, data: vec![c64::ONE, c64::ZERO] };
+        Self { sites: vec![site; num_qubits], center: 0, max_bond, threshold, truncation_error: 0.0 }
+    }
+
+    pub fn num_qubits(&self) -> usize {
+        self.sites.len()
+    }
+
+    /// Returns the largest bond dimension of the state.
+    pub fn bond_dimension(&self) -> usize {
+        self.sites.iter().map(|site| site.right).max().unwrap_or(1)
+    }
+
+    /// Returns the accumulated weight of the singular values discarded so far, which bounds
+    /// the infidelity of the state.
+    pub fn truncation_error(&self) -> f64 {
+        self.truncation_error
+    }
+
+    /// Moves the orthogonality center to the site.
+    fn move_center(&mut self, to: usize) {
+        while self.center < to {
+            let k = self.center;
+            let site = &self.sites[k];
+            let (u, values, vh) = svd(&site.data, 2 * site.left, site.right);
+            let rank = values.len();
+
+            let next = &self.sites[k + 1];
+            let mut data = vec![c64::ZERO; rank * 2 * next.right];
+            for m in 0..rank {
+                for s in 0..2 {
+                    for r in 0..next.right {
+                        data[(2 * m + s) * next.right + r] = (0..next.left)
+                            .map(|j| vh[m * next.left + j] * values[m] * next.data[next.index(j, s, r)])
+                            .sum();
+                    }
+                }
+            }
+
+            let (left, right) = (site.left, next.right);
+            self.sites[k] = Site { left, right: rank, data: u };
+            self.sites[k + 1] = Site { left: rank, right, data };
+            self.center += 1;
+        }
+
+        while self.center > to {
+            let k = self.center;
+            let site = &self.sites[k];
+            let (u, values, vh) = svd(&site.data, site.left, 2 * site.right);
+            let rank = values.len();
+
+            let prev = &self.sites[k - 1];
+            let mut data = vec![c64::ZERO; prev.left * 2 * rank];
+            for l in 0..prev.left {
+                for s in 0..2 {
+                    for m in 0..rank {
+                        data[(2 * l + s) * rank + m] = (0..prev.right)
+                            .map(|j| prev.data[prev.index(l, s, j)] * u[j * rank + m] * values[m])
+                            .sum();
+                    }
+                }
+            }
+
+            let (left, right) = (prev.left, site.right);
+            self.sites[k] = Site { left: rank, right, data: vh };
+            self.sites[k - 1] = Site { left, right: rank, data };
+            self.center -= 1;
+        }
+    }
+
+    /// Applies the single qubit gate.
+    pub fn apply1(&mut self, gate: &DMatrix, qubit: usize) {
+        let site = &mut self.sites[qubit];
+        for l in 0..site.left {
+            for r in 0..site.right {
+                let (i0, i1) = (site.index(l, 0, r), site.index(l, 1, r));
+                let (a0, a1) = (site.data[i0], site.data[i1]);
+                site.data[i0] = gate[(0, 0)] * a0 + gate[(0, 1)] * a1;
+                site.data[i1] = gate[(1, 0)] * a0 + gate[(1, 1)] * a1;
+            }
+        }
+    }
+
+    /// Applies the two-qubit gate, whose first qubit is `qubit1`. Qubits which are not
+    /// adjacent are brought next to each other with swaps, which are undone afterwards.
+    ///
+    /// Panics if the qubits are the same.
+    pub fn apply2(&mut self, gate: &DMatrix, qubit1: usize, qubit2: usize) {
+        assert_ne!(qubit1, qubit2, "a two-qubit gate must act on different qubits");
+
+        let (p, q) = (qubit1.min(qubit2), qubit1.max(qubit2));
+        let swap = OpKind::Swap.matrix(&[]).unwrap();
+
+        for k in (p + 1..q).rev() {
+            self.apply_adjacent(&swap, k, true);
+        }
+        self.apply_adjacent(gate, p, qubit1 < qubit2);
+        for k in p + 1..q {
+            self.apply_adjacent(&swap, k, true);
+        }
+    }
+
+    /// Applies the two-qubit gate to the sites `k` and `k + 1`, where the first qubit of the
+    /// gate is the site `k` if `ordered` is `true`, and truncates the bond between them.
+    fn apply_adjacent(&mut self, gate: &DMatrix, k: usize, ordered: bool) {
+        self.move_center(k);
+
+        let (a, b) = (&self.sites[k], &self.sites[k + 1]);
+        let (left, bond, right) = (a.left, a.right, b.right);
+
+        // The two-site tensor, with the entry $ (l, s_1, s_2, r) $ at index $ ((2l + s_1) \cdot 2 + s_2) \cdot right + r $.
+        let mut theta = vec![c64::ZERO; left * 4 * right];
+        for l in 0..left {
+            for s1 in 0..2 {
+                for s2 in 0..2 {
+                    for r in 0..right {
+                        theta[((2 * l + s1) * 2 + s2) * right + r] = (0..bond)
+                            .map(|j| a.data[a.index(l, s1, j)] * b.data[b.index(j, s2, r)])
+                            .sum();
+                    }
+                }
+            }
+        }
+
+        let basis = |s1: usize, s2: usize| if ordered { 2 * s1 + s2 } else { 2 * s2 + s1 };
+        for l in 0..left {
+            for r in 0..right {
+                let index = |s1: usize, s2: usize| ((2 * l + s1) * 2 + s2) * right + r;
+                let old: Vec<_> = (0..4).map(|n| theta[index(n / 2, n % 2)]).collect();
+                for n in 0..4 {
+                    let (s1, s2) = (n / 2, n % 2);
+                    theta[index(s1, s2)] = (0..4).map(|m| gate[(basis(s1, s2), basis(m / 2, m % 2))] * old[m]).sum();
+                }
+            }
+        }
+
+        let (u, values, vh) = svd(&theta, 2 * left, 2 * right);
+
+        let total: f64 = values.iter().map(|value| value * value).sum();
+        let kept = values.iter()
+            .take(self.max_bond)
+            .take_while(|&&value| value * value / total > self.threshold)
+            .count()
+            .max(1);
+        let discarded: f64 = values[kept..].iter().map(|value| value * value).sum();
+        self.truncation_error += discarded / total;
+        let scale = (total / (total - discarded)).sqrt();
+
+        let u = (0..2 * left).flat_map(|i| u[i * values.len()..i * values.len() + kept].to_vec()).collect();
+        let vh = (0..kept)
+            .flat_map(|m| {
+                let factor = values[m] * scale;
+                vh[m * 2 * right..(m + 1) * 2 * right].iter().map(move |&z| z * factor)
+            })
+            .collect();
+
+        self.sites[k] = Site { left, right: kept, data: u };
+        self.sites[k + 1] = Site { left: kept, right, data: vh };
+        self.center = k + 1;
+    }
+
+    /// Measures the qubit in the computational basis, collapsing the state.
+    pub fn measure<R: Rng + ?Sized>(&mut self, qubit: usize, rng: &mut R) -> bool {
+        self.move_center(qubit);
+
+        let site = &mut self.sites[qubit];
+        let weight = |site: &Site, s: usize| -> f64 {
+            (0..site.left)
+                .flat_map(|l| (0..site.right).map(move |r| (l, r)))
+                .map(|(l, r)| site.data[site.index(l, s, r)].abs_sqr())
+                .sum()
+        };
+
+        let (p0, p1) = (weight(site, 0), weight(site, 1));
+        let outcome = rng.gen::<f64>() * (p0 + p1) >= p0;
+        let (keep, norm) = if outcome { (1, p1) } else { (0, p0) };
+
+        for l in 0..site.left {
+            for r in 0..site.right {
+                let (i, j) = (site.index(l, keep, r), site.index(l, 1 - keep, r));
+                site.data[i] *= norm.sqrt().recip();
+                site.data[j] = c64::ZERO;
+            }
+        }
+
+        outcome
+    }
+
+    /// Resets the qubit to the $ |0 \rangle $ state.
+    pub fn reset<R: Rng + ?Sized>(&mut self, qubit: usize, rng: &mut R) {
+        if self.measure(qubit, rng) {
+            self.apply1(&OpKind::X.matrix(&[]).unwrap(), qubit);
+        }
+    }
+
+    /// Samples the outcome of measuring every qubit, without collapsing the state. The
+    /// `n`th outcome is the one of the `n`th qubit.
+    pub fn sample<R: Rng + ?Sized>(&mut self, rng: &mut R) -> Vec<bool> {
+        self.move_center(0);
+
+        // The sites right of the current one are right-canonical, so the probabilities of
+        // it's outcomes only depend on the contraction of the sites left of it.
+        let mut env = vec![c64::ONE];
+        let mut res = Vec::with_capacity(self.sites.len());
+        for site in &self.sites {
+            let contract = |s: usize| -> Vec<c64> {
+                (0..site.right)
+                    .map(|r| (0..site.left).map(|l| env[l] * site.data[site.index(l, s, r)]).sum())
+                    .collect()
+            };
+
+            let (v0, v1) = (contract(0), contract(1));
+            let p0: f64 = v0.iter().map(c64::abs_sqr).sum();
+            let p1: f64 = v1.iter().map(c64::abs_sqr).sum();
+
+            let outcome = rng.gen::<f64>() * (p0 + p1) >= p0;
+            let (v, p) = if outcome { (v1, p1) } else { (v0, p0) };
+            env = v.into_iter().map(|z| z * p.sqrt().recip()).collect();
+            res.push(outcome);
+        }
+
+        res
+    }
+}
+
+/// A simulator representing the state as a [matrix product state](Mps), whose cost grows with
+/// the entanglement of the state rather than with it's number of qubits.
+///
+/// Gates acting on qubits which are not adjacent in the chain are applied with swaps, and
+/// Toffoli gates are decomposed into one and two-qubit gates. The part of the circuit before
+/// the first measurement is only simulated once.
+#[derive(Clone, PartialEq, Debug)]
+pub struct MpsSimulator {
+    /// The number of shots to sample.
+    pub shots: u64,
+    /// The seed of the random number generator, taken from the operating system if `None`.
+    pub seed: Option<u64>,
+    /// The maximum bond dimension of the state.
+    pub max_bond: usize,
+    /// Singular values whose weight relative to the norm of the state is below the threshold
+    /// are discarded.
+    pub threshold: f64,
+}
+
+impl MpsSimulator {
+    /// Creates a simulator with a maximum bond dimension of 64 and a threshold of $ 10^{-12} $.
+    pub fn new(shots: u64) -> Self {
+        Self { shots, seed: None, max_bond: 64, threshold: 1E-12 }
+    }
+
+    /// Returns `Ok` if the operation is supported by the simulator.
+    fn check(op: &OpKind<'_>) -> Result<(), SimulationError> {
+        match op {
+            OpKind::Nop | OpKind::Measure | OpKind::Reset | OpKind::Compute(_) | OpKind::CCX => Ok(()),
+            op if op.is_unitary() && matches!(op.qubits().get(), Some(1 | 2)) => Ok(()),
+            op => Err(SimulationError::UnsupportedOp(op.label())),
+        }
+    }
+
+    /// Applies the unitary operation to the state.
+    fn apply_gate(mps: &mut Mps, op: &OpKind<'_>, qubits: &[usize], parameters: &[f64]) {
+        match (op, qubits) {
+            (OpKind::CCX, &[a, b, t]) => {
+                use OpKind::*;
+
+                let decomposition = [
+                    (H, vec![t]), (CX, vec![b, t]), (Tdg, vec![t]), (CX, vec![a, t]),
+                    (T, vec![t]), (CX, vec![b, t]), (Tdg, vec![t]), (CX, vec![a, t]),
+                    (T, vec![b]), (T, vec![t]), (H, vec![t]), (CX, vec![a, b]),
+                    (T, vec![a]), (Tdg, vec![b]), (CX, vec![a, b]),
+                ];
+                decomposition.iter().for_each(|(op, qubits)| Self::apply_gate(mps, op, qubits, &[]));
+            }
+            (op, &[q]) => mps.apply1(&op.matrix(parameters).unwrap(), q),
+            (op, &[q1, q2]) => mps.apply2(&op.matrix(parameters).unwrap(), q1, q2),
+            (op, _) => unreachable!("unsupported operation `{}`", op.label()),
+        }
+    }
+
+    /// Applies the instruction's operation to the state and the bits.
+    fn apply<R: Rng + ?Sized>(circ: &QuantumCircuit, mps: &mut Mps, instr: &Instr<'_>, bits: &mut BitSet, rng: &mut R) -> Result<(), SimulationError> {
+        let qubits = super::qubits(instr);
+
+        match &instr.op {
+            OpKind::Nop => (),
+            OpKind::Measure => {
+                let outcome = mps.measure(qubits[0], rng);
+                bits.set(instr.bits[0].id() as usize, outcome);
+            }
+            OpKind::Reset => mps.reset(qubits[0], rng),
+            OpKind::Compute(compute) => super::run_compute(bits, compute, instr.bits),
+            op if op.blocks().is_some() => super::run_control_flow(instr, bits, &mut |body, bits| {
+                for instr in body {
+                    super::run_modified(&instr.modifiers, bits, &mut |bits| Self::apply(circ, mps, &instr, bits, rng))?;
+                }
+                Ok(())
+            })?,
+            op if !instr.has_quantum_modifier() => Self::apply_gate(mps, op, &qubits, &super::parameters(circ, instr)?),
+            op => {
+                let (controls, _) = super::controls(instr);
+                if controls.len() + qubits.len() <= 2 {
+                    match super::gate(circ, instr)? {
+                        (gate, qubits) if qubits.len() == 1 => mps.apply1(&gate, qubits[0]),
+                        (gate, qubits) => mps.apply2(&gate, qubits[0], qubits[1]),
+                    }
+                } else {
+                    // Larger modified gates are decomposed into one and two-qubit gates.
+                    let gates = transpiler::modified_gates(instr, &super::parameters(circ, instr)?, &super::exponents(circ, instr)?)
+                        .map_err(|_| SimulationError::UnsupportedOp(op.label()))?;
+                    gates.iter().for_each(|(op, qubits, parameters)| Self::apply_gate(mps, op, qubits, parameters));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Samples the outcomes of the circuit's bits, returning the histogram along with the
+    /// largest truncation error over all shots, see [`Mps::truncation_error`].
+    pub fn sample(&self, circ: &QuantumCircuit) -> Result<(Histogram, f64), SimulationError> {
+        let inlined = circ.inlined().map_err(SimulationError::Inline)?;
+        let circ = &*inlined;
+
+        let mut instrs = Vec::new();
+        for instr in circ.iter() {
+            super::check_nested(&instr, &Self::check)?;
+            instrs.push(instr);
+        }
+
+        let mut rng = super::rng(self.seed);
+
+        // The instructions before the first random or classical one are deterministic,
+        // they are shared by every shot.
+        let split = instrs.iter()
+            .position(|instr| instr.has_classical_modifier() || !instr.op.is_unitary())
+            .unwrap_or(instrs.len());
+        let mut prefix = Mps::new(circ.width(), self.max_bond, self.threshold);
+        let mut bits = BitSet::new(circ.num_bits());
+        for instr in &instrs[..split] {
+            Self::apply(circ, &mut prefix, instr, &mut bits, &mut rng)?;
+        }
+
+        let tail = &instrs[split..];
+        let mut counts: HashMap<BitSet, u64> = HashMap::new();
+        let mut truncation_error = prefix.truncation_error();
+
+        if tail.iter().all(|instr| !instr.has_modifier() && instr.op == OpKind::Measure) {
+            // Measurements at the end of the circuit are sampled without collapsing the state.
+            for _ in 0..self.shots {
+                let outcomes = prefix.sample(&mut rng);
+                let mut bits = bits.clone();
+                tail.iter().for_each(|instr| {
+                    bits.set(instr.bits[0].id() as usize, outcomes[instr.qubits[0].id() as usize]);
+                });
+                *counts.entry(bits).or_default() += 1;
+            }
+        } else {
+            for _ in 0..self.shots {
+                let mut mps = prefix.clone();
+                let mut bits = bits.clone();
+                for instr in tail {
+                    super::run_modified(&instr.modifiers, &mut bits, &mut |bits| Self::apply(circ, &mut mps, instr, bits, &mut rng))?;
+                }
+                truncation_error = truncation_error.max(mps.truncation_error());
+                *counts.entry(bits).or_default() += 1;
+            }
+        }
+
+        let mut histogram = Histogram::new(circ.num_bits());
+        counts.into_iter().for_each(|(outcome, count)| histogram.record(outcome, count));
+        Ok((histogram, truncation_error))
+    }
+}
+
+#[async_trait]
+impl Backend for MpsSimulator {
+    type Architecture = FullyConnected;
+
+    type RuntimeError = SimulationError;
+
+    fn execute(&self, circ: &TranspiledCircuit<Self::Architecture>) -> Result<Histogram, Self::RuntimeError> {
+        self.sample(circ).map(|(histogram, _)| histogram)
+    }
+
+    async fn execute_async(&self, circ: &TranspiledCircuit<Self::Architecture>) -> Result<Histogram, Self::RuntimeError> {
+        self.execute(circ)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use crate::circuit::QuantumCircuit;
+    use crate::linalg::{c64, DMatrix};
+    use crate::operation::OpKind;
+    use crate::simulator::Statevector;
+
+    use super::{Mps, MpsSimulator};
+
+    /// Contracts the chain into the amplitudes of the state, the `n`th qubit being the `n`th
+    /// bit of the index of a basis state.
+    fn amplitudes(mps: &Mps) -> Vec<c64> {
+        let mut res = vec![(0, vec![c64::ONE])];
+        for (q, site) in mps.sites.iter().enumerate() {
+            res = res.into_iter()
+                .flat_map(|(index, env)| (0..2).map(move |s| {
+                    let next = (0..site.right)
+                        .map(|r| (0..site.left).map(|l| env[l] * site.data[site.index(l, s, r)]).sum())
+                        .collect();
+                    (index | (s << q), next)
+                }))
+                .collect();
+        }
+
+        let mut amplitudes = vec![c64::ZERO; 1 << mps.num_qubits()];
+        res.into_iter().for_each(|(index, env)| amplitudes[index] = env[0]);
+        amplitudes
+    }
+
+    /// Returns $ |\langle a | b \rangle|^2 $.
+    fn fidelity(a: &[c64], b: &[c64]) -> f64 {
+        a.iter().zip(b).map(|(x, y)| x.conj() * *y).sum::<c64>().abs_sqr()
+    }
+
+    /// Applies the same random layers of gates, on random and possibly distant qubits, to
+    /// the state and to an exact statevector.
+    fn random_circuit(mps: &mut Mps, seed: u64) -> Statevector {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let n = mps.num_qubits();
+        let mut exact = Statevector::new(n);
+
+        for _ in 0..4 * n {
+            let q1 = rng.gen_range(0..n);
+            let q2 = (q1 + rng.gen_range(1..n)) % n;
+            let (a, b) = (DMatrix::random_unitary(2, &mut rng), DMatrix::random_unitary(4, &mut rng));
+
+            mps.apply1(&a, q1);
+            exact.apply(&a, &[q1]);
+            mps.apply2(&b, q1, q2);
+            exact.apply(&b, &[q1, q2]);
+        }
+
+        exact
+    }
+
+    #[test]
+    fn matches_statevectors_without_truncation() {
+        for seed in 0..4 {
+            let mut mps = Mps::new(6, 64, 0.0);
+            let exact = random_circuit(&mut mps, seed);
+
+            assert!(mps.truncation_error() < 1E-12);
+            assert!((fidelity(&amplitudes(&mps), exact.amplitudes()) - 1.0).abs() < 1E-9);
+        }
+    }
+
+    #[test]
+    fn truncation_error_bounds_the_infidelity() {
+        for seed in 0..4 {
+            let mut mps = Mps::new(7, 2, 0.0);
+            let exact = random_circuit(&mut mps, seed);
+
+            let error = mps.truncation_error();
+            let fidelity = fidelity(&amplitudes(&mps), exact.amplitudes());
+            assert!(mps.bond_dimension() <= 2);
+            assert!(error > 1E-3);
+            assert!(1.0 - fidelity <= 2.0 * error, "{fidelity} {error}");
+
+            // The truncated state stays normalized.
+            let norm: f64 = amplitudes(&mps).iter().map(c64::abs_sqr).sum();
+            assert!((norm - 1.0).abs() < 1E-9);
+        }
+    }
+
+    #[test]
+    fn threshold_discards_small_singular_values() {
+        // $ R_y(\epsilon) $ followed by a CNOT has a singular value of weight $ \sin^2(\epsilon / 2) $.
+        let epsilon = 1E-3f64;
+        for (threshold, discarded) in [(1E-9, false), (1E-5, true)] {
+            let mut mps = Mps::new(2, 4, threshold);
+            mps.apply1(&OpKind::RY.matrix(&[epsilon]).unwrap(), 0);
+            mps.apply2(&OpKind::CX.matrix(&[]).unwrap(), 0, 1);
+
+            assert_eq!(mps.bond_dimension(), if discarded { 1 } else { 2 });
+            let expected = if discarded { (epsilon / 2.0).sin().powi(2) } else { 0.0 };
+            assert!((mps.truncation_error() - expected).abs() < 1E-12);
+        }
+    }
+
+    #[test]
+    fn samples_wide_ghz_states() {
+        let n = 80;
+        let circ = QuantumCircuit::new(|b| {
+            let qubits: Vec<_> = (0..n).map(|_| b.qubit()).collect::<Result<_, _>>()?;
+            let bits: Vec<_> = (0..n).map(|_| b.bit()).collect::<Result<_, _>>()?;
+            b.h(qubits[0]);
+            // Distant gates are applied through swaps, which don't grow the bonds.
+            qubits[1..].iter().for_each(|&qubit| { b.cx(qubits[0], qubit); });
+            qubits.iter().zip(&bits).for_each(|(&qubit, &bit)| { b.measure(qubit, bit); });
+            Ok(())
+        }).unwrap();
+
+        let simulator = MpsSimulator { shots: 100, seed: Some(3), max_bond: 4, threshold: 1E-12 };
+        let (histogram, error) = simulator.sample(&circ).unwrap();
+
+        assert!(error < 1E-12);
+        assert_eq!(histogram.iter().count(), 2);
+        assert!(histogram.iter().all(|(outcome, _)| outcome.count_ones() == 0 || outcome.count_ones() == n));
+    }
+
+    #[test]
+    fn collapses_on_measurement() {
+        let mut rng = StdRng::seed_from_u64(5);
+        for _ in 0..16 {
+            let mut mps = Mps::new(4, 8, 0.0);
+            mps.apply1(&OpKind::H.matrix(&[]).unwrap(), 0);
+            mps.apply2(&OpKind::CX.matrix(&[]).unwrap(), 0, 3);
+
+            let outcome = mps.measure(3, &mut rng);
+            assert_eq!(mps.sample(&mut rng), [outcome, false, false, outcome]);
+
+            mps.reset(0, &mut rng);
+            assert_eq!(mps.sample(&mut rng), [false, false, false, outcome]);
+        }
+    }
+}
@@ -0,0 +1,348 @@
+//! Noise channels and noise models, attaching channels to the operations of a circuit.
+
+use std::collections::HashMap;
+
+use crate::linalg::{c64, DMatrix};
+use crate::pauli::{Pauli, PauliString};
+
+use super::SimulationError;
+
+/// A quantum channel in it's Kraus representation, $ \rho \mapsto \sum_k K_k \rho K_k^\dagger $.
+///
+/// As for gates, the first qubit the channel is applied to is the most significant one in
+/// the basis of the Kraus operators.
+#[derive(Clone, PartialEq, Debug)]
+pub struct KrausChannel {
+    num_qubits: usize,
+    operators: Vec<DMatrix>,
+    /// The probabilities of the operators, when they are all proportional to unitaries.
+    mixture: Option<Vec<f64>>,
+}
+
+impl KrausChannel {
+    /// Creates a channel from it's Kraus operators. Returns `None` if there are no operators,
+    /// if their dimensions differ or are not a power of two, or if the channel is not trace
+    /// preserving, that is if $ \sum_k K_k^\dagger K_k \neq I $.
+    pub fn new(operators: Vec<DMatrix>) -> Option<Self> {
+        let dim = operators.first()?.dim();
+        if !dim.is_power_of_two() || operators.iter().any(|op| op.dim() != dim) {
+            return None;
+        }
+
+        let sum = operators.iter().fold(DMatrix::zeros(dim), |acc, op| &acc + &(&op.adjoint() * op));
+        (sum == DMatrix::eye(dim)).then(|| Self::new_unchecked(operators))
+    }
+
+    /// Creates a channel from Kraus operators known to be valid, dropping the null ones.
+    fn new_unchecked(operators: impl IntoIterator<Item = DMatrix>) -> Self {
+        let operators: Vec<_> = operators.into_iter().filter(|op| op.norm() > 1E-12).collect();
+        let dim = operators[0].dim();
+
+        // $ K^\dagger K = p I $ when $ K $ is $ \sqrt{p} $ times a unitary.
+        let mixture = operators.iter()
+            .map(|op| {
+                let product = &op.adjoint() * op;
+                let p = product[(0, 0)].re;
+                (product == DMatrix::eye(dim).scale(p.into())).then_some(p)
+            })
+            .collect();
+
+        Self { num_qubits: dim.trailing_zeros() as usize, operators, mixture }
+    }
+
+    /// The channel applying the unitary.
+    ///
+    /// Panics if the matrix is not unitary.
+    pub fn unitary(unitary: DMatrix) -> Self {
+        assert!(unitary.is_unitary(), "matrix is not unitary");
+        Self::new_unchecked([unitary])
+    }
+
+    /// The depolarizing channel on `num_qubits` qubits, $ \rho \mapsto (1 - p) \rho + p I / d $.
+    ///
+    /// Panics if $ p $ is not in $ [0, 1 + 1 / (d^2 - 1)] $.
+    pub fn depolarizing(p: f64, num_qubits: usize) -> Self {
+        let d2 = (1usize << (2 * num_qubits)) as f64;
+        assert!((0.0..=d2 / (d2 - 1.0)).contains(&p), "invalid depolarizing probability");
+
+        let paulis = (0..1usize << (2 * num_qubits)).map(|n| {
+            let paulis: Vec<_> = (0..num_qubits)
+                .map(|q| (q, [Pauli::I, Pauli::X, Pauli::Y, Pauli::Z][(n >> (2 * q)) & 3]))
+                .collect();
+            let weight = if n == 0 { 1.0 - p + p / d2 } else { p / d2 };
+            PauliString::from_sparse(num_qubits, &paulis).to_matrix().scale(weight.sqrt().into())
+        });
+
+        Self::new_unchecked(paulis)
+    }
+
+    /// The single qubit Pauli channel, applying X, Y and Z with the given probabilities.
+    ///
+    /// Panics if the probabilities are negative or sum to more than $ 1 $.
+    pub fn pauli(px: f64, py: f64, pz: f64) -> Self {
+        let pi = 1.0 - px - py - pz;
+        assert!([px, py, pz, pi].iter().all(|&p| p >= 0.0), "invalid pauli channel probabilities");
+
+        let operators = [(pi, Pauli::I), (px, Pauli::X), (py, Pauli::Y), (pz, Pauli::Z)].map(|(p, pauli)| {
+            PauliString::from_sparse(1, &[(0, pauli)]).to_matrix().scale(p.sqrt().into())
+        });
+
+        Self::new_unchecked(operators)
+    }
+
+    /// The amplitude damping channel, decaying $ |1 \rangle $ to $ |0 \rangle $ with probability $ \gamma $.
+    ///
+    /// Panics if $ \gamma $ is not in $ [0, 1] $.
+    pub fn amplitude_damping(gamma: f64) -> Self {
+        assert!((0.0..=1.0).contains(&gamma), "invalid amplitude damping probability");
+
+        let (o, l) = (c64::ZERO, c64::ONE);
+        Self::new_unchecked([
+            DMatrix::new(2, vec![l, o, o, (1.0 - gamma).sqrt().into()]).unwrap(),
+            DMatrix::new(2, vec![o, gamma.sqrt().into(), o, o]).unwrap(),
+        ])
+    }
+
+    /// The phase damping channel, scaling the coherences by $ \sqrt{1 - \lambda} $.
+    ///
+    /// Panics if $ \lambda $ is not in $ [0, 1] $.
+    pub fn phase_damping(lambda: f64) -> Self {
+        assert!((0.0..=1.0).contains(&lambda), "invalid phase damping probability");
+
+        let (o, l) = (c64::ZERO, c64::ONE);
+        Self::new_unchecked([
+            DMatrix::new(2, vec![l, o, o, (1.0 - lambda).sqrt().into()]).unwrap(),
+            DMatrix::new(2, vec![o, o, o, lambda.sqrt().into()]).unwrap(),
+        ])
+    }
+
+    /// The thermal relaxation of a qubit at zero temperature during `time`, with relaxation
+    /// time $ T_1 $ and dephasing time $ T_2 $.
+    ///
+    /// Panics if the times are not positive, or if $ T_2 > 2 T_1 $.
+    pub fn thermal_relaxation(t1: f64, t2: f64, time: f64) -> Self {
+        assert!(t1 > 0.0 && t2 > 0.0 && time >= 0.0, "invalid relaxation times");
+        assert!(t2 <= 2.0 * t1, "T2 can't be greater than 2 T1");
+
+        // Amplitude damping already scales the coherences by $ e^{-t / 2 T_1} $, the pure
+        // dephasing accounts for the remaining decay.
+        let gamma = 1.0 - (-time / t1).exp();
+        let remaining = (time / (2.0 * t1) - time / t2).exp();
+        Self::amplitude_damping(gamma).compose(&Self::phase_damping(1.0 - remaining * remaining))
+    }
+
+    /// Returns the channel applying this channel, then the other one.
+    ///
+    /// Panics if the channels act on different numbers of qubits.
+    pub fn compose(&self, then: &Self) -> Self {
+        assert_eq!(self.num_qubits, then.num_qubits, "channels act on different numbers of qubits");
+        Self::new_unchecked(then.operators.iter().flat_map(|b| self.operators.iter().map(move |a| b * a)))
+    }
+
+    pub fn num_qubits(&self) -> usize {
+        self.num_qubits
+    }
+
+    /// Returns the groups of qubits to apply the channel to, when it is attached to the
+    /// operation with the given label acting on `qubits`.
+    pub(crate) fn targets<'a>(&self, label: &'static str, qubits: &'a [usize]) -> Result<Vec<&'a [usize]>, SimulationError> {
+        if self.num_qubits == qubits.len() {
+            Ok(vec![qubits])
+        } else if self.num_qubits == 1 {
+            Ok(qubits.chunks(1).collect())
+        } else {
+            Err(SimulationError::NoiseArity(label, self.num_qubits, qubits.len()))
+        }
+    }
+
+    /// Returns the Kraus operators of the channel.
+    pub fn operators(&self) -> &[DMatrix] {
+        &self.operators
+    }
+
+    /// Returns the probability of each Kraus operator if the channel is a mixture of unitaries,
+    /// that is if every operator is of the form $ \sqrt{p_k} U_k $.
+    pub fn unitary_mixture(&self) -> Option<&[f64]> {
+        self.mixture.as_deref()
+    }
+}
+
+/// A classical error on the outcome of a measurement.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct ReadoutError {
+    p01: f64,
+    p10: f64,
+}
+
+impl ReadoutError {
+    /// Creates a readout error, where `p01` is the probability of reading $ 1 $ when the
+    /// qubit was measured in the state $ |0 \rangle $, and `p10` the converse.
+    ///
+    /// Panics if the probabilities are not in $ [0, 1] $.
+    pub fn new(p01: f64, p10: f64) -> Self {
+        assert!((0.0..=1.0).contains(&p01) && (0.0..=1.0).contains(&p10), "invalid readout error probability");
+        Self { p01, p10 }
+    }
+
+    /// Returns the probability of reading the opposite of the measured outcome.
+    pub fn flip_probability(&self, outcome: bool) -> f64 {
+        if outcome { self.p10 } else { self.p01 }
+    }
+}
+
+/// The channels attached to an operation.
+#[derive(Clone, Default, Debug)]
+struct OpErrors {
+    /// Applied on any qubits, unless there are specific channels for them.
+    all: Vec<KrausChannel>,
+    /// Applied on specific qubits, in order.
+    qubits: HashMap<Vec<usize>, Vec<KrausChannel>>,
+}
+
+/// Describes the noise of a device, by attaching channels to operations.
+///
+/// Channels are keyed by the label of the operation, see [`OpKind::label`](crate::operation::OpKind::label),
+/// and optionally by the qubits it acts on. They are applied after the operation, except for
+/// measurements where they are applied before. A channel acting on a single qubit may be
+/// attached to an operation on several qubits, it is then applied to each of them.
+#[derive(Clone, Default, Debug)]
+pub struct NoiseModel {
+    errors: HashMap<String, OpErrors>,
+    readout: HashMap<usize, ReadoutError>,
+    default_readout: Option<ReadoutError>,
+}
+
+impl NoiseModel {
+    /// Creates a noise model without any noise.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns `true` if the model has no noise at all.
+    pub fn is_ideal(&self) -> bool {
+        self.errors.is_empty() && self.readout.is_empty() && self.default_readout.is_none()
+    }
+
+    /// Attaches the channel to every occurence of the operation with the given label.
+    pub fn add_error(&mut self, label: &str, channel: KrausChannel) -> &mut Self {
+        self.errors.entry(label.to_owned()).or_default().all.push(channel);
+        self
+    }
+
+    /// Attaches the channel to the operation with the given label, when it acts on exactly
+    /// the given qubits in that order. These channels replace the ones attached with
+    /// [`NoiseModel::add_error`].
+    pub fn add_qubit_error(&mut self, label: &str, qubits: &[usize], channel: KrausChannel) -> &mut Self {
+        self.errors.entry(label.to_owned()).or_default().qubits.entry(qubits.to_vec()).or_default().push(channel);
+        self
+    }
+
+    /// Sets the readout error of every qubit.
+    pub fn add_readout_error(&mut self, error: ReadoutError) -> &mut Self {
+        self.default_readout = Some(error);
+        self
+    }
+
+    /// Sets the readout error of the qubit, replacing the one set with [`NoiseModel::add_readout_error`].
+    pub fn add_qubit_readout_error(&mut self, qubit: usize, error: ReadoutError) -> &mut Self {
+        self.readout.insert(qubit, error);
+        self
+    }
+
+    /// Returns the channels to apply with the operation with the given label, on the given qubits.
+    pub fn errors(&self, label: &str, qubits: &[usize]) -> &[KrausChannel] {
+        self.errors.get(label)
+            .map(|errors| errors.qubits.get(qubits).unwrap_or(&errors.all).as_slice())
+            .unwrap_or(&[])
+    }
+
+    /// Returns the readout error of the qubit, if it has one.
+    pub fn readout_error(&self, qubit: usize) -> Option<&ReadoutError> {
+        self.readout.get(&qubit).or(self.default_readout.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::linalg::{c64, DMatrix};
+
+    use super::{KrausChannel, NoiseModel, ReadoutError};
+
+    /// Returns $ \sum_k K_k^\dagger K_k $.
+    fn completeness(channel: &KrausChannel) -> DMatrix {
+        let dim = 1 << channel.num_qubits();
+        channel.operators().iter().fold(DMatrix::zeros(dim), |acc, op| &acc + &(&op.adjoint() * op))
+    }
+
+    #[test]
+    fn channels_are_trace_preserving() {
+        let channels = [
+            KrausChannel::depolarizing(0.3, 1),
+            KrausChannel::depolarizing(0.1, 2),
+            KrausChannel::pauli(0.1, 0.2, 0.3),
+            KrausChannel::amplitude_damping(0.4),
+            KrausChannel::phase_damping(0.7),
+            KrausChannel::thermal_relaxation(50.0, 70.0, 10.0),
+        ];
+
+        for channel in &channels {
+            assert_eq!(completeness(channel), DMatrix::eye(1 << channel.num_qubits()));
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_operators() {
+        let (o, l) = (c64::ZERO, c64::ONE);
+        assert!(KrausChannel::new(Vec::new()).is_none());
+        assert!(KrausChannel::new(vec![DMatrix::eye(3)]).is_none());
+        assert!(KrausChannel::new(vec![DMatrix::eye(2), DMatrix::eye(4)]).is_none());
+        assert!(KrausChannel::new(vec![DMatrix::new(2, vec![l, o, o, o]).unwrap()]).is_none());
+        assert!(KrausChannel::new(vec![DMatrix::eye(2)]).is_some());
+    }
+
+    #[test]
+    fn detects_unitary_mixtures() {
+        let depolarizing = KrausChannel::depolarizing(0.2, 1);
+        let probabilities = depolarizing.unitary_mixture().unwrap();
+        assert!((probabilities.iter().sum::<f64>() - 1.0).abs() < 1E-12);
+        assert!((probabilities[0] - 0.85).abs() < 1E-12);
+
+        // Null operators are dropped.
+        assert_eq!(KrausChannel::pauli(0.0, 0.0, 0.1).operators().len(), 2);
+        assert!(KrausChannel::amplitude_damping(0.3).unitary_mixture().is_none());
+    }
+
+    #[test]
+    fn composes_channels() {
+        // Two bit flips with probability $ p $ flip with probability $ 2 p (1 - p) $.
+        let flip = KrausChannel::pauli(0.1, 0.0, 0.0);
+        let twice = flip.compose(&flip);
+        let probabilities = twice.unitary_mixture().unwrap();
+        let flips: f64 = twice.operators().iter().zip(probabilities)
+            .filter(|(op, _)| op[(0, 0)].abs_sqr() < 1E-12)
+            .map(|(_, p)| p)
+            .sum();
+        assert!((flips - 0.18).abs() < 1E-12);
+    }
+
+    #[test]
+    fn selects_qubit_specific_errors() {
+        let mut model = NoiseModel::new();
+        assert!(model.is_ideal());
+
+        model.add_error("cx", KrausChannel::depolarizing(0.1, 2))
+            .add_qubit_error("cx", &[1, 0], KrausChannel::depolarizing(0.2, 2))
+            .add_readout_error(ReadoutError::new(0.1, 0.2))
+            .add_qubit_readout_error(3, ReadoutError::new(0.3, 0.0));
+
+        assert_eq!(model.errors("cx", &[0, 1]), &[KrausChannel::depolarizing(0.1, 2)]);
+        assert_eq!(model.errors("cx", &[1, 0]), &[KrausChannel::depolarizing(0.2, 2)]);
+        assert!(model.errors("h", &[0]).is_empty());
+        assert_eq!(model.readout_error(0).unwrap().flip_probability(true), 0.2);
+        assert_eq!(model.readout_error(3).unwrap().flip_probability(false), 0.3);
+
+        let channel = KrausChannel::amplitude_damping(0.1);
+        assert_eq!(channel.targets("cx", &[2, 5]).unwrap(), vec![&[2][..], &[5][..]]);
+        assert!(KrausChannel::depolarizing(0.1, 2).targets("ccx", &[0, 1, 2]).is_err());
+    }
+}
@@ -0,0 +1,608 @@
+//! Efficient simulation of Clifford circuits, with the stabilizer tableau of
+//! [Aaronson and Gottesman](https://arxiv.org/abs/quant-ph/0406196).
+
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use rand::Rng;
+
+use crate::bitset::BitSet;
+use crate::circuit::{QuantumCircuit, TranspiledCircuit};
+use crate::instruction::{Instr, Modifier};
+use crate::operation::OpKind;
+use crate::provider::{Backend, Histogram};
+
+use super::{FullyConnected, SimulationError};
+
+/// The stabilizer tableau of a state of $ n $ qubits.
+///
+/// The tableau has $ 2n + 1 $ rows, each being a Pauli string with a sign: the $ n $
+/// destabilizers, the $ n $ stabilizers, and a scratch row. The X and Z components of the
+/// rows are packed in words, so that row operations are fast.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Tableau {
+    num_qubits: usize,
+    words: usize,
+    x: Vec<u64>,
+    z: Vec<u64>,
+    r: Vec<bool>,
+}
+
+impl Tableau {
+    /// Returns the tableau of the state $ |0 \dots 0 \rangle $.
+    pub fn new(num_qubits: usize) -> Self {
+        let words = num_qubits.div_ceil(64);
+        let rows = 2 * num_qubits + 1;
+        let mut res = Self {
+            num_qubits,
+            words,
+            x: vec![0; rows * words],
+            z: vec![0; rows * words],
+            r: vec![false; rows],
+        };
+
+        for q in 0..num_qubits {
+            let (word, mask) = Self::column(q);
+            res.x[q * words + word] |= mask;
+            res.z[(q + num_qubits) * words + word] |= mask;
+        }
+
+        res
+    }
+
+    pub fn num_qubits(&self) -> usize {
+        self.num_qubits
+    }
+
+    /// Returns the word and the mask of the qubit's column.
+    fn column(qubit: usize) -> (usize, u64) {
+        (qubit / 64, 1 << (qubit % 64))
+    }
+
+    /// Applies `f` to the X and Z bits of the qubit in each row but the scratch one, along
+    /// with the sign of the row. The new bits are written back.
+    fn update<F: Fn(bool, bool, &mut bool) -> (bool, bool)>(&mut self, qubit: usize, f: F) {
+        let (word, mask) = Self::column(qubit);
+        for row in 0..2 * self.num_qubits {
+            let i = row * self.words + word;
+            let (x, z) = f(self.x[i] & mask != 0, self.z[i] & mask != 0, &mut self.r[row]);
+            self.x[i] = if x { self.x[i] | mask } else { self.x[i] & !mask };
+            self.z[i] = if z { self.z[i] | mask } else { self.z[i] & !mask };
+        }
+    }
+
+    pub fn h(&mut self, qubit: usize) {
+        self.update(qubit, |x, z, r| {
+            *r ^= x & z;
+            (z, x)
+        });
+    }
+
+    pub fn s(&mut self, qubit: usize) {
+        self.update(qubit, |x, z, r| {
+            *r ^= x & z;
+            (x, z ^ x)
+        });
+    }
+
+    pub fn sdg(&mut self, qubit: usize) {
+        self.update(qubit, |x, z, r| {
+            *r ^= x & !z;
+            (x, z ^ x)
+        });
+    }
+
+    pub fn x(&mut self, qubit: usize) {
+        self.update(qubit, |x, z, r| {
+            *r ^= z;
+            (x, z)
+        });
+    }
+
+    pub fn y(&mut self, qubit: usize) {
+        self.update(qubit, |x, z, r| {
+            *r ^= x ^ z;
+            (x, z)
+        });
+    }
+
+    pub fn z(&mut self, qubit: usize) {
+        self.update(qubit, |x, z, r| {
+            *r ^= x;
+            (x, z)
+        });
+    }
+
+    /// Applies the controlled X gate.
+    ///
+    /// Panics if the control and the target are the same qubit.
+    pub fn cx(&mut self, control: usize, target: usize) {
+        assert_ne!(control, target, "control and target must be different qubits");
+
+        let (wc, mc) = Self::column(control);
+        let (wt, mt) = Self::column(target);
+        for row in 0..2 * self.num_qubits {
+            let (ic, it) = (row * self.words + wc, row * self.words + wt);
+            let (xc, zc) = (self.x[ic] & mc != 0, self.z[ic] & mc != 0);
+            let (xt, zt) = (self.x[it] & mt != 0, self.z[it] & mt != 0);
+
+            self.r[row] ^= xc & zt & !(xt ^ zc);
+            if xc {
+                self.x[it] ^= mt;
+            }
+            if zt {
+                self.z[ic] ^= mc;
+            }
+        }
+    }
+
+    pub fn cz(&mut self, qubit1: usize, qubit2: usize) {
+        self.h(qubit2);
+        self.cx(qubit1, qubit2);
+        self.h(qubit2);
+    }
+
+    pub fn cy(&mut self, control: usize, target: usize) {
+        self.sdg(target);
+        self.cx(control, target);
+        self.s(target);
+    }
+
+    pub fn swap(&mut self, qubit1: usize, qubit2: usize) {
+        if qubit1 != qubit2 {
+            self.cx(qubit1, qubit2);
+            self.cx(qubit2, qubit1);
+            self.cx(qubit1, qubit2);
+        }
+    }
+
+    /// Multiplies the row `h` by the row `i`, keeping track of the sign.
+    fn rowsum(&mut self, h: usize, i: usize) {
+        let (h0, i0) = (h * self.words, i * self.words);
+
+        // Twice the power of $ i $ of the product, modulo 4.
+        let mut sum = 2 * (self.r[h] as i64 + self.r[i] as i64);
+        for w in 0..self.words {
+            let (x1, z1, x2, z2) = (self.x[i0 + w], self.z[i0 + w], self.x[h0 + w], self.z[h0 + w]);
+
+            let plus = (x1 & z1 & z2 & !x2) | (x1 & !z1 & z2 & x2) | (!x1 & z1 & x2 & !z2);
+            let minus = (x1 & z1 & x2 & !z2) | (x1 & !z1 & z2 & !x2) | (!x1 & z1 & x2 & z2);
+            sum += plus.count_ones() as i64 - minus.count_ones() as i64;
+
+            self.x[h0 + w] ^= x1;
+            self.z[h0 + w] ^= z1;
+        }
+
+        self.r[h] = sum.rem_euclid(4) == 2;
+    }
+
+    /// Copies the row `src` into the row `dest`.
+    fn copy_row(&mut self, src: usize, dest: usize) {
+        let (s, d) = (src * self.words, dest * self.words);
+        self.x.copy_within(s..s + self.words, d);
+        self.z.copy_within(s..s + self.words, d);
+        self.r[dest] = self.r[src];
+    }
+
+    /// Clears the row, setting it to the identity with a positive sign.
+    fn clear_row(&mut self, row: usize) {
+        let start = row * self.words;
+        self.x[start..start + self.words].fill(0);
+        self.z[start..start + self.words].fill(0);
+        self.r[row] = false;
+    }
+
+    /// Returns the X components of the stabilizers, skipping the ones without any.
+    fn stabilizers_x(&self) -> impl Iterator<Item = &[u64]> + '_ {
+        (self.num_qubits..2 * self.num_qubits)
+            .map(|row| &self.x[row * self.words..(row + 1) * self.words])
+            .filter(|x| x.iter().any(|&word| word != 0))
+    }
+
+    /// Returns `true` if measuring the qubit in the computational basis yields a determined outcome.
+    pub fn is_deterministic(&self, qubit: usize) -> bool {
+        let (word, mask) = Self::column(qubit);
+        (self.num_qubits..2 * self.num_qubits).all(|row| self.x[row * self.words + word] & mask == 0)
+    }
+
+    /// Measures the qubit in the computational basis, collapsing the state.
+    pub fn measure<R: Rng + ?Sized>(&mut self, qubit: usize, rng: &mut R) -> bool {
+        let n = self.num_qubits;
+        let (word, mask) = Self::column(qubit);
+        let has_x = |tableau: &Self, row: usize| tableau.x[row * tableau.words + word] & mask != 0;
+
+        match (n..2 * n).find(|&row| has_x(self, row)) {
+            // The outcome is random, the stabilizer anticommuting with Z gets replaced by it.
+            Some(p) => {
+                for row in (0..2 * n).filter(|&row| row != p) {
+                    if has_x(self, row) {
+                        self.rowsum(row, p);
+                    }
+                }
+
+                self.copy_row(p, p - n);
+                self.clear_row(p);
+
+                let outcome = rng.gen();
+                self.z[p * self.words + word] |= mask;
+                self.r[p] = outcome;
+                outcome
+            }
+            // The outcome is determined, it is computed in the scratch row.
+            None => {
+                self.clear_row(2 * n);
+                for row in 0..n {
+                    if has_x(self, row) {
+                        self.rowsum(2 * n, row + n);
+                    }
+                }
+                self.r[2 * n]
+            }
+        }
+    }
+
+    /// Resets the qubit to the $ |0 \rangle $ state.
+    pub fn reset<R: Rng + ?Sized>(&mut self, qubit: usize, rng: &mut R) {
+        if self.measure(qubit, rng) {
+            self.x(qubit);
+        }
+    }
+}
+
+/// A simulator of Clifford circuits, made of the H, S, and Pauli gates and their
+/// combinations such as CX, CZ, SX or Swap, along with measurements, resets and
+/// classical operations.
+///
+/// Each gate takes $ O(n) $ time and each measurement $ O(n^2) $, so it scales to thousands
+/// of qubits. The part of the circuit before the first measurement is only simulated once,
+/// and when it is only followed by measurements, shots are sampled without simulating them.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct StabilizerSimulator {
+    /// The number of shots to sample.
+    pub shots: u64,
+    /// The seed of the random number generator, taken from the operating system if `None`.
+    pub seed: Option<u64>,
+}
+
+impl StabilizerSimulator {
+    pub fn new(shots: u64) -> Self {
+        Self { shots, seed: None }
+    }
+
+    /// Returns `Ok` if the operation is supported by the simulator.
+    fn check(op: &OpKind<'_>) -> Result<(), SimulationError> {
+        use OpKind::*;
+
+        match op {
+            Nop | H | X | Y | Z | S | Sdg | SX | SXdg | CX | CY | CZ | Swap | Measure | Reset | Compute(_) => Ok(()),
+            op => Err(SimulationError::NotClifford(op.label())),
+        }
+    }
+
+    /// Applies the instruction's operation to the tableau and the bits.
+    fn apply<R: Rng + ?Sized>(tableau: &mut Tableau, instr: &Instr<'_>, bits: &mut BitSet, rng: &mut R) -> Result<(), SimulationError> {
+        let q = |i: usize| instr.qubits[i].id() as usize;
+
+        // The adjoint of a Clifford gate is a Clifford gate, while it's powers are not in general.
+        let mut op = instr.op.clone();
+        for modifier in &instr.modifiers {
+            match modifier {
+                Modifier::Inverse => op = op.adjoint::<f64>(&[]).unwrap().0,
+                Modifier::Power(_) => return Err(SimulationError::NotClifford(instr.op.label())),
+                _ => (),
+            }
+        }
+
+        if !instr.controls().0.is_empty() {
+            return Self::apply_controlled(tableau, &op, instr);
+        }
+
+        match &op {
+            OpKind::Nop => (),
+            OpKind::H => tableau.h(q(0)),
+            OpKind::X => tableau.x(q(0)),
+            OpKind::Y => tableau.y(q(0)),
+            OpKind::Z => tableau.z(q(0)),
+            OpKind::S => tableau.s(q(0)),
+            OpKind::Sdg => tableau.sdg(q(0)),
+            OpKind::SX => {
+                tableau.h(q(0));
+                tableau.s(q(0));
+                tableau.h(q(0));
+            }
+            OpKind::SXdg => {
+                tableau.h(q(0));
+                tableau.sdg(q(0));
+                tableau.h(q(0));
+            }
+            OpKind::CX => tableau.cx(q(0), q(1)),
+            OpKind::CY => tableau.cy(q(0), q(1)),
+            OpKind::CZ => tableau.cz(q(0), q(1)),
+            OpKind::Swap => tableau.swap(q(0), q(1)),
+            OpKind::Measure => {
+                let outcome = tableau.measure(q(0), rng);
+                bits.set(instr.bits[0].id() as usize, outcome);
+            }
+            OpKind::Reset => tableau.reset(q(0), rng),
+            OpKind::Compute(compute) => super::run_compute(bits, compute, instr.bits),
+            op if op.blocks().is_some() => super::run_control_flow(instr, bits, &mut |body, bits| {
+                for instr in body {
+                    super::run_modified(&instr.modifiers, bits, &mut |bits| Self::apply(tableau, &instr, bits, rng))?;
+                }
+                Ok(())
+            })?,
+            op => return Err(SimulationError::NotClifford(op.label())),
+        }
+
+        Ok(())
+    }
+
+    /// Applies the instruction's operation under it's quantum controls to the tableau. Only
+    /// Pauli gates with a single control are Clifford gates.
+    fn apply_controlled(tableau: &mut Tableau, op: &OpKind<'_>, instr: &Instr<'_>) -> Result<(), SimulationError> {
+        let (controls, states) = super::controls(instr);
+        let (&[c], &[state]) = (controls.as_slice(), states.as_slice()) else {
+            return Err(SimulationError::NotClifford(op.label()));
+        };
+
+        let t = instr.qubits[0].id() as usize;
+        let apply: fn(&mut Tableau, usize, usize) = match op {
+            OpKind::X => Tableau::cx,
+            OpKind::Y => Tableau::cy,
+            OpKind::Z => Tableau::cz,
+            _ => return Err(SimulationError::NotClifford(op.label())),
+        };
+
+        if !state {
+            tableau.x(c);
+        }
+        apply(tableau, c, t);
+        if !state {
+            tableau.x(c);
+        }
+
+        Ok(())
+    }
+
+    /// Samples the outcomes of the circuit's bits.
+    pub fn sample(&self, circ: &QuantumCircuit) -> Result<Histogram, SimulationError> {
+        let inlined = circ.inlined().map_err(SimulationError::Inline)?;
+        let circ = &*inlined;
+
+        let mut instrs = Vec::new();
+        for instr in circ.iter() {
+            super::check_nested(&instr, &Self::check)?;
+            instrs.push(instr);
+        }
+
+        let mut rng = super::rng(self.seed);
+
+        // The instructions before the first random or classical one are deterministic,
+        // they are shared by every shot.
+        let split = instrs.iter()
+            .position(|instr| instr.has_classical_modifier() || !instr.op.is_unitary())
+            .unwrap_or(instrs.len());
+        let mut prefix = Tableau::new(circ.width());
+        let mut bits = BitSet::new(circ.num_bits());
+        for instr in &instrs[..split] {
+            Self::apply(&mut prefix, instr, &mut bits, &mut rng)?;
+        }
+
+        let tail = &instrs[split..];
+        if tail.iter().all(|instr| !instr.has_modifier() && instr.op == OpKind::Measure) {
+            return Self::sample_terminal(&prefix, tail, bits, self.shots, &mut rng);
+        }
+
+        let mut counts: HashMap<BitSet, u64> = HashMap::new();
+        for _ in 0..self.shots {
+            let mut tableau = prefix.clone();
+            let mut bits = bits.clone();
+            for instr in &instrs[split..] {
+                super::run_modified(&instr.modifiers, &mut bits, &mut |bits| Self::apply(&mut tableau, instr, bits, &mut rng))?;
+            }
+            *counts.entry(bits).or_default() += 1;
+        }
+
+        let mut histogram = Histogram::new(circ.num_bits());
+        counts.into_iter().for_each(|(outcome, count)| histogram.record(outcome, count));
+        Ok(histogram)
+    }
+
+    /// Samples the outcomes of measurements at the end of the circuit.
+    ///
+    /// The outcomes of measuring every qubit of a stabilizer state are uniformly distributed
+    /// over an affine space, whose direction is spanned by the X components of the stabilizers.
+    /// Shots are sampled by adding random combinations of them to a single reference shot.
+    fn sample_terminal<R: Rng + ?Sized>(tableau: &Tableau, measures: &[Instr<'_>], bits: BitSet, shots: u64, rng: &mut R) -> Result<Histogram, SimulationError> {
+        let mut reference = bits;
+        let mut scratch = tableau.clone();
+        for instr in measures {
+            Self::apply(&mut scratch, instr, &mut reference, rng)?;
+        }
+
+        let generators: Vec<_> = tableau.stabilizers_x().collect();
+        let measured: Vec<_> = measures.iter()
+            .map(|instr| (Tableau::column(instr.qubits[0].id() as usize), instr.bits[0].id() as usize))
+            .collect();
+
+        let mut counts: HashMap<BitSet, u64> = HashMap::new();
+        let mut delta = vec![0; tableau.words];
+        for _ in 0..shots {
+            delta.fill(0);
+            let mut random = 0u64;
+            for (k, generator) in generators.iter().enumerate() {
+                if k % 64 == 0 {
+                    random = rng.gen();
+                }
+                if (random >> (k % 64)) & 1 == 1 {
+                    delta.iter_mut().zip(generator.iter()).for_each(|(d, g)| *d ^= g);
+                }
+            }
+
+            let mut outcome = reference.clone();
+            for &((word, mask), bit) in &measured {
+                if delta[word] & mask != 0 {
+                    outcome.set(bit, !outcome.get(bit).unwrap());
+                }
+            }
+            *counts.entry(outcome).or_default() += 1;
+        }
+
+        let mut histogram = Histogram::new(reference.len());
+        counts.into_iter().for_each(|(outcome, count)| histogram.record(outcome, count));
+        Ok(histogram)
+    }
+}
+
+#[async_trait]
+impl Backend for StabilizerSimulator {
+    type Architecture = FullyConnected;
+
+    type RuntimeError = SimulationError;
+
+    fn execute(&self, circ: &TranspiledCircuit<Self::Architecture>) -> Result<Histogram, Self::RuntimeError> {
+        self.sample(circ)
+    }
+
+    async fn execute_async(&self, circ: &TranspiledCircuit<Self::Architecture>) -> Result<Histogram, Self::RuntimeError> {
+        self.execute(circ)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use crate::circuit::QuantumCircuit;
+    use crate::instruction::Modifier;
+    use crate::operation::OpKind;
+    use crate::random::RandomCircuit;
+    use crate::simulator::{DensityMatrixSimulator, SimulationError};
+
+    use super::{StabilizerSimulator, Tableau};
+
+    fn seeded(shots: u64) -> StabilizerSimulator {
+        StabilizerSimulator { shots, seed: Some(7) }
+    }
+
+    #[test]
+    fn measures_basis_states_deterministically() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut tableau = Tableau::new(70);
+        tableau.x(3);
+        tableau.h(65);
+        tableau.s(65);
+        tableau.s(65);
+        tableau.h(65);
+
+        assert!((0..70).all(|q| tableau.is_deterministic(q)));
+        assert!(tableau.measure(3, &mut rng));
+        assert!(tableau.measure(65, &mut rng));
+        assert!(!tableau.measure(64, &mut rng));
+
+        tableau.reset(65, &mut rng);
+        assert!(!tableau.measure(65, &mut rng));
+    }
+
+    #[test]
+    fn collapses_entangled_states() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..16 {
+            let mut tableau = Tableau::new(3);
+            tableau.h(0);
+            tableau.cx(0, 1);
+            tableau.cx(1, 2);
+
+            assert!(!tableau.is_deterministic(2));
+            let outcome = tableau.measure(2, &mut rng);
+            assert!(tableau.is_deterministic(0));
+            assert_eq!(tableau.measure(0, &mut rng), outcome);
+            assert_eq!(tableau.measure(1, &mut rng), outcome);
+        }
+    }
+
+    #[test]
+    fn samples_large_ghz_states() {
+        let n = 2000;
+        let circ = QuantumCircuit::new(|b| {
+            let qubits: Vec<_> = (0..n).map(|_| b.qubit()).collect::<Result<_, _>>()?;
+            let bits: Vec<_> = (0..n).map(|_| b.bit()).collect::<Result<_, _>>()?;
+            b.h(qubits[0]);
+            qubits.windows(2).for_each(|pair| { b.cx(pair[0], pair[1]); });
+            qubits.iter().zip(&bits).for_each(|(&qubit, &bit)| { b.measure(qubit, bit); });
+            Ok(())
+        }).unwrap();
+
+        let histogram = seeded(200).sample(&circ).unwrap();
+        assert_eq!(histogram.shots(), 200);
+        assert_eq!(histogram.iter().count(), 2);
+        assert!(histogram.iter().all(|(outcome, count)| count > 50 && (outcome.count_ones() == 0 || outcome.count_ones() == n)));
+    }
+
+    #[test]
+    fn matches_exact_distributions() {
+        let mut generator = RandomCircuit::new(5, 12);
+        generator.gates = vec!["h", "s", "sdg", "x", "y", "z", "cx", "cz", "swap"];
+        generator.measure = true;
+
+        for seed in 0..8 {
+            let circ = generator.generate_seeded(seed).unwrap();
+            let branches = DensityMatrixSimulator::new(0).run(&circ).unwrap();
+            let histogram = seeded(4000).sample(&circ).unwrap();
+
+            // Outcomes are uniformly distributed over the support of the distribution.
+            let support: HashSet<_> = branches.iter().map(|branch| branch.bits.clone()).collect();
+            assert_eq!(histogram.iter().map(|(outcome, _)| outcome.clone()).collect::<HashSet<_>>(), support);
+            for branch in &branches {
+                assert!((histogram.probability(&branch.bits) - branch.probability).abs() < 0.05);
+            }
+        }
+    }
+
+    #[test]
+    fn runs_mid_circuit_feedback() {
+        // Measures a random bit, copies it with feedback, and resets the measured qubit.
+        let circ = QuantumCircuit::new(|b| {
+            let [a, c] = b.qubits()?;
+            let bits = b.bits::<3>()?;
+            b.h(a).measure(a, bits[0]).reset(a);
+            b.instructions_mut().push_modified(OpKind::X, &[c], &[], &[], Modifier::IfBit(bits[0]))?;
+            b.measure(c, bits[1]).measure(a, bits[2]);
+            Ok(())
+        }).unwrap();
+
+        let histogram = seeded(500).sample(&circ).unwrap();
+        assert_eq!(histogram.iter().count(), 2);
+        for (outcome, _) in histogram.iter() {
+            assert_eq!(outcome.get(0), outcome.get(1));
+            assert_eq!(outcome.get(2), Some(false));
+        }
+    }
+
+    #[test]
+    fn rejects_non_clifford_operations() {
+        let circ = QuantumCircuit::new(|b| {
+            let qubit = b.qubit()?;
+            b.h(qubit).t(qubit);
+            Ok(())
+        }).unwrap();
+        assert_eq!(seeded(1).sample(&circ).unwrap_err(), SimulationError::NotClifford("t"));
+
+        let circ = QuantumCircuit::new(|b| {
+            let [a, c] = b.qubits()?;
+            b.instructions_mut().push_controlled(OpKind::H, &[a], &[true], &[c], &[]);
+            Ok(())
+        }).unwrap();
+        assert_eq!(seeded(1).sample(&circ).unwrap_err(), SimulationError::NotClifford("h"));
+
+        let circ = QuantumCircuit::new(|b| {
+            let qubit = b.qubit()?;
+            b.for_loop(2, |b| { b.rx(0.3, qubit); Ok(()) })?;
+            Ok(())
+        }).unwrap();
+        assert_eq!(seeded(1).sample(&circ).unwrap_err(), SimulationError::NotClifford("rx"));
+    }
+}
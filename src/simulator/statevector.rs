@@ -0,0 +1,522 @@
+//! Simulation of circuits with statevectors, and of noisy circuits with
+//! [quantum trajectories](https://arxiv.org/abs/quant-ph/9702007).
+
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use rand::Rng;
+
+use crate::bitset::BitSet;
+use crate::circuit::{QuantumCircuit, TranspiledCircuit};
+use crate::instruction::Instr;
+use crate::linalg::{c64, DMatrix};
+use crate::operation::OpKind;
+use crate::provider::{Backend, Histogram};
+
+use super::kernels::{Fusion, Kernel, PARALLEL_THRESHOLD};
+use super::{FullyConnected, KrausChannel, NoiseModel, SimulationError};
+
+/// The state of $ n $ qubits, as the vector of it's $ 2^n $ amplitudes. Qubit `q` corresponds
+/// to the `q`th bit of the indices of the basis states.
+///
+/// Gates are applied by kernels specialized for diagonal, permutation and dense matrices, which
+/// are spread over several threads for large states, see [`Statevector::set_threads`].
+#[derive(Clone, Debug)]
+pub struct Statevector {
+    num_qubits: usize,
+    amplitudes: Vec<c64>,
+    threads: usize,
+}
+
+impl PartialEq for Statevector {
+    fn eq(&self, rhs: &Self) -> bool {
+        self.num_qubits == rhs.num_qubits && self.amplitudes == rhs.amplitudes
+    }
+}
+
+impl Statevector {
+    /// Returns the state $ |0 \dots 0 \rangle $.
+    pub fn new(num_qubits: usize) -> Self {
+        let mut amplitudes = vec![c64::ZERO; 1 << num_qubits];
+        amplitudes[0] = c64::ONE;
+        Self { num_qubits, amplitudes, threads: 1 }
+    }
+
+    pub fn num_qubits(&self) -> usize {
+        self.num_qubits
+    }
+
+    /// Returns the number of threads gates are applied with.
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Sets the number of threads gates are applied with. Small states always use a single one.
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = threads.max(1);
+    }
+
+    pub fn amplitudes(&self) -> &[c64] {
+        &self.amplitudes
+    }
+
+    /// Returns the probability of each basis state.
+    pub fn probabilities(&self) -> Vec<f64> {
+        self.amplitudes.iter().map(c64::abs_sqr).collect()
+    }
+
+    /// Returns the squared norm of the state.
+    pub fn norm_sqr(&self) -> f64 {
+        self.amplitudes.iter().map(c64::abs_sqr).sum()
+    }
+
+    /// Returns the offsets of the basis states of the qubits in the full basis, the first qubit
+    /// being the most significant one, along with an iterator over the indices of the basis states
+    /// where all of the qubits are $ |0 \rangle $.
+    fn groups(&self, qubits: &[usize]) -> (Vec<usize>, impl Iterator<Item = usize>) {
+        let k = qubits.len();
+        let offsets = (0..1 << k)
+            .map(|sub| (0..k).fold(0, |acc, j| acc | (((sub >> (k - 1 - j)) & 1) << qubits[j])))
+            .collect();
+
+        let mut sorted = qubits.to_vec();
+        sorted.sort_unstable();
+        let bases = (0..self.amplitudes.len() >> k)
+            .map(move |i| sorted.iter().fold(i, |acc, &q| ((acc >> q) << (q + 1)) | (acc & ((1 << q) - 1))));
+
+        (offsets, bases)
+    }
+
+    /// Left-multiplies the state by the matrix, acting on the given qubits. The first qubit is
+    /// the most significant one in the matrix's basis. The matrix need not be unitary.
+    ///
+    /// Panics if the dimension of the matrix is not `2^qubits.len()`.
+    pub fn apply(&mut self, matrix: &DMatrix, qubits: &[usize]) {
+        assert_eq!(matrix.dim(), 1 << qubits.len(), "matrix dimension does not match its number of qubits");
+        self.apply_kernel(&Kernel::new(matrix), qubits);
+    }
+
+    /// Applies the gate, already classified in it's kernel.
+    pub(crate) fn apply_kernel(&mut self, kernel: &Kernel, qubits: &[usize]) {
+        kernel.apply(&mut self.amplitudes, qubits, self.threads);
+    }
+
+    /// Multiplies every amplitude by the factor.
+    fn scale(&mut self, factor: f64) {
+        self.apply_kernel(&Kernel::Diagonal(vec![factor.into()]), &[]);
+    }
+
+    /// Returns the squared norms $ \lVert M_k | \psi \rangle \rVert^2 $ of the state after
+    /// applying each of the matrices on the given qubits, without modifying it.
+    fn weights(&self, matrices: &[DMatrix], qubits: &[usize]) -> Vec<f64> {
+        let (offsets, bases) = self.groups(qubits);
+        let mut res = vec![0.0; matrices.len()];
+        let mut buf = vec![c64::ZERO; offsets.len()];
+        for base in bases {
+            offsets.iter().zip(buf.iter_mut()).for_each(|(&offset, z)| *z = self.amplitudes[base | offset]);
+            for (matrix, weight) in matrices.iter().zip(res.iter_mut()) {
+                *weight += (0..offsets.len())
+                    .map(|r| (0..offsets.len()).map(|c| matrix[(r, c)] * buf[c]).sum::<c64>().abs_sqr())
+                    .sum::<f64>();
+            }
+        }
+        res
+    }
+
+    /// Returns the probability of measuring the qubit in the $ |1 \rangle $ state.
+    pub fn probability(&self, qubit: usize) -> f64 {
+        let half = 1 << qubit;
+        self.amplitudes.chunks_exact(2 * half)
+            .flat_map(|block| &block[half..])
+            .map(c64::abs_sqr)
+            .sum()
+    }
+
+    /// Measures the qubit in the computational basis, collapsing the state.
+    pub fn measure<R: Rng + ?Sized>(&mut self, qubit: usize, rng: &mut R) -> bool {
+        let p1 = self.probability(qubit);
+        let outcome = rng.gen::<f64>() < p1;
+        let factor = if outcome { p1 } else { 1.0 - p1 }.sqrt().recip();
+
+        let diag = if outcome { [c64::ZERO, factor.into()] } else { [factor.into(), c64::ZERO] };
+        self.apply_kernel(&Kernel::Diagonal(diag.to_vec()), &[qubit]);
+
+        outcome
+    }
+
+    /// Resets the qubit to the $ |0 \rangle $ state.
+    pub fn reset<R: Rng + ?Sized>(&mut self, qubit: usize, rng: &mut R) {
+        if self.measure(qubit, rng) {
+            self.apply_kernel(&Kernel::Permutation(vec![(1, c64::ONE), (0, c64::ONE)]), &[qubit]);
+        }
+    }
+
+    /// Applies one of the Kraus operators of the channel on the given qubits, drawn with
+    /// probability $ \lVert K_k | \psi \rangle \rVert^2 $, and renormalizes the state.
+    ///
+    /// For mixtures of unitaries, these probabilities don't depend on the state, and are
+    /// not computed.
+    pub fn apply_channel<R: Rng + ?Sized>(&mut self, channel: &KrausChannel, qubits: &[usize], rng: &mut R) {
+        let operators = channel.operators();
+        let weights = match channel.unitary_mixture() {
+            Some(probabilities) => probabilities.to_vec(),
+            None => self.weights(operators, qubits),
+        };
+
+        let mut x = rng.gen::<f64>() * weights.iter().sum::<f64>();
+        let k = weights.iter()
+            .position(|&weight| {
+                x -= weight;
+                x < 0.0
+            })
+            .unwrap_or(operators.len() - 1);
+
+        self.apply(&operators[k], qubits);
+        self.scale(weights[k].sqrt().recip());
+    }
+}
+
+/// A step of the simulation of a circuit.
+enum Step {
+    /// Consecutive unitary instructions without classical modifiers nor noise, fused into a
+    /// single gate.
+    Gate(Vec<usize>, Kernel),
+    /// Any other instruction, given by it's index.
+    Instr(usize),
+}
+
+/// A statevector simulator.
+///
+/// When the noise model is not ideal, each shot is a quantum trajectory: the Kraus operators
+/// of the channels are drawn at random according to the state, which reproduces the statistics
+/// of the density matrix simulation with the memory cost of a statevector. For ideal circuits
+/// ending with measurements, the state is only computed once.
+///
+/// Consecutive unitary instructions without noise are fused into gates on up to `fusion`
+/// qubits. Small states run their shots in parallel, while large ones spread each gate over
+/// the threads.
+#[derive(Clone, Debug)]
+pub struct StatevectorSimulator {
+    /// The number of shots to sample.
+    pub shots: u64,
+    /// The seed of the random number generators, taken from the operating system if `None`.
+    pub seed: Option<u64>,
+    /// The noise applied during the simulation.
+    pub noise: NoiseModel,
+    /// The number of threads used by the simulation.
+    pub threads: usize,
+    /// The maximum number of qubits of fused gates. Gates acting on more qubits are applied on
+    /// their own, and `1` only fuses gates on the same qubit.
+    pub fusion: usize,
+}
+
+impl StatevectorSimulator {
+    /// The maximum number of qubits of the simulated circuits.
+    pub const MAX_QUBITS: usize = 32;
+
+    /// Creates a simulator without noise, using all the available threads.
+    pub fn new(shots: u64) -> Self {
+        let threads = std::thread::available_parallelism().map_or(1, usize::from);
+        Self { shots, seed: None, noise: NoiseModel::new(), threads, fusion: 3 }
+    }
+
+    /// Returns `Ok` if the operation is supported by the simulator.
+    fn check(op: &OpKind<'_>) -> Result<(), SimulationError> {
+        match op {
+            OpKind::Nop | OpKind::Measure | OpKind::Reset | OpKind::Compute(_) => Ok(()),
+            op if op.is_unitary() => Ok(()),
+            op => Err(SimulationError::UnsupportedOp(op.label())),
+        }
+    }
+
+    /// Applies the instruction's operation to the state and the bits, along with it's noise.
+    fn apply<R: Rng + ?Sized>(&self, circ: &QuantumCircuit, state: &mut Statevector, instr: &Instr<'_>, bits: &mut BitSet, rng: &mut R) -> Result<(), SimulationError> {
+        let qubits = super::qubits(instr);
+        let label = instr.op.label();
+
+        match &instr.op {
+            OpKind::Nop => (),
+            OpKind::Measure => {
+                self.apply_noise(label, &qubits, state, rng)?;
+
+                let outcome = state.measure(qubits[0], rng);
+                let flip = self.noise.readout_error(qubits[0]).map_or(0.0, |error| error.flip_probability(outcome));
+                bits.set(instr.bits[0].id() as usize, outcome ^ (flip > 0.0 && rng.gen::<f64>() < flip));
+            }
+            OpKind::Reset => {
+                state.reset(qubits[0], rng);
+                self.apply_noise(label, &qubits, state, rng)?;
+            }
+            OpKind::Compute(compute) => super::run_compute(bits, compute, instr.bits),
+            op if op.blocks().is_some() => super::run_control_flow(instr, bits, &mut |body, bits| {
+                for instr in body {
+                    super::run_modified(&instr.modifiers, bits, &mut |bits| self.apply(circ, state, &instr, bits, rng))?;
+                }
+                Ok(())
+            })?,
+            _ => {
+                let (gate, gate_qubits) = super::gate(circ, instr)?;
+                state.apply(&gate, &gate_qubits);
+                self.apply_noise(label, &qubits, state, rng)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Applies the channels attached to the operation with the given label.
+    fn apply_noise<R: Rng + ?Sized>(&self, label: &'static str, qubits: &[usize], state: &mut Statevector, rng: &mut R) -> Result<(), SimulationError> {
+        for channel in self.noise.errors(label, qubits) {
+            for targets in channel.targets(label, qubits)? {
+                state.apply_channel(channel, targets, rng);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Splits the instructions in steps, fusing the unitary instructions without classical
+    /// modifiers nor noise into gates.
+    fn steps(&self, circ: &QuantumCircuit, instrs: &[Instr<'_>]) -> Result<Vec<Step>, SimulationError> {
+        let mut steps = Vec::new();
+        let mut fusion = Fusion::new();
+
+        for (n, instr) in instrs.iter().enumerate() {
+            if !instr.has_modifier() && instr.op == OpKind::Nop {
+                continue;
+            }
+
+            let qubits = super::qubits(instr);
+            if !instr.has_classical_modifier() && instr.op.is_unitary() && self.noise.errors(instr.op.label(), &qubits).is_empty() {
+                let (matrix, qubits) = super::gate(circ, instr)?;
+                if !fusion.push(&matrix, &qubits, self.fusion) {
+                    steps.extend(fusion.take().map(|(qubits, kernel)| Step::Gate(qubits, kernel)));
+                    fusion.push(&matrix, &qubits, self.fusion);
+                }
+            } else {
+                steps.extend(fusion.take().map(|(qubits, kernel)| Step::Gate(qubits, kernel)));
+                steps.push(Step::Instr(n));
+            }
+        }
+
+        steps.extend(fusion.take().map(|(qubits, kernel)| Step::Gate(qubits, kernel)));
+        Ok(steps)
+    }
+
+    /// Samples the outcomes of the circuit's bits.
+    pub fn sample(&self, circ: &QuantumCircuit) -> Result<Histogram, SimulationError> {
+        let n = circ.width();
+        if n > Self::MAX_QUBITS {
+            return Err(SimulationError::TooManyQubits(n, Self::MAX_QUBITS));
+        }
+
+        let inlined = circ.inlined().map_err(SimulationError::Inline)?;
+        let circ = &*inlined;
+
+        let mut instrs = Vec::new();
+        for instr in circ.iter() {
+            super::check_nested(&instr, &Self::check)?;
+            instrs.push(instr);
+        }
+        let steps = self.steps(circ, &instrs)?;
+
+        // The fused gates before the first other instruction are deterministic and shared by
+        // every shot.
+        let split = steps.iter().position(|step| matches!(step, Step::Instr(_))).unwrap_or(steps.len());
+
+        let mut prefix = Statevector::new(n);
+        prefix.set_threads(self.threads);
+        for step in &steps[..split] {
+            if let Step::Gate(qubits, kernel) = step {
+                prefix.apply_kernel(kernel, qubits);
+            }
+        }
+
+        let mut rng = super::rng(self.seed);
+        let tail = &steps[split..];
+        let terminal = tail.iter().all(|step| matches!(step, &Step::Instr(n) if !instrs[n].has_modifier() && instrs[n].op == OpKind::Measure));
+        if self.noise.is_ideal() && terminal {
+            let measures: Vec<_> = instrs.iter().filter(|instr| instr.op == OpKind::Measure).collect();
+            return Ok(Self::sample_terminal(&prefix, &measures, BitSet::new(circ.num_bits()), self.shots, &mut rng));
+        }
+
+        // Shots of small states run in parallel, while the gates of large states are each spread
+        // over the threads.
+        let (shot_threads, state_threads) = if prefix.amplitudes().len() < PARALLEL_THRESHOLD {
+            (self.threads, 1)
+        } else {
+            (1, self.threads)
+        };
+        prefix.set_threads(state_threads);
+
+        super::parallel_shots(self.shots, self.seed, shot_threads, circ.num_bits(), |rng| {
+            let mut state = prefix.clone();
+            let mut bits = BitSet::new(circ.num_bits());
+            for step in tail {
+                match step {
+                    Step::Gate(qubits, kernel) => state.apply_kernel(kernel, qubits),
+                    &Step::Instr(n) => {
+                        let instr = &instrs[n];
+                        super::run_modified(&instr.modifiers, &mut bits, &mut |bits| self.apply(circ, &mut state, instr, bits, rng))?;
+                    }
+                }
+            }
+            Ok(bits)
+        })
+    }
+
+    /// Samples the outcomes of measurements at the end of the circuit, from the probabilities
+    /// of the final state.
+    fn sample_terminal<R: Rng + ?Sized>(state: &Statevector, measures: &[&Instr<'_>], bits: BitSet, shots: u64, rng: &mut R) -> Histogram {
+        let cumulative: Vec<f64> = state.amplitudes().iter()
+            .scan(0.0, |acc, z| {
+                *acc += z.abs_sqr();
+                Some(*acc)
+            })
+            .collect();
+        let total = cumulative.last().cloned().unwrap_or(0.0);
+
+        let mut counts = HashMap::<BitSet, u64>::new();
+        for _ in 0..shots {
+            let x = rng.gen::<f64>() * total;
+            let index = cumulative.partition_point(|&c| c <= x).min(cumulative.len() - 1);
+
+            let mut outcome = bits.clone();
+            measures.iter().for_each(|instr| {
+                outcome.set(instr.bits[0].id() as usize, (index >> instr.qubits[0].id()) & 1 == 1);
+            });
+            *counts.entry(outcome).or_default() += 1;
+        }
+
+        let mut histogram = Histogram::new(bits.len());
+        counts.into_iter().for_each(|(outcome, count)| histogram.record(outcome, count));
+        histogram
+    }
+}
+
+#[async_trait]
+impl Backend for StatevectorSimulator {
+    type Architecture = FullyConnected;
+
+    type RuntimeError = SimulationError;
+
+    fn execute(&self, circ: &TranspiledCircuit<Self::Architecture>) -> Result<Histogram, Self::RuntimeError> {
+        self.sample(circ)
+    }
+
+    async fn execute_async(&self, circ: &TranspiledCircuit<Self::Architecture>) -> Result<Histogram, Self::RuntimeError> {
+        self.execute(circ)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::circuit::QuantumCircuit;
+    use crate::instruction::Modifier;
+    use crate::operation::OpKind;
+    use crate::provider::Histogram;
+    use crate::random::RandomCircuit;
+    use crate::simulator::{DensityMatrixSimulator, KrausChannel, NoiseModel, ReadoutError};
+
+    use super::StatevectorSimulator;
+
+    /// A noise model with channels that are mixtures of unitaries and channels that are not.
+    fn noise() -> NoiseModel {
+        let mut noise = NoiseModel::new();
+        noise.add_error("h", KrausChannel::depolarizing(0.1, 1))
+            .add_error("cx", KrausChannel::depolarizing(0.05, 2))
+            .add_error("rz", KrausChannel::amplitude_damping(0.2))
+            .add_error("t", KrausChannel::thermal_relaxation(40.0, 30.0, 5.0))
+            .add_error("reset", KrausChannel::pauli(0.1, 0.0, 0.0))
+            .add_readout_error(ReadoutError::new(0.05, 0.1));
+        noise
+    }
+
+    /// Returns the total variation distance between the sampled distribution and the exact
+    /// one of the density matrix simulation.
+    fn distance(histogram: &Histogram, circ: &QuantumCircuit, noise: &NoiseModel) -> f64 {
+        let mut exact = DensityMatrixSimulator::new(0);
+        exact.noise = noise.clone();
+        let branches = exact.run(circ).unwrap();
+
+        // Outcomes missing from the branches have no probability.
+        let sampled: f64 = branches.iter().map(|branch| histogram.probability(&branch.bits)).sum();
+        let distance: f64 = branches.iter()
+            .map(|branch| (histogram.probability(&branch.bits) - branch.probability).abs())
+            .sum();
+        (distance + 1.0 - sampled) / 2.0
+    }
+
+    fn simulator(threads: usize) -> StatevectorSimulator {
+        let mut simulator = StatevectorSimulator::new(8000);
+        simulator.seed = Some(11);
+        simulator.threads = threads;
+        simulator.noise = noise();
+        simulator
+    }
+
+    #[test]
+    fn trajectories_match_density_matrices() {
+        let mut generator = RandomCircuit::new(3, 6);
+        generator.measure = true;
+
+        for seed in 0..4 {
+            let circ = generator.generate_seeded(seed).unwrap();
+            let histogram = simulator(4).sample(&circ).unwrap();
+
+            // With 8 outcomes, the expected distance is of order $ \sqrt{8 / shots} / 2 $.
+            assert_eq!(histogram.shots(), 8000);
+            assert!(distance(&histogram, &circ, &noise()) < 0.03);
+        }
+    }
+
+    #[test]
+    fn trajectories_match_with_feedback() {
+        let circ = QuantumCircuit::new(|b| {
+            let [a, c] = b.qubits()?;
+            let bits = b.bits::<3>()?;
+            b.h(a).rz(0.3, a).h(a).measure(a, bits[0]).reset(a);
+            b.instructions_mut().push_modified(OpKind::X, &[c], &[], &[], Modifier::IfBit(bits[0]))?;
+            b.h(c).cx(c, a).measure(c, bits[1]).measure(a, bits[2]);
+            Ok(())
+        }).unwrap();
+
+        let histogram = simulator(4).sample(&circ).unwrap();
+        assert!(distance(&histogram, &circ, &noise()) < 0.03);
+    }
+
+    #[test]
+    fn control_flow_matches_density_matrices() {
+        let circ = QuantumCircuit::new(|b| {
+            let [a, c, t] = b.qubits()?;
+            let bits = b.bits::<3>()?;
+            b.h(a).measure(a, bits[0]).h(c).measure(c, bits[1]);
+            b.if_else(bits[0], |b| { b.h(t); Ok(()) }, |b| { b.x(t); Ok(()) })?;
+            b.switch(&bits[..2], 1, |b, case| {
+                match case {
+                    Some(_) => { b.t(t).h(t); }
+                    None => { b.rz(0.3, t); }
+                }
+                Ok(())
+            })?;
+            b.for_loop(2, |b| { b.h(c).cx(c, t); Ok(()) })?;
+            b.measure(t, bits[2]);
+            Ok(())
+        }).unwrap();
+
+        let histogram = simulator(4).sample(&circ).unwrap();
+        assert!(distance(&histogram, &circ, &noise()) < 0.03);
+    }
+
+    #[test]
+    fn seeded_trajectories_are_reproducible() {
+        let mut generator = RandomCircuit::new(4, 5);
+        generator.measure = true;
+        let circ = generator.generate_seeded(2).unwrap();
+
+        let mut simulator = simulator(3);
+        simulator.shots = 500;
+        let (a, b) = (simulator.sample(&circ).unwrap(), simulator.sample(&circ).unwrap());
+        assert!(a.iter().all(|(outcome, count)| b.count(outcome) == count));
+    }
+}
@@ -0,0 +1,190 @@
+//! Summary statistics of a circuit, such as it's depth or gate counts, see
+//! [`QuantumCircuit::stats`].
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::circuit::QuantumCircuit;
+use crate::dag::{self, Wire};
+use crate::instruction::Instr;
+use crate::operation::OpKind;
+use crate::provider::Architecture;
+
+/// Statistics of the instructions of a circuit.
+///
+/// Depths are the lengths of the longest paths of instructions depending on each other through
+/// the qubits and bits they act on, as in a [`CircuitDag`](crate::dag::CircuitDag). Calls and
+/// control flow operations count as single instructions, their bodies are not looked into.
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct CircuitStats {
+    /// The number of instructions, not counting [`OpKind::Nop`].
+    pub num_instrs: usize,
+    /// The number of instructions of each operation, by label.
+    pub counts: BTreeMap<&'static str, usize>,
+    /// The depth of the circuit.
+    pub depth: usize,
+    /// The number of non-local instructions, acting on at least two qubits, control qubits
+    /// included.
+    pub num_non_local: usize,
+    /// The depth of the circuit, only counting non-local instructions.
+    pub non_local_depth: usize,
+    /// The number of [`OpKind::T`] and [`OpKind::Tdg`] instructions.
+    pub t_count: usize,
+    /// The depth of the circuit, only counting [`OpKind::T`] and [`OpKind::Tdg`] instructions.
+    pub t_depth: usize,
+    /// The number of measurements.
+    pub num_measures: usize,
+    /// The total duration of the longest path of instructions, given the duration of each of
+    /// them on an architecture, see [`QuantumCircuit::stats_on`].
+    pub critical_path: Option<f64>,
+}
+
+/// The depths and time reached by the instructions on a wire.
+#[derive(Copy, Clone, Default)]
+struct Reached {
+    depth: usize,
+    non_local_depth: usize,
+    t_depth: usize,
+    time: f64,
+}
+
+impl Reached {
+    fn max(self, other: Self) -> Self {
+        Self {
+            depth: self.depth.max(other.depth),
+            non_local_depth: self.non_local_depth.max(other.non_local_depth),
+            t_depth: self.t_depth.max(other.t_depth),
+            time: self.time.max(other.time),
+        }
+    }
+}
+
+impl CircuitStats {
+    /// Computes the statistics of the circuit, with the duration of each instruction if given.
+    pub(crate) fn new(circ: &QuantumCircuit, duration: Option<&dyn Fn(&Instr<'_>) -> f64>) -> Self {
+        let mut res = Self::default();
+        let mut reached: HashMap<Wire<'_>, Reached> = HashMap::new();
+        let mut total = Reached::default();
+
+        for instr in circ.iter() {
+            if instr.op == OpKind::Nop {
+                continue;
+            }
+
+            let non_local = instr.qubits.len() + instr.controls().0.len() >= 2;
+            let t = matches!(instr.op, OpKind::T | OpKind::Tdg);
+
+            res.num_instrs += 1;
+            *res.counts.entry(instr.op.label()).or_default() += 1;
+            res.num_non_local += usize::from(non_local);
+            res.t_count += usize::from(t);
+            res.num_measures += usize::from(instr.op == OpKind::Measure);
+
+            let wires = dag::wires(&instr);
+            let start = wires.iter()
+                .filter_map(|wire| reached.get(wire).copied())
+                .fold(Reached::default(), Reached::max);
+            let end = Reached {
+                depth: start.depth + 1,
+                non_local_depth: start.non_local_depth + usize::from(non_local),
+                t_depth: start.t_depth + usize::from(t),
+                time: start.time + duration.map_or(0.0, |duration| duration(&instr)),
+            };
+
+            total = total.max(end);
+            reached.extend(wires.into_iter().map(|wire| (wire, end)));
+        }
+
+        res.depth = total.depth;
+        res.non_local_depth = total.non_local_depth;
+        res.t_depth = total.t_depth;
+        res.critical_path = duration.map(|_| total.time);
+        res
+    }
+}
+
+impl QuantumCircuit {
+    /// Returns the statistics of the circuit, see [`CircuitStats`].
+    pub fn stats(&self) -> CircuitStats {
+        CircuitStats::new(self, None)
+    }
+
+    /// Returns the statistics of the circuit, including it's critical path given the duration
+    /// of each instruction on the architecture, see [`Architecture::duration`].
+    pub fn stats_on<T: Architecture>(&self, arch: &T) -> CircuitStats {
+        CircuitStats::new(self, Some(&|instr| arch.duration(instr)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use crate::circuit::QuantumCircuit;
+    use crate::instruction::{Instr, InstrVec, Modifier};
+    use crate::linalg::UnitaryMatrix;
+    use crate::operation::OpKind;
+    use crate::provider::Architecture;
+    use crate::symbol::Ancillas;
+
+    /// An architecture on which two qubit gates take 10 units of time, measurements 5 and the
+    /// other instructions 1.
+    struct Timed;
+
+    impl Architecture for Timed {
+        type TranspileError = Infallible;
+
+        fn num_qubits(&self) -> usize {
+            3
+        }
+
+        fn connected(&self, qubit1: usize, qubit2: usize) -> bool {
+            qubit1 != qubit2
+        }
+
+        fn decompose_su2(&self, unitary: UnitaryMatrix<2>) {}
+
+        fn non_local(&self) {}
+
+        fn supports<'id>(&self, instr: &Instr<'id>) -> Result<(), Self::TranspileError> {
+            Ok(())
+        }
+
+        fn duration<'id>(&self, instr: &Instr<'id>) -> f64 {
+            match instr.op {
+                OpKind::Measure => 5.0,
+                _ if instr.qubits.len() == 2 => 10.0,
+                _ => 1.0,
+            }
+        }
+
+        fn transpile<'id>(&self, instructions: InstrVec<'id>, ancillas: Option<Ancillas<'id>>) -> Result<InstrVec<'id>, Self::TranspileError> {
+            Ok(instructions)
+        }
+    }
+
+    #[test]
+    fn counts_depths_and_critical_path() {
+        // The `x` conditioned on the measured bit waits for the measurement, which makes the
+        // path through the bit the longest one: h, cx, measure, x, cx, t.
+        let circ = QuantumCircuit::new(|b| {
+            let [a, c, d] = b.qubits()?;
+            let bit = b.bit()?;
+            b.h(a).t(c).cx(a, c).tdg(c).measure(a, bit);
+            b.instructions_mut().push_modified(OpKind::X, &[d], &[], &[], Modifier::IfBit(bit))?;
+            b.cx(c, d).t(d);
+            Ok(())
+        }).unwrap();
+
+        let stats = circ.stats();
+        assert_eq!(stats.num_instrs, 8);
+        assert_eq!(stats.counts.into_iter().collect::<Vec<_>>(), [("cx", 2), ("h", 1), ("measure", 1), ("t", 2), ("tdg", 1), ("x", 1)]);
+        assert_eq!(stats.depth, 6);
+        assert_eq!((stats.num_non_local, stats.non_local_depth), (2, 2));
+        assert_eq!((stats.t_count, stats.t_depth), (3, 3));
+        assert_eq!(stats.num_measures, 1);
+        assert_eq!(stats.critical_path, None);
+
+        // 1 + 10 + 5 + 1 + 10 + 1, rather than 1 + 10 + 1 + 10 + 1 through the qubits only.
+        assert_eq!(circ.stats_on(&Timed).critical_path, Some(28.0));
+    }
+}
@@ -0,0 +1,440 @@
+//! Transpilation passes, rewriting instructions into the gates supported by devices.
+
+use thiserror::Error;
+
+use crate::instruction::{self, Instr, InstrVec, Modifier};
+use crate::linalg::{c64, DMatrix, NotUnitaryError};
+use crate::operation::OpKind;
+use crate::parameter::Parameter;
+use crate::symbol::Qubit;
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Error)]
+pub enum DecompositionError {
+    #[error("instruction has unbound formal parameters")]
+    NotConcrete,
+    #[error("operation `{0}` can't be decomposed")]
+    Unsupported(&'static str),
+    #[error(transparent)]
+    NotUnitary(#[from] NotUnitaryError),
+    #[error("instructions under a loop modifier can't be decomposed into several gates")]
+    LoopModifier,
+}
+
+/// A gate of a decomposition: the operation, the qubits it acts on and the values of it's parameters.
+pub type Gate<'id> = (OpKind<'id>, Vec<usize>, Vec<f64>);
+
+/// Decomposes the instructions with a [`Modifier::Controlled`], along with their inverse and
+/// power modifiers, into single qubit gates and CX gates, without ancillas. Other instructions
+/// are left as is.
+///
+/// An operation under $ n $ controls is decomposed recursively with lemma 7.5 of
+/// [Barenco et al.](https://arxiv.org/abs/quant-ph/9503016), into $ O(3^n) $ gates.
+pub fn decompose_controlled(instructions: InstrVec<'_>) -> Result<InstrVec<'_>, DecompositionError> {
+    let data = instructions.take();
+    let mut res = Vec::with_capacity(data.len());
+
+    instruction::rewrite(&data, &mut res, &mut |instr, dest| {
+        if !instr.modifiers.iter().any(|modifier| matches!(modifier, Modifier::Controlled(_))) {
+            instr.write(dest);
+            return Ok(());
+        }
+
+        let gates = modified_gates(instr, &values(instr.parameters.iter().copied())?, &values(instr.exponents())?)?;
+        let global = gates.into_iter()
+            .map(|(op, qubits, parameters)| (op, qubits.into_iter().map(|q| Qubit::new_unchecked(q as u32)).collect(), parameters));
+        write_gates(dest, instr, global, &[])
+    })?;
+
+    Ok(InstrVec::new(res))
+}
+
+/// Lowers the instructions with a [`Modifier::Inverse`] or a [`Modifier::Power`] to plain gates,
+/// see [`Instr::gates`], which keep the controls and classical modifiers of the instruction.
+/// Other instructions are left as is.
+pub fn decompose_inverse_power(instructions: InstrVec<'_>) -> Result<InstrVec<'_>, DecompositionError> {
+    let data = instructions.take();
+    let mut res = Vec::with_capacity(data.len());
+
+    instruction::rewrite(&data, &mut res, &mut |instr, dest| {
+        if !instr.modifiers.iter().any(|modifier| matches!(modifier, Modifier::Inverse | Modifier::Power(_))) {
+            instr.write(dest);
+            return Ok(());
+        }
+
+        let gates = instr.gates(&values(instr.parameters.iter().copied())?, &values(instr.exponents())?)
+            .ok_or(DecompositionError::Unsupported(instr.op.label()))?;
+        let controls: Vec<_> = instr.modifiers.iter()
+            .filter(|modifier| matches!(modifier, Modifier::Controlled(_)))
+            .cloned()
+            .collect();
+        let local = gates.into_iter()
+            .map(|(op, qubits, parameters)| (op, qubits.into_iter().map(|q| instr.qubits[q]).collect(), parameters));
+        write_gates(dest, instr, local, &controls)
+    })?;
+
+    Ok(InstrVec::new(res))
+}
+
+/// Returns the values of the parameters, or an error if one of them is formal.
+fn values<'id>(parameters: impl Iterator<Item = Parameter<'id>>) -> Result<Vec<f64>, DecompositionError> {
+    parameters
+        .map(|param| param.as_value().map(f64::from).ok_or(DecompositionError::NotConcrete))
+        .collect()
+}
+
+/// Writes the gates replacing the instruction to the destination, each under the given quantum
+/// modifiers followed by the classical modifiers of the instruction. The classical modifiers
+/// can only be shared by the gates if they don't repeat them.
+fn write_gates<'id>(dest: &mut Vec<u32>, instr: &Instr<'id>, gates: impl ExactSizeIterator<Item = (OpKind<'id>, Vec<Qubit<'id>>, Vec<f64>)>, quantum: &[Modifier<'id>]) -> Result<(), DecompositionError> {
+    let classical: Vec<_> = instr.modifiers.iter().filter(|modifier| modifier.is_classical()).cloned().collect();
+    if gates.len() > 1 && !classical.iter().all(|modifier| matches!(modifier, Modifier::IfBit(_) | Modifier::IfCompute(_))) {
+        return Err(DecompositionError::LoopModifier);
+    }
+
+    let modifiers = [quantum, &classical].concat();
+    for (op, qubits, parameters) in gates {
+        let parameters: Vec<_> = parameters.into_iter().map(|x| Parameter::from(x as f32)).collect();
+        instruction::write_parts(dest, &op, &qubits, &[], &parameters, &modifiers);
+    }
+
+    Ok(())
+}
+
+/// Returns single qubit gates and CX gates implementing the instruction's operation under it's
+/// quantum modifiers, given the values of it's parameters and exponents, on the indices of the
+/// qubits.
+pub(crate) fn modified_gates<'id>(instr: &Instr<'id>, parameters: &[f64], exponents: &[f64]) -> Result<Vec<Gate<'id>>, DecompositionError> {
+    let gates = instr.gates(parameters, exponents).ok_or(DecompositionError::Unsupported(instr.op.label()))?;
+    let (controls, states) = instr.controls();
+    let controls: Vec<_> = controls.iter().map(|qubit| qubit.id() as usize).collect();
+
+    let mut res = Vec::new();
+    for (op, qubits, parameters) in gates {
+        let targets: Vec<_> = qubits.iter().map(|&q| instr.qubits[q].id() as usize).collect();
+        res.extend(controlled_gates(&op, &parameters, &targets, &controls, &states)?);
+    }
+
+    Ok(res)
+}
+
+/// Returns single qubit gates and CX gates implementing the operation on the targets, only
+/// applied when each of the control qubits is in it's given state.
+pub(crate) fn controlled_gates<'id>(op: &OpKind<'_>, parameters: &[f64], targets: &[usize], controls: &[usize], states: &[bool]) -> Result<Vec<Gate<'id>>, DecompositionError> {
+    let matrix = op.matrix(parameters).ok_or(DecompositionError::Unsupported(op.label()))?;
+    let [x, y, z] = [OpKind::X, OpKind::Y, OpKind::Z].map(|op| op.matrix(&[]).unwrap());
+
+    // The operation as a product of single qubit gates, each controlled by some of it's qubits.
+    let parts = match (op, targets) {
+        (_, &[t]) => vec![(matrix, t, vec![])],
+        (OpKind::CX, &[c, t]) => vec![(x, t, vec![c])],
+        (OpKind::CY, &[c, t]) => vec![(y, t, vec![c])],
+        (OpKind::CZ, &[c, t]) => vec![(z, t, vec![c])],
+        (OpKind::CPhase, &[c, t]) => vec![(OpKind::Phase.matrix(parameters).unwrap(), t, vec![c])],
+        (OpKind::Swap, &[a, b]) => vec![(x.clone(), b, vec![a]), (x.clone(), a, vec![b]), (x, b, vec![a])],
+        (OpKind::CCX, &[a, b, t]) => vec![(x, t, vec![a, b])],
+        (op, _) => return Err(DecompositionError::Unsupported(op.label())),
+    };
+
+    // The controls which must be in the $ |0 \rangle $ state are flipped around the operation.
+    let flips: Vec<Gate<'id>> = controls.iter().zip(states)
+        .filter(|&(_, &state)| !state)
+        .map(|(&control, _)| (OpKind::X, vec![control], vec![]))
+        .collect();
+
+    let mut res = flips.clone();
+    for (gate, target, extra) in parts {
+        let all: Vec<_> = controls.iter().copied().chain(extra).collect();
+        multi_controlled(&mut res, &gate, &all, target)?;
+    }
+    res.extend(flips);
+
+    Ok(res)
+}
+
+/// Returns `true` if the matrix is exactly the Pauli X matrix.
+fn is_x(gate: &DMatrix) -> bool {
+    gate.raw().iter().zip([0.0, 1.0, 1.0, 0.0]).all(|(z, re)| z.re == re && z.im == 0.0)
+}
+
+/// Returns the angles $ (\alpha, \beta, \gamma, \delta) $ such that the single qubit unitary
+/// is $ e^{i \alpha} R_z(\beta) R_y(\gamma) R_z(\delta) $.
+pub(crate) fn zyz(gate: &DMatrix) -> (f64, f64, f64, f64) {
+    let det = gate[(0, 0)] * gate[(1, 1)] - gate[(0, 1)] * gate[(1, 0)];
+    let alpha = det.arg() / 2.0;
+
+    // The first column of the special unitary $ e^{-i \alpha} U $.
+    let phase = c64::cis(-alpha);
+    let (a, b) = (gate[(0, 0)] * phase, gate[(1, 0)] * phase);
+
+    let gamma = 2.0 * b.abs().atan2(a.abs());
+    (alpha, b.arg() - a.arg(), gamma, -a.arg() - b.arg())
+}
+
+/// Appends the rotation to the gates, unless it's angle is zero.
+fn rotation<'id>(res: &mut Vec<Gate<'id>>, op: OpKind<'id>, angle: f64, qubit: usize) {
+    if angle != 0.0 {
+        res.push((op, vec![qubit], vec![angle]));
+    }
+}
+
+/// Appends the gates implementing the single qubit gate on the target, controlled by the qubits
+/// being in the $ |1 \rangle $ state.
+fn multi_controlled(res: &mut Vec<Gate<'_>>, gate: &DMatrix, controls: &[usize], target: usize) -> Result<(), NotUnitaryError> {
+    use OpKind::*;
+
+    match *controls {
+        [] => {
+            let (_, beta, gamma, delta) = zyz(gate);
+            res.push((U, vec![target], vec![gamma, beta, delta]));
+        }
+        [c] if is_x(gate) => res.push((CX, vec![c, target], vec![])),
+        [c] => {
+            // $ U = e^{i \alpha} A X B X C $ with $ A B C = I $, see Nielsen & Chuang, corollary 4.2.
+            let (alpha, beta, gamma, delta) = zyz(gate);
+            rotation(res, RZ, (delta - beta) / 2.0, target);
+            res.push((CX, vec![c, target], vec![]));
+            rotation(res, RZ, -(delta + beta) / 2.0, target);
+            rotation(res, RY, -gamma / 2.0, target);
+            res.push((CX, vec![c, target], vec![]));
+            rotation(res, RY, gamma / 2.0, target);
+            rotation(res, RZ, beta, target);
+            rotation(res, Phase, alpha, c);
+        }
+        [a, b] if is_x(gate) => {
+            let t = target;
+            res.extend([
+                (H, vec![t]), (CX, vec![b, t]), (Tdg, vec![t]), (CX, vec![a, t]),
+                (T, vec![t]), (CX, vec![b, t]), (Tdg, vec![t]), (CX, vec![a, t]),
+                (T, vec![b]), (T, vec![t]), (H, vec![t]), (CX, vec![a, b]),
+                (T, vec![a]), (Tdg, vec![b]), (CX, vec![a, b]),
+            ].map(|(op, qubits)| (op, qubits, vec![])));
+        }
+        [ref rest @ .., last] => {
+            // With $ V^2 = U $, the last control applies $ V $ and $ V^\dagger $ around flips
+            // controlled by the others, which then apply the missing $ V $.
+            let sqrt = gate.sqrt_unitary()?;
+            let x = X.matrix(&[]).unwrap();
+            multi_controlled(res, &sqrt, &[last], target)?;
+            multi_controlled(res, &x, rest, last)?;
+            multi_controlled(res, &sqrt.adjoint(), &[last], target)?;
+            multi_controlled(res, &x, rest, last)?;
+            multi_controlled(res, &sqrt, rest, target)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::circuit::{CircuitBuilder, QuantumCircuit};
+    use crate::equivalence;
+    use crate::instruction::{InstrVec, Modifier};
+    use crate::linalg::DMatrix;
+    use crate::operation::OpKind;
+    use crate::parameter::Parameter;
+    use crate::symbol::Qubit;
+
+    use super::{decompose_controlled, decompose_inverse_power, DecompositionError};
+
+    /// Builds a circuit on `num_qubits` qubits, starting with layers of rotations so that
+    /// the controls are checked on arbitrary states.
+    fn circuit<F>(num_qubits: usize, build: F) -> QuantumCircuit
+    where
+        F: for<'id> Fn(&mut CircuitBuilder<'id>, &[Qubit<'id>])
+    {
+        QuantumCircuit::new(|b| {
+            let qubits = (0..num_qubits).map(|_| b.qubit()).collect::<Result<Vec<_>, _>>()?;
+            for (k, &qubit) in qubits.iter().enumerate() {
+                b.ry(0.3 + 0.2 * k as f32, qubit).rz(0.7 - 0.1 * k as f32, qubit);
+            }
+            build(b, &qubits);
+            Ok(())
+        }).unwrap()
+    }
+
+    /// Applies the pass to the instructions of the circuit.
+    fn decomposed_with(circ: &QuantumCircuit, pass: for<'id> fn(InstrVec<'id>) -> Result<InstrVec<'id>, DecompositionError>) -> Result<QuantumCircuit, DecompositionError> {
+        let mut res = Ok(());
+        let circ = circ.clone().edit(|b| {
+            let instructions = std::mem::take(b.instructions_mut());
+            match pass(instructions) {
+                Ok(instructions) => *b.instructions_mut() = instructions,
+                Err(err) => res = Err(err),
+            }
+            Ok(())
+        }).unwrap();
+        res.map(|_| circ)
+    }
+
+    fn decomposed(circ: &QuantumCircuit) -> Result<QuantumCircuit, DecompositionError> {
+        decomposed_with(circ, decompose_controlled)
+    }
+
+    /// Checks that the decomposition only holds single qubit gates and CX gates, and that it
+    /// implements the same unitary up to a global phase.
+    fn check(circ: &QuantumCircuit) {
+        let res = decomposed(circ).unwrap();
+
+        for instr in res.iter() {
+            assert!(!instr.has_modifier(), "`{}` has a modifier", instr.op.label());
+            assert!(instr.op.qubits().get() == Some(1) || instr.op == OpKind::CX, "`{}` is not a basis gate", instr.op.label());
+        }
+
+        assert!(equivalence::equivalent(circ, &res).unwrap());
+    }
+
+    #[test]
+    fn decomposes_single_controls() {
+        for (label, parameters) in [("x", vec![]), ("h", vec![]), ("t", vec![]), ("rx", vec![0.4]), ("p", vec![1.1]), ("u", vec![0.3, -0.8, 1.7])] {
+            for state in [true, false] {
+                check(&circuit(2, |b, q| {
+                    let op = OpKind::from_label(label).unwrap();
+                    let parameters: Vec<_> = parameters.iter().map(|&x| Parameter::from(x)).collect();
+                    b.instructions_mut().push_controlled(op, &[q[0]], &[state], &[q[1]], &parameters);
+                }));
+            }
+        }
+    }
+
+    #[test]
+    fn decomposes_multiple_controls() {
+        check(&circuit(3, |b, q| {
+            b.instructions_mut().push_controlled(OpKind::X, &q[..2], &[true, true], &[q[2]], &[]);
+        }));
+        check(&circuit(3, |b, q| {
+            b.instructions_mut().push_controlled(OpKind::RY, &[q[2], q[0]], &[true, false], &[q[1]], &[Parameter::from(0.9)]);
+        }));
+        check(&circuit(4, |b, q| {
+            b.instructions_mut().push_controlled(OpKind::X, &q[..3], &[false, true, false], &[q[3]], &[]);
+        }));
+        check(&circuit(5, |b, q| {
+            let parameters = [Parameter::from(0.5), Parameter::from(1.2), Parameter::from(-0.3)];
+            b.instructions_mut().push_controlled(OpKind::U, &[q[4], q[1], q[3], q[0]], &[true, true, false, true], &[q[2]], &parameters);
+        }));
+    }
+
+    #[test]
+    fn decomposes_controlled_two_qubit_gates() {
+        for label in ["cx", "cy", "cz", "swap"] {
+            check(&circuit(3, |b, q| {
+                b.instructions_mut().push_controlled(OpKind::from_label(label).unwrap(), &[q[0]], &[true], &[q[1], q[2]], &[]);
+            }));
+            check(&circuit(4, |b, q| {
+                b.instructions_mut().push_controlled(OpKind::from_label(label).unwrap(), &[q[3], q[0]], &[false, true], &[q[2], q[1]], &[]);
+            }));
+        }
+
+        check(&circuit(4, |b, q| {
+            b.instructions_mut().push_controlled(OpKind::CPhase, &[q[1], q[2]], &[true, false], &[q[0], q[3]], &[Parameter::from(0.6)]);
+        }));
+        check(&circuit(4, |b, q| {
+            b.instructions_mut().push_controlled(OpKind::CCX, &[q[2]], &[false], &[q[0], q[3], q[1]], &[]);
+        }));
+    }
+
+    #[test]
+    fn keeps_other_instructions() {
+        let labels = |circ: &QuantumCircuit| {
+            circ.iter()
+                .map(|instr| (instr.op.label(), instr.qubits.iter().map(|qubit| qubit.id()).collect::<Vec<_>>()))
+                .collect::<Vec<_>>()
+        };
+
+        let circ = circuit(2, |b, q| { b.cx(q[0], q[1]).swap(q[1], q[0]); });
+        assert_eq!(labels(&decomposed(&circ).unwrap()), labels(&circ));
+    }
+
+    #[test]
+    fn rejects_formal_parameters() {
+        let circ = QuantumCircuit::new(|b| {
+            let [a, c] = b.qubits()?;
+            let theta = b.formal()?;
+            b.instructions_mut().push_controlled(OpKind::RZ, &[a], &[true], &[c], &[theta.into()]);
+            Ok(())
+        }).unwrap();
+        assert_eq!(decomposed(&circ).unwrap_err(), DecompositionError::NotConcrete);
+    }
+
+    /// The unitary operations, with values for their parameters.
+    const UNITARIES: [(&str, &[f32]); 21] = [
+        ("h", &[]), ("x", &[]), ("y", &[]), ("z", &[]), ("s", &[]), ("sdg", &[]), ("t", &[]), ("tdg", &[]),
+        ("sx", &[]), ("sxdg", &[]), ("rx", &[0.7]), ("ry", &[-1.3]), ("rz", &[2.9]), ("p", &[0.4]),
+        ("u", &[0.3, -0.8, 1.7]), ("cx", &[]), ("cy", &[]), ("cz", &[]), ("swap", &[]), ("cp", &[-2.2]), ("ccx", &[]),
+    ];
+
+    /// Returns the unitary of the circuit applying the operation on the last of three qubits
+    /// in reverse order, raised to the power `exponent` or inverted if there is none, after
+    /// lowering it with [`decompose_inverse_power`], along with the unitary of the bare operation.
+    fn lowered(label: &str, parameters: &[f32], exponent: Option<f32>) -> (DMatrix, DMatrix) {
+        let circ = |modified: bool| QuantumCircuit::new(|b| {
+            let qubits = b.qubits::<3>()?;
+            let op = OpKind::from_label(label).unwrap();
+            let targets: Vec<_> = qubits.iter().rev().take(op.qubits().get().unwrap() as usize).copied().collect();
+            let parameters: Vec<_> = parameters.iter().map(|&x| Parameter::from(x)).collect();
+            let modifier = exponent.map_or(Modifier::Inverse, |exponent| Modifier::Power(Parameter::from(exponent)));
+            match modified {
+                true => b.instructions_mut().push_modified(op, &targets, &[], &parameters, modifier)?,
+                false => b.instructions_mut().push(op, &targets, &[], &parameters),
+            }
+            Ok(())
+        }).unwrap();
+
+        let res = decomposed_with(&circ(true), decompose_inverse_power).unwrap();
+        assert!(res.iter().all(|instr| !instr.has_modifier()));
+
+        (equivalence::unitary(&res).unwrap(), equivalence::unitary(&circ(false)).unwrap())
+    }
+
+    // Decompositions carry their angles as `f32` parameters.
+    fn assert_close(lhs: &DMatrix, rhs: &DMatrix, label: &str) {
+        assert!((lhs - rhs).norm() < 1E-5, "`{label}`");
+    }
+
+    #[test]
+    fn lowers_inverses() {
+        for (label, parameters) in UNITARIES {
+            let (res, op) = lowered(label, parameters, None);
+            assert_close(&res, &op.adjoint(), label);
+        }
+    }
+
+    #[test]
+    fn lowers_powers() {
+        let pow = |matrix: &DMatrix, n: usize| (0..n).fold(DMatrix::eye(matrix.dim()), |acc, _| &acc * matrix);
+
+        for (label, parameters) in UNITARIES {
+            // Roots are checked against the operation, which holds on any branch.
+            let (res, op) = lowered(label, parameters, Some(0.5));
+            assert_close(&pow(&res, 2), &op, label);
+            let (res, op) = lowered(label, parameters, Some(0.25));
+            assert_close(&pow(&res, 4), &op, label);
+            let (res, op) = lowered(label, parameters, Some(-0.5));
+            assert_close(&pow(&res, 2), &op.adjoint(), label);
+            let (res, op) = lowered(label, parameters, Some(2.0));
+            assert_close(&res, &pow(&op, 2), label);
+            let (res, op) = lowered(label, parameters, Some(-1.0));
+            assert_close(&res, &op.adjoint(), label);
+            let (res, _) = lowered(label, parameters, Some(0.0));
+            assert_close(&res, &DMatrix::eye(8), label);
+        }
+    }
+
+    #[test]
+    fn lowers_modifiers_within_circuits() {
+        let circ = circuit(3, |b, q| {
+            b.instructions_mut().push_modified(OpKind::U, &[q[1]], &[], &[Parameter::from(0.2), Parameter::from(1.1), Parameter::from(-0.4)], Modifier::Inverse).unwrap();
+            b.cx(q[1], q[2]);
+            b.instructions_mut().push_modified(OpKind::CCX, &[q[2], q[0], q[1]], &[], &[], Modifier::Power(Parameter::from(0.3))).unwrap();
+            b.instructions_mut().push_controlled(OpKind::H, &[q[0]], &[false], &[q[2]], &[]);
+            b.instructions_mut().push_modified(OpKind::Swap, &[q[0], q[2]], &[], &[], Modifier::Power(Parameter::from(-1.7))).unwrap();
+        });
+
+        let res = decomposed_with(&circ, decompose_inverse_power).unwrap();
+        assert!(equivalence::equivalent(&circ, &res).unwrap());
+
+        for instr in res.iter() {
+            assert!(!instr.modifiers.iter().any(|modifier| matches!(modifier, Modifier::Inverse | Modifier::Power(_))));
+        }
+    }
+}